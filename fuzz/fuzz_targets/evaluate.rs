@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ls_calc::command::{run_line, Session};
+use ls_calc::evaluator::evaluate;
+
+// Any input gives `Ok` or `Err`, a panic is a bug. Inputs that found one
+// belong in `tests/fixtures/panic_corpus`.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(expr) = std::str::from_utf8(data) {
+        let _ = evaluate(&expr.to_string());
+        let _ = run_line(expr, &mut Session::default());
+    }
+});
@@ -0,0 +1,336 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::ast::{Node, NodePtr, TokenType};
+use super::builtins::{builtin_overloads, find_constant};
+use super::special_forms::find_special_form;
+use super::visitor::{walk, walk_node, Visitor};
+
+/// Collects the variables a tree reads that aren't bound inside of it by a
+/// lambda or a `sum`-like function.
+struct FreeVariables {
+    bound: Vec<String>,
+    free: BTreeSet<String>,
+}
+
+impl FreeVariables {
+    fn walk_bound(&mut self, var: &str, body: &Node) {
+        self.bound.push(var.to_string());
+        walk_node(self, body);
+        self.bound.pop();
+    }
+}
+
+impl Visitor for FreeVariables {
+    type Output = ();
+
+    fn visit_number(&mut self, _n: f64) {}
+
+    fn visit_variable(&mut self, name: &str) {
+        if !self.bound.iter().any(|bound| bound == name) {
+            self.free.insert(name.to_string());
+        }
+    }
+
+    fn visit_history(&mut self, _n: usize) {}
+
+    fn visit_text(&mut self, _text: &str) {}
+
+    fn visit_call(&mut self, name: &str, args: &[Node]) {
+        let scoped = find_special_form(name).and_then(|form| form.scoped_args(args));
+        match scoped {
+            Some((var, body)) => {
+                for (i, arg) in args.iter().enumerate() {
+                    if i != var && i != body {
+                        walk_node(self, arg);
+                    }
+                }
+                match args[var].token {
+                    TokenType::Variable(ref var) => self.walk_bound(var, &args[body]),
+                    _ => walk_node(self, &args[body]),
+                }
+            }
+            None => {
+                for arg in args {
+                    walk_node(self, arg);
+                }
+            }
+        }
+    }
+
+    fn visit_lambda(&mut self, param: &str, body: &NodePtr) {
+        if let Some(body) = body {
+            self.walk_bound(param, body);
+        }
+    }
+
+    fn visit_unary(&mut self, _op: &TokenType, operand: &NodePtr) {
+        walk(self, operand);
+    }
+
+    fn visit_binary(&mut self, _op: &TokenType, left: &NodePtr, right: &NodePtr) {
+        walk(self, left);
+        walk(self, right);
+    }
+
+    fn visit_none(&mut self) {}
+}
+
+/// Checks that a tree doesn't call an impure function like `random()`.
+struct Purity;
+
+impl Visitor for Purity {
+    type Output = bool;
+
+    fn visit_number(&mut self, _n: f64) -> bool {
+        true
+    }
+
+    fn visit_variable(&mut self, _name: &str) -> bool {
+        true
+    }
+
+    // The history grows with every result.
+    fn visit_history(&mut self, _n: usize) -> bool {
+        false
+    }
+
+    fn visit_text(&mut self, _text: &str) -> bool {
+        true
+    }
+
+    fn visit_call(&mut self, name: &str, args: &[Node]) -> bool {
+        let pure = builtin_overloads(name).all(|builtin| builtin.pure);
+        pure && args.iter().all(|arg| walk_node(self, arg))
+    }
+
+    fn visit_lambda(&mut self, _param: &str, body: &NodePtr) -> bool {
+        walk(self, body)
+    }
+
+    fn visit_unary(&mut self, _op: &TokenType, operand: &NodePtr) -> bool {
+        walk(self, operand)
+    }
+
+    fn visit_binary(&mut self, _op: &TokenType, left: &NodePtr, right: &NodePtr) -> bool {
+        walk(self, left) && walk(self, right)
+    }
+
+    fn visit_none(&mut self) -> bool {
+        true
+    }
+}
+
+/// Size and shape of a tree.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct AstStats {
+    /// Number of nodes. Parentheses don't create nodes, so they aren't
+    /// counted.
+    pub nodes: usize,
+    /// Number of nodes on the longest path from the root, so a lone number
+    /// has depth 1.
+    pub depth: usize,
+    /// How often every operator occurs, keyed by its symbol. Prefix minus and
+    /// plus are `neg` and `pos`.
+    pub operators: BTreeMap<&'static str, usize>,
+    pub numbers: usize,
+    pub free_variables: BTreeSet<String>,
+}
+
+fn operator_symbol(op: &TokenType) -> &'static str {
+    match op {
+        TokenType::Power => "^",
+        TokenType::Plus => "+",
+        TokenType::Minus => "-",
+        TokenType::Multiply => "*",
+        TokenType::Divide => "/",
+        TokenType::FloorDivide => "//",
+        TokenType::And => "and",
+        TokenType::Or => "or",
+        TokenType::Not => "not",
+        TokenType::Less => "<",
+        TokenType::LessEqual => "<=",
+        TokenType::Greater => ">",
+        TokenType::GreaterEqual => ">=",
+        TokenType::Equal => "==",
+        TokenType::NotEqual => "!=",
+        TokenType::Bar => "|",
+        TokenType::Factorial => "!",
+        TokenType::DoubleFactorial => "!!",
+        TokenType::Percent => "percent",
+        TokenType::Modulo => "%",
+        TokenType::PrefixMinus => "neg",
+        TokenType::PrefixPlus => "pos",
+        _ => panic!("Unknown operator token! {:?}", op),
+    }
+}
+
+/// Counts the nodes of a tree. Every hook returns the depth of its node.
+struct StatsCollector {
+    stats: AstStats,
+}
+
+impl StatsCollector {
+    fn leaf(&mut self) -> usize {
+        self.stats.nodes += 1;
+        1
+    }
+
+    fn operator(&mut self, op: &TokenType) {
+        self.stats.nodes += 1;
+        *self.stats.operators.entry(operator_symbol(op)).or_insert(0) += 1;
+    }
+}
+
+impl Visitor for StatsCollector {
+    type Output = usize;
+
+    fn visit_number(&mut self, _n: f64) -> usize {
+        self.stats.numbers += 1;
+        self.leaf()
+    }
+
+    fn visit_variable(&mut self, _name: &str) -> usize {
+        self.leaf()
+    }
+
+    fn visit_history(&mut self, _n: usize) -> usize {
+        self.leaf()
+    }
+
+    fn visit_text(&mut self, _text: &str) -> usize {
+        self.leaf()
+    }
+
+    fn visit_call(&mut self, _name: &str, args: &[Node]) -> usize {
+        self.stats.nodes += 1;
+        let depth = args.iter().map(|arg| walk_node(self, arg)).max();
+        depth.unwrap_or(0) + 1
+    }
+
+    fn visit_lambda(&mut self, _param: &str, body: &NodePtr) -> usize {
+        self.stats.nodes += 1;
+        walk(self, body) + 1
+    }
+
+    fn visit_unary(&mut self, op: &TokenType, operand: &NodePtr) -> usize {
+        self.operator(op);
+        walk(self, operand) + 1
+    }
+
+    fn visit_binary(&mut self, op: &TokenType, left: &NodePtr, right: &NodePtr) -> usize {
+        self.operator(op);
+        let left = walk(self, left);
+        let right = walk(self, right);
+        left.max(right) + 1
+    }
+
+    fn visit_none(&mut self) -> usize {
+        0
+    }
+}
+
+impl Node {
+    /// Counts the nodes, operators and numbers of the tree, e.g. to reject
+    /// overly complex expressions.
+    pub fn stats(&self) -> AstStats {
+        let mut collector = StatsCollector {
+            stats: AstStats::default(),
+        };
+        collector.stats.depth = walk_node(&mut collector, self);
+        collector.stats.free_variables = self.free_variables();
+        collector.stats
+    }
+
+    /// Names of the variables the tree depends on. Built-in constants like
+    /// `pi` aren't included.
+    pub fn free_variables(&self) -> BTreeSet<String> {
+        let mut visitor = FreeVariables {
+            bound: Vec::new(),
+            free: BTreeSet::new(),
+        };
+        walk_node(&mut visitor, self);
+        visitor
+            .free
+            .into_iter()
+            .filter(|name| find_constant(name).is_none())
+            .collect()
+    }
+
+    /// Whether evaluating the tree twice always gives the same result.
+    pub fn is_pure(&self) -> bool {
+        walk_node(&mut Purity, self)
+    }
+}
+
+#[cfg(test)]
+mod analysis_tests {
+    use super::*;
+    use crate::ast::parse;
+
+    fn free_variables(expr: &str) -> Vec<String> {
+        let tree = parse(expr).unwrap().unwrap();
+        tree.free_variables().into_iter().collect()
+    }
+
+    #[test]
+    fn free_variables_test() {
+        assert_eq!(free_variables("1 + 2"), Vec::<String>::new());
+        assert_eq!(free_variables("x * y + x"), vec!["x", "y"]);
+        assert_eq!(free_variables("sin(b) * pi + a"), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn bound_variables_test() {
+        assert_eq!(free_variables("sum(i, 1, n, i * k)"), vec!["k", "n"]);
+        assert_eq!(free_variables("sum(i -> i * k, 1, n)"), vec!["k", "n"]);
+        assert_eq!(free_variables("integrate(x^2, x, 0, x)"), vec!["x"]);
+        assert_eq!(free_variables("(x -> x + y) + x"), vec!["x", "y"]);
+    }
+
+    fn stats(expr: &str) -> AstStats {
+        parse(expr).unwrap().unwrap().stats()
+    }
+
+    #[test]
+    fn stats_test() {
+        let result = stats("2 * x + 3 * x - -y!");
+        assert_eq!(result.nodes, 11);
+        assert_eq!(result.depth, 4);
+        assert_eq!(result.numbers, 2);
+        assert_eq!(
+            result.operators.into_iter().collect::<Vec<_>>(),
+            vec![("!", 1), ("*", 2), ("+", 1), ("-", 1), ("neg", 1)]
+        );
+        assert_eq!(
+            result.free_variables.into_iter().collect::<Vec<_>>(),
+            vec!["x", "y"]
+        );
+
+        let result = stats("sum(i -> i ^ 2, 1, n) + pi");
+        assert_eq!(result.nodes, 9);
+        assert_eq!(result.depth, 5);
+        assert_eq!(result.numbers, 2);
+        assert_eq!(
+            result.free_variables.into_iter().collect::<Vec<_>>(),
+            vec!["n"]
+        );
+    }
+
+    #[test]
+    fn parentheses_stats_test() {
+        // Parentheses only group, they don't add nodes.
+        assert_eq!(stats("((((1))))").depth, 1);
+        assert_eq!(stats("((((1))))").nodes, 1);
+        assert_eq!(stats("|((1))|").depth, 2);
+    }
+
+    #[test]
+    fn purity_test() {
+        assert!(parse("sin(x) + 1").unwrap().unwrap().is_pure());
+        assert!(!parse("random() * 2").unwrap().unwrap().is_pure());
+        assert!(!parse("sum(i -> random(), 1, 3)")
+            .unwrap()
+            .unwrap()
+            .is_pure());
+    }
+}
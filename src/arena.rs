@@ -0,0 +1,919 @@
+use std::fmt;
+use std::ops::Index;
+
+use super::ast::{check_operands, Ast, Node, NodePtr, TokenType, TreeBuilder};
+use super::builtins::Builtin;
+use super::context::Context;
+use super::derivative::{outer_derivative, DiffError};
+use super::error::Span;
+use super::evaluator::{truth, Evaluator};
+use super::scanner::Scanner;
+use super::special_forms::find_special_form;
+use super::visitor::Visitor;
+
+/// The index of a node in an `Arena`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(u32);
+
+impl NodeId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// A node of an `Arena`, like `Node` with ids instead of boxes. The
+/// arguments of a call are in `args`, its token has none.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArenaNode {
+    pub token: TokenType,
+    pub pos: usize,
+    pub span: Span,
+    pub left: Option<NodeId>,
+    pub right: Option<NodeId>,
+    pub args: Vec<NodeId>,
+}
+
+/// A tree with all of its nodes in one `Vec`, children before their
+/// parents, so that it takes a single allocation rather than one per node
+/// and evaluating it doesn't chase pointers across the heap. A node can be
+/// the child of several others, which `substitute` and `differentiate` use
+/// to share subtrees instead of copying them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Arena {
+    nodes: Vec<ArenaNode>,
+    root: Option<NodeId>,
+}
+
+impl Arena {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Moves the nodes of a tree into an arena, which is empty for `None`.
+    pub fn from_tree(root: NodePtr) -> Self {
+        let mut arena = Arena::new();
+        arena.root = root.map(|root| arena.add(*root));
+        arena
+    }
+
+    /// Adds a node whose children are in the arena already.
+    pub fn alloc(&mut self, node: ArenaNode) -> NodeId {
+        let id = NodeId(self.nodes.len() as u32);
+        self.nodes.push(node);
+        id
+    }
+
+    /// Moves the nodes of a tree into the arena, like a replacement for
+    /// `substitute`, and returns the id of its root.
+    pub fn add(&mut self, node: Node) -> NodeId {
+        let left = node.left.map(|left| self.add(*left));
+        let right = node.right.map(|right| self.add(*right));
+        let (token, args) = match node.token {
+            TokenType::Call(name, args) => {
+                let args = args.into_iter().map(|arg| self.add(arg)).collect();
+                (TokenType::Call(name, Vec::new()), args)
+            }
+            token => (token, Vec::new()),
+        };
+        self.alloc(ArenaNode {
+            token,
+            pos: node.pos,
+            span: node.span,
+            left,
+            right,
+            args,
+        })
+    }
+
+    pub fn root(&self) -> Option<NodeId> {
+        self.root
+    }
+
+    pub fn set_root(&mut self, root: Option<NodeId>) {
+        self.root = root;
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// The tree of boxed nodes below `id`, for the passes that walk those.
+    pub fn node(&self, id: NodeId) -> Node {
+        let node = &self[id];
+        let child = |id: Option<NodeId>| id.map(|id| Box::new(self.node(id)));
+        let token = match &node.token {
+            TokenType::Call(name, _) => TokenType::Call(
+                name.clone(),
+                node.args.iter().map(|&arg| self.node(arg)).collect(),
+            ),
+            token => token.clone(),
+        };
+        Node {
+            token,
+            pos: node.pos,
+            span: node.span,
+            left: child(node.left),
+            right: child(node.right),
+        }
+    }
+
+    pub fn to_tree(&self) -> NodePtr {
+        self.root.map(|root| Box::new(self.node(root)))
+    }
+
+    fn leaf(&mut self, token: TokenType) -> NodeId {
+        self.op(token, None, None)
+    }
+
+    fn op(&mut self, token: TokenType, left: Option<NodeId>, right: Option<NodeId>) -> NodeId {
+        self.alloc(ArenaNode {
+            token,
+            pos: 0,
+            span: Span::default(),
+            left,
+            right,
+            args: Vec::new(),
+        })
+    }
+
+    fn call(&mut self, name: &str, args: Vec<NodeId>) -> NodeId {
+        let call = self.leaf(TokenType::Call(name.to_string(), Vec::new()));
+        self.nodes[call.index()].args = args;
+        call
+    }
+
+    /// Lambdas and calls like `sum(i, 1, 3, i)` bind variables, which the
+    /// passes over an arena leave to those over the tree.
+    fn binds(&self, id: NodeId) -> bool {
+        match &self[id].token {
+            TokenType::Lambda(_) => true,
+            TokenType::Call(name, _) => find_special_form(name).is_some(),
+            _ => false,
+        }
+    }
+
+    /// Whether the tree below `id` mentions the variable `var`, see
+    /// `Node::depends_on`.
+    pub fn depends_on(&self, id: NodeId, var: &str) -> bool {
+        let node = &self[id];
+        match &node.token {
+            _ if self.binds(id) => self.node(id).depends_on(var),
+            TokenType::Variable(name) => name == var,
+            _ => node
+                .left
+                .iter()
+                .chain(node.right.iter())
+                .chain(node.args.iter())
+                .any(|&child| self.depends_on(child, var)),
+        }
+    }
+
+    /// Replaces the variable `name` below `id` with the node `replacement`
+    /// like `Node::substitute`. Rather than copies of it, every occurrence
+    /// shares the one node, and the subtrees without an occurrence are
+    /// shared too. Only the nodes above an occurrence are added.
+    pub fn substitute(&mut self, id: NodeId, name: &str, replacement: NodeId) -> NodeId {
+        let node = self[id].clone();
+        match node.token {
+            TokenType::Variable(ref var) if var == name => return replacement,
+            _ if !self.depends_on(id, name) => return id,
+            _ if self.binds(id) => {
+                let tree = self.node(id).substitute(name, &self.node(replacement));
+                return self.add(tree);
+            }
+            _ => (),
+        }
+        let mut child =
+            |child: Option<NodeId>| child.map(|child| self.substitute(child, name, replacement));
+        let left = child(node.left);
+        let right = child(node.right);
+        let args = node
+            .args
+            .iter()
+            .map(|&arg| self.substitute(arg, name, replacement))
+            .collect();
+        self.alloc(ArenaNode {
+            left,
+            right,
+            args,
+            ..node
+        })
+    }
+
+    /// The derivative of the tree below `id` with respect to `var`, with
+    /// the rules of `Node::differentiate` but without folding constants. The
+    /// derivative shares the operands it repeats, like `u` in the `cos(u) *
+    /// u'` of `sin(u)`, with the tree.
+    pub fn differentiate(&mut self, id: NodeId, var: &str) -> Result<NodeId, DiffError> {
+        if !self.depends_on(id, var) {
+            return Ok(self.leaf(TokenType::Number(0.)));
+        }
+        let node = self[id].clone();
+        let operand = |child: Option<NodeId>| {
+            child.ok_or_else(|| DiffError::Unsupported(String::from("incomplete node")))
+        };
+        let d = match &node.token {
+            TokenType::Variable(_) => self.leaf(TokenType::Number(1.)),
+            TokenType::PrefixPlus => self.differentiate(operand(node.left)?, var)?,
+            TokenType::PrefixMinus => {
+                let du = self.differentiate(operand(node.left)?, var)?;
+                self.op(TokenType::PrefixMinus, Some(du), None)
+            }
+            TokenType::Percent => {
+                let du = self.differentiate(operand(node.left)?, var)?;
+                let hundred = self.leaf(TokenType::Number(100.));
+                self.op(TokenType::Divide, Some(du), Some(hundred))
+            }
+            TokenType::Plus | TokenType::Minus => {
+                let du = self.differentiate(operand(node.left)?, var)?;
+                let dv = self.differentiate(operand(node.right)?, var)?;
+                self.op(node.token.clone(), Some(du), Some(dv))
+            }
+            TokenType::Multiply | TokenType::Divide => {
+                let (u, v) = (operand(node.left)?, operand(node.right)?);
+                let du = self.differentiate(u, var)?;
+                let dv = self.differentiate(v, var)?;
+                let du_v = self.op(TokenType::Multiply, Some(du), Some(v));
+                let u_dv = self.op(TokenType::Multiply, Some(u), Some(dv));
+                if node.token == TokenType::Multiply {
+                    self.op(TokenType::Plus, Some(du_v), Some(u_dv))
+                } else {
+                    let numerator = self.op(TokenType::Minus, Some(du_v), Some(u_dv));
+                    let two = self.leaf(TokenType::Number(2.));
+                    let square = self.op(TokenType::Power, Some(v), Some(two));
+                    self.op(TokenType::Divide, Some(numerator), Some(square))
+                }
+            }
+            TokenType::Power => {
+                let (u, v) = (operand(node.left)?, operand(node.right)?);
+                if !self.depends_on(v, var) {
+                    // n * u^(n - 1) * u'
+                    let one = self.leaf(TokenType::Number(1.));
+                    let lowered = self.op(TokenType::Minus, Some(v), Some(one));
+                    let power = self.op(TokenType::Power, Some(u), Some(lowered));
+                    let factor = self.op(TokenType::Multiply, Some(v), Some(power));
+                    let du = self.differentiate(u, var)?;
+                    self.op(TokenType::Multiply, Some(factor), Some(du))
+                } else if !self.depends_on(u, var) {
+                    // a^v * ln(a) * v'
+                    let ln = self.call("ln", vec![u]);
+                    let factor = self.op(TokenType::Multiply, Some(id), Some(ln));
+                    let dv = self.differentiate(v, var)?;
+                    self.op(TokenType::Multiply, Some(factor), Some(dv))
+                } else {
+                    // u^v * (v' * ln(u) + v * u' / u)
+                    let dv = self.differentiate(v, var)?;
+                    let ln = self.call("ln", vec![u]);
+                    let dv_ln = self.op(TokenType::Multiply, Some(dv), Some(ln));
+                    let du = self.differentiate(u, var)?;
+                    let v_du = self.op(TokenType::Multiply, Some(v), Some(du));
+                    let quotient = self.op(TokenType::Divide, Some(v_du), Some(u));
+                    let sum = self.op(TokenType::Plus, Some(dv_ln), Some(quotient));
+                    self.op(TokenType::Multiply, Some(id), Some(sum))
+                }
+            }
+            TokenType::Bar => {
+                let u = operand(node.left)?;
+                let sign = self.op(TokenType::Divide, Some(u), Some(id));
+                let du = self.differentiate(u, var)?;
+                self.op(TokenType::Multiply, Some(sign), Some(du))
+            }
+            TokenType::Call(name, _) if node.args.len() == 1 => {
+                let u = node.args[0];
+                // The rule is written for a variable that stands for `u`.
+                let placeholder = Node::new(TokenType::Variable(String::from("#")), None, None);
+                let outer = outer_derivative(name, &placeholder)
+                    .ok_or_else(|| DiffError::Unsupported(format!("function {}", name)))?;
+                let outer = self.add(outer);
+                let outer = self.substitute(outer, "#", u);
+                let du = self.differentiate(u, var)?;
+                self.op(TokenType::Multiply, Some(outer), Some(du))
+            }
+            // The same errors as for the tree.
+            _ => return self.node(id).differentiate(var).map(|tree| self.add(tree)),
+        };
+        Ok(d)
+    }
+
+    /// Evaluates the tree with floats like `Evaluator`, once it's checked
+    /// like `ast::validate`.
+    pub fn evaluate(&self, ctx: &Context) -> Result<f64, String> {
+        if let Some(root) = &self.root {
+            self.validate(root)?;
+        }
+        let mut evaluator = Evaluator::new(ctx);
+        self.eval(&mut evaluator, self.root)
+    }
+
+    fn eval(&self, evaluator: &mut Evaluator, id: Option<NodeId>) -> Result<f64, String> {
+        let node = match id {
+            Some(id) => &self[id],
+            None => return evaluator.visit_none(),
+        };
+        evaluator.visit_pos(node.pos);
+        match &node.token {
+            TokenType::Number(n) => Ok(*n),
+            TokenType::Variable(name) => evaluator.visit_variable(name),
+            TokenType::History(n) => evaluator.visit_history(*n),
+            TokenType::Text(text) => evaluator.visit_text(text),
+            // Special forms and `env` take their arguments as trees, other
+            // calls the values of the arguments.
+            TokenType::Call(name, _) if evaluator.takes_trees(name) => {
+                let args: Vec<Node> = node.args.iter().map(|&arg| self.node(arg)).collect();
+                evaluator.visit_call(name, &args)
+            }
+            TokenType::Call(name, _) => {
+                let builtin = self.resolve(evaluator, node, name)?;
+                let args = node
+                    .args
+                    .iter()
+                    .map(|&arg| self.eval(evaluator, Some(arg)))
+                    .collect::<Result<Vec<f64>, String>>()?;
+                evaluator.apply(&builtin, name, &args, node.pos)
+            }
+            TokenType::Lambda(param) => evaluator.visit_lambda(param, &None),
+            TokenType::And if self.eval(evaluator, node.left)? == 0. => Ok(0.),
+            TokenType::Or if self.eval(evaluator, node.left)? != 0. => Ok(1.),
+            TokenType::And | TokenType::Or => Ok(truth(self.eval(evaluator, node.right)? != 0.)),
+            op if op.is_unary() => {
+                let operand = self.eval(evaluator, node.left)?;
+                evaluator.unary(op, operand, node.pos)
+            }
+            op => {
+                let left = self.eval(evaluator, node.left)?;
+                let right = self.eval(evaluator, node.right)?;
+                evaluator.binary(op, left, right, node.pos)
+            }
+        }
+    }
+
+    /// The overload of the function `name` that the call `node` resolves to.
+    fn resolve(
+        &self,
+        evaluator: &Evaluator,
+        node: &ArenaNode,
+        name: &str,
+    ) -> Result<Builtin, String> {
+        let positions: Vec<usize> = node.args.iter().map(|&arg| self[arg].pos).collect();
+        evaluator.resolve(name, &positions, node.pos)
+    }
+}
+
+/// A step of the program that `eval_vec` runs for every value, which
+/// evaluates the operands of a node before it like `Arena::eval` but with a
+/// stack of values instead of recursion.
+enum Step {
+    /// Pops the values of the operands of the node and pushes its own.
+    Node(NodeId),
+    /// A call with its arguments as the trees it takes, built once.
+    Call(NodeId, Vec<Node>),
+    /// Pops the values of the arguments of a call and pushes the value of
+    /// the function it resolved to.
+    Apply(NodeId, Builtin),
+    /// A call that doesn't resolve, like `sin(1, 2)`, fails without
+    /// evaluating its arguments, like it does in the tree.
+    Fail(String),
+    Missing,
+    /// The left operand of an `or` (or of an `and`) on top of the stack that
+    /// is true (false) decides it and skips the right one, the step `to`.
+    ShortCircuit {
+        or: bool,
+        to: usize,
+    },
+    /// Replaces the value on top of the stack by its truth.
+    Truth,
+}
+
+impl Arena {
+    fn compile(&self, id: Option<NodeId>, evaluator: &Evaluator, steps: &mut Vec<Step>) {
+        let id = match id {
+            Some(id) => id,
+            None => return steps.push(Step::Missing),
+        };
+        let node = &self[id];
+        match &node.token {
+            TokenType::Number(_)
+            | TokenType::Variable(_)
+            | TokenType::History(_)
+            | TokenType::Text(_)
+            | TokenType::Lambda(_) => (),
+            TokenType::Call(name, _) if evaluator.takes_trees(name) => {
+                let args = node.args.iter().map(|&arg| self.node(arg)).collect();
+                return steps.push(Step::Call(id, args));
+            }
+            TokenType::Call(name, _) => {
+                return match self.resolve(evaluator, node, name) {
+                    Ok(builtin) => {
+                        for &arg in &node.args {
+                            self.compile(Some(arg), evaluator, steps);
+                        }
+                        steps.push(Step::Apply(id, builtin));
+                    }
+                    Err(err_msg) => steps.push(Step::Fail(err_msg)),
+                };
+            }
+            op @ TokenType::And | op @ TokenType::Or => {
+                self.compile(node.left, evaluator, steps);
+                let short_circuit = steps.len();
+                steps.push(Step::Truth);
+                self.compile(node.right, evaluator, steps);
+                steps.push(Step::Truth);
+                let or = *op == TokenType::Or;
+                steps[short_circuit] = Step::ShortCircuit {
+                    or,
+                    to: steps.len(),
+                };
+                return;
+            }
+            op if op.is_unary() => self.compile(node.left, evaluator, steps),
+            _ => {
+                self.compile(node.left, evaluator, steps);
+                self.compile(node.right, evaluator, steps);
+            }
+        }
+        steps.push(Step::Node(id));
+    }
+
+    fn run(
+        &self,
+        steps: &[Step],
+        evaluator: &mut Evaluator,
+        stack: &mut Vec<f64>,
+    ) -> Result<f64, String> {
+        // The steps push the operands of a node before it pops them, a
+        // program that doesn't is malformed rather than short of a zero.
+        fn malformed(pos: usize) -> String {
+            format!("Malformed program! An operand is missing at pos {}!", pos)
+        }
+        fn pop(stack: &mut Vec<f64>, pos: usize) -> Result<f64, String> {
+            stack.pop().ok_or_else(|| malformed(pos))
+        }
+        stack.clear();
+        // The position of the node evaluated last.
+        let mut pos = 0;
+        let mut i = 0;
+        while i < steps.len() {
+            let value = match &steps[i] {
+                Step::Missing => evaluator.visit_none()?,
+                Step::Truth => truth(pop(stack, pos)? != 0.),
+                &Step::ShortCircuit { or, to } => {
+                    let left = pop(stack, pos)?;
+                    if (left != 0.) == or {
+                        stack.push(truth(or));
+                        i = to;
+                    } else {
+                        i += 1;
+                    }
+                    continue;
+                }
+                Step::Call(id, args) => {
+                    let node = &self[*id];
+                    pos = node.pos;
+                    evaluator.visit_pos(node.pos);
+                    match &node.token {
+                        TokenType::Call(name, _) => evaluator.visit_call(name, args)?,
+                        _ => evaluator.visit_none()?,
+                    }
+                }
+                Step::Apply(id, builtin) => {
+                    let node = &self[*id];
+                    pos = node.pos;
+                    let start = stack
+                        .len()
+                        .checked_sub(node.args.len())
+                        .ok_or_else(|| malformed(pos))?;
+                    let value = match &node.token {
+                        TokenType::Call(name, _) => {
+                            evaluator.apply(builtin, name, &stack[start..], node.pos)?
+                        }
+                        _ => evaluator.visit_none()?,
+                    };
+                    stack.truncate(start);
+                    value
+                }
+                Step::Fail(err_msg) => return Err(err_msg.clone()),
+                Step::Node(id) => {
+                    let node = &self[*id];
+                    pos = node.pos;
+                    evaluator.visit_pos(node.pos);
+                    match &node.token {
+                        TokenType::Number(n) => *n,
+                        TokenType::Variable(name) => evaluator.visit_variable(name)?,
+                        TokenType::History(n) => evaluator.visit_history(*n)?,
+                        TokenType::Text(text) => evaluator.visit_text(text)?,
+                        TokenType::Lambda(param) => evaluator.visit_lambda(param, &None)?,
+                        op if op.is_unary() => {
+                            let operand = pop(stack, pos)?;
+                            evaluator.unary(op, operand, node.pos)?
+                        }
+                        op => {
+                            let right = pop(stack, pos)?;
+                            let left = pop(stack, pos)?;
+                            evaluator.binary(op, left, right, node.pos)?
+                        }
+                    }
+                }
+            };
+            stack.push(value);
+            i += 1;
+        }
+        match stack.as_slice() {
+            [value] => Ok(*value),
+            values => Err(format!(
+                "Malformed program! It ends with {} values rather than one at pos {}!",
+                values.len(),
+                pos
+            )),
+        }
+    }
+}
+
+/// The parser puts its nodes straight into an arena, see `parse_arena`.
+impl TreeBuilder for Arena {
+    type Id = NodeId;
+
+    fn make(
+        &mut self,
+        token: TokenType,
+        left: Option<NodeId>,
+        right: Option<NodeId>,
+        pos: usize,
+    ) -> NodeId {
+        let span = left
+            .iter()
+            .chain(right.iter())
+            .fold(Span { start: pos, len: 1 }, |span, &child| {
+                span.cover(self[child].span)
+            });
+        self.alloc(ArenaNode {
+            token,
+            pos,
+            span,
+            left,
+            right,
+            args: Vec::new(),
+        })
+    }
+
+    fn make_call(&mut self, name: &str, args: Vec<NodeId>, pos: usize) -> NodeId {
+        let span = args.iter().fold(Span { start: pos, len: 1 }, |span, &arg| {
+            span.cover(self[arg].span)
+        });
+        self.alloc(ArenaNode {
+            token: TokenType::Call(name.to_string(), Vec::new()),
+            pos,
+            span,
+            left: None,
+            right: None,
+            args,
+        })
+    }
+
+    fn cover(&mut self, id: &mut NodeId, span: Span) {
+        let node = &mut self.nodes[id.index()];
+        node.span = node.span.cover(span);
+    }
+
+    fn validate(&self, root: &NodeId) -> Result<(), String> {
+        let node = &self[*root];
+        check_operands(
+            &node.token,
+            node.pos,
+            node.left.is_some(),
+            node.right.is_some(),
+        )?;
+        node.left
+            .iter()
+            .chain(node.right.iter())
+            .chain(node.args.iter())
+            .try_for_each(|child| self.validate(child))
+    }
+}
+
+impl Index<NodeId> for Arena {
+    type Output = ArenaNode;
+
+    fn index(&self, id: NodeId) -> &ArenaNode {
+        &self.nodes[id.index()]
+    }
+}
+
+impl fmt::Display for Arena {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.to_tree() {
+            Some(root) => write!(f, "{}", root),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parses `expr` into an arena. The parser adds the nodes to it as it makes
+/// them, without a tree of boxes in between.
+pub fn parse_arena(expr: &str) -> Result<Arena, String> {
+    let expr = expr.to_string();
+    let mut scanner = Scanner::new(&expr);
+    scanner.scan();
+    let mut ast = Ast::with_builder(&mut scanner, Arena::new());
+    ast.build()?;
+    let mut arena = ast.builder;
+    arena.root = ast.root;
+    Ok(arena)
+}
+
+/// Evaluates the arena like `Arena::evaluate` with `var` bound to each of
+/// `values` in turn, for plots and tables of thousands of values. The steps
+/// of the evaluation and its stack are made once for all values. A value
+/// that fails to evaluate, like a division by zero at one `x`, only fails
+/// its own result.
+pub fn eval_vec(
+    arena: &Arena,
+    var: &str,
+    values: &[f64],
+    ctx: &Context,
+) -> Vec<Result<f64, String>> {
+    let mut steps = Vec::new();
+    let mut evaluator = Evaluator::new(ctx);
+    match arena.root.map(|root| arena.validate(&root)) {
+        Some(Err(err_msg)) => steps.push(Step::Fail(err_msg)),
+        _ => arena.compile(arena.root, &evaluator, &mut steps),
+    }
+    evaluator.bind(var, 0.);
+    let mut stack = Vec::new();
+    values
+        .iter()
+        .map(|&value| {
+            evaluator.rebind(var, value);
+            arena.run(&steps, &mut evaluator, &mut stack)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod arena_tests {
+    use super::*;
+    use crate::ast::parse;
+    use crate::visitor::walk;
+
+    #[test]
+    fn layout_test() {
+        let arena = parse_arena("1 + max(x, 2) * 3").unwrap();
+        assert_eq!(arena.len(), 7);
+        assert_eq!(arena.root(), Some(NodeId(6)));
+        assert_eq!(arena.to_tree(), parse("1 + max(x, 2) * 3").unwrap());
+        assert_eq!(arena.to_string(), "1 + max(x, 2) * 3");
+
+        // Children come before their parents.
+        for (i, node) in arena.nodes.iter().enumerate() {
+            let mut children = node
+                .left
+                .iter()
+                .chain(node.right.iter())
+                .chain(node.args.iter());
+            assert!(children.all(|child| child.index() < i));
+        }
+        let call = &arena[NodeId(3)];
+        assert_eq!(call.token, TokenType::Call(String::from("max"), Vec::new()));
+        assert_eq!(
+            (call.args.len(), call.pos, call.span),
+            (2, 4, Span { start: 4, len: 9 })
+        );
+
+        let max = format!("max({}) + 1", vec!["2 * 3"; 1000].join(", "));
+        let arena = parse_arena(&max).unwrap();
+        assert_eq!(arena.len(), 3003);
+        assert_eq!(arena.evaluate(&Context::new()), Ok(7.));
+
+        assert_eq!(Arena::from_tree(None), Arena::new());
+        assert_eq!(Arena::new().to_string(), "");
+    }
+
+    #[test]
+    fn parse_test() {
+        // The parser builds the same nodes into the arena as into boxes.
+        fn spans(node: &Node, out: &mut Vec<(usize, Span)>) {
+            out.push((node.pos, node.span));
+            if let TokenType::Call(_, args) = &node.token {
+                args.iter().for_each(|arg| spans(arg, out));
+            }
+            node.left
+                .iter()
+                .chain(node.right.iter())
+                .for_each(|child| spans(child, out));
+        }
+        let exprs = ["(1 + 2)! * √x²", "max(|y|, 10%) mod 3", "env(\"X\") - 2x"];
+        for expr in exprs.iter() {
+            let (mut expected, mut built) = (Vec::new(), Vec::new());
+            spans(&parse(expr).unwrap().unwrap(), &mut expected);
+            spans(&parse_arena(expr).unwrap().to_tree().unwrap(), &mut built);
+            assert_eq!(built, expected, "{}", expr);
+        }
+
+        for expr in ["", "1 +", "max(1", "2 3", "10%-3"].iter() {
+            assert_eq!(parse_arena(expr), Err(parse(expr).unwrap_err()), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn evaluate_test() {
+        let mut ctx = Context::new();
+        ctx.set_var("x", 1.5);
+        ctx.set_var("y", -4.);
+        let exprs = [
+            "1 + 2 * 3 - 4 / 8",
+            "2^10 // 3 % 5",
+            "-3! + |-2| * 5!!",
+            "50% * x",
+            "max(1, 7, 3) + min(x, y)",
+            "sum(i, 1, 10, i^2)",
+            "sum(n -> n * x, 1, 3)",
+            "integrate(t, 0, 1, t^2)",
+            "1 == 1 or sqrt(-1) > 0",
+            "0 and 1 % 0",
+            "not x < y",
+            "2x(y + 1)",
+            "foo(1)",
+            "1 + 7 % 0",
+            "z + 1",
+            "171.5!",
+            "x -> x",
+            "sin(1, 2)",
+        ];
+        for expr in exprs.iter() {
+            let tree = parse(expr).unwrap();
+            let expected = walk(&mut Evaluator::new(&ctx), &tree);
+            assert_eq!(
+                parse_arena(expr).unwrap().evaluate(&ctx),
+                expected,
+                "{}",
+                expr
+            );
+        }
+    }
+
+    #[test]
+    fn substitute_test() {
+        let mut ctx = Context::new();
+        ctx.set_var("y", 0.25);
+        let expr = ["sin(x) * x"; 10].join(" + ");
+        let replacement = "sqrt(y + 1) ^ 3 - ln(y * 2) / (y + 7)";
+        let mut arena = parse_arena(&expr).unwrap();
+        let root = arena.root().unwrap();
+        let len = arena.len();
+        let y = arena.add(*parse(replacement).unwrap().unwrap());
+        let added = arena.len() - len;
+        let substituted = arena.substitute(root, "x", y);
+
+        // The replacement once, and then only the 10 calls, 10 products and
+        // 9 sums above the 20 occurrences of x.
+        assert_eq!(arena.len(), len + added + 29);
+        let tree = parse(&expr).unwrap().unwrap();
+        let copied = tree.substitute("x", &parse(replacement).unwrap().unwrap());
+        assert_eq!(arena.node(substituted), copied);
+        arena.set_root(Some(substituted));
+        assert_eq!(
+            arena.evaluate(&ctx),
+            walk(&mut Evaluator::new(&ctx), &Some(Box::new(copied)))
+        );
+
+        // Nothing to replace, or only in a scope where x is bound.
+        let mut arena = parse_arena("y + sum(x, 1, 3, x)").unwrap();
+        let root = arena.root().unwrap();
+        let len = arena.len();
+        assert_eq!(arena.substitute(root, "x", NodeId(0)), root);
+        assert_eq!(arena.len(), len);
+    }
+
+    #[test]
+    fn differentiate_test() {
+        let exprs = [
+            "x^3 - 2x + 1",
+            "sin(x) * cos(x) / x",
+            "2^x + x^x",
+            "ln(x^2 + 1) + sqrt(|x|)",
+            "-50% * exp(x)",
+            "sum(i, 1, 3, i * x) + sum(x, 1, 3, x)",
+            "7 * y",
+        ];
+        for expr in exprs.iter() {
+            let mut arena = parse_arena(expr).unwrap();
+            let d = arena.differentiate(arena.root().unwrap(), "x");
+            let expected = parse(expr).unwrap().unwrap().differentiate("x");
+            assert_eq!(
+                d.map(|d| arena.node(d).fold_constants()),
+                expected,
+                "{}",
+                expr
+            );
+        }
+        for expr in ["x!", "max(x, 1)", "y -> x", "x < 1"].iter() {
+            let mut arena = parse_arena(expr).unwrap();
+            let d = arena.differentiate(arena.root().unwrap(), "x");
+            assert_eq!(
+                d,
+                Err(parse(expr)
+                    .unwrap()
+                    .unwrap()
+                    .differentiate("x")
+                    .unwrap_err())
+            );
+        }
+
+        // sin(x^2) shares x^2 with the cos(x^2) of its derivative.
+        let mut arena = parse_arena("sin(x^2)").unwrap();
+        let d = arena.differentiate(arena.root().unwrap(), "x").unwrap();
+        let outer = arena[d].left.unwrap();
+        assert_eq!(arena[outer].args, vec![NodeId(2)]);
+    }
+
+    #[test]
+    fn eval_vec_test() {
+        let mut ctx = Context::new();
+        ctx.set_var("y", -4.);
+        ctx.set_strict_floats(true);
+        let values: Vec<f64> = (-20..=20).map(|i| i as f64 / 4.).collect();
+        let exprs = [
+            "3x^3 - 2x^2 + x - 7",
+            "1 / (x - 1) + ln(x)",
+            "x % 2 + x // 0.5",
+            "x > 0 and 1 / x > 1 or not x",
+            "(x - 1)! + sqrt(x) * y",
+            "sum(i, 1, 3, i * x) + max(x, y, 0)",
+            "sum(n -> n * x, 1, 3)",
+            "x -> x",
+            "x > 0 and sin(x, 2)",
+            "atan2(foo(x), 1)",
+        ];
+        for expr in exprs.iter() {
+            let arena = parse_arena(expr).unwrap();
+            let tree = parse(expr).unwrap().unwrap();
+            let expected: Vec<_> = values
+                .iter()
+                .map(|&x| Evaluator::new(&ctx).eval_with_binding("x", x, &tree))
+                .collect();
+            assert_eq!(eval_vec(&arena, "x", &values, &ctx), expected, "{}", expr);
+        }
+
+        // Only the values at the singularity fail.
+        let arena = parse_arena("1 / (x - 1)").unwrap();
+        let results = eval_vec(&arena, "x", &[0., 1., 2.], &ctx);
+        assert_eq!(results[0], Ok(-1.));
+        assert_eq!(
+            results[1],
+            Err(String::from("Float error! 1 / 0 at pos 2 is inf!"))
+        );
+        assert_eq!(results[2], Ok(1.));
+        assert_eq!(eval_vec(&arena, "x", &[], &ctx), Vec::new());
+    }
+
+    #[test]
+    fn malformed_tree_test() {
+        let ctx = Context::new();
+        let mut arena = Arena::new();
+        let one = arena.leaf(TokenType::Number(1.));
+        let plus = arena.op(TokenType::Plus, Some(one), None);
+        arena.set_root(Some(plus));
+        let error = String::from("Malformed tree! Plus at pos 0 is missing its right operand!");
+        assert_eq!(arena.evaluate(&ctx), Err(error.clone()));
+        assert_eq!(
+            eval_vec(&arena, "x", &[1., 2.], &ctx),
+            vec![Err(error.clone()), Err(error)]
+        );
+    }
+
+    #[test]
+    fn malformed_program_test() {
+        let ctx = Context::new();
+        let arena = parse_arena("1 + 2 * x").unwrap();
+        let mut evaluator = Evaluator::new(&ctx);
+        evaluator.bind("x", 3.);
+        let mut steps = Vec::new();
+        arena.compile(arena.root, &evaluator, &mut steps);
+        let mut stack = Vec::new();
+        assert_eq!(arena.run(&steps, &mut evaluator, &mut stack), Ok(7.));
+
+        // Without the `1` the `+` is short of an operand.
+        assert_eq!(
+            arena.run(&steps[1..], &mut evaluator, &mut stack),
+            Err(String::from(
+                "Malformed program! An operand is missing at pos 2!"
+            ))
+        );
+        // Without the `+` the `1` is left over.
+        assert_eq!(
+            arena.run(&steps[..steps.len() - 1], &mut evaluator, &mut stack),
+            Err(String::from(
+                "Malformed program! It ends with 2 values rather than one at pos 6!"
+            ))
+        );
+        assert_eq!(
+            arena.run(&[], &mut evaluator, &mut stack),
+            Err(String::from(
+                "Malformed program! It ends with 0 values rather than one at pos 0!"
+            ))
+        );
+    }
+}
@@ -1,257 +1,1002 @@
-use super::scanner::{Scanner, Token, TokenType as STokenType};
+use std::fmt::Write;
+
+use super::context::Context;
+use super::error::{Diagnostic, Span};
+use super::scanner::{Scanner, TextEdit, Token, TokenType as STokenType, SEMICOLON_ERROR};
+use super::visitor::{walk, walk_node, Visitor};
 
 pub type NodePtr = Option<Box<Node>>;
 type ErrMsg = String;
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum TokenType {
     Number(f64),
+    Variable(String),
+    Call(String, Vec<Node>),
+    /// The body of a lambda is kept in `left`.
+    Lambda(String),
+    /// `$3`, the third result of the history.
+    History(usize),
+    /// `"HOME"`, a string, which is only an argument of a call.
+    Text(String),
     Power,
     Plus,
     Minus,
     Multiply,
     Divide,
+    /// `//`, the quotient rounded down.
+    FloorDivide,
     Bar,
     Factorial,
+    /// `n!!`, the product of every other number down from `n`. The scanner
+    /// reads two adjacent `!` as one token, so the factorial of a factorial
+    /// needs parentheses: `(3!)!`.
+    DoubleFactorial,
+    /// Postfix `%`, i.e. `x / 100`.
+    Percent,
     Modulo,
 
     PrefixMinus,
     PrefixPlus,
+
+    /// Logical operators treat any nonzero operand as true and evaluate to
+    /// 1 or 0. `and` and `or` don't evaluate their right operand if the left
+    /// one decides the result.
+    And,
+    Or,
+    Not,
+
+    /// Comparisons evaluate to 1 if they hold and to 0 otherwise.
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
 }
-#[derive(PartialEq, Debug)]
+impl TokenType {
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            TokenType::Less
+                | TokenType::LessEqual
+                | TokenType::Greater
+                | TokenType::GreaterEqual
+                | TokenType::Equal
+                | TokenType::NotEqual
+        )
+    }
+
+    /// Unary nodes keep their only operand in `left`.
+    pub fn is_unary(&self) -> bool {
+        matches!(
+            self,
+            TokenType::PrefixMinus
+                | TokenType::PrefixPlus
+                | TokenType::Factorial
+                | TokenType::DoubleFactorial
+                | TokenType::Percent
+                | TokenType::Not
+                | TokenType::Bar
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Node {
     pub token: TokenType,
+    /// Position of the token in the input, in chars, for error messages.
+    /// Nodes that weren't parsed are at 0, and trees compare equal whatever
+    /// their positions.
+    pub pos: usize,
+    /// The chars of the input the node covers with its operands, from the
+    /// leftmost one to the rightmost and with parentheses around it, e.g.
+    /// all of `(1 + 2) * 3` for the product. Empty for nodes that weren't
+    /// parsed.
+    pub span: Span,
     pub left: NodePtr,
     pub right: NodePtr,
 }
 
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        self.token == other.token && self.left == other.left && self.right == other.right
+    }
+}
+
 impl Node {
     pub fn new(token: TokenType, left: NodePtr, right: NodePtr) -> Self {
-        Self { token, left, right }
+        Self { token, pos: 0, span: Span::default(), left, right }
     }
 
     pub fn new_ptr(token: TokenType, left: NodePtr, right: NodePtr) -> NodePtr {
         Some(Box::new(Node::new(token, left, right)))
     }
+
+    /// Like `new_ptr` for a node parsed at `pos`, which spans its operands
+    /// and the arguments of a call.
+    pub fn new_ptr_at(token: TokenType, left: NodePtr, right: NodePtr, pos: usize) -> NodePtr {
+        let mut span = Span { start: pos, len: 1 };
+        for child in left.iter().chain(right.iter()) {
+            span = span.cover(child.span);
+        }
+        if let TokenType::Call(_, args) = &token {
+            span = args.iter().fold(span, |span, arg| span.cover(arg.span));
+        }
+        trace!("builds {:?} at pos {}", token, pos);
+        Some(Box::new(Node { token, pos, span, left, right }))
+    }
 }
 
-pub struct Ast<'a> {
-    pub root: NodePtr,
-    pub scanner: &'a mut Scanner<'a>,
-    pub index: usize,
+/// Where the parser puts the nodes it makes, children before their parents:
+/// boxed trees for `Ast::new`, or an `Arena` for `arena::parse_arena`, which
+/// saves the allocation per node.
+pub trait TreeBuilder {
+    /// A node that was built, a box or an id.
+    type Id;
+
+    /// Builds a node parsed at `pos`, which spans its operands.
+    fn make(&mut self, token: TokenType, left: Option<Self::Id>, right: Option<Self::Id>, pos: usize) -> Self::Id;
+
+    /// Builds a call of `name` parsed at `pos`, which spans its arguments.
+    fn make_call(&mut self, name: &str, args: Vec<Self::Id>, pos: usize) -> Self::Id;
+
+    /// Widens the span of a node, e.g. over the closing `)` of a call.
+    fn cover(&mut self, id: &mut Self::Id, span: Span);
+
+    /// Checks the tree below `root` like `validate`.
+    fn validate(&self, root: &Self::Id) -> Result<(), ErrMsg>;
 }
 
-impl<'a> Ast<'a> {
-    pub fn new(scanner: &'a mut Scanner<'a>) -> Self {
-        Self {
-            root: None,
-            scanner: scanner,
-            index: 0,
+/// Builds trees of boxed `Node`s.
+#[derive(Debug, Default)]
+pub struct Boxes;
+
+impl TreeBuilder for Boxes {
+    type Id = Box<Node>;
+
+    fn make(&mut self, token: TokenType, left: NodePtr, right: NodePtr, pos: usize) -> Box<Node> {
+        match Node::new_ptr_at(token, left, right, pos) {
+            Some(node) => node,
+            None => unreachable!(),
         }
     }
 
-    fn print_node(&self, node: &NodePtr, offset: usize) {
-        match node {
-            Some(ref node) => {
-                println!("{:w$}{{", "", w = offset);
-                println!("{:w$}Token: {:?}", "", node.token, w = (offset + 1));
+    fn make_call(&mut self, name: &str, args: Vec<Box<Node>>, pos: usize) -> Box<Node> {
+        let args = args.into_iter().map(|arg| *arg).collect();
+        self.make(TokenType::Call(name.to_string(), args), None, None, pos)
+    }
 
-                if node.left.is_some() {
-                    println!("{:w$}Left: ", "", w = (offset + 1));
-                    self.print_node(&node.left, offset + 1);
-                }
+    fn cover(&mut self, node: &mut Box<Node>, span: Span) {
+        node.span = node.span.cover(span);
+    }
 
-                if node.right.is_some() {
-                    println!("{:w$}Right: ", "", w = (offset + 1));
-                    self.print_node(&node.right, offset + 1);
-                }
+    fn validate(&self, root: &Box<Node>) -> Result<(), ErrMsg> {
+        validate(root)
+    }
+}
 
-                println!("{:w$}}}", "", w = offset);
-            }
-            None => {
-                println!("{:w$}{{ None }}", "", w = offset);
-            }
-        };
+/// Writes a tree with one token per line, children indented below it.
+struct TreePrinter {
+    offset: usize,
+    out: String,
+}
+
+impl TreePrinter {
+    fn print_child(&mut self, name: &str, child: &NodePtr) {
+        if child.is_some() {
+            writeln!(self.out, "{:w$}{}: ", "", name, w = (self.offset + 1)).unwrap();
+            self.offset += 1;
+            walk(self, child);
+            self.offset -= 1;
+        }
     }
+}
 
-    pub fn print(&self) {
-        match self.root {
-            Some(_) => self.print_node(&self.root, 0),
-            None => println!("This AST is empty!"),
+impl Visitor for TreePrinter {
+    type Output = ();
+
+    fn visit_number(&mut self, n: f64) {
+        writeln!(self.out, "{:w$}{{", "", w = self.offset).unwrap();
+        writeln!(self.out, "{:w$}Token: {:?}", "", TokenType::Number(n), w = (self.offset + 1)).unwrap();
+        writeln!(self.out, "{:w$}}}", "", w = self.offset).unwrap();
+    }
+
+    fn visit_variable(&mut self, name: &str) {
+        writeln!(self.out, "{:w$}{{", "", w = self.offset).unwrap();
+        writeln!(self.out, "{:w$}Token: {:?}", "", TokenType::Variable(name.to_string()), w = (self.offset + 1)).unwrap();
+        writeln!(self.out, "{:w$}}}", "", w = self.offset).unwrap();
+    }
+
+    fn visit_history(&mut self, n: usize) {
+        writeln!(self.out, "{:w$}{{", "", w = self.offset).unwrap();
+        writeln!(self.out, "{:w$}Token: {:?}", "", TokenType::History(n), w = (self.offset + 1)).unwrap();
+        writeln!(self.out, "{:w$}}}", "", w = self.offset).unwrap();
+    }
+
+    fn visit_text(&mut self, text: &str) {
+        writeln!(self.out, "{:w$}{{", "", w = self.offset).unwrap();
+        writeln!(self.out, "{:w$}Token: {:?}", "", TokenType::Text(text.to_string()), w = (self.offset + 1)).unwrap();
+        writeln!(self.out, "{:w$}}}", "", w = self.offset).unwrap();
+    }
+
+    fn visit_call(&mut self, name: &str, args: &[Node]) {
+        writeln!(self.out, "{:w$}{{", "", w = self.offset).unwrap();
+        writeln!(self.out, "{:w$}Token: Call({:?})", "", name, w = (self.offset + 1)).unwrap();
+        for (i, arg) in args.iter().enumerate() {
+            writeln!(self.out, "{:w$}Arg {}: ", "", i, w = (self.offset + 1)).unwrap();
+            self.offset += 1;
+            walk_node(self, arg);
+            self.offset -= 1;
         }
+        writeln!(self.out, "{:w$}}}", "", w = self.offset).unwrap();
+    }
+
+    fn visit_lambda(&mut self, param: &str, body: &NodePtr) {
+        writeln!(self.out, "{:w$}{{", "", w = self.offset).unwrap();
+        writeln!(self.out, "{:w$}Token: Lambda({:?})", "", param, w = (self.offset + 1)).unwrap();
+        self.print_child("Left", body);
+        writeln!(self.out, "{:w$}}}", "", w = self.offset).unwrap();
+    }
+
+    fn visit_unary(&mut self, op: &TokenType, operand: &NodePtr) {
+        writeln!(self.out, "{:w$}{{", "", w = self.offset).unwrap();
+        writeln!(self.out, "{:w$}Token: {:?}", "", op, w = (self.offset + 1)).unwrap();
+        self.print_child("Left", operand);
+        writeln!(self.out, "{:w$}}}", "", w = self.offset).unwrap();
+    }
+
+    fn visit_binary(&mut self, op: &TokenType, left: &NodePtr, right: &NodePtr) {
+        writeln!(self.out, "{:w$}{{", "", w = self.offset).unwrap();
+        writeln!(self.out, "{:w$}Token: {:?}", "", op, w = (self.offset + 1)).unwrap();
+        self.print_child("Left", left);
+        self.print_child("Right", right);
+        writeln!(self.out, "{:w$}}}", "", w = self.offset).unwrap();
+    }
+
+    fn visit_none(&mut self) {
+        writeln!(self.out, "{:w$}{{ None }}", "", w = self.offset).unwrap();
+    }
+}
+
+/// How deep a tree and the parentheses of an expression can nest. Evaluating
+/// and printing a tree recurse into it, so a deeper one could overflow the
+/// stack instead of failing with an error.
+pub const MAX_DEPTH: usize = 100;
+
+/// Juxtaposition binds like an explicit `*`, so `1/2x` is `(1/2)*x`.
+const IMPLICIT_MULTIPLY_BINDING_POWER: (u32, u32) = (13, 14);
+
+const PERCENT_BINDING_POWER: u32 = 17;
+
+/// `√` binds like a prefix `-`, so `√9 + 1` is `sqrt(9) + 1`. The postfix
+/// `²` and `³` bind like the `^` they stand for, so `-3²` is `-3^2` and
+/// `√2²` is `√2^2`.
+const ROOT_BINDING_POWER: u32 = 15;
+const SUPERSCRIPT_BINDING_POWER: u32 = 14;
+
+/// The exponent a superscript stands for: `5²` is `5^2`.
+fn superscript_exponent(token: STokenType) -> Option<f64> {
+    match token {
+        STokenType::Squared => Some(2.),
+        STokenType::Cubed => Some(3.),
+        _ => None,
+    }
+}
+
+/// The words `and`, `or` and `not` are operators, not names.
+fn keyword_operator(token: STokenType) -> Option<TokenType> {
+    match token {
+        STokenType::Str("and") => Some(TokenType::And),
+        STokenType::Str("or") => Some(TokenType::Or),
+        STokenType::Str("not") => Some(TokenType::Not),
+        _ => None,
+    }
+}
+
+/// `not` binds weaker than comparisons, but stronger than `and` and `or`.
+const NOT_BINDING_POWER: u32 = 5;
+
+fn keyword_binding_power(op: &TokenType) -> Option<(u32, u32)> {
+    match op {
+        TokenType::Or => Some((1, 2)),
+        TokenType::And => Some((3, 4)),
+        _ => None,
+    }
+}
+
+/// Words that are infix operators for a function of two arguments: `10 mod
+/// 3` is `mod(10, 3)`, `10 div 3` is `div(10, 3)` and `5 xor 3` is
+/// `bxor(5, 3)`. `mod` and `div` bind like `%` and `/`, `xor` binds weaker
+/// than `+`, but stronger than comparisons.
+fn word_operator(token: STokenType) -> Option<(&'static str, (u32, u32))> {
+    match token {
+        STokenType::Str("mod") => Some(("mod", (13, 14))),
+        STokenType::Str("div") => Some(("div", (13, 14))),
+        STokenType::Str("xor") => Some(("bxor", (9, 10))),
+        _ => None,
+    }
+}
+
+/// Operator words can't be used as names.
+pub fn is_reserved(token: STokenType) -> bool {
+    keyword_operator(token).is_some() || word_operator(token).is_some()
+}
+
+pub struct Ast<'s, 'a, B: TreeBuilder = Boxes> {
+    pub root: Option<B::Id>,
+    pub scanner: &'s mut Scanner<'a>,
+    pub index: usize,
+    pub builder: B,
+    /// Number of `|` the parser is inside of, so it can tell a closing bar
+    /// from an opening one after an operand.
+    bars: usize,
+    /// Number of `parse_expr` calls the parser is inside of.
+    nesting: usize,
+    /// Depth of the tree parsed last.
+    depth: usize,
+    /// The span of the token the last error names, for its diagnostic.
+    pub failed: Option<Span>,
+}
+
+impl<'s, 'a> Ast<'s, 'a> {
+    pub fn new(scanner: &'s mut Scanner<'a>) -> Self {
+        Ast::with_builder(scanner, Boxes)
+    }
+
+    pub fn print(&self) {
+        print!("{}", tree_string(&self.root));
     }
 
     fn infix_binding_power(token: STokenType) -> Option<(u32, u32)> {
-        let o = match token {
-            STokenType::Plus | STokenType::Minus => Some((1, 2)),
-            STokenType::Multiplication | STokenType::Division | STokenType::Modulo => Some((3, 4)),
-            STokenType::Power => Some((4, 5)),
+        match token {
+            STokenType::Less
+            | STokenType::LessEqual
+            | STokenType::Greater
+            | STokenType::GreaterEqual
+            | STokenType::EqualEqual
+            | STokenType::NotEqual => Some((7, 8)),
+            STokenType::Plus | STokenType::Minus => Some((11, 12)),
+            STokenType::Multiplication
+            | STokenType::Division
+            | STokenType::FloorDivision
+            | STokenType::Modulo => Some((13, 14)),
+            STokenType::Power => Some((14, 15)),
             _ => None,
-        };
-        o
+        }
     }
 
     fn prefix_binding_power(token: STokenType) -> Option<((), u32)> {
         match token {
-            STokenType::Plus | STokenType::Minus => Some(((), 5)),
+            STokenType::Plus | STokenType::Minus => Some(((), 15)),
             _ => None
         }
     }
 
     fn postfix_binding_power(op: STokenType) -> Option<(u32, ())> {
         let res = match op {
-            STokenType::Factorial => (7, ()),
+            STokenType::Factorial | STokenType::DoubleFactorial => (17, ()),
             _ => return None,
         };
         Some(res)
     }
 
+    /// Whether `token` with the left binding power `l_bp` takes the operand
+    /// parsed so far, rather than the operator before it that binds with
+    /// `min_bp`.
+    fn binds(token: Token<'a>, l_bp: u32, min_bp: u32) -> bool {
+        let binds = l_bp >= min_bp;
+        trace!(
+            "{:?} at pos {} binds with {} against {}: {}",
+            token.t,
+            token.pos,
+            l_bp,
+            min_bp,
+            if binds { "takes the operand" } else { "leaves it" }
+        );
+        binds
+    }
+
     fn is_operator(token: STokenType) -> bool {
-        match token {
+        matches!(
+            token,
             STokenType::Minus
-            | STokenType::Power
-            | STokenType::Modulo
-            | STokenType::Plus
-            | STokenType::Multiplication
-            | STokenType::Division
-            | STokenType::Factorial => true,
-            _ => false,
-        }
+                | STokenType::Power
+                | STokenType::Modulo
+                | STokenType::Plus
+                | STokenType::Multiplication
+                | STokenType::Division
+                | STokenType::FloorDivision
+                | STokenType::Factorial
+                | STokenType::DoubleFactorial
+                | STokenType::Root
+                | STokenType::Squared
+                | STokenType::Cubed
+                | STokenType::Less
+                | STokenType::LessEqual
+                | STokenType::Greater
+                | STokenType::GreaterEqual
+                | STokenType::EqualEqual
+                | STokenType::NotEqual
+                | STokenType::Str("and")
+                | STokenType::Str("or")
+                | STokenType::Str("not")
+                | STokenType::Str("mod")
+                | STokenType::Str("div")
+                | STokenType::Str("xor")
+        )
     }
 
-    fn scanner_token_to_ast_token(token: Token<'a>) -> TokenType {
-        match token.t {
+    fn scanner_token_to_ast_token(token: Token<'a>) -> Result<TokenType, ErrMsg> {
+        let token_type = match token.t {
             STokenType::Power => TokenType::Power,
             STokenType::Number(number) => TokenType::Number(number),
             STokenType::Plus => TokenType::Plus,
             STokenType::Minus => TokenType::Minus,
             STokenType::Multiplication => TokenType::Multiply,
             STokenType::Division => TokenType::Divide,
+            STokenType::FloorDivision => TokenType::FloorDivide,
             STokenType::Factorial => TokenType::Factorial,
+            STokenType::DoubleFactorial => TokenType::DoubleFactorial,
+            STokenType::Less => TokenType::Less,
+            STokenType::LessEqual => TokenType::LessEqual,
+            STokenType::Greater => TokenType::Greater,
+            STokenType::GreaterEqual => TokenType::GreaterEqual,
+            STokenType::EqualEqual => TokenType::Equal,
+            STokenType::NotEqual => TokenType::NotEqual,
             STokenType::Bar => TokenType::Bar,
             STokenType::Modulo => TokenType::Modulo,
-            _ => panic!("CAN'T CONVERT THIS TOKEN!, {:?}", token),
-        }
+            _ => return Err(format!("Unknown operator {:?} at pos {}!", token.t, token.pos)),
+        };
+        Ok(token_type)
     }
 
-    fn scanner_token_to_prefix_token(token: Token) -> TokenType {
+    fn scanner_token_to_prefix_token(token: Token) -> Result<TokenType, ErrMsg> {
         match token.t {
-            STokenType::Plus => TokenType::PrefixPlus,
-            STokenType::Minus => TokenType::PrefixMinus,
-            _ => panic!("Unkown prefix token! {:?}", token),
+            STokenType::Plus => Ok(TokenType::PrefixPlus),
+            STokenType::Minus => Ok(TokenType::PrefixMinus),
+            _ => Err(format!("Unknown prefix operator {:?} at pos {}!", token.t, token.pos)),
         }
     }
 
-    fn log_error(prev_token: Token<'a>, token: Token<'a>) -> Result<NodePtr, ErrMsg> {
+    fn log_error(prev_token: Token<'a>, token: Token<'a>) -> ErrMsg {
         if Ast::is_operator(prev_token.t) && token.t == STokenType::End {
-            Err(format!("Operator {:?} at pos {} expects an operand, but gets End!", prev_token.t, prev_token.pos))
+            format!("Operator {:?} at pos {} expects an operand, but gets End!", prev_token.t, prev_token.pos)
         } else if prev_token.t == STokenType::None && token.t == STokenType::End {
-            Err(String::from("Empty expression!"))
+            String::from("Empty expression!")
         } else {
-            let msg = format!("Unkown error! Prev token {:?} at pos {}, last token {:?} at pos {}", prev_token.t, prev_token.pos, token.t, token.pos);
-            Err(msg)
+            format!("Unkown error! Prev token {:?} at pos {}, last token {:?} at pos {}", prev_token.t, prev_token.pos, token.t, token.pos)
+        }
+    }
+
+    pub fn invalid_number(text: &str, reason: &str, pos: usize) -> ErrMsg {
+        format!("Number {} at pos {} {}!", text, pos, reason)
+    }
+
+    pub fn misplaced_separator(text: &str, reason: &str, pos: usize) -> ErrMsg {
+        format!("'{}' at pos {} {}!", text, pos, reason)
+    }
+}
+
+impl<'s, 'a, B: TreeBuilder> Ast<'s, 'a, B> {
+    /// A parser that puts the nodes it makes into `builder`.
+    pub fn with_builder(scanner: &'s mut Scanner<'a>, builder: B) -> Self {
+        Self {
+            root: None,
+            scanner,
+            index: 0,
+            builder,
+            bars: 0,
+            nesting: 0,
+            depth: 0,
+            failed: None,
+        }
+    }
+
+    /// Keeps `token` as what `err` points at.
+    fn fail(&mut self, token: Token<'a>, err: ErrMsg) -> ErrMsg {
+        self.failed = Some(Span::between(token.pos, token.end));
+        err
+    }
+
+    /// Checks the depth of a node of `token` over children of `depth`, which
+    /// becomes the depth of the tree parsed last.
+    fn deepen(&mut self, depth: usize, token: Token<'a>) -> Result<usize, ErrMsg> {
+        if depth + 1 > MAX_DEPTH {
+            let err = format!("Expression at pos {} nests deeper than {} levels!", token.pos, MAX_DEPTH);
+            return Err(self.fail(token, err));
+        }
+        self.depth = depth + 1;
+        Ok(self.depth)
+    }
+
+    /// Widens the span of `node` over `token`, e.g. the closing `)` of a
+    /// call.
+    fn cover(&mut self, mut node: Option<B::Id>, token: Token<'a>) -> Option<B::Id> {
+        if let Some(node) = &mut node {
+            self.builder.cover(node, Span::between(token.pos, token.end));
         }
+        node
     }
 
-    fn parse_lhs(&mut self, prev_token: Token<'a>) -> Result<NodePtr, ErrMsg> {
+    /// Builds a node parsed at `pos`.
+    fn node(&mut self, token: TokenType, left: Option<B::Id>, right: Option<B::Id>, pos: usize) -> Option<B::Id> {
+        Some(self.builder.make(token, left, right, pos))
+    }
+
+    /// Builds a leaf for `token` that spans it.
+    fn leaf(&mut self, t: TokenType, token: Token<'a>) -> Option<B::Id> {
+        let leaf = self.node(t, None, None, token.pos);
+        self.cover(leaf, token)
+    }
+
+    fn parse_lhs(&mut self, prev_token: Token<'a>) -> Result<Option<B::Id>, ErrMsg> {
         let token = self.scanner.next();
+        // A leaf, or else the arms below deepen it over their operands.
+        self.depth = 1;
         match token.t {
-            STokenType::Number(number) => Ok(Node::new_ptr(TokenType::Number(number), None, None)),
+            STokenType::Number(number) => Ok(self.leaf(TokenType::Number(number), token)),
+            STokenType::History(n) => Ok(self.leaf(TokenType::History(n), token)),
+            STokenType::Invalid(text, reason) => Err(self.fail(token, Ast::invalid_number(text, reason, token.pos))),
+            STokenType::Misplaced(text, reason) => Err(self.fail(token, Ast::misplaced_separator(text, reason, token.pos))),
+            STokenType::Semicolon => Err(self.fail(token, Ast::misplaced_separator(";", SEMICOLON_ERROR, token.pos))),
+            STokenType::Text(text) => {
+                let err = format!("String \"{}\" at pos {} can only be an argument of a function!", text, token.pos);
+                Err(self.fail(token, err))
+            }
+            STokenType::Str(name) if keyword_operator(token.t).is_some() => {
+                if keyword_operator(token.t) == Some(TokenType::Not) {
+                    let rhs = self.parse_expr(NOT_BINDING_POWER, token)?;
+                    self.deepen(self.depth, token)?;
+                    Ok(self.node(TokenType::Not, rhs, None, token.pos))
+                } else {
+                    Err(self.fail(token, format!("Unknown prefix operator {} at pos {}!", name, token.pos)))
+                }
+            }
+            STokenType::Str(name) if word_operator(token.t).is_some() => {
+                // `mod(10, 3)` and `div(10, 3)` are still calls.
+                if self.scanner.peek().t == STokenType::Lparen {
+                    self.parse_call(name, token)
+                } else {
+                    Err(self.fail(token, format!("Reserved word {} at pos {} can't be used as a name!", name, token.pos)))
+                }
+            }
+            STokenType::Str(name) => {
+                let next = self.scanner.peek();
+                if next.t == STokenType::Lparen {
+                    self.parse_call(name, token)
+                } else if next.t == STokenType::Arrow {
+                    self.scanner.next();
+                    let body = self.parse_expr(0, next)?;
+                    self.deepen(self.depth, token)?;
+                    Ok(self.node(TokenType::Lambda(name.to_string()), body, None, token.pos))
+                } else {
+                    Ok(self.leaf(TokenType::Variable(name.to_string()), token))
+                }
+            }
+            STokenType::Root => {
+                let rhs = self.parse_expr(ROOT_BINDING_POWER, token)?;
+                self.deepen(self.depth, token)?;
+                Ok(Some(self.builder.make_call("sqrt", rhs.into_iter().collect(), token.pos)))
+            }
             STokenType::Lparen => {
+                let bars = std::mem::replace(&mut self.bars, 0);
                 let lhs = self.parse_expr(0, token)?;
+                self.bars = bars;
 
                 let next = self.scanner.next();
                 if next.t != STokenType::Rparen {
-                    Err(format!("LParen at pos = {} doesn't have corresponding RParen!", token.pos))
+                    Err(self.fail(token, format!("LParen at pos = {} doesn't have corresponding RParen!", token.pos)))
                 } else {
-                    Ok(lhs)
+                    let lhs = self.cover(lhs, token);
+                    Ok(self.cover(lhs, next))
                 }
             }
             STokenType::Bar => {
-                let lhs = Node::new_ptr(TokenType::Bar, self.parse_expr(0, token)?, None);
+                self.bars += 1;
+                let operand = self.parse_expr(0, token)?;
+                let lhs = self.node(TokenType::Bar, operand, None, token.pos);
+                self.bars -= 1;
+                self.deepen(self.depth, token)?;
 
                 let next = self.scanner.next();
                 if next.t != STokenType::Bar {
-                    Err(format!("Bar at pos = {} odesn't have corresponding RParen", token.pos))
+                    Err(self.fail(token, format!("Bar at pos = {} odesn't have corresponding RParen", token.pos)))
                 } else {
-                    Ok(lhs)
+                    Ok(self.cover(lhs, next))
                 }
             }
-            STokenType::End => Ast::log_error(prev_token, token),
+            STokenType::End if Ast::is_operator(prev_token.t) => Err(self.fail(prev_token, Ast::log_error(prev_token, token))),
+            STokenType::End => Err(Ast::log_error(prev_token, token)),
             _ => {
                 if Ast::is_operator(token.t) {
                     if let Some(((), r_bp)) = Ast::prefix_binding_power(token.t) {
                         let rhs = self.parse_expr(r_bp, token)?;
-                        Ok(Node::new_ptr(
-                            Ast::scanner_token_to_prefix_token(token),
-                            rhs,
-                            None,
-                        ))
+                        self.deepen(self.depth, token)?;
+                        let prefix = Ast::scanner_token_to_prefix_token(token).map_err(|err| self.fail(token, err))?;
+                        Ok(self.node(prefix, rhs, None, token.pos))
                     } else {
-                        Err(format!("Unknown prefix operator {:?} at pos {}!", token.t, token.pos))
+                        Err(self.fail(token, format!("Unknown prefix operator {:?} at pos {}!", token.t, token.pos)))
                     }
                 } else {
-                    Err(format!("Unknown token! {:?}", token))
+                    let text = self.scanner.text(&token);
+                    Err(self.fail(token, format!("Unexpected '{}' at pos {}!", text, token.pos)))
                 }
             }
         }
     }
 
-    fn parse_expr(&mut self, min_bp: u32, prev_token: Token<'a>) -> Result<NodePtr, ErrMsg> {
-        let mut lhs = match self.parse_lhs(prev_token) {
-            Ok(ptr) => ptr,
-            Err(token) => return Err(token),
+    fn parse_call(&mut self, name: &str, token: Token<'a>) -> Result<Option<B::Id>, ErrMsg> {
+        let lparen = self.scanner.next();
+
+        let bars = std::mem::replace(&mut self.bars, 0);
+        let mut args = Vec::new();
+        let mut depth = 0;
+        let rparen = if self.scanner.peek().t == STokenType::Rparen {
+            self.scanner.next()
+        } else {
+            loop {
+                let next = self.scanner.peek();
+                if next.t == STokenType::Comma {
+                    let err = format!(
+                        "Function {} at pos {} is missing an argument before the separator at pos {}!",
+                        name, token.pos, next.pos
+                    );
+                    return Err(self.fail(next, err));
+                }
+                let arg = match next.t {
+                    // A string is a whole argument, `env("HOME" + 1)` isn't one.
+                    STokenType::Text(text) if matches!(self.scanner.peek_next().t, STokenType::Comma | STokenType::Rparen | STokenType::End) => {
+                        self.scanner.next();
+                        self.depth = 1;
+                        self.leaf(TokenType::Text(text.to_string()), next)
+                    }
+                    _ => self.parse_expr(0, lparen)?,
+                };
+                if let Some(arg) = arg {
+                    args.push(arg);
+                    depth = depth.max(self.depth);
+                }
+
+                let next = self.scanner.next();
+                match next.t {
+                    // A trailing separator like in `max(1, 2,)`.
+                    STokenType::Comma if self.scanner.peek().t == STokenType::Rparen => break self.scanner.next(),
+                    STokenType::Comma => continue,
+                    STokenType::Rparen => break next,
+                    _ => {
+                        let err = format!("Function {} at pos = {} doesn't have corresponding RParen!", name, token.pos);
+                        return Err(self.fail(token, err));
+                    }
+                }
+            }
         };
 
+        self.bars = bars;
+        self.deepen(depth, token)?;
+        let call = Some(self.builder.make_call(name, args, token.pos));
+        Ok(self.cover(call, rparen))
+    }
+
+    /// Whether `token` right after a complete operand starts another one,
+    /// which is then multiplied with it: `2x`, `2(3 + 4)`, `(1 + 2)(3 + 4)`,
+    /// `3|-2|`, `2√9`. A number may only follow a closing `)` or `|`, so a typo
+    /// like `2 3` stays an error. Inside of bars a `|` always closes them.
+    fn starts_operand(&mut self, token: Token<'a>) -> Result<bool, ErrMsg> {
+        match token.t {
+            STokenType::Lparen | STokenType::Root => Ok(true),
+            STokenType::Str(_) => Ok(!is_reserved(token.t)),
+            STokenType::Bar => Ok(self.bars == 0),
+            STokenType::Number(number) => match self.scanner.last().t {
+                STokenType::Rparen | STokenType::Bar => Ok(true),
+                _ => {
+                    let err = format!("Number {} at pos {} follows another operand without an operator!", number, token.pos);
+                    Err(self.fail(token, err))
+                }
+            },
+            _ => Ok(false),
+        }
+    }
+
+    /// Whether the `%` at the front of the scanner is a postfix percent. It's
+    /// infix modulo when an operand follows it, e.g. `10 % 3`, otherwise a
+    /// percent, e.g. `10% * 3`. A sign after it is settled by spacing: in
+    /// `10% - 3` the `%` hugs its operand and the `-` doesn't, so it's a
+    /// percent, in `7 % -3` and `10 %-3` the sign hugs the operand after it,
+    /// so it's the modulus. `10%-3` and `7 % - 3` could be either and are
+    /// errors rather than a guess.
+    fn is_percent(&mut self) -> Result<bool, ErrMsg> {
+        let next = self.scanner.peek_next();
+        Ok(match next.t {
+            STokenType::Number(_) | STokenType::History(_) | STokenType::Lparen => false,
+            STokenType::Str(_) => is_reserved(next.t),
+            STokenType::Bar => self.bars > 0,
+            STokenType::Minus | STokenType::Plus => {
+                let operand = self.scanner.peek_nth(2);
+                if !self.begins_operand(operand.t) {
+                    return Ok(true);
+                }
+                let percent = self.scanner.peek();
+                let hugs_left = self.scanner.last().end == percent.pos;
+                let hugs_operand = next.end == operand.pos;
+                if hugs_left == hugs_operand {
+                    let sign = if next.t == STokenType::Minus { '-' } else { '+' };
+                    let err = format!(
+                        "Operator % at pos {} is ambiguous before a sign, write `x% {} y` for a percent or `x % {}y` for the modulus!",
+                        percent.pos, sign, sign
+                    );
+                    return Err(self.fail(percent, err));
+                }
+                hugs_left
+            }
+            _ => true,
+        })
+    }
+
+    /// Whether a token after a prefix sign starts its operand.
+    fn begins_operand(&self, t: STokenType) -> bool {
+        match t {
+            STokenType::Number(_) | STokenType::History(_) | STokenType::Lparen | STokenType::Root => true,
+            STokenType::Str(_) => !is_reserved(t),
+            STokenType::Bar => self.bars == 0,
+            _ => false,
+        }
+    }
+
+    fn parse_expr(&mut self, min_bp: u32, prev_token: Token<'a>) -> Result<Option<B::Id>, ErrMsg> {
+        // Parentheses nest calls without deepening the tree.
+        if self.nesting >= MAX_DEPTH {
+            let err = format!("Expression at pos {} nests deeper than {} levels!", prev_token.pos, MAX_DEPTH);
+            return Err(self.fail(prev_token, err));
+        }
+        self.nesting += 1;
+        let parsed = self.parse_operations(min_bp, prev_token);
+        self.nesting -= 1;
+        parsed
+    }
+
+    fn parse_operations(&mut self, min_bp: u32, prev_token: Token<'a>) -> Result<Option<B::Id>, ErrMsg> {
+        let mut lhs = self.parse_lhs(prev_token)?;
+        let mut depth = self.depth;
+        let mut compared = false;
+
         loop {
             let token = self.scanner.peek();
+            if let Some(op) = keyword_operator(token.t) {
+                if let Some((l_bp, r_bp)) = keyword_binding_power(&op) {
+                    if !Ast::binds(token, l_bp, min_bp) {
+                        break;
+                    }
+                    self.scanner.next();
+                    let rhs = self.parse_expr(r_bp, token)?;
+                    lhs = self.node(op, lhs, rhs, token.pos);
+                    depth = self.deepen(depth.max(self.depth), token)?;
+                    continue;
+                }
+            }
+
+            if let Some((name, (l_bp, r_bp))) = word_operator(token.t) {
+                if !Ast::binds(token, l_bp, min_bp) {
+                    break;
+                }
+                self.scanner.next();
+                let rhs = self.parse_expr(r_bp, token)?;
+                depth = self.deepen(depth.max(self.depth), token)?;
+                let args = vec![lhs, rhs].into_iter().flatten().collect();
+                lhs = Some(self.builder.make_call(name, args, token.pos));
+                continue;
+            }
+
+            if self.starts_operand(token)? {
+                let (l_bp, r_bp) = IMPLICIT_MULTIPLY_BINDING_POWER;
+                if !Ast::binds(token, l_bp, min_bp) {
+                    break;
+                }
+                let rhs = self.parse_expr(r_bp, token)?;
+                lhs = self.node(TokenType::Multiply, lhs, rhs, token.pos);
+                depth = self.deepen(depth.max(self.depth), token)?;
+                continue;
+            }
+
             let op = if Ast::is_operator(token.t)
                 || token.t == STokenType::Rparen
+                || token.t == STokenType::Comma
                 || token.t == STokenType::Bar
             {
                 token
             } else if token.t == STokenType::End {
                 break;
+            } else if let STokenType::Invalid(text, reason) = token.t {
+                return Err(self.fail(token, Ast::invalid_number(text, reason, token.pos)));
+            } else if let STokenType::Misplaced(text, reason) = token.t {
+                return Err(self.fail(token, Ast::misplaced_separator(text, reason, token.pos)));
+            } else if token.t == STokenType::Semicolon {
+                return Err(self.fail(token, Ast::misplaced_separator(";", SEMICOLON_ERROR, token.pos)));
             } else {
-                return Err(format!("Unkown token {:?} at pos {}!", token.t, token.pos))
+                return Err(self.fail(token, format!("Unkown token {:?} at pos {}!", token.t, token.pos)))
             };
 
+            if op.t == STokenType::Modulo && self.is_percent()? {
+                // Percent binds like factorial.
+                if !Ast::binds(token, PERCENT_BINDING_POWER, min_bp) {
+                    break;
+                }
+                self.scanner.next();
+                let percent = self.node(TokenType::Percent, lhs, None, op.pos);
+                lhs = self.cover(percent, op);
+                depth = self.deepen(depth, op)?;
+                continue;
+            }
+
+            if let Some(exponent) = superscript_exponent(op.t) {
+                if !Ast::binds(token, SUPERSCRIPT_BINDING_POWER, min_bp) {
+                    break;
+                }
+                self.scanner.next();
+                let exponent = self.node(TokenType::Number(exponent), None, None, op.pos);
+                lhs = self.node(TokenType::Power, lhs, exponent, op.pos);
+                depth = self.deepen(depth, op)?;
+                continue;
+            }
+
             if let Some((l_bp, ())) = Ast::postfix_binding_power(op.t) {
-                if l_bp < min_bp {
+                if !Ast::binds(token, l_bp, min_bp) {
                     break;
                 }
                 self.scanner.next();
-                let token_type = Ast::scanner_token_to_ast_token(op);
-                lhs = Node::new_ptr(token_type, lhs, None);
+                let token_type = Ast::scanner_token_to_ast_token(op).map_err(|err| self.fail(op, err))?;
+                let postfix = self.node(token_type, lhs, None, op.pos);
+                lhs = self.cover(postfix, op);
+                depth = self.deepen(depth, op)?;
                 continue;
             }
 
             if let Some((l_bp, r_bp)) = Ast::infix_binding_power(op.t) {
-                if l_bp < min_bp {
+                if !Ast::binds(token, l_bp, min_bp) {
                     break;
                 }
 
                 self.scanner.next();
-                let token_type = Ast::scanner_token_to_ast_token(token);
-                lhs = Node::new_ptr(token_type, lhs, self.parse_expr(r_bp, token)?);
+                let token_type = Ast::scanner_token_to_ast_token(token).map_err(|err| self.fail(token, err))?;
+                if token_type.is_comparison() {
+                    // `1 < 2 < 3` would compare 1 or 0 to 3, which is hardly
+                    // ever what is meant.
+                    if compared {
+                        let err = format!(
+                            "Comparison {:?} at pos {} can't follow another comparison without parentheses!",
+                            token.t, token.pos
+                        );
+                        return Err(self.fail(token, err));
+                    }
+                    compared = true;
+                }
+                let rhs = self.parse_expr(r_bp, token)?;
+                lhs = self.node(token_type, lhs, rhs, token.pos);
+                depth = self.deepen(depth.max(self.depth), token)?;
                 continue;
             }
 
             break;
         }
 
+        self.depth = depth;
         Ok(lhs)
     }
 
     pub fn build(&mut self) -> Result<(), ErrMsg> {
-        match self.parse_expr(0, Token::new(STokenType::None, 0)) {
+        match self.parse_expr(0, Token::new(STokenType::None, 0, 0)) {
             Ok(ptr) => self.root = ptr,
-            Err(err_msg) => return Err(err_msg),
+            Err(err_msg) => {
+                debug!("fails to parse: {}", err_msg);
+                return Err(err_msg);
+            }
+        }
+        // The expression ends early at a token it can't continue with, which
+        // mustn't be dropped silently.
+        let token = self.scanner.peek();
+        if token.t != STokenType::End {
+            let err = format!("Unexpected token {:?} at pos {} after the end of the expression!", token.t, token.pos);
+            return Err(self.fail(token, err));
         }
-        Ok(())
+        match &self.root {
+            Some(root) => self.builder.validate(root),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Checks that every node has the operands of its kind: none for numbers,
+/// variables, strings and calls, which keep their arguments in the token, a body for
+/// lambdas, one for unary operators and two for binary ones. The evaluators
+/// can't tell a missing operand from a bug, so the parser's trees are checked
+/// before they're evaluated.
+pub fn validate(node: &Node) -> Result<(), ErrMsg> {
+    check_operands(&node.token, node.pos, node.left.is_some(), node.right.is_some())?;
+    if let TokenType::Call(_, args) = &node.token {
+        args.iter().try_for_each(validate)?;
+    }
+    node.left.iter().chain(node.right.iter()).try_for_each(|child| validate(child))
+}
+
+/// Checks that a node of `token` at `pos` has the operands of its kind, see
+/// `validate`.
+pub fn check_operands(token: &TokenType, pos: usize, has_left: bool, has_right: bool) -> Result<(), ErrMsg> {
+    let name = match token {
+        TokenType::Call(name, _) => format!("Call {}", name),
+        TokenType::Lambda(param) => format!("Lambda {}", param),
+        token => format!("{:?}", token),
+    };
+    let (left, right) = match token {
+        TokenType::Number(_) | TokenType::Variable(_) | TokenType::History(_) | TokenType::Text(_) | TokenType::Call(..) => (false, false),
+        TokenType::Lambda(_) => (true, false),
+        token if token.is_unary() => (true, false),
+        _ => (true, true),
+    };
+    for (child, expected, side) in [(has_left, left, "left"), (has_right, right, "right")] {
+        match (child, expected) {
+            (true, false) => {
+                return Err(format!("Malformed tree! {} at pos {} has an unexpected {} operand!", name, pos, side))
+            }
+            (false, true) => {
+                return Err(format!("Malformed tree! {} at pos {} is missing its {} operand!", name, pos, side))
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// The tree as `Ast::print` shows it.
+pub fn tree_string(root: &NodePtr) -> String {
+    match root {
+        Some(_) => {
+            let mut printer = TreePrinter { offset: 0, out: String::new() };
+            walk(&mut printer, root);
+            printer.out
+        }
+        None => String::from("This AST is empty!\n"),
+    }
+}
+
+/// Scans and parses `expr` into a tree.
+pub fn parse(expr: &str) -> Result<NodePtr, ErrMsg> {
+    parse_with(expr, &Context::new())
+}
+
+/// Like `parse`, with the decimal separator and SI suffixes of `ctx`.
+pub fn parse_with(expr: &str, ctx: &Context) -> Result<NodePtr, ErrMsg> {
+    let expr = expr.to_string();
+    let mut scanner = ctx.scanner(&expr);
+    scanner.scan();
+
+    let mut ast = Ast::new(&mut scanner);
+    ast.build()?;
+    Ok(ast.root)
+}
+
+/// Scans and parses `expr` with the settings of `ctx`.
+pub fn parse_expr(expr: &str, ctx: &Context) -> Result<NodePtr, Diagnostic> {
+    let expr = expr.to_string();
+    let mut scanner = ctx.scanner(&expr);
+    scanner.scan();
+
+    let mut ast = Ast::new(&mut scanner);
+    match ast.build() {
+        Ok(()) => Ok(ast.root),
+        Err(err) => Err(Diagnostic::parse(format!("Ast build error! {}", err), ast.failed)),
     }
 }
 
+/// Parses `expr` again after `edit` made it of the expression `scanner`
+/// scanned last, for an expression that is edited a char at a time. Only
+/// the tokens around the edit are scanned again, see `Scanner::retokenize`.
+pub fn reparse<'a>(scanner: &mut Scanner<'a>, expr: &'a str, edit: &TextEdit) -> Result<NodePtr, ErrMsg> {
+    scanner.retokenize(expr, edit);
+    let mut ast = Ast::new(scanner);
+    ast.build()?;
+    Ok(ast.root)
+}
+
 #[cfg(test)]
 mod ast_tests {
     use super::*;
@@ -264,7 +1009,7 @@ mod ast_tests {
         scanner.scan();
 
         let mut ast = Ast::new(&mut scanner);
-        ast.build();
+        ast.build().unwrap();
         ast.print();
 
         assert_eq!(ast.root, expect);
@@ -274,13 +1019,19 @@ mod ast_tests {
     fn tree_build_test_0() {
         let tree = Some(Box::new(Node {
             token: TokenType::Plus,
+            pos: 0,
+            span: Span::default(),
             left: Some(Box::new(Node {
                 token: TokenType::Number(1.),
+                pos: 0,
+                span: Span::default(),
                 left: None,
                 right: None,
             })),
             right: Some(Box::new(Node {
                 token: TokenType::Number(2.),
+                pos: 0,
+                span: Span::default(),
                 left: None,
                 right: None,
             })),
@@ -293,6 +1044,8 @@ mod ast_tests {
     fn tree_build_test_1() {
         let tree = Some(Box::new(Node {
             token: TokenType::Number(1.),
+            pos: 0,
+            span: Span::default(),
             left: None,
             right: None,
         }));
@@ -304,8 +1057,12 @@ mod ast_tests {
     fn tree_build_prefixes_test() {
         let tree = Some(Box::new(Node {
             token: TokenType::PrefixMinus,
+            pos: 0,
+            span: Span::default(),
             left: Some(Box::new(Node {
                 token: TokenType::Number(1.),
+                pos: 0,
+                span: Span::default(),
                 left: None,
                 right: None,
             })),
@@ -319,20 +1076,30 @@ mod ast_tests {
     fn tree_build_parenthesis_test() {
         let tree = Some(Box::new(Node {
             token: TokenType::Plus,
+            pos: 0,
+            span: Span::default(),
             left: Some(Box::new(Node {
                 token: TokenType::Number(1.),
+                pos: 0,
+                span: Span::default(),
                 left: None,
                 right: None,
             })),
             right: Some(Box::new(Node {
                 token: TokenType::Plus,
+                pos: 0,
+                span: Span::default(),
                 left: Some(Box::new(Node {
                     token: TokenType::Number(2.),
+                    pos: 0,
+                    span: Span::default(),
                     left: None,
                     right: None,
                 })),
                 right: Some(Box::new(Node {
                     token: TokenType::Number(3.),
+                    pos: 0,
+                    span: Span::default(),
                     left: None,
                     right: None,
                 })),
@@ -346,8 +1113,12 @@ mod ast_tests {
     fn tree_build_postfixes_test() {
         let tree = Some(Box::new(Node {
             token: TokenType::Factorial,
+            pos: 0,
+            span: Span::default(),
             left: Some(Box::new(Node {
                 token: TokenType::Number(3.),
+                pos: 0,
+                span: Span::default(),
                 left: None,
                 right: None,
             })),
@@ -357,14 +1128,33 @@ mod ast_tests {
         build_tree_with_compare("3!", tree);
     }
 
+    #[test]
+    fn tree_build_double_factorial_test() {
+        let number = Node::new_ptr(TokenType::Number(7.), None, None);
+        let double = Node::new_ptr(TokenType::DoubleFactorial, number.clone(), None);
+        build_tree_with_compare("7!!", double.clone());
+        build_tree_with_compare("7!!!", Node::new_ptr(TokenType::Factorial, double, None));
+
+        let single = Node::new_ptr(TokenType::Factorial, number, None);
+        let twice = Node::new_ptr(TokenType::Factorial, single, None);
+        build_tree_with_compare("(7!)!", twice.clone());
+        build_tree_with_compare("7! !", twice);
+    }
+
     #[test]
     fn tree_build_prefixes_test_1() {
         let tree = Some(Box::new(Node {
             token: TokenType::Plus,
+            pos: 0,
+            span: Span::default(),
             left: Some(Box::new(Node {
                 token: TokenType::PrefixMinus,
+                pos: 0,
+                span: Span::default(),
                 left: Some(Box::new(Node {
                     token: TokenType::Number(1.),
+                    pos: 0,
+                    span: Span::default(),
                     left: None,
                     right: None,
                 })),
@@ -372,6 +1162,8 @@ mod ast_tests {
             })),
             right: Some(Box::new(Node {
                 token: TokenType::Number(2.),
+                pos: 0,
+                span: Span::default(),
                 left: None,
                 right: None,
             })),
@@ -384,13 +1176,19 @@ mod ast_tests {
     fn tree_build_test_2() {
         let tree = Some(Box::new(Node {
             token: TokenType::Plus,
+            pos: 0,
+            span: Span::default(),
             left: Some(Box::new(Node {
                 token: TokenType::Number(1.0),
+                pos: 0,
+                span: Span::default(),
                 left: None,
                 right: None,
             })),
             right: Some(Box::new(Node {
                 token: TokenType::Number(2.),
+                pos: 0,
+                span: Span::default(),
                 left: None,
                 right: None,
             })),
@@ -403,21 +1201,31 @@ mod ast_tests {
     fn tree_build_test_3() {
         let tree = Some(Box::new(Node {
             token: TokenType::Minus,
+            pos: 0,
+            span: Span::default(),
             left: Some(Box::new(Node {
                 token: TokenType::Plus,
+                pos: 0,
+                span: Span::default(),
                 left: Some(Box::new(Node {
                     token: TokenType::Number(1.),
+                    pos: 0,
+                    span: Span::default(),
                     left: None,
                     right: None,
                 })),
                 right: Some(Box::new(Node {
                     token: TokenType::Number(2.),
+                    pos: 0,
+                    span: Span::default(),
                     left: None,
                     right: None,
                 })),
             })),
             right: Some(Box::new(Node {
                 token: TokenType::Number(4.0),
+                pos: 0,
+                span: Span::default(),
                 left: None,
                 right: None,
             })),
@@ -430,28 +1238,42 @@ mod ast_tests {
     fn tree_build_test_4() {
         let tree = Some(Box::new(Node {
             token: TokenType::Plus,
+            pos: 0,
+            span: Span::default(),
             left: Some(Box::new(Node {
                 token: TokenType::Multiply,
+                pos: 0,
+                span: Span::default(),
                 left: Some(Box::new(Node {
                     token: TokenType::Number(2.0),
+                    pos: 0,
+                    span: Span::default(),
                     left: None,
                     right: None,
                 })),
                 right: Some(Box::new(Node {
                     token: TokenType::Number(3.0),
+                    pos: 0,
+                    span: Span::default(),
                     left: None,
                     right: None,
                 })),
             })),
             right: Some(Box::new(Node {
                 token: TokenType::Multiply,
+                pos: 0,
+                span: Span::default(),
                 left: Some(Box::new(Node {
                     token: TokenType::Number(4.),
+                    pos: 0,
+                    span: Span::default(),
                     left: None,
                     right: None,
                 })),
                 right: Some(Box::new(Node {
                     token: TokenType::Number(5.),
+                    pos: 0,
+                    span: Span::default(),
                     left: None,
                     right: None,
                 })),
@@ -482,4 +1304,460 @@ mod ast_tests {
         build_illegal_tree("+", "Operator Plus at pos 0 expects an operand, but gets End!");
         build_illegal_tree("", "Empty expression!")
     }
+
+    #[test]
+    fn validate_test() {
+        let number = |n: f64| Node::new_ptr(TokenType::Number(n), None, None);
+        assert_eq!(validate(&parse("-x! + sum(i -> i^2, 1, |y|)").unwrap().unwrap()), Ok(()));
+
+        let missing = Node::new_ptr_at(TokenType::Plus, number(1.), None, 2).unwrap();
+        assert_eq!(
+            validate(&missing),
+            Err(String::from("Malformed tree! Plus at pos 2 is missing its right operand!"))
+        );
+        let missing = Node::new_ptr(TokenType::Factorial, None, None).unwrap();
+        assert_eq!(
+            validate(&missing),
+            Err(String::from("Malformed tree! Factorial at pos 0 is missing its left operand!"))
+        );
+        let extra = Node::new_ptr(TokenType::PrefixMinus, number(1.), number(2.)).unwrap();
+        assert_eq!(
+            validate(&extra),
+            Err(String::from("Malformed tree! PrefixMinus at pos 0 has an unexpected right operand!"))
+        );
+        let extra = Node::new_ptr(TokenType::Number(1.), number(2.), None).unwrap();
+        assert_eq!(
+            validate(&extra),
+            Err(String::from("Malformed tree! Number(1.0) at pos 0 has an unexpected left operand!"))
+        );
+
+        // Deep in the tree and in the arguments of calls.
+        let arg = Node::new(TokenType::Minus, None, number(1.));
+        let call = Node::new_ptr(TokenType::Call(String::from("max"), vec![arg]), None, None);
+        let tree = Node::new_ptr(TokenType::Multiply, number(2.), call).unwrap();
+        assert_eq!(
+            validate(&tree),
+            Err(String::from("Malformed tree! Minus at pos 0 is missing its left operand!"))
+        );
+    }
+
+    #[test]
+    fn tree_build_positions_test() {
+        let tree = parse("1 + max(x, -2) * 3").unwrap().unwrap();
+        assert_eq!(tree.pos, 2);
+        assert_eq!(tree.left.as_ref().unwrap().pos, 0);
+
+        let product = tree.right.unwrap();
+        assert_eq!(product.pos, 15);
+        match product.left.unwrap().token {
+            TokenType::Call(_, args) => assert_eq!((args[0].pos, args[1].pos), (8, 11)),
+            token => panic!("Unexpected token {:?}", token),
+        }
+
+        // Positions don't take part in comparisons.
+        assert_eq!(parse("1+2"), parse("1 + 2"));
+    }
+
+    #[test]
+    fn tree_build_spans_test() {
+        let span = |start, len| Span { start, len };
+        let tree = parse("(1 + 2) * 3").unwrap().unwrap();
+        assert_eq!(tree.span, span(0, 11));
+        assert_eq!(tree.left.as_ref().unwrap().span, span(0, 7));
+        assert_eq!(tree.right.as_ref().unwrap().span, span(10, 1));
+
+        let tree = parse("-max(x_y, 2.5)!! + |y| mod 10%").unwrap().unwrap();
+        assert_eq!(tree.span, span(0, 30));
+        let negative = tree.left.unwrap();
+        assert_eq!(negative.span, span(0, 16));
+        let factorial = negative.left.unwrap();
+        assert_eq!(factorial.span, span(1, 15));
+        match factorial.left.unwrap().token {
+            TokenType::Call(_, args) => assert_eq!((args[0].span, args[1].span), (span(5, 3), span(10, 3))),
+            token => panic!("Unexpected token {:?}", token),
+        }
+        match tree.right.unwrap().token {
+            TokenType::Call(_, args) => assert_eq!((args[0].span, args[1].span), (span(19, 3), span(27, 3))),
+            token => panic!("Unexpected token {:?}", token),
+        }
+        assert_eq!(parse("x -> x^2").unwrap().unwrap().span, span(0, 8));
+    }
+
+    #[test]
+    fn tree_build_trailing_tokens_test() {
+        let errors = [
+            ("1 not 2", "Unexpected token Str(\"not\") at pos 2 after the end of the expression!"),
+            ("1 2", "Number 2 at pos 2 follows another operand without an operator!"),
+            ("1 ) 2", "Unexpected token Rparen at pos 2 after the end of the expression!"),
+            ("2, 3", "Unexpected token Comma at pos 1 after the end of the expression!"),
+            ("x -> x, 1", "Unexpected token Comma at pos 6 after the end of the expression!"),
+        ];
+
+        for (expr, error) in errors.iter() {
+            println!("Expression = {}", expr);
+            assert_eq!(parse(expr), Err(error.to_string()));
+        }
+    }
+
+    #[test]
+    fn tree_build_implicit_multiplication_test() {
+        let implicit = [
+            "2(3+4)", "(1+2)(3+4)", "2pi", "3|-2|", "2x^2", "1/2x", "-2x!", "|x|y", "(x)2", "|2(x)|", "|(2|x|)|",
+        ];
+        let explicit = [
+            "2*(3+4)", "(1+2)*(3+4)", "2*pi", "3*|-2|", "2*x^2", "1/2*x", "-2*x!", "|x|*y", "(x)*2", "|2*(x)|", "|(2*|x|)|",
+        ];
+
+        for (implicit, explicit) in implicit.iter().zip(explicit.iter()) {
+            println!("Expression = {}", implicit);
+            assert_eq!(parse(implicit), parse(explicit));
+        }
+    }
+
+    #[test]
+    fn tree_build_unicode_operators_test() {
+        let unicode = ["6 ÷ 2 × (1 + 2)", "−3", "2·x − 1", "−x^2"];
+        let ascii = ["6 / 2 * (1 + 2)", "-3", "2*x - 1", "-x^2"];
+
+        for (unicode, ascii) in unicode.iter().zip(ascii.iter()) {
+            println!("Expression = {}", unicode);
+            assert_eq!(parse(unicode), parse(ascii));
+        }
+
+        build_illegal_tree("2 × 3 ÷ 0x", "Number 0x at pos 8 is malformed!");
+        build_illegal_tree("1 × −", "Operator Minus at pos 4 expects an operand, but gets End!");
+    }
+
+    #[test]
+    fn tree_build_root_and_superscript_test() {
+        let unicode = ["√9 + 1", "√(9+16)", "5²", "2³ + 1", "√2²", "-3²", "2^3²", "x²!", "2√9", "√√16", "√-4"];
+        let ascii = [
+            "sqrt(9) + 1", "sqrt(9+16)", "5^2", "2^3 + 1", "sqrt(2)^2", "-3^2", "2^3^2", "(x^2)!", "2*sqrt(9)",
+            "sqrt(sqrt(16))", "sqrt(-4)",
+        ];
+
+        for (unicode, ascii) in unicode.iter().zip(ascii.iter()) {
+            println!("Expression = {}", unicode);
+            assert_eq!(parse(unicode), parse(ascii));
+        }
+
+        build_illegal_tree("√", "Operator Root at pos 0 expects an operand, but gets End!");
+        build_illegal_tree("√4 + ²", "Unknown prefix operator Squared at pos 5!");
+        build_illegal_tree("√4 × 3² ÷ 0x", "Number 0x at pos 10 is malformed!");
+    }
+
+    #[test]
+    fn tree_build_whitespace_test() {
+        assert_eq!(parse("\t1 +\u{a0}2\r\n"), parse("1 + 2"));
+        build_illegal_tree("\t1 +\u{a0}\r\n", "Operator Plus at pos 3 expects an operand, but gets End!");
+        // Only whitespace and comments are skipped, any other char is an error.
+        assert_eq!(parse("1 @ + 2"), Err(String::from("'@' at pos 2 is an unknown character!")));
+        assert_eq!(parse("1 + 2 ?"), Err(String::from("'?' at pos 6 is an unknown character!")));
+        assert_eq!(parse("2 * ∞"), Err(String::from("'∞' at pos 4 is an unknown character!")));
+        build_illegal_tree(" \t\r\n", "Empty expression!");
+    }
+
+    #[test]
+    fn tree_build_implicit_number_test() {
+        build_illegal_tree("2 3", "Number 3 at pos 2 follows another operand without an operator!");
+        build_illegal_tree("x 2", "Number 2 at pos 2 follows another operand without an operator!");
+    }
+
+    #[test]
+    fn tree_build_percent_test() {
+        let percent = |operand: f64| {
+            Some(Box::new(Node {
+                token: TokenType::Percent,
+                pos: 0,
+                span: Span::default(),
+                left: Node::new_ptr(TokenType::Number(operand), None, None),
+                right: None,
+            }))
+        };
+
+        build_tree_with_compare("50%", percent(50.));
+        build_tree_with_compare(
+            "10% * 3",
+            Node::new_ptr(TokenType::Multiply, percent(10.), Node::new_ptr(TokenType::Number(3.), None, None)),
+        );
+        build_tree_with_compare(
+            "10 % 3",
+            Node::new_ptr(
+                TokenType::Modulo,
+                Node::new_ptr(TokenType::Number(10.), None, None),
+                Node::new_ptr(TokenType::Number(3.), None, None),
+            ),
+        );
+        build_tree_with_compare("|10%|", Node::new_ptr(TokenType::Bar, percent(10.), None));
+        build_tree_with_compare(
+            "10% - 3",
+            Node::new_ptr(TokenType::Minus, percent(10.), Node::new_ptr(TokenType::Number(3.), None, None)),
+        );
+        let modulo_of_negative = Node::new_ptr(
+            TokenType::Modulo,
+            Node::new_ptr(TokenType::Number(10.), None, None),
+            Node::new_ptr(TokenType::PrefixMinus, Node::new_ptr(TokenType::Number(3.), None, None), None),
+        );
+        build_tree_with_compare("10 % -3", modulo_of_negative.clone());
+        build_tree_with_compare("10 %-3", modulo_of_negative);
+
+        build_illegal_tree("% 5", "Unknown prefix operator Modulo at pos 0!");
+        build_illegal_tree(
+            "10%-3",
+            "Operator % at pos 2 is ambiguous before a sign, write `x% - y` for a percent or `x % -y` for the modulus!",
+        );
+        build_illegal_tree(
+            "10 % + 3",
+            "Operator % at pos 3 is ambiguous before a sign, write `x% + y` for a percent or `x % +y` for the modulus!",
+        );
+    }
+
+    #[test]
+    fn tree_build_comparison_test() {
+        let number = |n: f64| Node::new_ptr(TokenType::Number(n), None, None);
+
+        build_tree_with_compare(
+            "1 + 2 <= 3 * 4",
+            Node::new_ptr(
+                TokenType::LessEqual,
+                Node::new_ptr(TokenType::Plus, number(1.), number(2.)),
+                Node::new_ptr(TokenType::Multiply, number(3.), number(4.)),
+            ),
+        );
+        build_tree_with_compare(
+            "(1 < 2) != 3",
+            Node::new_ptr(
+                TokenType::NotEqual,
+                Node::new_ptr(TokenType::Less, number(1.), number(2.)),
+                number(3.),
+            ),
+        );
+
+        build_illegal_tree(
+            "1 < 2 < 3",
+            "Comparison Less at pos 6 can't follow another comparison without parentheses!",
+        );
+        build_illegal_tree(
+            "1 == 2 + 1 >= 3",
+            "Comparison GreaterEqual at pos 11 can't follow another comparison without parentheses!",
+        );
+    }
+
+    #[test]
+    fn tree_build_logical_test() {
+        let number = |n: f64| Node::new_ptr(TokenType::Number(n), None, None);
+        let less = |a: f64, b: f64| Node::new_ptr(TokenType::Less, number(a), number(b));
+
+        build_tree_with_compare(
+            "1 < 2 and 3 < 4 or not 5 < 6",
+            Node::new_ptr(
+                TokenType::Or,
+                Node::new_ptr(TokenType::And, less(1., 2.), less(3., 4.)),
+                Node::new_ptr(TokenType::Not, less(5., 6.), None),
+            ),
+        );
+        build_tree_with_compare(
+            "1 or 2 and not 3",
+            Node::new_ptr(
+                TokenType::Or,
+                number(1.),
+                Node::new_ptr(TokenType::And, number(2.), Node::new_ptr(TokenType::Not, number(3.), None)),
+            ),
+        );
+
+        build_illegal_tree("and 1", "Unknown prefix operator and at pos 0!");
+        build_illegal_tree("1 or", "Operator Str(\"or\") at pos 2 expects an operand, but gets End!");
+    }
+
+    #[test]
+    fn tree_build_word_operators_test() {
+        let words = [
+            "10 mod 3",
+            "10 div 3",
+            "5 xor 3",
+            "10 mod 3 * 2",
+            "1 + 2 xor 3 < 4",
+            "10% mod 3",
+            "mod(10, 3) div 2",
+        ];
+        let calls = [
+            "mod(10, 3)",
+            "div(10, 3)",
+            "bxor(5, 3)",
+            "mod(10, 3) * 2",
+            "bxor(1 + 2, 3) < 4",
+            "mod(10%, 3)",
+            "div(mod(10, 3), 2)",
+        ];
+
+        for (words, calls) in words.iter().zip(calls.iter()) {
+            println!("Expression = {}", words);
+            assert_eq!(parse(words), parse(calls));
+        }
+    }
+
+    #[test]
+    fn tree_build_reserved_word_test() {
+        build_illegal_tree("mod + 1", "Reserved word mod at pos 0 can't be used as a name!");
+        build_illegal_tree("2 * div", "Reserved word div at pos 4 can't be used as a name!");
+        build_illegal_tree("xor -> xor", "Reserved word xor at pos 0 can't be used as a name!");
+        build_illegal_tree("10 mod", "Operator Str(\"mod\") at pos 3 expects an operand, but gets End!");
+    }
+
+    #[test]
+    fn tree_build_invalid_number_test() {
+        build_illegal_tree("0x", "Number 0x at pos 0 is malformed!");
+        build_illegal_tree("1 + 0b102", "Number 0b102 at pos 4 is malformed!");
+        build_illegal_tree("(1)0o9", "Number 0o9 at pos 3 is malformed!");
+        build_illegal_tree("2.5.1 * 3", "Number 2.5.1 at pos 0 is malformed!");
+        build_illegal_tree("1 + 10__0", "Number 10__0 at pos 4 has a misplaced _!");
+        build_illegal_tree("0x20000000000001", "Number 0x20000000000001 at pos 0 is too large to be exact!");
+    }
+
+    #[test]
+    fn tree_build_depth_test() {
+        let depth_error = |pos: usize| format!("Expression at pos {} nests deeper than {} levels!", pos, MAX_DEPTH);
+        let sum = "1+".repeat(MAX_DEPTH - 1) + "1";
+        assert!(parse(&sum).is_ok());
+        assert_eq!(parse(&format!("{}+1", sum)), Err(depth_error(2 * MAX_DEPTH - 1)));
+        assert_eq!(parse(&("-".repeat(MAX_DEPTH) + "1")), Err(depth_error(MAX_DEPTH - 1)));
+        // Parentheses don't deepen the tree, but they nest the parser.
+        let parens = |n: usize| "(".repeat(n) + "1" + &")".repeat(n);
+        assert!(parse(&parens(MAX_DEPTH - 1)).is_ok());
+        assert_eq!(parse(&parens(MAX_DEPTH)), Err(depth_error(MAX_DEPTH - 1)));
+    }
+
+    #[test]
+    fn tree_build_call_test() {
+        let tree = Some(Box::new(Node {
+            token: TokenType::Call(
+                String::from("max"),
+                vec![
+                    Node {
+                        token: TokenType::Variable(String::from("x")),
+                        pos: 0,
+                        span: Span::default(),
+                        left: None,
+                        right: None,
+                    },
+                    Node {
+                        token: TokenType::Plus,
+                        pos: 0,
+                        span: Span::default(),
+                        left: Some(Box::new(Node {
+                            token: TokenType::Number(1.),
+                            pos: 0,
+                            span: Span::default(),
+                            left: None,
+                            right: None,
+                        })),
+                        right: Some(Box::new(Node {
+                            token: TokenType::Call(String::from("pi"), vec![]),
+                            pos: 0,
+                            span: Span::default(),
+                            left: None,
+                            right: None,
+                        })),
+                    },
+                ],
+            ),
+            pos: 0,
+            span: Span::default(),
+            left: None,
+            right: None,
+        }));
+
+        build_tree_with_compare("max(x, 1 + pi())", tree);
+    }
+
+    #[test]
+    fn tree_build_trailing_comma_test() {
+        assert_eq!(parse("max(1, 2, 3,)"), parse("max(1, 2, 3)"));
+        assert_eq!(parse("sin(x,)"), parse("sin(x)"));
+        let missing = |name: &str, sep: usize| {
+            format!("Function {} at pos 0 is missing an argument before the separator at pos {}!", name, sep)
+        };
+        build_illegal_tree("max(,1)", &missing("max", 4));
+        build_illegal_tree("max(1,,2)", &missing("max", 6));
+        build_illegal_tree("max(,)", &missing("max", 4));
+        build_illegal_tree("max(1, 2,,)", &missing("max", 9));
+        build_illegal_tree("random(,)", &missing("random", 7));
+
+        let s = "max(1,5; 2,5;)".to_string();
+        let mut scanner = Scanner::new(&s);
+        scanner.set_decimal_comma(true);
+        scanner.scan();
+        assert_eq!(Ast::new(&mut scanner).build(), Ok(()));
+    }
+
+    #[test]
+    fn tree_build_text_test() {
+        let text = |text: &str| Node::new(TokenType::Text(text.to_string()), None, None);
+        let env = Node::new_ptr(TokenType::Call(String::from("env"), vec![text("THREADS")]), None, None);
+        let two = Node::new_ptr(TokenType::Number(2.), None, None);
+        assert_eq!(parse("env(\"THREADS\") * 2"), Ok(Node::new_ptr(TokenType::Multiply, env, two)));
+        let call = Node::new_ptr(TokenType::Call(String::from("f"), vec![text(""), text("a, b")]), None, None);
+        assert_eq!(parse("f(\"\", \"a, b\",)"), Ok(call));
+
+        // The span of a string covers its quotes.
+        let tree = parse("env(\"HOME\")").unwrap().unwrap();
+        match &tree.token {
+            TokenType::Call(_, args) => assert_eq!(args[0].span, Span::between(4, 10)),
+            token => panic!("{:?} isn't a call!", token),
+        }
+
+        // A string is only a whole argument.
+        let misplaced = |pos: usize| Err(format!("String \"a\" at pos {} can only be an argument of a function!", pos));
+        assert_eq!(parse("\"a\""), misplaced(0));
+        assert_eq!(parse("1 + \"a\""), misplaced(4));
+        assert_eq!(parse("f(\"a\" + 1)"), misplaced(2));
+        assert_eq!(parse("f(\"a)"), Err(String::from("'\"a)' at pos 2 has no closing quote!")));
+        assert_eq!(
+            parse("f(\"a\""),
+            Err(String::from("Function f at pos = 0 doesn't have corresponding RParen!"))
+        );
+    }
+
+    #[test]
+    fn tree_build_lambda_test() {
+        let tree = Some(Box::new(Node {
+            token: TokenType::Lambda(String::from("x")),
+            pos: 0,
+            span: Span::default(),
+            left: Some(Box::new(Node {
+                token: TokenType::Plus,
+                pos: 0,
+                span: Span::default(),
+                left: Some(Box::new(Node {
+                    token: TokenType::Power,
+                    pos: 0,
+                    span: Span::default(),
+                    left: Some(Box::new(Node {
+                        token: TokenType::Variable(String::from("x")),
+                        pos: 0,
+                        span: Span::default(),
+                        left: None,
+                        right: None,
+                    })),
+                    right: Some(Box::new(Node {
+                        token: TokenType::Number(2.),
+                        pos: 0,
+                        span: Span::default(),
+                        left: None,
+                        right: None,
+                    })),
+                })),
+                right: Some(Box::new(Node {
+                    token: TokenType::Number(1.),
+                    pos: 0,
+                    span: Span::default(),
+                    left: None,
+                    right: None,
+                })),
+            })),
+            right: None,
+        }));
+
+        build_tree_with_compare("x -> x^2 + 1", tree);
+    }
 }
@@ -0,0 +1,138 @@
+use std::fmt;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use super::ast::parse_expr;
+use super::context::Context;
+use super::error::Diagnostic;
+use super::evaluator::evaluate_tree;
+use super::json::{self, Object};
+use super::timing::format_duration;
+
+/// How long an expression took to parse once and to evaluate `iterations`
+/// times.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BenchReport {
+    pub iterations: usize,
+    /// Scanning and parsing.
+    pub parse: Duration,
+    /// All the evaluations together.
+    pub total: Duration,
+    /// The fastest evaluation.
+    pub min: Duration,
+    /// The sum of the values, which the evaluations can't be optimized away
+    /// without.
+    pub checksum: f64,
+}
+
+impl BenchReport {
+    pub fn mean(&self) -> Duration {
+        self.total / self.iterations as u32
+    }
+
+    /// The report as a JSON object, with the durations in nanoseconds.
+    pub fn json(&self) -> Object {
+        let nanos = |duration: Duration| duration.as_nanos().to_string();
+        Object::new()
+            .field("iterations", self.iterations.to_string())
+            .field("parse_ns", nanos(self.parse))
+            .field("total_ns", nanos(self.total))
+            .field("mean_ns", nanos(self.mean()))
+            .field("min_ns", nanos(self.min))
+            .field("checksum", json::number(self.checksum))
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} iterations (parse {}, total {}, mean {}, min {}, checksum {})",
+            self.iterations,
+            format_duration(self.parse),
+            format_duration(self.total),
+            format_duration(self.mean()),
+            format_duration(self.min),
+            self.checksum
+        )
+    }
+}
+
+/// Parses `expr` once and evaluates the tree `iterations` times with the
+/// settings and variables of `ctx`. Fails if it can't be parsed or the first
+/// evaluation fails, the others evaluate the same tree the same way.
+pub fn bench(expr: &str, iterations: usize, ctx: &Context) -> Result<BenchReport, Diagnostic> {
+    if iterations == 0 || iterations > u32::MAX as usize {
+        return Err(Diagnostic::from(format!("Can't benchmark {} iterations!", iterations)));
+    }
+    let start = Instant::now();
+    let root = parse_expr(expr, ctx)?;
+    let parse = start.elapsed();
+
+    let mut report = BenchReport {
+        iterations,
+        parse,
+        total: Duration::ZERO,
+        min: Duration::MAX,
+        checksum: 0.,
+    };
+    for _ in 0..iterations {
+        let start = Instant::now();
+        let value = evaluate_tree(black_box(&root), expr, ctx)?;
+        let elapsed = start.elapsed();
+        report.checksum += black_box(value.as_f64());
+        report.total += elapsed;
+        report.min = report.min.min(elapsed);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod bench_tests {
+    use super::*;
+
+    #[test]
+    fn bench_test() {
+        let mut ctx = Context::new();
+        ctx.set_var("x", 1.5);
+        let report = bench("x^2 + 1", 10, &ctx).unwrap();
+        assert_eq!(report.iterations, 10);
+        assert_eq!(report.checksum, 32.5);
+        assert!(report.min <= report.mean() && report.mean() <= report.total);
+
+        assert_eq!(
+            bench("1 +", 10, &ctx).map_err(String::from),
+            Err(String::from(
+                "Ast build error! Operator Plus at pos 2 expects an operand, but gets End!"
+            ))
+        );
+        assert_eq!(
+            bench("y", 10, &ctx).map_err(String::from),
+            Err(String::from("Unknown variable y!"))
+        );
+        assert_eq!(
+            bench("x", 0, &ctx).map_err(String::from),
+            Err(String::from("Can't benchmark 0 iterations!"))
+        );
+    }
+
+    #[test]
+    fn display_test() {
+        let report = BenchReport {
+            iterations: 1000,
+            parse: Duration::from_micros(12),
+            total: Duration::from_micros(350),
+            min: Duration::from_nanos(280),
+            checksum: 2.5,
+        };
+        assert_eq!(
+            report.to_string(),
+            "1000 iterations (parse 12µs, total 350µs, mean 350ns, min 280ns, checksum 2.5)"
+        );
+        assert_eq!(
+            report.json().to_string(),
+            "{\"iterations\":1000,\"parse_ns\":12000,\"total_ns\":350000,\
+             \"mean_ns\":350,\"min_ns\":280,\"checksum\":2.5}"
+        );
+    }
+}
@@ -0,0 +1,442 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops;
+
+/// An integer of any size, for results that f64 can't hold exactly.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct BigInt {
+    negative: bool,
+    /// Base 2^32 digits, least significant first, without leading zeros.
+    /// Zero has no digits and isn't negative.
+    digits: Vec<u32>,
+}
+
+fn trim(mut digits: Vec<u32>) -> Vec<u32> {
+    while digits.last() == Some(&0) {
+        digits.pop();
+    }
+    digits
+}
+
+fn cmp_magnitude(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut sum = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0u64;
+    for i in 0..a.len().max(b.len()) {
+        let digit = *a.get(i).unwrap_or(&0) as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+        sum.push(digit as u32);
+        carry = digit >> 32;
+    }
+    sum.push(carry as u32);
+    trim(sum)
+}
+
+/// `a - b` for `a >= b`.
+fn sub_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut difference = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &digit) in a.iter().enumerate() {
+        let mut digit = digit as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = (digit < 0) as i64;
+        digit += borrow << 32;
+        difference.push(digit as u32);
+    }
+    trim(difference)
+}
+
+fn mul_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut product = vec![0u32; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, &y) in b.iter().enumerate() {
+            let digit = product[i + j] as u64 + x as u64 * y as u64 + carry;
+            product[i + j] = digit as u32;
+            carry = digit >> 32;
+        }
+        product[i + b.len()] = carry as u32;
+    }
+    trim(product)
+}
+
+/// `a / b` and `a % b` for a nonzero `b`.
+fn div_rem_magnitude(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if let [divisor] = b {
+        let mut quotient = vec![0u32; a.len()];
+        let mut remainder = 0u64;
+        for (i, &digit) in a.iter().enumerate().rev() {
+            let n = remainder << 32 | digit as u64;
+            quotient[i] = (n / *divisor as u64) as u32;
+            remainder = n % *divisor as u64;
+        }
+        return (trim(quotient), trim(vec![remainder as u32]));
+    }
+
+    // Long division one bit at a time.
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder: Vec<u32> = Vec::new();
+    for i in (0..a.len() * 32).rev() {
+        let mut carry = a[i / 32] >> (i % 32) & 1;
+        for digit in remainder.iter_mut() {
+            let shifted = *digit >> 31;
+            *digit = *digit << 1 | carry;
+            carry = shifted;
+        }
+        if carry != 0 {
+            remainder.push(carry);
+        }
+        if cmp_magnitude(&remainder, b) != Ordering::Less {
+            remainder = sub_magnitude(&remainder, b);
+            quotient[i / 32] |= 1 << (i % 32);
+        }
+    }
+    (trim(quotient), remainder)
+}
+
+impl BigInt {
+    fn new(negative: bool, digits: Vec<u32>) -> Self {
+        let digits = trim(digits);
+        Self {
+            negative: negative && !digits.is_empty(),
+            digits,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    /// The quotient rounded toward zero and the remainder, which has the sign
+    /// of `self` like with `/` and `%` on i64. Panics if `other` is zero.
+    pub fn div_rem(&self, other: &BigInt) -> (BigInt, BigInt) {
+        assert!(!other.is_zero(), "Division of a BigInt by zero!");
+        let (quotient, remainder) = div_rem_magnitude(&self.digits, &other.digits);
+        (
+            BigInt::new(self.negative != other.negative, quotient),
+            BigInt::new(self.negative, remainder),
+        )
+    }
+
+    /// The greatest common divisor, which is never negative.
+    pub fn gcd(&self, other: &BigInt) -> BigInt {
+        let (mut a, mut b) = (self.abs(), other.abs());
+        while !b.is_zero() {
+            let remainder = a.div_rem(&b).1;
+            a = std::mem::replace(&mut b, remainder);
+        }
+        a
+    }
+
+    /// Divides by 2^`n`, rounding toward zero.
+    pub fn shr(&self, n: u64) -> BigInt {
+        let (whole, bits) = ((n / 32) as usize, (n % 32) as u32);
+        let digits: Vec<u32> = (whole..self.digits.len())
+            .map(|i| {
+                let high = self.digits.get(i + 1).map_or(0, |&d| (d as u64) << 32);
+                ((high | self.digits[i] as u64) >> bits) as u32
+            })
+            .collect();
+        BigInt::new(self.negative, digits)
+    }
+
+    /// Reads a decimal integer like `-123`.
+    pub fn parse(s: &str) -> Option<BigInt> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s),
+        };
+        if digits.is_empty() {
+            return None;
+        }
+        let ten = BigInt::from(10);
+        let mut n = BigInt::from(0);
+        for c in digits.chars() {
+            let digit = BigInt::from(c.to_digit(10)? as i64);
+            n = &(&n * &ten) + &digit;
+        }
+        Some(if negative { -n } else { n })
+    }
+
+    /// Number of bits of the absolute value.
+    pub fn bits(&self) -> u64 {
+        match self.digits.last() {
+            Some(top) => self.digits.len() as u64 * 32 - top.leading_zeros() as u64,
+            None => 0,
+        }
+    }
+
+    pub fn abs(&self) -> Self {
+        Self::new(false, self.digits.clone())
+    }
+
+    pub fn pow(&self, mut exponent: u32) -> Self {
+        let mut result = BigInt::from(1);
+        let mut base = self.clone();
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = &result * &base;
+            }
+            exponent >>= 1;
+            if exponent > 0 {
+                base = &base * &base;
+            }
+        }
+        result
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.digits.len() > 2 {
+            return None;
+        }
+        let magnitude = self
+            .digits
+            .iter()
+            .rev()
+            .fold(0u64, |n, &digit| n << 32 | digit as u64);
+        if self.negative {
+            0i64.checked_sub_unsigned(magnitude)
+        } else {
+            i64::try_from(magnitude).ok()
+        }
+    }
+
+    /// The absolute value's bit `i`, counting from the lowest.
+    fn bit(&self, i: u64) -> u32 {
+        self.digits
+            .get((i / 32) as usize)
+            .map_or(0, |digit| digit >> (i % 32) & 1)
+    }
+
+    /// Writes the number in base 2, 8 or 16 or any other power of two up to
+    /// 32, without a prefix, `-255` is `-ff` in base 16.
+    pub fn to_str_radix(&self, radix: u32) -> String {
+        assert!(radix.is_power_of_two() && (2..=32).contains(&radix));
+        let step = radix.trailing_zeros() as u64;
+        let mut digits = Vec::new();
+        for start in (0..self.bits().max(1)).step_by(step as usize) {
+            let digit = (0..step).fold(0, |digit, i| digit | self.bit(start + i) << i);
+            digits.push(std::char::from_digit(digit, radix).unwrap());
+        }
+        if self.negative {
+            digits.push('-');
+        }
+        digits.iter().rev().collect()
+    }
+
+    /// The integer `x`, or `None` unless it's a finite whole number.
+    pub fn from_f64(x: f64) -> Option<BigInt> {
+        if !x.is_finite() || x.fract() != 0. {
+            return None;
+        }
+        let n = BigInt::parse(&format!("{:.0}", x.abs()))?;
+        Some(if x < 0. { -n } else { n })
+    }
+
+    /// The nearest f64, or an infinity beyond its range.
+    pub fn to_f64(&self) -> f64 {
+        // Parsing the decimal digits rounds correctly.
+        self.to_string().parse().unwrap()
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(n: i64) -> Self {
+        let magnitude = n.unsigned_abs();
+        Self::new(n < 0, vec![magnitude as u32, (magnitude >> 32) as u32])
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_magnitude(&self.digits, &other.digits),
+            (true, true) => cmp_magnitude(&other.digits, &self.digits),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl ops::Neg for BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::new(!self.negative, self.digits)
+    }
+}
+
+impl<'a> ops::Add for &'a BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &'a BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::new(self.negative, add_magnitude(&self.digits, &other.digits));
+        }
+        match cmp_magnitude(&self.digits, &other.digits) {
+            Ordering::Less => {
+                BigInt::new(other.negative, sub_magnitude(&other.digits, &self.digits))
+            }
+            _ => BigInt::new(self.negative, sub_magnitude(&self.digits, &other.digits)),
+        }
+    }
+}
+
+impl<'a> ops::Sub for &'a BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &'a BigInt) -> BigInt {
+        self + &-other.clone()
+    }
+}
+
+impl<'a> ops::Mul for &'a BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &'a BigInt) -> BigInt {
+        BigInt::new(
+            self.negative != other.negative,
+            mul_magnitude(&self.digits, &other.digits),
+        )
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const CHUNK: u64 = 1_000_000_000;
+
+        // Divide by 10^9 repeatedly, the remainders are 9 decimal digits each.
+        let mut digits = self.digits.clone();
+        let mut chunks = Vec::new();
+        while !digits.is_empty() {
+            let mut remainder = 0u64;
+            for digit in digits.iter_mut().rev() {
+                let n = remainder << 32 | *digit as u64;
+                *digit = (n / CHUNK) as u32;
+                remainder = n % CHUNK;
+            }
+            chunks.push(remainder);
+            digits = trim(digits);
+        }
+
+        let mut s = String::from(if self.negative { "-" } else { "" });
+        match chunks.pop() {
+            Some(top) => s.push_str(&top.to_string()),
+            None => s.push('0'),
+        }
+        for chunk in chunks.iter().rev() {
+            s.push_str(&format!("{:09}", chunk));
+        }
+        write!(f, "{}", s)
+    }
+}
+
+#[cfg(test)]
+mod bigint_tests {
+    use super::*;
+
+    fn big(n: i64) -> BigInt {
+        BigInt::from(n)
+    }
+
+    #[test]
+    fn bigint_arithmetic_test() {
+        let large = big(i64::MAX);
+        assert_eq!((&large + &big(1)).to_string(), "9223372036854775808");
+        assert_eq!(
+            (&big(i64::MIN) - &big(1)).to_string(),
+            "-9223372036854775809"
+        );
+        assert_eq!(
+            (&large * &large).to_string(),
+            "85070591730234615847396907784232501249"
+        );
+        assert_eq!((&big(-3) * &big(4)).to_string(), "-12");
+        assert_eq!((&big(5) - &big(5)), big(0));
+        assert_eq!((&big(-5) + &big(7)), big(2));
+        assert_eq!(
+            big(2).pow(100).to_string(),
+            "1267650600228229401496703205376"
+        );
+        assert_eq!(big(-10).pow(21).to_string(), "-1000000000000000000000");
+        assert_eq!(big(7).pow(0), big(1));
+    }
+
+    #[test]
+    fn bigint_conversion_test() {
+        assert_eq!(big(0).to_string(), "0");
+        assert_eq!(big(-1_000_000_000).to_string(), "-1000000000");
+        assert_eq!(big(i64::MIN).to_i64(), Some(i64::MIN));
+        assert_eq!((&big(i64::MAX) + &big(1)).to_i64(), None);
+        assert_eq!((-(&big(i64::MIN) - &big(1))).to_i64(), None);
+        assert_eq!(big(2).pow(100).to_f64(), 2f64.powi(100));
+        assert_eq!(big(2).pow(1024).to_f64(), f64::INFINITY);
+        assert_eq!(big(2).pow(100).bits(), 101);
+        assert_eq!(big(0).bits(), 0);
+    }
+
+    #[test]
+    fn bigint_order_test() {
+        let large = big(2).pow(70);
+        assert!(big(-2) < big(1));
+        assert!(-large.clone() < big(i64::MIN));
+        assert!(large > big(i64::MAX));
+        assert!(big(-3) < big(-2));
+        assert_eq!(large.abs(), (-large.clone()).abs());
+    }
+
+    #[test]
+    fn bigint_division_test() {
+        let large = big(2).pow(100);
+        let (quotient, remainder) = large.div_rem(&big(7));
+        assert_eq!(quotient.to_string(), "181092942889747057356671886482");
+        assert_eq!(remainder, big(2));
+
+        let divisor = BigInt::parse("-12345678901234567890123").unwrap();
+        let (quotient, remainder) = large.div_rem(&divisor);
+        assert_eq!(quotient.to_string(), "-102679699");
+        assert_eq!(remainder.to_string(), "6698813242143808492399");
+        assert_eq!(&(&quotient * &divisor) + &remainder, large);
+
+        assert_eq!(big(-7).div_rem(&big(2)), (big(-3), big(-1)));
+        assert_eq!(big(2).div_rem(&large), (big(0), big(2)));
+        assert_eq!(big(12).gcd(&big(-18)), big(6));
+        assert_eq!(
+            large.gcd(&big(2).pow(70).pow(1)).to_string(),
+            "1180591620717411303424"
+        );
+        assert_eq!(large.shr(98), big(4));
+        assert_eq!(big(-5).shr(1), big(-2));
+        assert_eq!(BigInt::parse("-0012"), Some(big(-12)));
+        assert_eq!(BigInt::parse("1x"), None);
+    }
+
+    #[test]
+    fn bigint_radix_test() {
+        assert_eq!(big(255).to_str_radix(16), "ff");
+        assert_eq!(big(-255).to_str_radix(16), "-ff");
+        assert_eq!(big(10).to_str_radix(2), "1010");
+        assert_eq!(big(15).to_str_radix(8), "17");
+        assert_eq!(big(64).to_str_radix(8), "100");
+        assert_eq!(big(0).to_str_radix(2), "0");
+        assert_eq!(
+            BigInt::from(2).pow(100).to_str_radix(16),
+            format!("1{}", "0".repeat(25))
+        );
+        assert_eq!(big(i64::MIN).to_str_radix(16), "-8000000000000000");
+    }
+}
@@ -0,0 +1,558 @@
+use std::collections::hash_map::RandomState;
+use std::f64::consts;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+
+use super::ast::Node;
+use super::fraction::Fraction;
+use super::printer::format_number;
+
+/// Named values available to every expression. `inf` and `nan` are spelled
+/// the way results are printed, so they read back in.
+pub const CONSTANTS: &[(&str, f64)] = &[
+    ("pi", consts::PI),
+    ("e", consts::E),
+    ("true", 1.),
+    ("false", 0.),
+    ("inf", f64::INFINITY),
+    ("nan", f64::NAN),
+];
+
+pub fn find_constant(name: &str) -> Option<f64> {
+    CONSTANTS
+        .iter()
+        .find(|(constant, _)| *constant == name)
+        .map(|(_, value)| *value)
+}
+
+/// How many arguments a function takes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    Exact(usize),
+    /// From the first number up to the second one, the last arguments may be
+    /// left out.
+    Range(usize, usize),
+    /// Any number from this one on, like for `max`.
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn min(&self) -> usize {
+        match *self {
+            Arity::Exact(n) | Arity::Range(n, _) | Arity::AtLeast(n) => n,
+        }
+    }
+
+    pub fn max(&self) -> Option<usize> {
+        match *self {
+            Arity::Exact(n) | Arity::Range(_, n) => Some(n),
+            Arity::AtLeast(_) => None,
+        }
+    }
+
+    /// Whether a number of arguments is accepted by both arities, which
+    /// overloads of one name must not share.
+    pub fn overlaps(&self, other: &Arity) -> bool {
+        let below = |arity: &Arity, min: usize| !matches!(arity.max(), Some(max) if min > max);
+        below(self, other.min()) && below(other, self.min())
+    }
+
+    pub fn accepts(&self, given: usize) -> bool {
+        given >= self.min() && !matches!(self.max(), Some(max) if given > max)
+    }
+
+    /// Fails unless `given` arguments, which were evaluated already, are as
+    /// many as `name` takes.
+    pub fn check_count(&self, name: &str, given: usize) -> Result<(), String> {
+        if self.accepts(given) {
+            Ok(())
+        } else {
+            Err(format!(
+                "Function {} expects {}, but gets {}!",
+                name, self, given
+            ))
+        }
+    }
+
+    /// Fails unless `args`, the arguments of a call of `name` at `pos`, are
+    /// as many as it takes. Too many are reported at the first extra one.
+    pub fn check(&self, name: &str, args: &[Node], pos: usize) -> Result<(), String> {
+        let positions: Vec<usize> = args.iter().map(|arg| arg.pos).collect();
+        self.check_at(name, &positions, pos)
+    }
+
+    /// Like `check`, for a call with its arguments at `args`.
+    pub fn check_at(&self, name: &str, args: &[usize], pos: usize) -> Result<(), String> {
+        match self.max() {
+            Some(max) if args.len() > max => Err(format!(
+                "Function {} at pos {} expects {}, but gets {}, the first extra one at pos {}!",
+                name,
+                pos,
+                self,
+                args.len(),
+                args[max]
+            )),
+            _ if args.len() < self.min() => Err(format!(
+                "Function {} at pos {} expects {}, but gets {}!",
+                name,
+                pos,
+                self,
+                args.len()
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// `1 argument`, `1 to 2 arguments` or `at least 2 arguments`.
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Arity::Exact(1) => write!(f, "1 argument"),
+            Arity::Exact(n) => write!(f, "{} arguments", n),
+            Arity::Range(min, max) => write!(f, "{} to {} arguments", min, max),
+            Arity::AtLeast(n) => write!(f, "at least {} arguments", n),
+        }
+    }
+}
+
+/// A function callable by name from expressions. Functions of the same name
+/// are overloads of it, which take different numbers of arguments, like
+/// `random()` and `random(a, b)`.
+#[derive(Debug, Clone, Copy)]
+pub struct Builtin {
+    pub name: &'static str,
+    pub arity: Arity,
+    /// Pure functions always return the same value for the same arguments,
+    /// so calls to them may be evaluated ahead of time.
+    pub pure: bool,
+    pub func: fn(&[f64]) -> Result<f64, String>,
+}
+
+pub const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "sin",
+        arity: Arity::Exact(1),
+        pure: true,
+        func: |args| Ok(args[0].sin()),
+    },
+    Builtin {
+        name: "cos",
+        arity: Arity::Exact(1),
+        pure: true,
+        func: |args| Ok(args[0].cos()),
+    },
+    Builtin {
+        name: "tan",
+        arity: Arity::Exact(1),
+        pure: true,
+        func: |args| Ok(args[0].tan()),
+    },
+    Builtin {
+        name: "asin",
+        arity: Arity::Exact(1),
+        pure: true,
+        func: |args| Ok(args[0].asin()),
+    },
+    Builtin {
+        name: "acos",
+        arity: Arity::Exact(1),
+        pure: true,
+        func: |args| Ok(args[0].acos()),
+    },
+    Builtin {
+        name: "atan",
+        arity: Arity::Exact(1),
+        pure: true,
+        func: |args| Ok(args[0].atan()),
+    },
+    Builtin {
+        name: "sinh",
+        arity: Arity::Exact(1),
+        pure: true,
+        func: |args| Ok(args[0].sinh()),
+    },
+    Builtin {
+        name: "cosh",
+        arity: Arity::Exact(1),
+        pure: true,
+        func: |args| Ok(args[0].cosh()),
+    },
+    Builtin {
+        name: "tanh",
+        arity: Arity::Exact(1),
+        pure: true,
+        func: |args| Ok(args[0].tanh()),
+    },
+    Builtin {
+        name: "exp",
+        arity: Arity::Exact(1),
+        pure: true,
+        func: |args| Ok(args[0].exp()),
+    },
+    Builtin {
+        name: "ln",
+        arity: Arity::Exact(1),
+        pure: true,
+        func: |args| Ok(args[0].ln()),
+    },
+    Builtin {
+        name: "log",
+        arity: Arity::Range(1, 2),
+        pure: true,
+        func: |args| match args.get(1) {
+            Some(&base) => Ok(log(args[0], base)),
+            None => Ok(args[0].ln()),
+        },
+    },
+    Builtin {
+        name: "sqrt",
+        arity: Arity::Exact(1),
+        pure: true,
+        func: |args| Ok(args[0].sqrt()),
+    },
+    Builtin {
+        name: "root",
+        arity: Arity::Range(1, 2),
+        pure: true,
+        func: |args| match args.get(1) {
+            Some(&n) => root(args[0], integer("root", n)?),
+            None => Ok(args[0].sqrt()),
+        },
+    },
+    Builtin {
+        name: "abs",
+        arity: Arity::Exact(1),
+        pure: true,
+        func: |args| Ok(args[0].abs()),
+    },
+    Builtin {
+        name: "floor",
+        arity: Arity::Exact(1),
+        pure: true,
+        func: |args| Ok(args[0].floor()),
+    },
+    Builtin {
+        name: "ceil",
+        arity: Arity::Exact(1),
+        pure: true,
+        func: |args| Ok(args[0].ceil()),
+    },
+    Builtin {
+        name: "round",
+        arity: Arity::Range(1, 2),
+        pure: true,
+        func: |args| match args.get(1) {
+            Some(&digits) => Ok(round_to(args[0], integer("round", digits)?)),
+            None => Ok(args[0].round()),
+        },
+    },
+    // Rounds to significant figures rather than decimal places.
+    Builtin {
+        name: "sigfig",
+        arity: Arity::Exact(2),
+        pure: true,
+        func: |args| {
+            let figures = integer("sigfig", args[1])?;
+            if figures < 1 {
+                return Err(format!(
+                    "Function sigfig expects at least 1 significant figure, but gets {}!",
+                    figures
+                ));
+            }
+            if args[0] == 0. || !args[0].is_finite() {
+                return Ok(args[0]);
+            }
+            // The exponent of the first digit, read off the decimal form
+            // rather than computed with log10, which is off at powers of ten.
+            let scientific = format!("{:e}", args[0]);
+            let exponent: i64 = scientific.split_once('e').unwrap().1.parse().unwrap();
+            Ok(round_to(args[0], figures - 1 - exponent))
+        },
+    },
+    Builtin {
+        name: "min",
+        arity: Arity::AtLeast(2),
+        pure: true,
+        func: |args| Ok(args[1..].iter().fold(args[0], |min, arg| min.min(*arg))),
+    },
+    Builtin {
+        name: "max",
+        arity: Arity::AtLeast(2),
+        pure: true,
+        func: |args| Ok(args[1..].iter().fold(args[0], |max, arg| max.max(*arg))),
+    },
+    // Unlike the `%` operator, `mod` is never negative: mod(-7, 3) is 2, and
+    // `div` is the quotient that goes with it, so a = b * div(a, b) + mod(a, b).
+    Builtin {
+        name: "mod",
+        arity: Arity::Exact(2),
+        pure: true,
+        func: |args| Ok(args[0].rem_euclid(args[1])),
+    },
+    Builtin {
+        name: "div",
+        arity: Arity::Exact(2),
+        pure: true,
+        func: |args| match args[1] {
+            0. => Ok(f64::NAN),
+            b => Ok(args[0].div_euclid(b)),
+        },
+    },
+    // 1 if both arguments agree up to rounding errors, for where `==` is too
+    // strict.
+    Builtin {
+        name: "approx",
+        arity: Arity::Exact(2),
+        pure: true,
+        func: |args| {
+            let (a, b) = (args[0], args[1]);
+            let tolerance = 1e-9 * a.abs().max(b.abs()).max(1.);
+            if (a - b).abs() <= tolerance {
+                Ok(1.)
+            } else {
+                Ok(0.)
+            }
+        },
+    },
+    // Same as `%`, the sign follows the dividend.
+    Builtin {
+        name: "rem",
+        arity: Arity::Exact(2),
+        pure: true,
+        func: |args| Ok(args[0] % args[1]),
+    },
+    // Bitwise functions take whole numbers in the i64 range. `|` and `^` are
+    // taken by absolute values and powers, so these have no operators.
+    Builtin {
+        name: "band",
+        arity: Arity::Exact(2),
+        pure: true,
+        func: |args| Ok((integer("band", args[0])? & integer("band", args[1])?) as f64),
+    },
+    Builtin {
+        name: "bor",
+        arity: Arity::Exact(2),
+        pure: true,
+        func: |args| Ok((integer("bor", args[0])? | integer("bor", args[1])?) as f64),
+    },
+    Builtin {
+        name: "bxor",
+        arity: Arity::Exact(2),
+        pure: true,
+        func: |args| Ok((integer("bxor", args[0])? ^ integer("bxor", args[1])?) as f64),
+    },
+    Builtin {
+        name: "bnot",
+        arity: Arity::Exact(1),
+        pure: true,
+        func: |args| Ok(!integer("bnot", args[0])? as f64),
+    },
+    Builtin {
+        name: "shl",
+        arity: Arity::Exact(2),
+        pure: true,
+        func: |args| {
+            let (a, n) = shift("shl", args)?;
+            let shifted = a << n;
+            if shifted >> n != a {
+                return Err(format!(
+                    "Function shl overflows shifting {} by {} bit(s)!",
+                    a, n
+                ));
+            }
+            Ok(shifted as f64)
+        },
+    },
+    // Shifts in the sign bit, so shr(-8, 1) is -4.
+    Builtin {
+        name: "shr",
+        arity: Arity::Exact(2),
+        pure: true,
+        func: |args| {
+            let (a, n) = shift("shr", args)?;
+            Ok((a >> n) as f64)
+        },
+    },
+    Builtin {
+        name: "random",
+        arity: Arity::Exact(0),
+        pure: false,
+        func: |_| Ok(random()),
+    },
+    // A number in `[a, b)`.
+    Builtin {
+        name: "random",
+        arity: Arity::Exact(2),
+        pure: false,
+        func: |args| Ok(args[0] + random() * (args[1] - args[0])),
+    },
+    // The results of the history are in the context, see `Evaluator`.
+    Builtin {
+        name: "out",
+        arity: Arity::Exact(1),
+        pure: false,
+        func: |_| Err(String::from("out needs the history of a session!")),
+    },
+    // The environment variable of a string, see `evaluator::env_var`.
+    Builtin {
+        name: "env",
+        arity: Arity::Exact(1),
+        pure: false,
+        func: |_| Err(String::from("env needs the name of an environment variable!")),
+    },
+    // The value of the fraction, see `ExactEvaluator` for the fraction.
+    Builtin {
+        name: "frac",
+        arity: Arity::Range(1, 2),
+        pure: true,
+        func: |args| {
+            let max = max_denominator(args)?;
+            Ok(Fraction::approximate(args[0], max).map_or(args[0], |fraction| fraction.to_f64()))
+        },
+    },
+];
+
+/// The maximum denominator `frac` uses unless it's given.
+pub const DEFAULT_MAX_DENOMINATOR: i64 = 1000;
+
+/// The maximum denominator of a call to `frac`, its optional second argument.
+pub fn max_denominator(args: &[f64]) -> Result<i64, String> {
+    match args.get(1) {
+        None => Ok(DEFAULT_MAX_DENOMINATOR),
+        Some(&max) if max >= 1. && max.fract() == 0. && max <= (1u64 << 53) as f64 => {
+            Ok(max as i64)
+        }
+        Some(&max) => Err(format!(
+            "Function frac expects a whole maximum denominator of at least 1, but gets {}!",
+            format_number(max)
+        )),
+    }
+}
+
+/// 2^63, the first whole number beyond the i64 range.
+const I64_LIMIT: f64 = 9_223_372_036_854_775_808.;
+
+/// Converts an argument of the function `name` to an integer. It has to be
+/// a whole number up to rounding errors.
+fn integer(name: &str, x: f64) -> Result<i64, String> {
+    let whole = x.round();
+    if x.is_nan() || (x - whole).abs() > 1e-9 * x.abs().max(1.) {
+        Err(format!(
+            "Function {} expects integer arguments, but gets {}!",
+            name, x
+        ))
+    } else if !(-I64_LIMIT..I64_LIMIT).contains(&whole) {
+        Err(format!(
+            "Function {} expects arguments in the 64-bit integer range, but gets {}!",
+            name, x
+        ))
+    } else {
+        Ok(whole as i64)
+    }
+}
+
+/// Rounds `x` half away from zero to `digits` decimal places, or to tens,
+/// hundreds and so on if `digits` is negative.
+fn round_to(x: f64, digits: i64) -> f64 {
+    let scale = 10f64.powi(digits.unsigned_abs().min(400) as i32);
+    let scaled = if digits >= 0 { x * scale } else { x / scale };
+    // Beyond the range of f64 every digit is kept anyway.
+    if !scaled.is_finite() {
+        return x;
+    }
+    // Scaling back through the decimal form, as dividing by a power of ten
+    // that isn't exact would leave a rounding error.
+    format!("{}e{}", scaled.round(), -digits).parse().unwrap()
+}
+
+/// The logarithm of `x` to `base`, exact for powers of 2 and 10.
+fn log(x: f64, base: f64) -> f64 {
+    if base == 2. {
+        x.log2()
+    } else if base == 10. {
+        x.log10()
+    } else {
+        x.ln() / base.ln()
+    }
+}
+
+/// The `n`th root of `x`. Odd roots of negative numbers are real,
+/// `root(-8, 3)` is `-2`, and roots that are whole numbers are exact.
+fn root(x: f64, n: i64) -> Result<f64, String> {
+    if n == 0 {
+        return Err(String::from("Function root expects a nonzero index, but gets 0!"));
+    }
+    let root = if x < 0. && n % 2 != 0 {
+        -(-x).powf(1. / n as f64)
+    } else {
+        x.powf(1. / n as f64)
+    };
+    let whole = root.round();
+    if n > 0 && n <= i32::MAX as i64 && whole.powi(n as i32) == x {
+        Ok(whole)
+    } else {
+        Ok(root)
+    }
+}
+
+/// The value and the number of bits of a shift.
+fn shift(name: &str, args: &[f64]) -> Result<(i64, u32), String> {
+    let a = integer(name, args[0])?;
+    let n = integer(name, args[1])?;
+    if !(0..64).contains(&n) {
+        return Err(format!(
+            "Function {} expects a shift by 0 to 63 bits, but gets {}!",
+            name, n
+        ));
+    }
+    Ok((a, n as u32))
+}
+
+/// Returns a uniformly distributed number in `[0, 1)`.
+fn random() -> f64 {
+    // Every RandomState is seeded with fresh random keys.
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// The first builtin overload of `name`.
+pub fn find_builtin(name: &str) -> Option<&'static Builtin> {
+    builtin_overloads(name).next()
+}
+
+pub fn builtin_overloads(name: &str) -> impl Iterator<Item = &'static Builtin> + '_ {
+    BUILTINS.iter().filter(move |builtin| builtin.name == name)
+}
+
+/// The overload of `name` that takes the arguments of a call at `pos`,
+/// which are at `args`. As overloads don't overlap, at most one of them
+/// does. A name without overloads fails like `Arity::check`.
+pub fn resolve<'b>(
+    name: &str,
+    overloads: &[&'b Builtin],
+    args: &[usize],
+    pos: usize,
+) -> Result<&'b Builtin, String> {
+    match overloads {
+        [] => Err(format!("Unknown function {}!", name)),
+        [builtin] => builtin.arity.check_at(name, args, pos).map(|_| *builtin),
+        _ => overloads
+            .iter()
+            .find(|builtin| builtin.arity.accepts(args.len()))
+            .copied()
+            .ok_or_else(|| {
+                let arities: Vec<String> = overloads
+                    .iter()
+                    .map(|builtin| builtin.arity.to_string())
+                    .collect();
+                format!(
+                    "No overload of function {} at pos {} accepts {}, it takes {}!",
+                    name,
+                    pos,
+                    Arity::Exact(args.len()),
+                    arities.join(" or ")
+                )
+            }),
+    }
+}
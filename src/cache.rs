@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+
+use super::ast::{parse_with, NodePtr};
+use super::context::Context;
+use super::evaluator::evaluate_tree;
+use super::value::Value;
+
+/// How many trees a cache keeps by default.
+pub const CACHE_SIZE: usize = 64;
+
+/// How often an `ExprCache` found a tree and how often it had to parse one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    tree: NodePtr,
+    /// The decimal separator and SI suffixes it was parsed with.
+    syntax: (bool, bool),
+    /// The tick of the last evaluation, the oldest one is dropped first.
+    used: u64,
+}
+
+/// The parsed trees of the expressions evaluated last, for formulas that
+/// are evaluated over and over with other variables. It holds up to
+/// `capacity` trees and drops the least recently used one for a new one.
+///
+/// A cache is for a single thread, it's borrowed mutably for every
+/// evaluation.
+#[derive(Debug, Clone)]
+pub struct ExprCache {
+    entries: HashMap<String, Entry>,
+    capacity: usize,
+    tick: u64,
+    stats: CacheStats,
+}
+
+impl Default for ExprCache {
+    fn default() -> Self {
+        ExprCache::new(CACHE_SIZE)
+    }
+}
+
+impl ExprCache {
+    /// A cache of up to `capacity` trees, which is at least 1.
+    pub fn new(capacity: usize) -> Self {
+        ExprCache {
+            entries: HashMap::new(),
+            capacity: capacity.max(1),
+            tick: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// Evaluates `src` with the variables and modes of `ctx` like
+    /// `evaluate_with`, parsing it only if it isn't cached. Expressions that
+    /// don't parse aren't cached.
+    pub fn eval(&mut self, src: &str, ctx: &Context) -> Result<f64, String> {
+        match self.eval_value(src, ctx)? {
+            Value::Complex(z) => Err(format!("{} isn't a real number!", z)),
+            value => Ok(value.as_f64()),
+        }
+    }
+
+    /// Like `eval`, but keeps integers exact like `evaluate_value`.
+    pub fn eval_value(&mut self, src: &str, ctx: &Context) -> Result<Value, String> {
+        self.tick += 1;
+        let syntax = (ctx.decimal_comma(), ctx.si_suffixes());
+        // A tree parsed with the other decimal separator or SI suffixes is
+        // parsed again.
+        match self.entries.get_mut(src) {
+            Some(entry) if entry.syntax == syntax => {
+                self.stats.hits += 1;
+                entry.used = self.tick;
+                return evaluate_tree(&entry.tree, src, ctx).map_err(String::from);
+            }
+            _ => self.stats.misses += 1,
+        }
+
+        let tree =
+            parse_with(src, ctx).map_err(|err| format!("Ast build error! {}", err))?;
+        let value = evaluate_tree(&tree, src, ctx).map_err(String::from);
+        if !self.entries.contains_key(src) && self.entries.len() >= self.capacity {
+            self.evict();
+        }
+        let entry = Entry {
+            tree,
+            syntax,
+            used: self.tick,
+        };
+        self.entries.insert(src.to_string(), entry);
+        value
+    }
+
+    fn evict(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.used)
+            .map(|(src, _)| src.clone());
+        if let Some(src) = oldest {
+            self.entries.remove(&src);
+        }
+    }
+
+    /// Drops the tree of `src`, and returns whether it was cached.
+    pub fn invalidate(&mut self, src: &str) -> bool {
+        self.entries.remove(src).is_some()
+    }
+
+    /// Drops all trees. The stats stay.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn contains(&self, src: &str) -> bool {
+        self.entries.contains_key(src)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod cache_tests {
+    use super::*;
+    use crate::evaluator::evaluate_with;
+
+    fn context(x: f64) -> Context {
+        let mut ctx = Context::new();
+        ctx.set_var("x", x);
+        ctx
+    }
+
+    #[test]
+    fn eval_test() {
+        let mut cache = ExprCache::new(4);
+        let formula = "x^2 + 3x - sqrt(x)";
+        for i in 1..=100 {
+            let ctx = context(i as f64);
+            let expected = evaluate_with(&formula.to_string(), &ctx);
+            assert_eq!(cache.eval(formula, &ctx), expected);
+        }
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 99,
+                misses: 1
+            }
+        );
+        assert_eq!(cache.eval(formula, &context(4.)), Ok(26.));
+        assert_eq!(cache.eval(formula, &context(9.)), Ok(105.));
+
+        // Errors of evaluating come from the cached tree, those of parsing
+        // aren't cached.
+        assert_eq!(
+            cache.eval("1 % x", &context(0.)),
+            Err(String::from("Division by zero in 1 % 0 at pos 2!"))
+        );
+        assert_eq!(cache.eval("1 % x", &context(4.)), Ok(1.));
+        assert_eq!(
+            cache.eval("1 +", &context(0.)),
+            evaluate_with(&String::from("1 +"), &context(0.))
+        );
+        assert!(!cache.contains("1 +"));
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 102,
+                misses: 3
+            }
+        );
+
+        // Another decimal separator parses it again.
+        let mut ctx = context(0.);
+        assert!(cache.eval("max(1,5; 2)", &ctx).is_err());
+        ctx.set_decimal_comma(true);
+        assert_eq!(cache.eval("max(1,5; 2)", &ctx), Ok(2.));
+        assert_eq!(cache.stats().misses, 5);
+        // And so do SI suffixes.
+        assert!(cache.eval("2k", &ctx).is_err());
+        ctx.set_si_suffixes(true);
+        assert_eq!(cache.eval("2k", &ctx), Ok(2000.));
+        assert_eq!(cache.stats().misses, 7);
+    }
+
+    #[test]
+    fn eviction_test() {
+        let mut cache = ExprCache::new(2);
+        let ctx = context(2.);
+        assert_eq!(cache.eval("x + 1", &ctx), Ok(3.));
+        assert_eq!(cache.eval("x + 2", &ctx), Ok(4.));
+        // x + 1 was used last, so x + 2 is dropped for x + 3.
+        assert_eq!(cache.eval("x + 1", &ctx), Ok(3.));
+        assert_eq!(cache.eval("x + 3", &ctx), Ok(5.));
+        assert_eq!(cache.len(), 2);
+        assert!(cache.contains("x + 1") && cache.contains("x + 3"));
+        assert!(!cache.contains("x + 2"));
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 3 });
+
+        assert!(cache.invalidate("x + 1"));
+        assert!(!cache.invalidate("x + 1"));
+        assert_eq!(cache.eval("x + 1", &ctx), Ok(3.));
+        assert_eq!(cache.stats().misses, 4);
+        cache.clear();
+        assert!(cache.is_empty());
+        assert_eq!(ExprCache::new(0).capacity(), 1);
+    }
+}
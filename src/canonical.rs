@@ -0,0 +1,168 @@
+use std::cmp::Ordering;
+
+use super::ast::{Node, NodePtr, TokenType};
+use super::visitor::{transform, Transformer};
+
+/// Rewrites a tree into a normal form, so trees that only differ in the
+/// order of `+` and `*` operands compare equal.
+struct Canonicalizer;
+
+fn negate(node: NodePtr) -> NodePtr {
+    match node {
+        Some(node) => match node.token {
+            TokenType::Number(n) => Node::new_ptr(TokenType::Number(-n), None, None),
+            TokenType::PrefixMinus => node.left,
+            _ => Node::new_ptr(TokenType::PrefixMinus, Some(node), None),
+        },
+        None => None,
+    }
+}
+
+/// Collects the operands of a chain of `op`.
+fn flatten(op: &TokenType, node: NodePtr, operands: &mut Vec<NodePtr>) {
+    match node {
+        Some(node) if node.token == *op => {
+            let Node { left, right, .. } = *node;
+            flatten(op, left, operands);
+            flatten(op, right, operands);
+        }
+        node => operands.push(node),
+    }
+}
+
+fn rank(node: &NodePtr) -> u8 {
+    match node.as_ref().map(|node| &node.token) {
+        Some(TokenType::Number(_)) => 0,
+        Some(TokenType::Variable(_)) => 1,
+        _ => 2,
+    }
+}
+
+/// Numbers go first, then variables by name, then everything else by its
+/// rendered form.
+fn compare(a: &NodePtr, b: &NodePtr) -> Ordering {
+    rank(a).cmp(&rank(b)).then_with(|| match (a, b) {
+        (Some(a), Some(b)) => match (&a.token, &b.token) {
+            (TokenType::Number(a), TokenType::Number(b)) => a.total_cmp(b),
+            (TokenType::Variable(a), TokenType::Variable(b)) => a.cmp(b),
+            _ => a.to_string().cmp(&b.to_string()),
+        },
+        _ => a.is_some().cmp(&b.is_some()),
+    })
+}
+
+impl Transformer for Canonicalizer {
+    fn transform_unary(&mut self, op: TokenType, operand: NodePtr) -> NodePtr {
+        match op {
+            TokenType::PrefixPlus => operand,
+            TokenType::PrefixMinus => negate(operand),
+            _ => Node::new_ptr(op, operand, None),
+        }
+    }
+
+    fn transform_binary(&mut self, op: TokenType, left: NodePtr, right: NodePtr) -> NodePtr {
+        let (op, right) = match op {
+            TokenType::Minus => (TokenType::Plus, negate(right)),
+            op => (op, right),
+        };
+        if op != TokenType::Plus && op != TokenType::Multiply {
+            return Node::new_ptr(op, left, right);
+        }
+
+        let mut operands = Vec::new();
+        flatten(&op, left, &mut operands);
+        flatten(&op, right, &mut operands);
+        operands.sort_by(compare);
+
+        let mut operands = operands.into_iter();
+        let first = operands.next().flatten();
+        operands
+            .fold(first.map(|node| *node), |left, right| {
+                Some(Node::new(op.clone(), left.map(Box::new), right))
+            })
+            .map(Box::new)
+    }
+}
+
+impl Node {
+    /// Returns the tree in a normal form: operands of `+` and `*` chains are
+    /// flattened, sorted (numbers, variables, then other subtrees by their
+    /// rendered form) and nested to the left again, and `x - y` turns into
+    /// `x + -y`. Trees that only differ in that are equal after this.
+    ///
+    /// The value is the same, though with floats a reordered sum may differ
+    /// in the last bits.
+    pub fn canonicalize(&self) -> Node {
+        let tree = transform(&mut Canonicalizer, Some(Box::new(self.clone())));
+        *tree.expect("Canonicalizing a node always yields a node")
+    }
+}
+
+#[cfg(test)]
+mod canonical_tests {
+    use super::*;
+    use crate::ast::parse;
+    use crate::context::Context;
+    use crate::evaluator::Evaluator;
+    use crate::visitor::walk_node;
+
+    fn build(expr: &str) -> Node {
+        *parse(expr).unwrap().unwrap()
+    }
+
+    fn do_test(a: &str, b: &str) {
+        println!("Expressions = {}, {}", a, b);
+
+        assert_eq!(build(a).canonicalize(), build(b).canonicalize());
+    }
+
+    #[test]
+    fn commutative_test() {
+        do_test("b*2*a", "2*a*b");
+        do_test("1 + x", "x + 1");
+        do_test("(a + b) + (c + d)", "d + (c + (b + a))");
+        do_test("sin(y + x) * x", "x * sin(x + y)");
+        do_test("x^2 + 2*x + 1", "1 + x*2 + x^2");
+    }
+
+    #[test]
+    fn subtraction_test() {
+        do_test("x - y + 3", "3 - y + x");
+        do_test("x - 2", "-2 + x");
+        do_test("+x - -y", "y + x");
+    }
+
+    #[test]
+    fn not_commutative_test() {
+        assert_ne!(build("x - y").canonicalize(), build("y - x").canonicalize());
+        assert_ne!(build("x / y").canonicalize(), build("y / x").canonicalize());
+        assert_ne!(build("x ^ 2").canonicalize(), build("2 ^ x").canonicalize());
+    }
+
+    #[test]
+    fn normal_form_test() {
+        assert_eq!(build("b + 1 + a").canonicalize(), build("1 + a + b"));
+        assert_eq!(build("y * x - 3").canonicalize().to_string(), "-3 + x * y");
+        assert_eq!(build("-(-x) * 2").canonicalize().to_string(), "2 * x");
+    }
+
+    #[test]
+    fn same_value_test() {
+        let mut ctx = Context::new();
+        ctx.set_var("x", 3.);
+        ctx.set_var("y", -2.);
+
+        for expr in &[
+            "x - y * 2 + 1",
+            "(y - x) * (x + 2) / 4",
+            "sum(i, 1, x, i * y - i)",
+            "|y - x|! - 2^x",
+        ] {
+            let tree = build(expr);
+            let eval = |node: &Node| walk_node(&mut Evaluator::new(&ctx), node);
+
+            println!("Expression = {}", expr);
+            assert_eq!(eval(&tree.canonicalize()), eval(&tree));
+        }
+    }
+}
@@ -0,0 +1,1247 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::thread;
+use std::time::Duration;
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+use super::ast::{parse_expr, tree_string};
+use super::bench::bench;
+use super::command::{assign, assignable, token_list, Evaluated, Session};
+use super::config::{self, Config};
+use super::csv::eval_csv;
+use super::error::{ColorChoice, Diagnostic, ErrorCode, Span};
+use super::format::format_value;
+use super::json::{self, Object};
+use super::scanner::{is_name, tokens_to_json};
+use super::session_file;
+use super::stream::{eval_lines, evaluate_line, Shown};
+
+pub const USAGE: &str = "\
+Usage: ls_calc [OPTIONS]
+
+Without options the calculator reads expressions interactively.
+
+Options:
+  -e, --expr EXPR    evaluate EXPR and print its value, can be repeated
+  --config FILE      read the settings from FILE instead of the default one
+  --no-config        don't read a config file
+  --load FILE        set the variables saved with :save first
+  --var NAME=EXPR    set the variable NAME to EXPR first, can be repeated
+  -f, --file FILE    evaluate the lines of FILE, like piped input
+  -k, --keep-going   evaluate the lines after an error in a file
+  -w, --watch FILE   evaluate FILE again whenever it changes
+  --bench N          evaluate the EXPR of -e N times and show how long it took
+  --csv FILE         add the value of the EXPR of -e for each row of FILE
+  --csv-error TEXT   the value of a row of --csv that fails, empty by default
+  -q, --quiet        show only the results of stdin, even on a terminal
+  -v, -vv            log how expressions are evaluated, or also each step
+  -i, --interactive  show the prompts of stdin, even if it isn't a terminal
+  --color=WHEN       color errors always, never or auto on a terminal
+  --json             show each result or error as a JSON object on stdout
+  --ast EXPR         show the parse tree of EXPR without evaluating it
+  --ast=json EXPR    show the parse tree of EXPR as JSON
+  --tokens EXPR      show the tokens of EXPR with their positions
+  --tokens=json EXPR show the tokens of EXPR as JSON
+  -h, --help         show this help";
+
+/// Exit codes of the calculator.
+pub const EXIT_OK: i32 = 0;
+/// The command line itself is wrong, like an unknown option.
+pub const EXIT_USAGE: i32 = 1;
+/// An expression can't be parsed.
+pub const EXIT_PARSE: i32 = 2;
+/// An expression can't be evaluated, like `sqrt(-1)`.
+pub const EXIT_MATH: i32 = 3;
+
+/// What `--ast` and `--tokens` show of an expression instead of its value.
+#[derive(Debug, PartialEq)]
+pub enum Dump {
+    Ast(String),
+    AstJson(String),
+    Tokens(String),
+    TokensJson(String),
+}
+
+/// What the command line asks for.
+#[derive(Debug, PartialEq, Default)]
+pub struct Options {
+    /// The expressions of `-e`, which are evaluated instead of starting the
+    /// REPL.
+    pub exprs: Vec<String>,
+    /// The names and expressions of `--var`, which are set in order before
+    /// anything else is evaluated.
+    pub vars: Vec<(String, String)>,
+    /// The config file of `--config`, which replaces the default one.
+    pub config: Option<String>,
+    /// Whether no config file is read, `--no-config`.
+    pub no_config: bool,
+    /// The session file of `--load`, which is loaded before `--var`.
+    pub load: Option<String>,
+    /// The file of `--file`, which is evaluated line by line.
+    pub file: Option<String>,
+    /// The file of `--watch`, which is evaluated whenever it changes.
+    pub watch: Option<String>,
+    /// The iterations of `--bench`, which times the only expression of `-e`
+    /// instead of showing its value.
+    pub bench: Option<usize>,
+    /// The CSV file of `--csv`, for each row of which the only expression of
+    /// `-e` is evaluated.
+    pub csv: Option<String>,
+    /// The result `--csv-error` shows for a row that fails.
+    pub csv_error: String,
+    /// Whether the lines after an error are evaluated, `--keep-going`.
+    pub keep_going: bool,
+    /// `--quiet` and `--interactive`, which override how stdin is read.
+    pub quiet: bool,
+    pub interactive: bool,
+    /// The colors of `--color=always` or `never`, `None` for `auto`.
+    pub color: Option<ColorChoice>,
+    /// Whether results and errors are JSON objects, `--json`.
+    pub json: bool,
+    /// The last `--ast` or `--tokens`, which is shown before anything is
+    /// evaluated.
+    pub dump: Option<Dump>,
+    /// How much `-v` and `-vv` log to stderr: 1 for each expression, 2 for
+    /// each token, binding and operation too.
+    pub verbosity: u8,
+    pub help: bool,
+}
+
+/// Writes the log records of the library to stderr.
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        eprintln!("[{} {}] {}", record.level(), record.target(), record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+/// Logs the debug records to stderr for a `verbosity` of 1, the trace ones
+/// too for 2 and more, and nothing for 0.
+pub fn install_logger(verbosity: u8) {
+    let level = match verbosity {
+        0 => return,
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// Reads the arguments after the program name.
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-e" | "--expr" => match args.next() {
+                Some(expr) => options.exprs.push(expr),
+                None => return Err(format!("Option {} expects an expression!", arg)),
+            },
+            "--var" => match args.next() {
+                Some(var) => options.vars.push(parse_var(&var)?),
+                None => return Err(format!("Option {} expects NAME=EXPR!", arg)),
+            },
+            "--config" => match args.next() {
+                Some(file) => options.config = Some(file),
+                None => return Err(format!("Option {} expects a file name!", arg)),
+            },
+            "--no-config" => options.no_config = true,
+            "--load" => match args.next() {
+                Some(file) => options.load = Some(file),
+                None => return Err(format!("Option {} expects a file name!", arg)),
+            },
+            "-f" | "--file" => match args.next() {
+                Some(file) => options.file = Some(file),
+                None => return Err(format!("Option {} expects a file name!", arg)),
+            },
+            "-w" | "--watch" => match args.next() {
+                Some(file) => options.watch = Some(file),
+                None => return Err(format!("Option {} expects a file name!", arg)),
+            },
+            "--bench" => match args.next().map(|n| n.parse::<usize>()) {
+                Some(Ok(n)) if n > 0 && n <= u32::MAX as usize => options.bench = Some(n),
+                Some(_) => {
+                    return Err(format!(
+                        "Option {} expects a number of iterations from 1 to {}!",
+                        arg,
+                        u32::MAX
+                    ))
+                }
+                None => return Err(format!("Option {} expects a number of iterations!", arg)),
+            },
+            "--csv" => match args.next() {
+                Some(file) => options.csv = Some(file),
+                None => return Err(format!("Option {} expects a file name!", arg)),
+            },
+            "--csv-error" => match args.next() {
+                Some(marker) => options.csv_error = marker,
+                None => return Err(format!("Option {} expects a text!", arg)),
+            },
+            "-k" | "--keep-going" => options.keep_going = true,
+            "-q" | "--quiet" => options.quiet = true,
+            "-i" | "--interactive" => options.interactive = true,
+            "--color=always" => options.color = Some(ColorChoice::Always),
+            "--color=never" => options.color = Some(ColorChoice::Never),
+            "--color=auto" => options.color = None,
+            "--json" => options.json = true,
+            "-v" => options.verbosity += 1,
+            "-vv" => options.verbosity += 2,
+            "--ast" | "--ast=text" | "--ast=json" | "--tokens" | "--tokens=text"
+            | "--tokens=json" => {
+                let expr = match args.next() {
+                    Some(expr) => expr,
+                    None => return Err(format!("Option {} expects an expression!", arg)),
+                };
+                options.dump = Some(match arg.as_str() {
+                    "--ast=json" => Dump::AstJson(expr),
+                    "--tokens=json" => Dump::TokensJson(expr),
+                    "--tokens" | "--tokens=text" => Dump::Tokens(expr),
+                    _ => Dump::Ast(expr),
+                });
+            }
+            "-h" | "--help" => options.help = true,
+            _ => return Err(format!("Unknown option {}!", arg)),
+        }
+    }
+    if options.quiet && options.interactive {
+        return Err(String::from(
+            "Options --quiet and --interactive exclude each other!",
+        ));
+    }
+    if options.config.is_some() && options.no_config {
+        return Err(String::from(
+            "Options --config and --no-config exclude each other!",
+        ));
+    }
+    if options.watch.is_some() && (options.file.is_some() || !options.exprs.is_empty()) {
+        return Err(String::from("Option --watch excludes --file and --expr!"));
+    }
+    if options.bench.is_some() && (options.exprs.len() != 1 || options.file.is_some()) {
+        return Err(String::from(
+            "Option --bench expects exactly one --expr and no --file!",
+        ));
+    }
+    if options.csv.is_some()
+        && (options.exprs.len() != 1
+            || options.file.is_some()
+            || options.watch.is_some()
+            || options.bench.is_some())
+    {
+        return Err(String::from(
+            "Option --csv expects exactly one --expr and no --file, --watch or --bench!",
+        ));
+    }
+    Ok(options)
+}
+
+/// Splits the value of `--var` into the name and the expression.
+fn parse_var(var: &str) -> Result<(String, String), String> {
+    let (name, expr) = match var.split_once('=') {
+        Some((name, expr)) => (name.trim(), expr.trim()),
+        None => return Err(format!("Option --var expects NAME=EXPR, but gets {}!", var)),
+    };
+    if !is_name(name) {
+        return Err(format!(
+            "Option --var {}: {} isn't a variable name!",
+            var, name
+        ));
+    }
+    Ok((name.to_string(), expr.to_string()))
+}
+
+/// How the lines of stdin are read and shown.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Mode {
+    /// Prompts, `<<<` before the results and errors on stdout, for a person
+    /// at a terminal.
+    Repl,
+    /// Only the results on stdout and the errors on stderr, like `run_batch`,
+    /// for pipes and scripts.
+    Batch { keep_going: bool },
+}
+
+/// Decides how stdin is read: as the REPL if both stdin and stdout are
+/// terminals, and in batch otherwise, so that piped output holds only the
+/// results. `--interactive` and `--quiet` override it, and `--json` is
+/// always batch. Lines typed at a terminal go on after an error.
+pub fn stdin_mode(options: &Options, stdin_tty: bool, stdout_tty: bool) -> Mode {
+    let repl = if options.json || options.quiet {
+        false
+    } else {
+        options.interactive || (stdin_tty && stdout_tty)
+    };
+    if repl {
+        Mode::Repl
+    } else {
+        Mode::Batch {
+            keep_going: options.keep_going || stdin_tty,
+        }
+    }
+}
+
+/// The exit code of an evaluation error, by its code.
+pub fn error_code(err: &Diagnostic) -> i32 {
+    match err.code {
+        ErrorCode::Parse => EXIT_PARSE,
+        ErrorCode::Eval => EXIT_MATH,
+    }
+}
+
+/// Writes what `expr` shows, `number` is its line in a batch. As text a value
+/// goes to `output` unless it's assigned, and an error goes to `errors` with
+/// a caret under its position. As JSON every statement or error is an object
+/// on its own line of `output`.
+fn report<W: Write, E: Write>(
+    expr: &str,
+    number: Option<usize>,
+    result: &Result<Shown, Diagnostic>,
+    session: &Session,
+    json: bool,
+    mut output: W,
+    mut errors: E,
+) {
+    // There is nothing left to do if the output is gone.
+    let _ = if json {
+        let mut object = Object::new();
+        if let Some(number) = number {
+            object = object.field("line", number.to_string());
+        }
+        object = object.field("expr", json::string(expr.trim()));
+        let object = match result {
+            Ok(Shown::Value(last)) => {
+                let object = object.field("ok", String::from("true"));
+                let object = match &last.name {
+                    Some(name) => object.field("name", json::string(name)),
+                    None => object,
+                };
+                object.field("value", json::value(&last.value))
+            }
+            Ok(Shown::Text(text)) => object
+                .field("ok", String::from("true"))
+                .field("output", json::string(text)),
+            Ok(Shown::Nothing) | Ok(Shown::Quit) => return,
+            Err(err) => {
+                let pos = err.pos.map_or(String::from("null"), |pos| pos.to_string());
+                let error = Object::new()
+                    .field("code", json::string(err.code.id()))
+                    .field("message", json::string(&err.message))
+                    .field("pos", pos);
+                object
+                    .field("ok", String::from("false"))
+                    .field("error", error.to_string())
+            }
+        };
+        writeln!(output, "{}", object)
+    } else {
+        match result {
+            Ok(Shown::Value(Evaluated { name: None, value })) => {
+                writeln!(
+                    output,
+                    "{}",
+                    format_value(value, session.ctx.format_options())
+                )
+            }
+            Ok(Shown::Text(text)) => writeln!(output, "{}", text),
+            Ok(_) => return,
+            Err(err) => match number {
+                Some(number) => writeln!(errors, "{}", line_error(number, expr, err, session)),
+                None => writeln!(errors, "{}", session.diagnostic(expr, err)),
+            },
+        }
+    };
+}
+
+/// Evaluates the expressions of `-e` in order in one session, so that
+/// `x = 3` and then `x * 2` prints 6. The first error stops the evaluation.
+/// Returns the exit code.
+pub fn run_exprs<W: Write, E: Write>(
+    options: &Options,
+    session: &mut Session,
+    mut output: W,
+    mut errors: E,
+) -> i32 {
+    for expr in &options.exprs {
+        let result = evaluate_line(expr, session);
+        report(
+            expr,
+            None,
+            &result,
+            session,
+            options.json,
+            &mut output,
+            &mut errors,
+        );
+        match result {
+            Ok(Shown::Quit) => break,
+            Err(err) => return error_code(&err),
+            Ok(_) => {}
+        }
+    }
+    EXIT_OK
+}
+
+/// Times the expression of `--bench`. The report goes to `errors`, and also
+/// to `output` as JSON with `--json`, while an error goes to `errors` with a
+/// caret under its position. Returns the exit code.
+pub fn run_bench<W: Write, E: Write>(
+    options: &Options,
+    session: &Session,
+    mut output: W,
+    mut errors: E,
+) -> i32 {
+    let (expr, iterations) = match (options.exprs.first(), options.bench) {
+        (Some(expr), Some(iterations)) => (expr, iterations),
+        _ => return EXIT_OK,
+    };
+    match bench(expr, iterations, &session.ctx) {
+        Ok(report) => {
+            let _ = writeln!(errors, "Benchmark of {}: {}", expr, report);
+            if options.json {
+                let object = Object::new()
+                    .field("expr", json::string(expr))
+                    .field("bench", report.json().to_string());
+                let _ = writeln!(output, "{}", object);
+            }
+            EXIT_OK
+        }
+        Err(err) => {
+            let _ = writeln!(errors, "{}", session.diagnostic(expr, &err));
+            error_code(&err)
+        }
+    }
+}
+
+/// Evaluates the expression of `-e` for each row of the file of `--csv` with
+/// `eval_csv`, and writes the rows with a result column to `output` and a
+/// warning for each row that fails to `errors`. Returns the exit code, the
+/// one of an evaluation error if a row failed.
+pub fn run_csv<W: Write, E: Write>(
+    options: &Options,
+    session: &Session,
+    output: W,
+    mut errors: E,
+) -> i32 {
+    let (path, formula) = match (&options.csv, options.exprs.first()) {
+        (Some(path), Some(formula)) => (path, formula),
+        _ => return EXIT_OK,
+    };
+    let csv = match fs::read_to_string(path) {
+        Ok(csv) => csv,
+        Err(err) => {
+            let _ = writeln!(errors, "Can't read the file {}! {}", path, err);
+            return EXIT_USAGE;
+        }
+    };
+    match eval_csv(
+        &csv,
+        formula,
+        &session.ctx,
+        &options.csv_error,
+        output,
+        &mut errors,
+    ) {
+        Ok(stats) if stats.failed > 0 => EXIT_MATH,
+        Ok(_) => EXIT_OK,
+        Err(err) if err.code == ErrorCode::Parse => {
+            let _ = writeln!(errors, "{}", session.diagnostic(formula, &err));
+            EXIT_PARSE
+        }
+        Err(err) => {
+            let _ = writeln!(errors, "File {}: {}", path, err);
+            EXIT_USAGE
+        }
+    }
+}
+
+/// Writes the tree or the tokens of `--ast` or `--tokens` to `output`, with
+/// the same rendering as `:ast` and `:tokens`. A parse error goes to
+/// `errors` with a caret under its position. Returns the exit code.
+pub fn run_dump<W: Write, E: Write>(
+    dump: &Dump,
+    session: &Session,
+    mut output: W,
+    mut errors: E,
+) -> i32 {
+    let ctx = &session.ctx;
+    let (expr, shown) = match dump {
+        Dump::Ast(expr) => (expr, parse_expr(expr, ctx).map(|root| tree_string(&root))),
+        Dump::AstJson(expr) => (expr, parse_expr(expr, ctx).map(|root| json::tree(&root))),
+        Dump::Tokens(expr) => (expr, token_list(expr, ctx)),
+        Dump::TokensJson(expr) => (expr, tokens_to_json(expr).map_err(Diagnostic::from)),
+    };
+    match shown {
+        Ok(shown) => {
+            let _ = writeln!(output, "{}", shown.trim_end());
+            EXIT_OK
+        }
+        Err(err) => {
+            let _ = writeln!(errors, "{}", session.diagnostic(expr, &err));
+            error_code(&err)
+        }
+    }
+}
+
+/// Reads the config file of `--config`, or the default one if it exists and
+/// there is no `--no-config`, and applies it to `session`. A line of the file
+/// that can't be read or evaluated goes to `errors` as a warning and the
+/// others still count, but a `--config` file that can't be read is an error.
+pub fn load_config<E: Write>(
+    options: &Options,
+    session: &mut Session,
+    mut errors: E,
+) -> Result<Config, i32> {
+    let path = match (&options.config, options.no_config) {
+        (Some(path), _) => path.clone(),
+        (None, false) => match config::default_path() {
+            Some(path) => path.to_string_lossy().into_owned(),
+            None => return Ok(Config::default()),
+        },
+        (None, true) => return Ok(Config::default()),
+    };
+    let (config, mut warnings) = match config::read(&path) {
+        Ok(Some(read)) => read,
+        Ok(None) if options.config.is_none() => return Ok(Config::default()),
+        Ok(None) => {
+            let _ = writeln!(errors, "Option --config {}: The file doesn't exist!", path);
+            return Err(EXIT_USAGE);
+        }
+        Err(err) if options.config.is_none() => {
+            let _ = writeln!(errors, "Warning! {}", err);
+            return Ok(Config::default());
+        }
+        Err(err) => {
+            let _ = writeln!(errors, "Option --config {}: {}", path, err);
+            return Err(EXIT_USAGE);
+        }
+    };
+    warnings.extend(config.apply(session));
+    for warning in warnings {
+        let _ = writeln!(errors, "Warning! Config file {}: {}", path, warning);
+    }
+    Ok(config)
+}
+
+/// The colors of `--color=always` or `never`, or else those of the config
+/// file, `None` for `auto`.
+pub fn chosen_color(options: &Options, config: &Config) -> Option<ColorChoice> {
+    options.color.or(config.color)
+}
+
+/// Loads the session file of `--load`. A failure goes to `errors` with the
+/// line it's at. Returns the exit code.
+pub fn load_session<E: Write>(path: &str, session: &mut Session, mut errors: E) -> i32 {
+    match session_file::load(path, session) {
+        Ok(_) => EXIT_OK,
+        Err(err) => {
+            let _ = writeln!(errors, "Option --load {}: {}", path, err);
+            if err.message.starts_with("Can't load") {
+                EXIT_USAGE
+            } else {
+                error_code(&err)
+            }
+        }
+    }
+}
+
+/// Sets the variables of `--var` in order, so a later one can use an earlier
+/// one. As in the REPL, constants, functions and reserved words can't be
+/// set, which exits like a name that isn't a variable name. The first error
+/// goes to `errors` with the flag it comes from, and points into the name or
+/// the expression. Returns the exit code.
+pub fn define_vars<E: Write>(
+    vars: &[(String, String)],
+    session: &mut Session,
+    mut errors: E,
+) -> i32 {
+    for (name, expr) in vars {
+        let assigned = match assignable(name, 0, &session.ctx) {
+            Ok(()) => assign(name, expr, session).map_err(|err| (expr, error_code(&err), err)),
+            Err(err) => {
+                let span = Span::between(0, name.chars().count());
+                Err((name, EXIT_USAGE, Diagnostic::eval(err, Some((0, span)))))
+            }
+        };
+        if let Err((source, code, err)) = assigned {
+            let _ = writeln!(
+                errors,
+                "Option --var {}={}:\n{}",
+                name,
+                expr,
+                session.diagnostic(source, &err)
+            );
+            return code;
+        }
+    }
+    EXIT_OK
+}
+
+/// Evaluates the lines of `input` in order in one session, like the REPL
+/// without its prompts: values are shown one per line, and blank lines and
+/// comments show nothing. An error is shown with the number of its line and
+/// stops the evaluation, unless `keep_going`. Results and errors are JSON
+/// objects on `output` if `json`. Returns the exit code of the first error.
+pub fn run_batch<R: BufRead, W: Write, E: Write>(
+    input: R,
+    session: &mut Session,
+    keep_going: bool,
+    json: bool,
+    mut output: W,
+    mut errors: E,
+) -> i32 {
+    let mut code = EXIT_OK;
+    let read = eval_lines(input, session, |number, line, result, session| {
+        report(
+            line,
+            Some(number),
+            result,
+            session,
+            json,
+            &mut output,
+            &mut errors,
+        );
+        match result {
+            Err(err) => {
+                if code == EXIT_OK {
+                    code = error_code(err);
+                }
+                keep_going
+            }
+            Ok(_) => true,
+        }
+    });
+    if let Err((number, err)) = read {
+        let _ = writeln!(errors, "Line {}: Input reading error! {}", number, err);
+        return EXIT_USAGE;
+    }
+    code
+}
+
+/// How often `watch` looks whether the file changed.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Results and errors written to one buffer, in the order they come.
+struct Shared<'a>(&'a RefCell<Vec<u8>>);
+
+impl Write for Shared<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// What a run of `--watch` shows for the lines of `text`: they're evaluated
+/// like `--file --keep-going` in a copy of `base`, so a run doesn't see the
+/// variables of the one before. The errors are among the results, right
+/// after the lines before them.
+pub fn watch_output(text: &str, base: &Session, json: bool) -> String {
+    let mut session = base.clone();
+    let shown = RefCell::new(Vec::new());
+    run_batch(
+        text.as_bytes(),
+        &mut session,
+        true,
+        json,
+        Shared(&shown),
+        Shared(&shown),
+    );
+    String::from_utf8_lossy(&shown.into_inner()).into_owned()
+}
+
+/// Evaluates the file `path` with `watch_output` now and whenever its
+/// modification time changes, until the calculator is ended. Each run clears
+/// the screen first if `clear`, otherwise a line with the file name
+/// separates it from the one before, except in JSON.
+pub fn watch<W: Write>(
+    path: &str,
+    base: &Session,
+    json: bool,
+    clear: bool,
+    mut output: W,
+) -> io::Result<()> {
+    let mut last = None;
+    let mut first = true;
+    loop {
+        let modified = fs::metadata(path).and_then(|meta| meta.modified()).ok();
+        if first || modified != last {
+            last = modified;
+            let shown = match fs::read_to_string(path) {
+                Ok(text) => watch_output(&text, base, json),
+                Err(err) => format!("Can't read the file {}! {}\n", path, err),
+            };
+            if clear {
+                write!(output, "\x1b[2J\x1b[H")?;
+            } else if !first && !json {
+                writeln!(output, "--- {} changed ---", path)?;
+            }
+            write!(output, "{}", shown)?;
+            output.flush()?;
+            first = false;
+        }
+        thread::sleep(WATCH_INTERVAL);
+    }
+}
+
+/// An error of the line `number` of a batch, with the line and a caret under
+/// the position the error names.
+fn line_error(number: usize, line: &str, err: &Diagnostic, session: &Session) -> String {
+    let shown = session.diagnostic(line, err);
+    match err.span {
+        Some(_) => format!("Line {}:\n{}", number, shown),
+        None => format!("Line {}:\n{}\n{}", number, line.trim_end(), shown),
+    }
+}
+
+#[cfg(test)]
+mod cli_tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Result<Options, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn do_run_test(exprs: &[&str], expect_code: i32, expect_output: &str, expect_errors: &str) {
+        println!("Expressions = {:?}", exprs);
+
+        let options = Options {
+            exprs: exprs.iter().map(|expr| expr.to_string()).collect(),
+            json: expect_output.starts_with('{'),
+            ..Options::default()
+        };
+        let (mut output, mut errors) = (Vec::new(), Vec::new());
+        let code = run_exprs(&options, &mut Session::default(), &mut output, &mut errors);
+        assert_eq!(code, expect_code);
+        assert_eq!(String::from_utf8(output).unwrap(), expect_output);
+        assert_eq!(String::from_utf8(errors).unwrap(), expect_errors);
+    }
+
+    #[test]
+    fn parse_args_test() {
+        assert_eq!(args(&[]), Ok(Options::default()));
+        assert_eq!(
+            args(&["-e", "1 + 2", "--expr", "-3"]).unwrap().exprs,
+            ["1 + 2", "-3"]
+        );
+        assert!(args(&["--help"]).unwrap().help);
+        let options = args(&["--csv", "data.csv", "--csv-error", "NA", "-e", "x"]).unwrap();
+        assert_eq!(
+            (options.csv.as_deref(), options.csv_error.as_str()),
+            (Some("data.csv"), "NA")
+        );
+        assert_eq!(
+            args(&["--csv", "data.csv"]),
+            Err(String::from(
+                "Option --csv expects exactly one --expr and no --file, --watch or --bench!"
+            ))
+        );
+        assert_eq!(args(&["-v"]).unwrap().verbosity, 1);
+        assert_eq!(args(&["-v", "-vv"]).unwrap().verbosity, 3);
+        assert_eq!(
+            args(&["-e"]),
+            Err(String::from("Option -e expects an expression!"))
+        );
+        assert_eq!(
+            args(&["--load", "work.calc"]).unwrap().load.as_deref(),
+            Some("work.calc")
+        );
+        let options = args(&["--file", "calc.txt", "-k"]).unwrap();
+        assert_eq!(options.file.as_deref(), Some("calc.txt"));
+        assert!(options.keep_going);
+        assert_eq!(
+            args(&["-f"]),
+            Err(String::from("Option -f expects a file name!"))
+        );
+        assert_eq!(
+            args(&["--var", "r=2.5", "--var", " x = 2^10 "])
+                .unwrap()
+                .vars,
+            [
+                (String::from("r"), String::from("2.5")),
+                (String::from("x"), String::from("2^10"))
+            ]
+        );
+        assert_eq!(
+            args(&["--var", "r"]),
+            Err(String::from("Option --var expects NAME=EXPR, but gets r!"))
+        );
+        assert_eq!(
+            args(&["--var", "2x=1"]),
+            Err(String::from("Option --var 2x=1: 2x isn't a variable name!"))
+        );
+        assert_eq!(
+            args(&["--var", "=1"]),
+            Err(String::from("Option --var =1:  isn't a variable name!"))
+        );
+        assert_eq!(
+            args(&["--ast", "1 + 2"]).unwrap().dump,
+            Some(Dump::Ast(String::from("1 + 2")))
+        );
+        assert_eq!(
+            args(&["--tokens", "1", "--ast=json", "2"]).unwrap().dump,
+            Some(Dump::AstJson(String::from("2")))
+        );
+        assert_eq!(
+            args(&["--tokens=json", "1"]).unwrap().dump,
+            Some(Dump::TokensJson(String::from("1")))
+        );
+        assert_eq!(
+            args(&["--tokens"]),
+            Err(String::from("Option --tokens expects an expression!"))
+        );
+        assert_eq!(
+            args(&["--ast=xml", "1"]),
+            Err(String::from("Unknown option --ast=xml!"))
+        );
+        assert_eq!(
+            args(&["--color=never"]).unwrap().color,
+            Some(ColorChoice::Never)
+        );
+        assert_eq!(
+            args(&["--color=always", "--color=auto"]).unwrap().color,
+            None
+        );
+        assert_eq!(
+            args(&["--color=red"]),
+            Err(String::from("Unknown option --color=red!"))
+        );
+        assert_eq!(
+            args(&["--watch", "calc.txt"]).unwrap().watch.as_deref(),
+            Some("calc.txt")
+        );
+        assert_eq!(
+            args(&["-w", "calc.txt", "-e", "1"]),
+            Err(String::from("Option --watch excludes --file and --expr!"))
+        );
+        assert_eq!(
+            args(&["--config", "calc.conf"]).unwrap().config.as_deref(),
+            Some("calc.conf")
+        );
+        assert!(args(&["--no-config"]).unwrap().no_config);
+        assert_eq!(
+            args(&["--config", "calc.conf", "--no-config"]),
+            Err(String::from(
+                "Options --config and --no-config exclude each other!"
+            ))
+        );
+        assert_eq!(
+            args(&["--bench", "100", "-e", "x"]).unwrap().bench,
+            Some(100)
+        );
+        assert_eq!(
+            args(&["--bench", "0", "-e", "x"]),
+            Err(String::from(
+                "Option --bench expects a number of iterations from 1 to 4294967295!"
+            ))
+        );
+        assert_eq!(
+            args(&["--bench", "100", "-e", "x", "-e", "y"]),
+            Err(String::from(
+                "Option --bench expects exactly one --expr and no --file!"
+            ))
+        );
+        assert_eq!(args(&["-x"]), Err(String::from("Unknown option -x!")));
+        assert_eq!(args(&["1 + 2"]), Err(String::from("Unknown option 1 + 2!")));
+    }
+
+    #[test]
+    fn run_exprs_test() {
+        do_run_test(&["2^10 - 24"], EXIT_OK, "1000\n", "");
+        do_run_test(&["x = 3", "x * 2"], EXIT_OK, "6\n", "");
+        do_run_test(&["1", "2; 3"], EXIT_OK, "1\n3\n", "");
+        do_run_test(
+            &["1", "1 +", "2"],
+            EXIT_PARSE,
+            "1\n",
+            "1 +\n  ^\nAst build error! Operator Plus at pos 2 expects an operand, but gets End!\n",
+        );
+        do_run_test(
+            &["sqrt(-1)"],
+            EXIT_MATH,
+            "",
+            "sqrt(-1)\n^^^^^^^^\nDomain error! sqrt(-1) is undefined!\n",
+        );
+    }
+
+    fn do_batch_test(input: &str, options: Options, expect: (i32, &str, &str)) {
+        println!("Input = {:?}", input);
+
+        let (mut output, mut errors) = (Vec::new(), Vec::new());
+        let code = run_batch(
+            input.as_bytes(),
+            &mut Session::default(),
+            options.keep_going,
+            options.json,
+            &mut output,
+            &mut errors,
+        );
+        assert_eq!(code, expect.0);
+        assert_eq!(String::from_utf8(output).unwrap(), expect.1);
+        assert_eq!(String::from_utf8(errors).unwrap(), expect.2);
+    }
+
+    #[test]
+    fn stdin_mode_test() {
+        let options = |flags: &[&str]| args(flags).unwrap();
+        let stop = Mode::Batch { keep_going: false };
+        let go_on = Mode::Batch { keep_going: true };
+        let cases = [
+            // Flags, stdin and stdout are terminals, mode.
+            (&[][..], true, true, Mode::Repl),
+            (&[], false, true, stop),
+            (&[], true, false, go_on),
+            (&[], false, false, stop),
+            (&["-k"], false, false, go_on),
+            (&["--interactive"], true, true, Mode::Repl),
+            (&["--interactive"], false, false, Mode::Repl),
+            (&["--quiet"], true, true, go_on),
+            (&["--quiet"], false, true, stop),
+            (&["--json"], true, true, go_on),
+            (&["--json", "-i"], false, false, stop),
+        ];
+        for (flags, stdin_tty, stdout_tty, mode) in cases.iter() {
+            println!("Flags = {:?}, {} {}", flags, stdin_tty, stdout_tty);
+            assert_eq!(stdin_mode(&options(flags), *stdin_tty, *stdout_tty), *mode);
+        }
+        assert_eq!(
+            args(&["-q", "-i"]),
+            Err(String::from(
+                "Options --quiet and --interactive exclude each other!"
+            ))
+        );
+    }
+
+    fn do_dump_test(dump: Dump, expect_code: i32, expect_output: &str, expect_errors: &str) {
+        println!("Dump = {:?}", dump);
+
+        let (mut output, mut errors) = (Vec::new(), Vec::new());
+        let code = run_dump(&dump, &Session::default(), &mut output, &mut errors);
+        assert_eq!(code, expect_code);
+        assert_eq!(String::from_utf8(output).unwrap(), expect_output);
+        assert_eq!(String::from_utf8(errors).unwrap(), expect_errors);
+    }
+
+    #[test]
+    fn run_dump_test() {
+        do_dump_test(
+            Dump::Ast(String::from("-x")),
+            EXIT_OK,
+            "{\n Token: PrefixMinus\n Left: \n {\n  Token: Variable(\"x\")\n }\n}\n",
+            "",
+        );
+        do_dump_test(
+            Dump::AstJson(String::from("2*x")),
+            EXIT_OK,
+            "{\"type\":\"binary\",\"pos\":1,\"op\":\"*\",\
+             \"left\":{\"type\":\"number\",\"pos\":0,\"value\":2.0},\
+             \"right\":{\"type\":\"variable\",\"pos\":2,\"name\":\"x\"}}\n",
+            "",
+        );
+        do_dump_test(
+            Dump::Tokens(String::from("2*3+4")),
+            EXIT_OK,
+            "  0: Number(2.0)\n  1: Multiplication\n  2: Number(3.0)\n  3: Plus\n  4: Number(4.0)\n",
+            "",
+        );
+        do_dump_test(
+            Dump::Ast(String::from("2*")),
+            EXIT_PARSE,
+            "",
+            "2*\n ^\nAst build error! Operator Multiplication at pos 1 expects an operand, but gets End!\n",
+        );
+        do_dump_test(
+            Dump::Tokens(String::from("0x + 1")),
+            EXIT_PARSE,
+            "",
+            "0x + 1\n^^\nAst build error! Number 0x at pos 0 is malformed!\n",
+        );
+        do_dump_test(
+            Dump::TokensJson(String::from("2*x")),
+            EXIT_OK,
+            "[{\"type\":\"number\",\"start\":0,\"end\":1,\"text\":\"2\",\"value\":2.0},\
+             {\"type\":\"multiply\",\"start\":1,\"end\":2,\"text\":\"*\"},\
+             {\"type\":\"name\",\"start\":2,\"end\":3,\"text\":\"x\"}]\n",
+            "",
+        );
+        // An expression that can't be evaluated can still be shown.
+        do_dump_test(
+            Dump::Tokens(String::from("w")),
+            EXIT_OK,
+            "  0: Str(\"w\")\n",
+            "",
+        );
+    }
+
+    #[test]
+    fn run_bench_test() {
+        let mut options = args(&["--bench", "5", "-e", "2^3"]).unwrap();
+        let (mut output, mut errors) = (Vec::new(), Vec::new());
+        let code = run_bench(&options, &Session::default(), &mut output, &mut errors);
+        assert_eq!(code, EXIT_OK);
+        assert!(output.is_empty());
+        let errors = String::from_utf8(errors).unwrap();
+        assert!(errors.starts_with("Benchmark of 2^3: 5 iterations (parse "));
+        assert!(errors.ends_with(", checksum 40)\n"));
+
+        options.json = true;
+        let mut output = Vec::new();
+        run_bench(&options, &Session::default(), &mut output, Vec::new());
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("{\"expr\":\"2^3\",\"bench\":{\"iterations\":5,"));
+
+        options.exprs = vec![String::from("sqrt(-1)")];
+        let mut errors = Vec::new();
+        let code = run_bench(&options, &Session::default(), Vec::new(), &mut errors);
+        assert_eq!(code, EXIT_MATH);
+        assert_eq!(
+            String::from_utf8(errors).unwrap(),
+            "sqrt(-1)\n^^^^^^^^\nDomain error! sqrt(-1) is undefined!\n"
+        );
+    }
+
+    fn do_vars_test(vars: &[(&str, &str)], expect_code: i32, expect_errors: &str) -> Session {
+        println!("Vars = {:?}", vars);
+
+        let vars: Vec<(String, String)> = vars
+            .iter()
+            .map(|(name, expr)| (name.to_string(), expr.to_string()))
+            .collect();
+        let mut session = Session::default();
+        let mut errors = Vec::new();
+        assert_eq!(define_vars(&vars, &mut session, &mut errors), expect_code);
+        assert_eq!(String::from_utf8(errors).unwrap(), expect_errors);
+        session
+    }
+
+    #[test]
+    fn define_vars_test() {
+        let mut session = do_vars_test(&[("r", "2.5"), ("d", "2 * r"), ("k", "3")], EXIT_OK, "");
+        assert_eq!(session.ctx.get_var("d"), Some(5.));
+        let mut output = Vec::new();
+        let options = Options {
+            exprs: vec![String::from("k * r^2")],
+            ..Options::default()
+        };
+        run_exprs(&options, &mut session, &mut output, Vec::new());
+        assert_eq!(String::from_utf8(output).unwrap(), "18.75\n");
+
+        do_vars_test(
+            &[("x", "1 +")],
+            EXIT_PARSE,
+            "Option --var x=1 +:\n1 +\n  ^\nAst build error! Operator Plus at pos 2 expects an operand, but gets End!\n",
+        );
+        do_vars_test(
+            &[("x", "1"), ("y", "w")],
+            EXIT_MATH,
+            "Option --var y=w:\nw\n^\nUnknown variable w!\n",
+        );
+        do_vars_test(
+            &[("pi", "3")],
+            EXIT_USAGE,
+            "Option --var pi=3:\npi\n^^\nCan't assign to pi at pos 0, it's a constant!\n",
+        );
+    }
+
+    fn fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    #[test]
+    fn load_config_test() {
+        // Defaults < file < flags.
+        let mut options = args(&["--config", &fixture("config"), "--var", "tau=6"]).unwrap();
+        let mut session = Session::default();
+        let mut errors = Vec::new();
+        let config = load_config(&options, &mut session, &mut errors).unwrap();
+        assert_eq!(String::from_utf8(errors).unwrap(), "");
+        assert_eq!(session.ctx.format_options().precision, Some(6));
+        assert_eq!(session.ctx.get_var("tau"), Some(2. * std::f64::consts::PI));
+        define_vars(&options.vars, &mut session, Vec::new());
+        assert_eq!(session.ctx.get_var("tau"), Some(6.));
+        assert_eq!(chosen_color(&options, &config), Some(ColorChoice::Never));
+        options.color = Some(ColorChoice::Always);
+        assert_eq!(chosen_color(&options, &config), Some(ColorChoice::Always));
+        assert_eq!(
+            chosen_color(&options, &Config::default()),
+            Some(ColorChoice::Always)
+        );
+        options.color = None;
+        assert_eq!(chosen_color(&options, &Config::default()), None);
+
+        let path = fixture("bad_config");
+        let options = args(&["--config", &path]).unwrap();
+        let mut session = Session::default();
+        let mut errors = Vec::new();
+        let config = load_config(&options, &mut session, &mut errors).unwrap();
+        assert_eq!(config.history_size, Some(5));
+        assert_eq!(session.ctx.get_var("half"), Some(0.5));
+        let errors = String::from_utf8(errors).unwrap();
+        assert_eq!(errors.lines().count(), 4);
+        assert!(errors.starts_with(&format!(
+            "Warning! Config file {}: Line 2: Invalid precision 0!\n",
+            path
+        )));
+
+        let options = args(&["--config", "no/such/config"]).unwrap();
+        let mut errors = Vec::new();
+        assert_eq!(
+            load_config(&options, &mut Session::default(), &mut errors),
+            Err(EXIT_USAGE)
+        );
+        assert_eq!(
+            String::from_utf8(errors).unwrap(),
+            "Option --config no/such/config: The file doesn't exist!\n"
+        );
+
+        let options = args(&["--no-config"]).unwrap();
+        assert_eq!(
+            load_config(&options, &mut Session::default(), Vec::new()),
+            Ok(Config::default())
+        );
+    }
+
+    #[test]
+    fn run_csv_test() {
+        let path = fixture("prices.csv");
+        let mut options = args(&["--csv", &path, "-e", "price * qty * (1 + tax)"]).unwrap();
+        let (mut output, mut errors) = (Vec::new(), Vec::new());
+        let code = run_csv(&options, &Session::default(), &mut output, &mut errors);
+        assert_eq!(code, EXIT_MATH);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "item,price,qty,tax,result\n\
+             pen,1.5,4,0.2,7.2\n\
+             \"paper, A4\",5,2,0.1,11\n\
+             ink,n/a,1,0.2,\n"
+        );
+        assert_eq!(
+            String::from_utf8(errors).unwrap(),
+            "Row 3: Unknown variable price!\n"
+        );
+
+        options.exprs = vec![String::from("qty +")];
+        let mut errors = Vec::new();
+        let code = run_csv(&options, &Session::default(), Vec::new(), &mut errors);
+        assert_eq!(code, EXIT_PARSE);
+        assert!(String::from_utf8(errors).unwrap().contains("expects an operand"));
+
+        options.csv = Some(String::from("no/such.csv"));
+        let code = run_csv(&options, &Session::default(), Vec::new(), Vec::new());
+        assert_eq!(code, EXIT_USAGE);
+    }
+
+    #[test]
+    fn run_batch_test() {
+        let input = "r = 2\n\n# the area\npi * r^2 / pi\n1 +\nw * 2\nr + 1\n";
+        do_batch_test(
+            input,
+            Options::default(),
+            (
+                EXIT_PARSE,
+                "4\n",
+                "Line 5:\n1 +\n  ^\nAst build error! Operator Plus at pos 2 expects an operand, but gets End!\n",
+            ),
+        );
+        do_batch_test(
+            input,
+            Options {
+                keep_going: true,
+                ..Options::default()
+            },
+            (
+                EXIT_PARSE,
+                "4\n3\n",
+                "Line 5:\n1 +\n  ^\nAst build error! Operator Plus at pos 2 expects an operand, but gets End!\n\
+                 Line 6:\nw * 2\n^\nUnknown variable w!\n",
+            ),
+        );
+        let options = Options::default;
+        do_batch_test("1\n2; 3\n:quit\n4\n", options(), (EXIT_OK, "1\n3\n", ""));
+        do_batch_test(
+            ":vars\nx = 1\n:vars\n",
+            options(),
+            (EXIT_OK, "No variables are defined.\nx = 1\n", ""),
+        );
+        do_batch_test("", options(), (EXIT_OK, "", ""));
+    }
+
+    #[test]
+    fn watch_output_test() {
+        let mut base = Session::default();
+        base.ctx.set_var("rate", 0.5);
+        let text = "x = 10\nx * rate\n1 +\nx + y\nx / 4\n";
+        let shown = "5\n\
+                     Line 3:\n1 +\n  ^\nAst build error! Operator Plus at pos 2 expects an operand, but gets End!\n\
+                     Line 4:\nx + y\n    ^\nUnknown variable y!\n\
+                     2.5\n";
+        assert_eq!(watch_output(text, &base, false), shown);
+        // Every run starts from the same session.
+        assert_eq!(watch_output(text, &base, false), shown);
+        assert_eq!(base.ctx.get_var("x"), None);
+
+        assert_eq!(
+            watch_output("y = 2\nrate * y\n", &base, true),
+            "{\"line\":1,\"expr\":\"y = 2\",\"ok\":true,\"name\":\"y\",\"value\":2}\n\
+             {\"line\":2,\"expr\":\"rate * y\",\"ok\":true,\"value\":1.0}\n"
+        );
+    }
+
+    #[test]
+    fn json_test() {
+        do_run_test(
+            &["1/3", "x = 2", "x < 3"],
+            EXIT_OK,
+            "{\"expr\":\"1/3\",\"ok\":true,\"value\":0.3333333333333333}\n\
+             {\"expr\":\"x = 2\",\"ok\":true,\"name\":\"x\",\"value\":2}\n\
+             {\"expr\":\"x < 3\",\"ok\":true,\"value\":true}\n",
+            "",
+        );
+        do_run_test(
+            &["1 +"],
+            EXIT_PARSE,
+            "{\"expr\":\"1 +\",\"ok\":false,\"error\":{\"code\":\"E001\",\
+             \"message\":\"Ast build error! Operator Plus at pos 2 expects an operand, but gets End!\",\
+             \"pos\":2}}\n",
+            "",
+        );
+        // The position is the one of the error, not the statement's.
+        do_run_test(
+            &["1; 2 + w"],
+            EXIT_MATH,
+            "{\"expr\":\"1; 2 + w\",\"ok\":false,\"error\":{\"code\":\"E002\",\
+             \"message\":\"Statement 2 at pos 3: Unknown variable w!\",\"pos\":7}}\n",
+            "",
+        );
+
+        let options = Options {
+            json: true,
+            keep_going: true,
+            ..Options::default()
+        };
+        do_batch_test(
+            "# values\n2^10\n\nsqrt(-1)\n:vars\n",
+            options,
+            (
+                EXIT_MATH,
+                "{\"line\":2,\"expr\":\"2^10\",\"ok\":true,\"value\":1024}\n\
+                 {\"line\":4,\"expr\":\"sqrt(-1)\",\"ok\":false,\"error\":{\"code\":\"E002\",\
+                 \"message\":\"Domain error! sqrt(-1) is undefined!\",\"pos\":0}}\n\
+                 {\"line\":5,\"expr\":\":vars\",\"ok\":true,\"output\":\"No variables are defined.\"}\n",
+                "",
+            ),
+        );
+    }
+}
@@ -0,0 +1,1535 @@
+use super::ast::{is_reserved, parse_expr, tree_string};
+use super::builtins::find_constant;
+use super::completion::complete;
+use super::context::Context;
+use super::error::{render_diagnostic, ColorChoice, Diagnostic, Span};
+use super::evaluator::{evaluate_timed, evaluate_with};
+use super::format::{format_value, Base, Notation};
+use super::plot::plot;
+use super::prompt::{check_prompt, render_prompt, DEFAULT_PROMPT};
+use super::recall::{parse_edit, recall, substitute};
+use super::scanner::{is_name, ScanError, Scanner, TokenType};
+use super::session_file;
+use super::special_forms::find_special_form;
+use super::table::{render, tabulate};
+use super::timing::EvalReport;
+use super::value::Value;
+
+/// The commands listed by `:help`, and the operators and functions that
+/// are easily mixed up.
+pub const HELP: &str = "\
+:quit, :q, quit, exit          leave the calculator
+name = expr                    store the value of expr in a variable
+:help                          show this list
+:ast [expr]                    show the tree of expr, or of the last expression
+:explain [expr]                show expr with parentheses around every operator
+:tokens [expr]                 show the tokens of expr and their positions
+:trace [expr]                  evaluate expr one operation at a time
+:last                          show the last expression
+:sub /old/new/ [g] [$n]        evaluate the last expression, or that of $n, edited
+:complete text                 list the completions of the last word of text
+:vars                          list the variables and their values
+:hist                          list the results, which $1, $2, ... refer to
+:hist size N                   keep the last N results
+:table x = a..b : expr         list expr for x from a to b, `step s` after b
+:table rows N                  let tables have at most N rows
+:plot expr, x = a..b           plot expr for x from a to b
+:plot size WxH                 let plots be W chars wide and H high
+:time [expr]                   show how long results take, or how long expr takes
+:prompt [template | reset]     show or set the prompt, {modes} and {base} show settings
+:del name                      remove a variable
+:undo, :redo                   revert the last change of a variable, or apply it again
+:reset                         remove every variable
+:save file                     save the variables to a file
+:load file                     set the variables saved in a file
+:precision N | full            display N significant digits or the exact float
+:notation normal | scientific | engineering
+:base dec | bin | oct | hex    display integers in another base
+:width N | none                two's complement width of other bases
+:grouping comma | underscore | space | none
+:decimal comma | point         the decimal separator of the input
+:mode float | int | rational   evaluate with floats, exact integers or fractions
+:complex on | off              let results be complex numbers, with i
+:case sensitive | insensitive  whether function and constant names have a case
+:si on | off                   read numbers like 4.7k with an SI prefix
+
+a % b, rem(a, b)               remainder with the sign of a, -7 % 3 is -1
+mod(a, b), a mod b             modulus that is never negative, mod(-7, 3) is 2
+div(a, b), a div b             quotient that goes with mod, div(-7, 3) is -3";
+
+/// The command names, which `:` and a prefix complete to.
+pub const COMMANDS: &[&str] = &[
+    "ast",
+    "base",
+    "case",
+    "complete",
+    "complex",
+    "decimal",
+    "del",
+    "exit",
+    "explain",
+    "grouping",
+    "help",
+    "hist",
+    "last",
+    "load",
+    "mode",
+    "notation",
+    "plot",
+    "precision",
+    "prompt",
+    "q",
+    "quit",
+    "redo",
+    "reset",
+    "save",
+    "si",
+    "sub",
+    "table",
+    "time",
+    "tokens",
+    "trace",
+    "undo",
+    "vars",
+    "width",
+];
+
+/// A line of REPL input.
+#[derive(Debug, PartialEq)]
+pub enum Line<'a> {
+    /// A command, without its ':'.
+    Command(&'a str),
+    /// `name = expr`, the variable name and the expression.
+    Assignment(&'a str, &'a str),
+    Expression(&'a str),
+}
+
+/// What the REPL does after a command.
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Continue,
+    Quit,
+    Print(String),
+}
+
+/// The state of a REPL session.
+#[derive(Debug, Default, Clone)]
+pub struct Session {
+    pub ctx: Context,
+    /// The last expression that was evaluated, which `:ast` shows by default.
+    pub last_expr: Option<String>,
+    /// Whether errors are shown in color.
+    pub color: ColorChoice,
+    /// Whether results are shown with how long they took, see `:time`.
+    pub time: bool,
+    /// How long the last evaluated expression took.
+    pub last_report: Option<EvalReport>,
+    /// The template of the prompt set with `:prompt`, `None` for the default.
+    pub prompt: Option<String>,
+}
+
+impl Session {
+    /// An error of `source` with carets under what it points at, in the
+    /// colors of the session.
+    pub fn diagnostic(&self, source: &str, err: &Diagnostic) -> String {
+        render_diagnostic(source, err, self.color)
+    }
+
+    /// The prompt of the REPL with the current settings.
+    pub fn prompt(&self) -> String {
+        let template = self.prompt.as_deref().unwrap_or(DEFAULT_PROMPT);
+        render_prompt(template, &self.ctx)
+    }
+}
+
+/// Tells commands from expressions: a line starting with ':' or that is only
+/// `quit` or `exit` is a command, so `quotient = 5` is an assignment rather
+/// than `:quit`. A variable name followed by a single '=' is an assignment.
+pub fn parse_line(line: &str) -> Line<'_> {
+    let line = line.trim();
+    match line {
+        "quit" | "exit" => Line::Command(line),
+        _ => match (line.strip_prefix(':'), line.split_once('=')) {
+            (Some(command), _) => Line::Command(command.trim()),
+            (None, Some((name, expr))) if is_name(name.trim()) && !expr.starts_with('=') => {
+                Line::Assignment(name.trim(), expr.trim())
+            }
+            (None, _) => Line::Expression(line),
+        },
+    }
+}
+
+/// The part of a line before its `#` comment.
+fn without_comment(line: &str) -> &str {
+    line.split('#').next().unwrap_or("")
+}
+
+/// Whether `input` is a whole expression. It isn't if it ends in a '\', a
+/// binary operator or a comma, or has an unclosed parenthesis, bracket or
+/// '|', and then the REPL keeps reading lines. A '|' both opens and closes,
+/// so an odd number of them leaves one unclosed.
+pub fn is_complete(input: &str, ctx: &Context) -> bool {
+    let code = without_comment(input).to_string();
+    if code.trim_end().ends_with('\\') {
+        return false;
+    }
+    let mut scanner = ctx.scanner(&code);
+    scanner.scan();
+
+    let tokens = scanner.get_tokens();
+    let mut depth = 0;
+    let mut bars = 0;
+    for token in tokens.iter() {
+        match token.t {
+            TokenType::Lparen => depth += 1,
+            TokenType::Rparen => depth -= 1,
+            TokenType::Bar => bars += 1,
+            _ => (),
+        }
+    }
+    let last = tokens.iter().rev().find(|token| token.t != TokenType::End);
+    let dangling = matches!(
+        last.map(|token| token.t),
+        Some(
+            TokenType::Plus
+                | TokenType::Minus
+                | TokenType::Multiplication
+                | TokenType::Division
+                | TokenType::FloorDivision
+                | TokenType::Power
+                | TokenType::Comma
+                | TokenType::Equals
+                | TokenType::EqualEqual
+                | TokenType::NotEqual
+                | TokenType::Less
+                | TokenType::LessEqual
+                | TokenType::Greater
+                | TokenType::GreaterEqual
+                | TokenType::Arrow
+                | TokenType::Root
+                | TokenType::Str("and" | "or" | "not" | "xor" | "mod" | "div")
+        )
+    );
+    depth <= 0 && bars % 2 == 0 && !dangling
+}
+
+/// Appends a continuation line to the input read so far, dropping the
+/// comment and the '\' of the input.
+pub fn join_lines(input: &str, line: &str) -> String {
+    let code = without_comment(input).trim_end();
+    let code = code.strip_suffix('\\').unwrap_or(code);
+    format!("{} {}", code.trim_end(), line.trim())
+}
+
+/// Checks that an assignment can set the variable `name`, which starts at
+/// `pos`: reserved words like `mod`, constants like `pi` and `true` and the
+/// names of functions can't be assigned to.
+pub fn assignable(name: &str, pos: usize, ctx: &Context) -> Result<(), String> {
+    let builtin_name = ctx.builtin_name(name);
+    let kind = if is_reserved(TokenType::Str(&builtin_name)) {
+        "a reserved word"
+    } else if find_constant(&builtin_name).is_some() {
+        "a constant"
+    } else if !ctx.overloads(name).is_empty() || find_special_form(&builtin_name).is_some() {
+        "a function"
+    } else {
+        return Ok(());
+    };
+    Err(format!("Can't assign to {} at pos {}, it's {}!", name, pos, kind))
+}
+
+/// Evaluates `expr` and stores its value in the variable `name`, which has
+/// to be `assignable`. Variables are real numbers, so a complex value can't
+/// be assigned.
+pub fn assign(name: &str, expr: &str, session: &mut Session) -> Result<Value, Diagnostic> {
+    assignable(name, 0, &session.ctx)?;
+    let (value, report) = evaluate_timed(&expr.to_string(), &session.ctx);
+    session.last_report = Some(report);
+    let value = value?;
+    if let Value::Complex(z) = value {
+        return Err(Diagnostic::from(format!("{} isn't a real number!", z)));
+    }
+    session.ctx.define_var(name, value.as_f64());
+    session.last_expr = Some(expr.to_string());
+    Ok(value)
+}
+
+/// What a statement evaluates to, with the variable an assignment sets.
+#[derive(Debug, PartialEq)]
+pub struct Evaluated {
+    pub name: Option<String>,
+    pub value: Value,
+}
+
+/// The value of a statement as it's shown, with the variable an assignment
+/// sets: `x = 3`.
+pub fn show_evaluated(evaluated: &Evaluated, ctx: &Context) -> String {
+    let value = format_value(&evaluated.value, ctx.format_options());
+    match &evaluated.name {
+        Some(name) => format!("{} = {}", name, value),
+        None => value,
+    }
+}
+
+/// Char position at which `part`, a slice of `line`, starts in it.
+fn char_offset(line: &str, part: &str) -> usize {
+    let bytes = part.as_ptr() as usize - line.as_ptr() as usize;
+    line[..bytes].chars().count()
+}
+
+/// The statements of a line, which are separated by a ';' outside of
+/// parentheses. Empty statements, like the one of `;;`, are left out.
+fn statements<'l>(line: &'l str, ctx: &Context) -> Vec<&'l str> {
+    let code = line.to_string();
+    let mut scanner = ctx.scanner(&code);
+    scanner.scan();
+
+    let offsets: Vec<usize> = line.char_indices().map(|(i, _)| i).collect();
+    let mut depth = 0;
+    let mut start = 0;
+    let mut statements = Vec::new();
+    for token in scanner.get_tokens() {
+        match token.t {
+            TokenType::Lparen => depth += 1,
+            TokenType::Rparen => depth -= 1,
+            TokenType::Semicolon if depth <= 0 => {
+                let end = offsets[token.pos];
+                statements.push(&line[start..end]);
+                start = end + 1;
+            }
+            _ => (),
+        }
+    }
+    statements.push(&line[start..]);
+    statements.retain(|statement| {
+        let statement = statement.to_string();
+        let mut scanner = Scanner::new(&statement);
+        scanner.scan();
+        !scanner.is_empty()
+    });
+    statements
+}
+
+/// Evaluates the statements of `line` in order, so that `a = 3; b = 4;
+/// sqrt(a^2 + b^2)` is 3, 4 and 5. The first error ends the line, and names
+/// the statement by its number and position when there are several. The
+/// statements before it are evaluated, and their assignments kept.
+pub fn run_line(line: &str, session: &mut Session) -> Result<Vec<Evaluated>, Diagnostic> {
+    let statements = statements(line, &session.ctx);
+    let mut results = Vec::new();
+    for (index, statement) in statements.iter().enumerate() {
+        let (name, expr) = match parse_line(statement) {
+            Line::Assignment(name, expr) => (Some(name), expr),
+            _ => (None, statement.trim()),
+        };
+        // Errors of the name are at its position, the others at the one of
+        // the expression.
+        let mut offset = char_offset(line, expr);
+        let value = match name {
+            Some(name) => match assignable(name, 0, &session.ctx) {
+                Ok(()) => assign(name, expr, session),
+                Err(err) => {
+                    offset = char_offset(line, name);
+                    let span = Span::between(0, name.chars().count());
+                    Err(Diagnostic::eval(err, Some((0, span))))
+                }
+            },
+            None => {
+                session.last_expr = Some(expr.to_string());
+                let (value, report) = evaluate_timed(&expr.to_string(), &session.ctx);
+                session.last_report = Some(report);
+                if let Ok(value) = &value {
+                    session.ctx.history_mut().push(expr, value.clone());
+                }
+                value
+            }
+        };
+        match value {
+            Ok(value) => results.push(Evaluated {
+                name: name.map(String::from),
+                value,
+            }),
+            Err(err) => {
+                let mut err = err.shifted(offset);
+                if statements.len() > 1 {
+                    err.message = format!(
+                        "Statement {} at pos {}: {}",
+                        index + 1,
+                        char_offset(line, statement.trim_start()),
+                        err.message
+                    );
+                }
+                return Err(err);
+            }
+        }
+    }
+    Ok(results)
+}
+
+/// The expression a command is about: its argument or, without one, the
+/// last evaluated expression.
+fn command_expr(session: &Session, arg: &str) -> Result<String, String> {
+    match (arg, &session.last_expr) {
+        ("", Some(expr)) => Ok(expr.clone()),
+        ("", None) => Err(String::from("No expression was evaluated yet!")),
+        (arg, _) => Ok(arg.to_string()),
+    }
+}
+
+/// `expr` with every operator in parentheses, or the parse error with a
+/// caret under its position.
+fn show_explain(expr: &str, session: &Session) -> String {
+    match parse_expr(expr, &session.ctx) {
+        Ok(Some(root)) => root.explain(),
+        Ok(None) => String::new(),
+        Err(err) => session.diagnostic(expr, &err),
+    }
+}
+
+/// The table of `x = 0..10 step 0.5 : x^2 - 3*x`, whose range is made of
+/// expressions. The step is 1 if it's left out.
+fn show_table(spec: &str, ctx: &Context) -> Result<String, String> {
+    let syntax = || {
+        format!(
+            "Invalid table {}, write it like x = 0..10 step 0.5 : x^2!",
+            spec
+        )
+    };
+    let (range, expr) = spec.split_once(':').ok_or_else(syntax)?;
+    let expr = expr.trim();
+    let (range, step) = match range.split_once(" step ") {
+        Some((range, step)) => (range, evaluate_with(&step.trim().to_string(), ctx)?),
+        None => (range, 1.),
+    };
+    let (var, from, to) = parse_range(range, ctx)?.ok_or_else(syntax)?;
+    let table = tabulate(expr, var, from, to, step, ctx).map_err(|err| err.to_string())?;
+    Ok(render(var, expr, &table, ctx))
+}
+
+/// The plot of `sin(x) * x, x = -10..10` in the size of `ctx`.
+fn show_plot(spec: &str, ctx: &Context) -> Result<String, String> {
+    let syntax = || format!("Invalid plot {}, write it like sin(x), x = -10..10!", spec);
+    // Arguments of functions have commas too.
+    let (expr, range) = spec.rsplit_once(',').ok_or_else(syntax)?;
+    let (var, from, to) = parse_range(range, ctx)?.ok_or_else(syntax)?;
+    let rows =
+        plot(expr.trim(), var, from, to, ctx.plot_size(), ctx).map_err(|err| err.to_string())?;
+    Ok(rows.join("\n"))
+}
+
+/// The variable of a range like `x = 0..2 * pi` and its bounds, which are
+/// expressions. `None` if `spec` isn't written like a range.
+fn parse_range<'s>(spec: &'s str, ctx: &Context) -> Result<Option<(&'s str, f64, f64)>, String> {
+    let (var, range) = match spec.split_once('=') {
+        Some((var, range)) => (var.trim(), range),
+        None => return Ok(None),
+    };
+    let (from, to) = match range.split_once("..") {
+        Some(bounds) => bounds,
+        None => return Ok(None),
+    };
+    if !is_name(var) {
+        return Err(format!("{} isn't a variable name!", var));
+    }
+    let number = |expr: &str| evaluate_with(&expr.trim().to_string(), ctx);
+    Ok(Some((var, number(from)?, number(to)?)))
+}
+
+/// The steps of evaluating `expr`, one per line, each after an arrow.
+fn show_trace(expr: &str, ctx: &Context) -> Result<String, String> {
+    let root = parse_expr(expr, ctx)?.ok_or_else(|| String::from("Empty expression!"))?;
+    let steps: Vec<String> = root
+        .trace(ctx)?
+        .iter()
+        .enumerate()
+        .map(|(i, step)| match i {
+            0 => format!("  {}", step),
+            _ => format!("→ {}", step),
+        })
+        .collect();
+    Ok(steps.join("\n"))
+}
+
+/// The parse tree of `expr`, or the parse error with a caret under its
+/// position.
+fn show_ast(expr: &str, session: &Session) -> String {
+    match parse_expr(expr, &session.ctx) {
+        Ok(root) => tree_string(&root).trim_end().to_string(),
+        Err(err) => session.diagnostic(expr, &err),
+    }
+}
+
+/// The tokens of `expr`, one per line with its position. A number or a
+/// separator the parser would reject is an error.
+pub fn token_list(expr: &str, ctx: &Context) -> Result<String, Diagnostic> {
+    let expr = expr.to_string();
+    let mut scanner = ctx.scanner(&expr);
+    scanner.scan();
+    let mut tokens = Vec::new();
+    for token in scanner.get_tokens() {
+        if let Some(err) = ScanError::of(token) {
+            return Err(Diagnostic::from(err));
+        }
+        if token.t != TokenType::End {
+            tokens.push(format!("{:>3}: {:?}", token.pos, token.t));
+        }
+    }
+    Ok(tokens.join("\n"))
+}
+
+/// The tokens of `expr`, or the error with a caret under its position.
+fn show_tokens(expr: &str, session: &Session) -> String {
+    token_list(expr, &session.ctx).unwrap_or_else(|err| session.diagnostic(expr, &err))
+}
+
+/// The completions of the word at the end of `text`, separated by spaces.
+fn show_completions(text: &str, ctx: &Context) -> String {
+    let completion = complete(text, text.len(), ctx);
+    if completion.candidates.is_empty() {
+        String::from("No completions.")
+    } else {
+        completion.candidates.join(" ")
+    }
+}
+
+/// The variables with their values, one per line with the `=` aligned.
+fn show_vars(ctx: &Context) -> String {
+    let vars = ctx.vars();
+    if vars.is_empty() {
+        return String::from("No variables are defined.");
+    }
+    let width = vars
+        .iter()
+        .map(|(name, _)| name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let lines: Vec<String> = vars
+        .iter()
+        .map(|(name, value)| {
+            let value = format_value(&Value::from_f64(*value), ctx.format_options());
+            format!("{:w$} = {}", name, value, w = width)
+        })
+        .collect();
+    lines.join("\n")
+}
+
+fn show_history(ctx: &Context) -> String {
+    let history = ctx.history();
+    let lines: Vec<String> = history
+        .entries()
+        .map(|(n, expr, value)| {
+            let value = format_value(value, ctx.format_options());
+            format!("${}: {} = {}", n, expr, value)
+        })
+        .collect();
+    if lines.is_empty() {
+        String::from("The history is empty.")
+    } else {
+        lines.join("\n")
+    }
+}
+
+/// The value of the variable `name` after `:undo` or `:redo`.
+fn show_var_state(name: &str, ctx: &Context) -> String {
+    match ctx.get_var(name) {
+        Some(value) => format!(
+            "{} = {}",
+            name,
+            format_value(&Value::from_f64(value), ctx.format_options())
+        ),
+        None => format!("{} isn't defined.", name),
+    }
+}
+
+fn delete_var(name: &str, ctx: &mut Context) -> Result<(), String> {
+    if ctx.undefine_var(name).is_some() {
+        Ok(())
+    } else if find_constant(&ctx.builtin_name(name)).is_some() {
+        Err(format!("Constant {} can't be deleted!", name))
+    } else {
+        Err(format!("Variable {} isn't defined!", name))
+    }
+}
+
+/// Shows the prompt template with no `arg`, or sets it to `arg`, which can
+/// be quoted to keep spaces at its ends. `reset` brings back the default.
+fn set_prompt(arg: &str, session: &mut Session) -> Result<Outcome, String> {
+    let template = match arg {
+        "" => {
+            let template = session.prompt.as_deref().unwrap_or(DEFAULT_PROMPT);
+            return Ok(Outcome::Print(format!("The prompt is {:?}.", template)));
+        }
+        "reset" => None,
+        _ => {
+            let quoted = arg.len() >= 2 && arg.starts_with('"') && arg.ends_with('"');
+            let template = if quoted { &arg[1..arg.len() - 1] } else { arg };
+            check_prompt(template)?;
+            Some(template.to_string())
+        }
+    };
+    session.prompt = template;
+    Ok(Outcome::Continue)
+}
+
+/// Runs a command, changing the display settings of the session.
+pub fn run_command(command: &str, session: &mut Session) -> Result<Outcome, String> {
+    let (name, arg) = match command.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, arg.trim()),
+        None => (command, ""),
+    };
+    match name {
+        "ast" => {
+            let expr = command_expr(session, arg)?;
+            return Ok(Outcome::Print(show_ast(&expr, session)));
+        }
+        "explain" => {
+            let expr = command_expr(session, arg)?;
+            return Ok(Outcome::Print(show_explain(&expr, session)));
+        }
+        "tokens" => {
+            let expr = command_expr(session, arg)?;
+            return Ok(Outcome::Print(show_tokens(&expr, session)));
+        }
+        "trace" => {
+            let expr = command_expr(session, arg)?;
+            return Ok(Outcome::Print(show_trace(&expr, &session.ctx)?));
+        }
+        "table" if arg.contains('=') => return Ok(Outcome::Print(show_table(arg, &session.ctx)?)),
+        "plot" if arg.contains('=') => return Ok(Outcome::Print(show_plot(arg, &session.ctx)?)),
+        "complete" => return Ok(Outcome::Print(show_completions(arg, &session.ctx))),
+        // A file name can have spaces.
+        "save" if !arg.is_empty() => {
+            session_file::save(arg, &session.ctx)?;
+            let count = session.ctx.vars().len();
+            return Ok(Outcome::Print(format!(
+                "Saved {} variables to {}.",
+                count, arg
+            )));
+        }
+        "time" if !arg.is_empty() => {
+            let last = run_line(arg, session)?
+                .pop()
+                .ok_or_else(|| String::from("There is no expression to time!"))?;
+            let report = session.last_report.unwrap_or_default();
+            let value = show_evaluated(&last, &session.ctx);
+            return Ok(Outcome::Print(format!("<<< {} {}", value, report)));
+        }
+        "prompt" => return set_prompt(arg, session),
+        "last" if arg.is_empty() => {
+            let expr = command_expr(session, arg)?;
+            return Ok(Outcome::Print(expr));
+        }
+        "sub" => {
+            let edit = parse_edit(arg)?;
+            let last = session.last_expr.as_deref();
+            let expr = substitute(recall(&edit, last, session.ctx.history())?, &edit)?;
+            let shown = match run_line(&expr, session)?.pop() {
+                Some(last) => format!("{}\n<<< {}", expr, show_evaluated(&last, &session.ctx)),
+                None => expr,
+            };
+            return Ok(Outcome::Print(shown));
+        }
+        "load" if !arg.is_empty() => {
+            let count = session_file::load(arg, session)?;
+            return Ok(Outcome::Print(format!(
+                "Loaded {} variables from {}.",
+                count, arg
+            )));
+        }
+        _ => {}
+    }
+
+    let ctx = &mut session.ctx;
+    let words: Vec<&str> = command.split_whitespace().collect();
+    match words.as_slice() {
+        ["quit"] | ["q"] | ["exit"] => return Ok(Outcome::Quit),
+        ["help"] => return Ok(Outcome::Print(HELP.to_string())),
+        ["vars"] => return Ok(Outcome::Print(show_vars(ctx))),
+        ["time"] => {
+            session.time = !session.time;
+            let state = if session.time { "on" } else { "off" };
+            return Ok(Outcome::Print(format!("Timing is {}.", state)));
+        }
+        ["table", "rows", rows] => match rows.parse::<usize>() {
+            Ok(rows) if rows > 0 => ctx.set_table_rows(rows),
+            _ => return Err(format!("Invalid number of rows {}!", rows)),
+        },
+        ["plot", "size", size] => match size.split_once('x').map(|(w, h)| (w.parse(), h.parse())) {
+            Some((Ok(width), Ok(height)))
+                if (2..=1000).contains(&width) && (2..=1000).contains(&height) =>
+            {
+                ctx.set_plot_size(width, height)
+            }
+            _ => return Err(format!("Invalid plot size {}!", size)),
+        },
+        ["hist"] => return Ok(Outcome::Print(show_history(ctx))),
+        ["hist", "size", size] => match size.parse::<usize>() {
+            Ok(size) if size > 0 => ctx.history_mut().set_size(size),
+            _ => return Err(format!("Invalid history size {}!", size)),
+        },
+        ["del", name] => delete_var(name, ctx)?,
+        ["undo"] => match ctx.undo() {
+            Some(mutation) => return Ok(Outcome::Print(show_var_state(&mutation.name, ctx))),
+            None => return Err(String::from("There is nothing to undo!")),
+        },
+        ["redo"] => match ctx.redo() {
+            Some(mutation) => return Ok(Outcome::Print(show_var_state(&mutation.name, ctx))),
+            None => return Err(String::from("There is nothing to redo!")),
+        },
+        ["reset"] => {
+            ctx.clear_vars();
+            session.last_expr = None;
+        }
+        ["precision", "full"] => ctx.set_precision(None),
+        ["precision", digits] => match digits.parse::<usize>() {
+            Ok(digits) if digits > 0 => ctx.set_precision(Some(digits)),
+            _ => return Err(format!("Invalid precision {}!", digits)),
+        },
+        ["notation", "normal"] => ctx.set_notation(Notation::Normal),
+        ["notation", "scientific"] => ctx.set_notation(Notation::Scientific),
+        ["notation", "engineering"] => ctx.set_notation(Notation::Engineering),
+        ["notation", notation] => return Err(format!("Unknown notation {}!", notation)),
+        ["base", "dec"] => ctx.set_base(Base::Decimal),
+        ["base", "bin"] => ctx.set_base(Base::Binary),
+        ["base", "oct"] => ctx.set_base(Base::Octal),
+        ["base", "hex"] => ctx.set_base(Base::Hexadecimal),
+        ["base", base] => return Err(format!("Unknown base {}!", base)),
+        ["width", "none"] => ctx.set_width(None),
+        ["width", width] => match width.parse::<u32>() {
+            Ok(width) if (1..=1024).contains(&width) => ctx.set_width(Some(width)),
+            _ => return Err(format!("Invalid width {}!", width)),
+        },
+        ["grouping", "comma"] => ctx.set_grouping(Some(',')),
+        ["grouping", "underscore"] => ctx.set_grouping(Some('_')),
+        // A thin space.
+        ["grouping", "space"] => ctx.set_grouping(Some('\u{2009}')),
+        ["grouping", "none"] => ctx.set_grouping(None),
+        ["grouping", grouping] => return Err(format!("Unknown grouping {}!", grouping)),
+        ["decimal", "comma"] => ctx.set_decimal_comma(true),
+        ["decimal", "point"] => ctx.set_decimal_comma(false),
+        ["decimal", separator] => return Err(format!("Unknown decimal separator {}!", separator)),
+        ["mode", mode] => {
+            let (integer, rational) = match *mode {
+                "float" => (false, false),
+                "int" => (true, false),
+                "rational" => (false, true),
+                _ => return Err(format!("Unknown mode {}!", mode)),
+            };
+            ctx.set_integer_mode(integer);
+            ctx.set_rational_mode(rational);
+        }
+        ["complex", "on"] => ctx.set_complex_mode(true),
+        ["complex", "off"] => ctx.set_complex_mode(false),
+        ["complex", state] => return Err(format!("Unknown complex mode {}!", state)),
+        ["case", "sensitive"] => ctx.set_case_insensitive(false),
+        ["case", "insensitive"] => ctx.set_case_insensitive(true),
+        ["case", case] => return Err(format!("Unknown case mode {}!", case)),
+        ["si", "on"] => ctx.set_si_suffixes(true),
+        ["si", "off"] => ctx.set_si_suffixes(false),
+        ["si", state] => return Err(format!("Unknown SI suffix mode {}!", state)),
+        _ => {
+            return Err(format!(
+                "Unknown command :{}, enter :help for the list of commands!",
+                command
+            ))
+        }
+    }
+    Ok(Outcome::Continue)
+}
+
+#[cfg(test)]
+mod command_tests {
+    use super::*;
+
+    fn do_test(command: &str) -> Result<Outcome, String> {
+        run_command(command, &mut Session::default())
+    }
+
+    fn do_print_test(command: &str, session: &mut Session, expect: &str) {
+        println!("Command = {}", command);
+        assert_eq!(
+            run_command(command, session),
+            Ok(Outcome::Print(expect.to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_line_test() {
+        assert_eq!(parse_line(":quit\n"), Line::Command("quit"));
+        assert_eq!(parse_line("  : help "), Line::Command("help"));
+        assert_eq!(parse_line("exit\n"), Line::Command("exit"));
+        assert_eq!(parse_line("quit"), Line::Command("quit"));
+        assert_eq!(parse_line("q"), Line::Expression("q"));
+        assert_eq!(
+            parse_line("quotient = 5\n"),
+            Line::Assignment("quotient", "5")
+        );
+        assert_eq!(parse_line("x=2*y"), Line::Assignment("x", "2*y"));
+        assert_eq!(
+            parse_line("pi_approx = 3.14"),
+            Line::Assignment("pi_approx", "3.14")
+        );
+        assert_eq!(parse_line("_x = 1"), Line::Assignment("_x", "1"));
+        assert_eq!(parse_line("x_1 = 1"), Line::Assignment("x_1", "1"));
+        assert_eq!(parse_line("_1 = 1"), Line::Expression("_1 = 1"));
+        assert_eq!(parse_line("x == 2"), Line::Expression("x == 2"));
+        assert_eq!(parse_line("x <= 2"), Line::Expression("x <= 2"));
+        assert_eq!(parse_line("2x = 4"), Line::Expression("2x = 4"));
+        assert_eq!(parse_line("qrt(2)"), Line::Expression("qrt(2)"));
+        assert_eq!(parse_line("exit + 1"), Line::Expression("exit + 1"));
+        assert_eq!(parse_line("1 + 2"), Line::Expression("1 + 2"));
+    }
+
+    #[test]
+    fn run_command_test() {
+        assert_eq!(do_test("quit"), Ok(Outcome::Quit));
+        assert_eq!(do_test("q"), Ok(Outcome::Quit));
+        assert_eq!(do_test("exit"), Ok(Outcome::Quit));
+        assert_eq!(do_test("help"), Ok(Outcome::Print(HELP.to_string())));
+        assert_eq!(do_test("base hex"), Ok(Outcome::Continue));
+
+        let mut session = Session::default();
+        run_command("precision 6", &mut session).unwrap();
+        run_command("notation  engineering", &mut session).unwrap();
+        run_command("decimal comma", &mut session).unwrap();
+        assert_eq!(session.ctx.format_options().precision, Some(6));
+        assert_eq!(session.ctx.format_options().notation, Notation::Engineering);
+        assert!(session.ctx.decimal_comma());
+    }
+
+    #[test]
+    fn is_complete_test() {
+        let ctx = Context::new();
+        let complete = [
+            "1 + 2",
+            "",
+            "sqrt(2)",
+            "|x| + |y|",
+            "5!",
+            "50%",
+            "(1 + 2) # (unclosed in a comment",
+            "1 + 2 # ends with +",
+            "[1 + 2] * 3",
+            "x = 5",
+            "f(1, 2)",
+            "(1 + 2))",
+        ];
+        let partial = [
+            "1 +",
+            "2 *",
+            "sqrt(2",
+            "max(1,",
+            "|x",
+            "|x| + |",
+            "[1 + 2",
+            "1 and",
+            "x ->",
+            "x =",
+            "1 + 2 \\",
+            "1 + 2 \\ # more to come",
+            "((1 + 2) * 3",
+            "2^",
+            "7 mod",
+        ];
+        for input in complete.iter() {
+            assert!(is_complete(input, &ctx), "{} is complete", input);
+        }
+        for input in partial.iter() {
+            assert!(!is_complete(input, &ctx), "{} isn't complete", input);
+        }
+
+        let mut ctx = Context::new();
+        ctx.set_decimal_comma(true);
+        assert!(is_complete("1,5", &ctx));
+        assert!(!is_complete("max(1,5;", &ctx));
+    }
+
+    #[test]
+    fn join_lines_test() {
+        assert_eq!(join_lines("1 +\n", "2\n"), "1 + 2");
+        assert_eq!(join_lines("max(1, # first\n", "2)"), "max(1, 2)");
+        assert_eq!(join_lines("1 + 2 \\\n", "* 3"), "1 + 2 * 3");
+
+        let ctx = Context::new();
+        let mut input = String::from("(1 +");
+        for line in ["2) *", "|-3", "|"].iter() {
+            assert!(!is_complete(&input, &ctx));
+            input = join_lines(&input, line);
+        }
+        assert!(is_complete(&input, &ctx));
+        assert_eq!(input, "(1 + 2) * |-3 |");
+    }
+
+    #[test]
+    fn run_line_test() {
+        let mut session = Session::default();
+        let value = |results: Result<Vec<Evaluated>, Diagnostic>| {
+            results.map(|results| results.last().map(|last| last.value.clone()))
+        };
+        assert_eq!(
+            value(run_line("a = 3; b = 4; sqrt(a^2 + b^2)", &mut session)),
+            Ok(Some(Value::Int(5)))
+        );
+        assert_eq!(session.last_expr, Some(String::from("sqrt(a^2 + b^2)")));
+        assert_eq!(
+            run_line("c = 1;; c + 1;", &mut session),
+            Ok(vec![
+                Evaluated {
+                    name: Some(String::from("c")),
+                    value: Value::Int(1)
+                },
+                Evaluated {
+                    name: None,
+                    value: Value::Int(2)
+                },
+            ])
+        );
+        assert_eq!(run_line(" ; # only a comment", &mut session), Ok(vec![]));
+        assert_eq!(
+            value(run_line("max(1, 2); min(3, 4)", &mut session)),
+            Ok(Some(Value::Int(3)))
+        );
+
+        // The statements before an error are still evaluated.
+        assert_eq!(
+            run_line("d = 2; g = d + w; f = 1", &mut session).map_err(String::from),
+            Err(String::from("Statement 2 at pos 7: Unknown variable w!"))
+        );
+        let err = run_line("d = 2; g = d + w; f = 1", &mut session).unwrap_err();
+        assert_eq!(err.span, Some(Span { start: 15, len: 1 }));
+        assert_eq!(session.ctx.get_var("d"), Some(2.));
+        assert_eq!(session.ctx.get_var("g"), None);
+        assert_eq!(session.ctx.get_var("f"), None);
+        assert_eq!(
+            run_line("1; 2 + * 3", &mut session).map_err(String::from),
+            Err(String::from(
+                "Statement 2 at pos 3: Ast build error! Unknown prefix operator Multiplication at pos 7!"
+            ))
+        );
+        assert_eq!(
+            run_line("x = 1 + * 2", &mut session).map_err(String::from),
+            Err(String::from(
+                "Ast build error! Unknown prefix operator Multiplication at pos 8!"
+            ))
+        );
+        assert_eq!(
+            run_line("max(1; 2)", &mut session).map_err(String::from),
+            Err(String::from(
+                "Ast build error! ';' at pos 5 separates statements, arguments are separated by ','!"
+            ))
+        );
+
+        // With a decimal comma ';' separates arguments.
+        session.ctx.set_decimal_comma(true);
+        assert_eq!(
+            value(run_line("max(1,5; 2)", &mut session)),
+            Ok(Some(Value::Float(2.)))
+        );
+    }
+
+    #[test]
+    fn assignment_test() {
+        let mut session = Session::default();
+        let run = |line: &str, session: &mut Session| match parse_line(line) {
+            Line::Assignment(name, expr) => assign(name, expr, session).map_err(String::from),
+            line => panic!("{:?} isn't an assignment!", line),
+        };
+        assert_eq!(run("x = 5\n", &mut session), Ok(Value::Int(5)));
+        assert_eq!(run("y = x^2 + 1", &mut session), Ok(Value::Int(26)));
+        assert_eq!(run("x = x / 2", &mut session), Ok(Value::Float(2.5)));
+        assert_eq!(run("z = y + w", &mut session), Err(String::from("Unknown variable w!")));
+        assert_eq!(session.ctx.get_var("x"), Some(2.5));
+        assert_eq!(session.ctx.get_var("y"), Some(26.));
+        assert_eq!(session.ctx.get_var("z"), None);
+        assert_eq!(session.last_expr, Some(String::from("x / 2")));
+        do_print_test("vars", &mut session, "x = 2.5\ny = 26");
+
+        // Reserved words, constants and functions keep their meaning.
+        let rejected = [
+            ("true", "a constant"),
+            ("false", "a constant"),
+            ("inf", "a constant"),
+            ("nan", "a constant"),
+            ("pi", "a constant"),
+            ("e", "a constant"),
+            ("sin", "a function"),
+            ("out", "a function"),
+            ("sum", "a function"),
+            ("mod", "a reserved word"),
+            ("div", "a reserved word"),
+            ("xor", "a reserved word"),
+            ("and", "a reserved word"),
+            ("or", "a reserved word"),
+            ("not", "a reserved word"),
+        ];
+        for (name, kind) in rejected.iter() {
+            let err = format!("Can't assign to {} at pos 0, it's {}!", name, kind);
+            assert_eq!(run(&format!("{} = 3", name), &mut session), Err(err));
+            assert_eq!(session.ctx.get_var(name), None);
+        }
+        let mut session = Session::default();
+        assert_eq!(
+            run_line("x = 1; true = 3", &mut session).map_err(String::from),
+            Err(String::from(
+                "Statement 2 at pos 7: Can't assign to true at pos 7, it's a constant!"
+            ))
+        );
+        let err = run_line("x = 1; true = 3", &mut session).unwrap_err();
+        assert_eq!(err.span, Some(Span { start: 7, len: 4 }));
+        assert_eq!(
+            run_line("  PI = 3", &mut session),
+            Ok(vec![Evaluated {
+                name: Some(String::from("PI")),
+                value: Value::Int(3)
+            }])
+        );
+        session.ctx.set_case_insensitive(true);
+        assert_eq!(
+            run_line("  PI = 3", &mut session).map_err(String::from),
+            Err(String::from("Can't assign to PI at pos 2, it's a constant!"))
+        );
+        assert_eq!(
+            run_line("true + 1", &mut session).map(|results| results[0].value.clone()),
+            Ok(Value::Int(2))
+        );
+
+        session.ctx.set_complex_mode(true);
+        assert_eq!(
+            assign("z", "sqrt(-4)", &mut session).map_err(String::from),
+            Err(String::from("2i isn't a real number!"))
+        );
+    }
+
+    #[test]
+    fn vars_command_test() {
+        let mut session = Session::default();
+        do_print_test("vars", &mut session, "No variables are defined.");
+
+        session.ctx.set_var("x", 2.);
+        session.ctx.set_var("radius", 0.5);
+        session.ctx.set_var("big", 1e20);
+        session.ctx.set_var("n", -3.);
+        do_print_test(
+            "vars",
+            &mut session,
+            "big    = 1e20\nn      = -3\nradius = 0.5\nx      = 2",
+        );
+
+        assert_eq!(
+            run_command("del radius", &mut session),
+            Ok(Outcome::Continue)
+        );
+        assert_eq!(session.ctx.get_var("radius"), None);
+        do_print_test("vars", &mut session, "big = 1e20\nn   = -3\nx   = 2");
+
+        // A variable named like a constant hides it and can be deleted.
+        session.ctx.set_var("e", 1.);
+        assert_eq!(run_command("del e", &mut session), Ok(Outcome::Continue));
+
+        session.ctx.set_precision(Some(3));
+        session.last_expr = Some(String::from("x + 1"));
+        assert_eq!(run_command("reset", &mut session), Ok(Outcome::Continue));
+        do_print_test("vars", &mut session, "No variables are defined.");
+        assert_eq!(session.last_expr, None);
+        assert_eq!(session.ctx.format_options().precision, Some(3));
+    }
+
+    #[test]
+    fn save_load_command_test() {
+        let path =
+            std::env::temp_dir().join(format!("ls_calc command {}.calc", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut session = Session::default();
+        assign("r", "2.5", &mut session).unwrap();
+        assign("area", "pi * r^2", &mut session).unwrap();
+        do_print_test(
+            &format!("save {}", path),
+            &mut session,
+            &format!("Saved 2 variables to {}.", path),
+        );
+
+        let mut loaded = Session::default();
+        do_print_test(
+            &format!("load {}", path),
+            &mut loaded,
+            &format!("Loaded 2 variables from {}.", path),
+        );
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(loaded.ctx.vars(), session.ctx.vars());
+    }
+
+    #[test]
+    fn ast_command_test() {
+        let mut session = Session::default();
+        do_print_test(
+            "ast 1 + 2 * 3",
+            &mut session,
+            "{\n Token: Plus\n Left: \n {\n  Token: Number(1.0)\n }\n Right: \n {\n  Token: Multiply\n  Left: \n  {\n   Token: Number(2.0)\n  }\n  Right: \n  {\n   Token: Number(3.0)\n  }\n }\n}",
+        );
+        do_print_test(
+            "ast 1 + * 2",
+            &mut session,
+            "1 + * 2\n    ^\nAst build error! Unknown prefix operator Multiplication at pos 4!",
+        );
+
+        session.last_expr = Some(String::from("max(x, 1)"));
+        do_print_test(
+            "ast",
+            &mut session,
+            "{\n Token: Call(\"max\")\n Arg 0: \n {\n  Token: Variable(\"x\")\n }\n Arg 1: \n {\n  Token: Number(1.0)\n }\n}",
+        );
+    }
+
+    #[test]
+    fn unicode_diagnostic_test() {
+        let mut session = Session::default();
+        let line = "é = 2; π × é + * 1";
+        let err = run_line(line, &mut session).unwrap_err();
+        assert_eq!(
+            err.message,
+            "Statement 2 at pos 7: Ast build error! Unknown prefix operator Multiplication at pos 15!"
+        );
+        // The caret is under the `*`, the 16th char, after three of two bytes.
+        assert_eq!(
+            session.diagnostic(line, &err),
+            format!("{}\n{}^\n{}", line, " ".repeat(15), err)
+        );
+        assert_eq!(line.chars().nth(15), Some('*'));
+    }
+
+    #[test]
+    fn explain_command_test() {
+        let mut session = Session::default();
+        do_print_test("explain -3! + 2", &mut session, "((-(3!)) + 2)");
+        do_print_test(
+            "explain 1 + * 2",
+            &mut session,
+            "1 + * 2\n    ^\nAst build error! Unknown prefix operator Multiplication at pos 4!",
+        );
+
+        session.last_expr = Some(String::from("2 * 3 - 4 ^ 2"));
+        do_print_test("explain", &mut session, "((2 * 3) - (4 ^ 2))");
+    }
+
+    #[test]
+    fn tokens_command_test() {
+        let mut session = Session::default();
+        do_print_test(
+            "tokens 12 * sin(x)",
+            &mut session,
+            "  0: Number(12.0)\n  3: Multiplication\n  5: Str(\"sin\")\n  8: Lparen\n  9: Str(\"x\")\n 10: Rparen",
+        );
+
+        session.last_expr = Some(String::from("2²"));
+        do_print_test("tokens", &mut session, "  0: Number(2.0)\n  1: Squared");
+        do_print_test(
+            "tokens 1 + 0x",
+            &mut session,
+            "1 + 0x\n    ^^\nAst build error! Number 0x at pos 4 is malformed!",
+        );
+
+        session.ctx.set_decimal_comma(true);
+        do_print_test(
+            "tokens max(1,5; 2)",
+            &mut session,
+            "  0: Str(\"max\")\n  3: Lparen\n  4: Number(1.5)\n  7: Comma\n  9: Number(2.0)\n 10: Rparen",
+        );
+    }
+
+    #[test]
+    fn complete_command_test() {
+        let mut session = Session::default();
+        do_print_test("complete 1 + co", &mut session, "cos( cosh(");
+        do_print_test("complete :t", &mut session, "table time tokens trace");
+        do_print_test("complete :to", &mut session, "tokens");
+        do_print_test("complete 2 * ", &mut session, "No completions.");
+        session.ctx.set_var("width", 3.);
+        do_print_test("complete wi", &mut session, "width");
+
+        // Every command is listed, and every listed command exists.
+        for command in COMMANDS.iter() {
+            assert!(HELP.contains(&format!(":{}", command)) || *command == "exit");
+        }
+    }
+
+    #[test]
+    fn mode_commands_test() {
+        let mut session = Session::default();
+        let value = |expr: &str, session: &mut Session| {
+            let last = run_line(expr, session).unwrap().pop().unwrap();
+            show_evaluated(&last, &session.ctx)
+        };
+        assert_eq!(session.prompt(), ">>> ");
+
+        run_command("mode int", &mut session).unwrap();
+        assert_eq!(session.prompt(), "[int] >>> ");
+        run_command("mode rational", &mut session).unwrap();
+        assert_eq!(value("1/3 + 1/6", &mut session), "1/2");
+        run_command("complex on", &mut session).unwrap();
+        assert_eq!(session.prompt(), "[rational,complex] >>> ");
+        run_command("mode float", &mut session).unwrap();
+        assert_eq!(value("sqrt(-4)", &mut session), "2i");
+        run_command("complex off", &mut session).unwrap();
+        assert_eq!(session.prompt(), ">>> ");
+
+        run_command("case insensitive", &mut session).unwrap();
+        assert_eq!(value("SQRT(16)", &mut session), "4");
+        run_command("case sensitive", &mut session).unwrap();
+        assert!(run_line("SQRT(16)", &mut session).is_err());
+
+        run_command("si on", &mut session).unwrap();
+        assert_eq!(value("4.7k * 2", &mut session), "9400");
+        run_command("si off", &mut session).unwrap();
+        assert!(run_line("4.7k * 2", &mut session).is_err());
+    }
+
+    #[test]
+    fn run_command_errors_test() {
+        let errors = [
+            (
+                "",
+                "Unknown command :, enter :help for the list of commands!",
+            ),
+            (
+                "qiut",
+                "Unknown command :qiut, enter :help for the list of commands!",
+            ),
+            (
+                "help me",
+                "Unknown command :help me, enter :help for the list of commands!",
+            ),
+            ("precision 0", "Invalid precision 0!"),
+            ("base ten", "Unknown base ten!"),
+            ("width 2000", "Invalid width 2000!"),
+            ("ast", "No expression was evaluated yet!"),
+            ("tokens", "No expression was evaluated yet!"),
+            ("del x", "Variable x isn't defined!"),
+            ("del pi", "Constant pi can't be deleted!"),
+            ("hist size 0", "Invalid history size 0!"),
+            ("time ;", "There is no expression to time!"),
+            ("table rows 0", "Invalid number of rows 0!"),
+            ("plot size 1x20", "Invalid plot size 1x20!"),
+            ("plot size 70", "Invalid plot size 70!"),
+            ("mode exact", "Unknown mode exact!"),
+            ("complex yes", "Unknown complex mode yes!"),
+            ("case upper", "Unknown case mode upper!"),
+            ("si 1", "Unknown SI suffix mode 1!"),
+            (
+                "del",
+                "Unknown command :del, enter :help for the list of commands!",
+            ),
+        ];
+
+        for (command, error) in errors.iter() {
+            assert_eq!(do_test(command), Err(error.to_string()));
+        }
+    }
+
+    #[test]
+    fn trace_test() {
+        let mut session = Session::default();
+        do_print_test(
+            "trace 2 + 3! * |1-5|",
+            &mut session,
+            "  2 + 3! * |1 - 5|\n→ 2 + 6 * |1 - 5|\n→ 2 + 6 * |-4|\n→ 2 + 6 * 4\n→ 2 + 24\n→ 26",
+        );
+        assert!(run_line("x = 2; x^2", &mut session).is_ok());
+        do_print_test("trace", &mut session, "  x ^ 2\n→ 2 ^ 2\n→ 4");
+        assert_eq!(
+            run_command("trace 1 + sqrt(-4)", &mut session),
+            Err(String::from("Domain error! sqrt(-4) is undefined!"))
+        );
+    }
+
+    #[test]
+    fn table_command_test() {
+        let mut session = Session::default();
+        session.ctx.set_strict_floats(true);
+        session.ctx.set_var("n", 2.);
+        do_print_test(
+            "table x = 0..n step 1/2 : x^2 - 3*x",
+            &mut session,
+            "  x | x^2 - 3*x\n----+----------\n  0 |         0\n0.5 |     -1.25\n  1 |        -2\n1.5 |     -2.25\n  2 |        -2",
+        );
+        do_print_test(
+            "table k = -1..1 : 1/k",
+            &mut session,
+            " k |   1/k\n---+------\n-1 |    -1\n 0 | error\n 1 |     1",
+        );
+
+        assert_eq!(
+            run_command("table rows 2", &mut session),
+            Ok(Outcome::Continue)
+        );
+        assert_eq!(
+            run_command("table x = 1..3 : x", &mut session),
+            Err(String::from(
+                "The table has 3 rows, more than the limit of 2!"
+            ))
+        );
+        assert_eq!(
+            run_command("table x = 1..2 step 0 : x", &mut session),
+            Err(String::from("The step of a table can't be 0!"))
+        );
+        assert_eq!(
+            run_command("table x = 1..2 : x +", &mut session),
+            Err(String::from(
+                "Ast build error! Operator Plus at pos 2 expects an operand, but gets End!"
+            ))
+        );
+        assert_eq!(
+            run_command("table x = 1..y : x", &mut session),
+            Err(String::from("Unknown variable y!"))
+        );
+        assert_eq!(
+            run_command("table 2 = 1..2 : x", &mut session),
+            Err(String::from("2 isn't a variable name!"))
+        );
+        assert_eq!(
+            run_command("table x = 1 : x", &mut session),
+            Err(String::from(
+                "Invalid table x = 1 : x, write it like x = 0..10 step 0.5 : x^2!"
+            ))
+        );
+    }
+
+    #[test]
+    fn plot_command_test() {
+        let mut session = Session::default();
+        assert_eq!(
+            run_command("plot size 5x3", &mut session),
+            Ok(Outcome::Continue)
+        );
+        do_print_test(
+            "plot max(x, 0), x = -1..1",
+            &mut session,
+            "  | *\n  |*\n***--",
+        );
+        do_print_test("plot -x, x = 1..2*2", &mut session, "*\n **\n   **");
+        assert_eq!(
+            run_command("plot x, x = 1..0", &mut session),
+            Err(String::from("A plot can't go from 1 to 0!"))
+        );
+        assert_eq!(
+            run_command("plot x = 1..2", &mut session),
+            Err(String::from(
+                "Invalid plot x = 1..2, write it like sin(x), x = -10..10!"
+            ))
+        );
+    }
+
+    #[test]
+    fn time_test() {
+        let mut session = Session::default();
+        let output = match run_command("time 2^10", &mut session) {
+            Ok(Outcome::Print(output)) => output,
+            outcome => panic!("Unexpected outcome {:?}!", outcome),
+        };
+        assert!(output.starts_with("<<< 1024 (scan "));
+        assert_eq!(session.last_expr, Some(String::from("2^10")));
+        assert!(!session.time);
+        assert_eq!(
+            run_command("time 1 +", &mut session),
+            Err(String::from(
+                "Ast build error! Operator Plus at pos 2 expects an operand, but gets End!"
+            ))
+        );
+
+        for state in ["on", "off"].iter() {
+            assert_eq!(
+                run_command("time", &mut session),
+                Ok(Outcome::Print(format!("Timing is {}.", state)))
+            );
+        }
+    }
+
+    #[test]
+    fn sub_command_test() {
+        let mut session = Session::default();
+        assert_eq!(
+            run_command("sub /1/2/", &mut session),
+            Err(String::from("No expression was evaluated yet!"))
+        );
+        run_line("g = 9.81; 2 * pi * sqrt(1 / 9.81) / pi", &mut session).unwrap();
+        assert_eq!(
+            run_command("last", &mut session),
+            Ok(Outcome::Print(String::from("2 * pi * sqrt(1 / 9.81) / pi")))
+        );
+        assert_eq!(
+            run_command("sub /9.81/4/", &mut session),
+            Ok(Outcome::Print(String::from(
+                "2 * pi * sqrt(1 / 4) / pi\n<<< 1"
+            )))
+        );
+        assert_eq!(
+            session.last_expr,
+            Some(String::from("2 * pi * sqrt(1 / 4) / pi"))
+        );
+
+        // No match evaluates nothing.
+        assert_eq!(
+            run_command("sub /9.81/4/", &mut session),
+            Err(String::from(
+                "2 * pi * sqrt(1 / 4) / pi doesn't contain 9.81!"
+            ))
+        );
+        assert_eq!(session.ctx.history().entries().count(), 2);
+
+        // `$n` edits the expression of a result, and history references in
+        // the edited expression refer to the results as usual.
+        assert_eq!(
+            run_command("sub /pi/1/g $1", &mut session),
+            Ok(Outcome::Print(String::from(
+                "2 * 1 * sqrt(1 / 9.81) / 1\n<<< 0.638550856814101"
+            )))
+        );
+        run_line("$1 + $2", &mut session).unwrap();
+        assert_eq!(
+            run_command("sub |$1|$3|", &mut session),
+            Ok(Outcome::Print(String::from(
+                "$3 + $2\n<<< 1.6385508568141"
+            )))
+        );
+        assert_eq!(
+            run_command("sub /x/y/ $9", &mut session),
+            Err(String::from("There is no result $9!"))
+        );
+        assert_eq!(
+            run_command("sub /9.81/w/ $1", &mut session),
+            Err(String::from("Unknown variable w!"))
+        );
+    }
+
+    #[test]
+    fn undo_command_test() {
+        let mut session = Session::default();
+        let print = |text: &str| Ok(Outcome::Print(text.to_string()));
+        run_line("x = 1; y = 2; x = 3", &mut session).unwrap();
+        run_command("del y", &mut session).unwrap();
+        assert_eq!(session.ctx.vars(), [("x", 3.)]);
+
+        assert_eq!(run_command("undo", &mut session), print("y = 2"));
+        assert_eq!(session.ctx.vars(), [("x", 3.), ("y", 2.)]);
+        assert_eq!(run_command("undo", &mut session), print("x = 1"));
+        assert_eq!(run_command("undo", &mut session), print("y isn't defined."));
+        assert_eq!(session.ctx.vars(), [("x", 1.)]);
+        assert_eq!(run_command("redo", &mut session), print("y = 2"));
+        assert_eq!(run_command("redo", &mut session), print("x = 3"));
+        assert_eq!(session.ctx.vars(), [("x", 3.), ("y", 2.)]);
+
+        assert_eq!(run_command("undo", &mut session), print("x = 1"));
+        run_line("z = 4", &mut session).unwrap();
+        assert_eq!(
+            run_command("redo", &mut session),
+            Err(String::from("There is nothing to redo!"))
+        );
+        assert_eq!(session.ctx.vars(), [("x", 1.), ("y", 2.), ("z", 4.)]);
+
+        assert_eq!(run_command("undo", &mut session), print("z isn't defined."));
+        assert_eq!(run_command("undo", &mut session), print("y isn't defined."));
+        assert_eq!(run_command("undo", &mut session), print("x isn't defined."));
+        assert_eq!(
+            run_command("undo", &mut session),
+            Err(String::from("There is nothing to undo!"))
+        );
+
+        // What `:reset` removed can't be brought back one variable at a time.
+        run_line("x = 1", &mut session).unwrap();
+        run_command("reset", &mut session).unwrap();
+        assert_eq!(
+            run_command("undo", &mut session),
+            Err(String::from("There is nothing to undo!"))
+        );
+    }
+
+    #[test]
+    fn prompt_command_test() {
+        let mut session = Session::default();
+        assert_eq!(session.prompt(), ">>> ");
+        assert_eq!(
+            run_command("prompt", &mut session),
+            Ok(Outcome::Print(String::from("The prompt is \"{modes}>>> \".")))
+        );
+        run_command("base hex", &mut session).unwrap();
+        assert_eq!(session.prompt(), "[hex] >>> ");
+
+        run_command("prompt \"{mode}:{base} > \"", &mut session).unwrap();
+        assert_eq!(session.prompt(), "float:hex > ");
+        run_command("prompt calc>", &mut session).unwrap();
+        assert_eq!(session.prompt(), "calc>");
+        assert_eq!(
+            run_command("prompt {angle}", &mut session),
+            Err(String::from(
+                "Unknown placeholder {angle}, the placeholders are \
+                 {base}, {complex}, {mode}, {modes}, {precision}, {strict}!"
+            ))
+        );
+        assert_eq!(session.prompt(), "calc>");
+
+        run_command("prompt reset", &mut session).unwrap();
+        assert_eq!(session.prompt, None);
+        assert_eq!(session.prompt(), "[hex] >>> ");
+    }
+
+    #[test]
+    fn history_test() {
+        let mut session = Session::default();
+        let mut value = |line: &str| {
+            run_line(line, &mut session)
+                .map(|results| results.last().map(|last| last.value.clone()))
+                .map_err(String::from)
+        };
+        assert_eq!(
+            value("$1"),
+            Err(String::from("Result $1 at pos 0 doesn't exist yet!"))
+        );
+        assert_eq!(value("1 / 2"), Ok(Some(Value::Float(0.5))));
+        assert_eq!(value("x = 3"), Ok(Some(Value::Int(3))));
+        assert_eq!(value("2^10; $1 * 4"), Ok(Some(Value::Float(2.))));
+        assert_eq!(value("$2 + out(3)"), Ok(Some(Value::Float(1026.))));
+        assert_eq!(
+            value("1 + $9"),
+            Err(String::from("Result $9 at pos 4 doesn't exist yet!"))
+        );
+        assert_eq!(
+            value("$0"),
+            Err(String::from(
+                "Ast build error! '$0' at pos 0 isn't a result, the first one is $1!"
+            ))
+        );
+        assert_eq!(
+            value("out(-1)"),
+            Err(String::from(
+                "Result index -1 at pos 0 isn't a positive integer!"
+            ))
+        );
+
+        assert_eq!(
+            run_command("hist", &mut session),
+            Ok(Outcome::Print(String::from(
+                "$1: 1 / 2 = 0.5\n$2: 2^10 = 1024\n$3: $1 * 4 = 2\n$4: $2 + out(3) = 1026"
+            )))
+        );
+        assert_eq!(
+            run_command("hist size 2", &mut session),
+            Ok(Outcome::Continue)
+        );
+        assert_eq!(
+            run_line("$2", &mut session).map_err(String::from),
+            Err(String::from(
+                "Result $2 at pos 0 was dropped, the history keeps the last 2!"
+            ))
+        );
+        assert_eq!(
+            run_command("hist", &mut session),
+            Ok(Outcome::Print(String::from(
+                "$3: $1 * 4 = 2\n$4: $2 + out(3) = 1026"
+            )))
+        );
+        assert_eq!(
+            do_test("hist"),
+            Ok(Outcome::Print(String::from("The history is empty.")))
+        );
+    }
+}
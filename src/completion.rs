@@ -0,0 +1,176 @@
+use super::builtins::{BUILTINS, CONSTANTS};
+use super::command::COMMANDS;
+use super::context::Context;
+use super::scanner::is_name_char;
+use super::special_forms::SPECIAL_FORMS;
+
+/// The names the word under the cursor can be completed to.
+#[derive(Debug, PartialEq)]
+pub struct Completion {
+    /// Byte offset of the start of the word, which a candidate replaces up
+    /// to the cursor.
+    pub start: usize,
+    /// The candidates in alphabetical order. Functions end in '(', so that
+    /// `sq` completes to `sqrt(`.
+    pub candidates: Vec<String>,
+}
+
+impl Completion {
+    /// The longest prefix all candidates share, which the word can be
+    /// extended to when there is more than one.
+    pub fn common_prefix(&self) -> String {
+        let mut candidates = self.candidates.iter();
+        let mut prefix = match candidates.next() {
+            Some(first) => first.clone(),
+            None => return String::new(),
+        };
+        for candidate in candidates {
+            let common = prefix
+                .char_indices()
+                .zip(candidate.chars())
+                .find(|((_, a), b)| a != b)
+                .map_or(prefix.len().min(candidate.len()), |((i, _), _)| i);
+            prefix.truncate(common);
+        }
+        prefix
+    }
+}
+
+/// Completes the word that ends at byte offset `cursor` of `line`. After a
+/// leading ':' it's a command, otherwise a function, constant or variable of
+/// `ctx`, so that new variables are completed as soon as they're defined.
+pub fn complete(line: &str, cursor: usize, ctx: &Context) -> Completion {
+    let mut cursor = cursor.min(line.len());
+    while !line.is_char_boundary(cursor) {
+        cursor -= 1;
+    }
+    let before = &line[..cursor];
+    let start = before
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_name_char(*c))
+        .last()
+        .map_or(cursor, |(i, _)| i);
+    // Digits before a name are a number, like in `2si`.
+    let start = start + before[start..].len()
+        - before[start..]
+            .trim_start_matches(|c: char| c.is_ascii_digit())
+            .len();
+    let word = &line[start..cursor];
+    if word.is_empty() {
+        return Completion {
+            start,
+            candidates: Vec::new(),
+        };
+    }
+
+    let mut candidates: Vec<String> = if before[..start].trim() == ":" {
+        COMMANDS
+            .iter()
+            .filter(|command| command.starts_with(word))
+            .map(|command| command.to_string())
+            .collect()
+    } else {
+        let builtin_word = ctx.builtin_name(word);
+        let functions = BUILTINS
+            .iter()
+            .map(|builtin| builtin.name)
+            .chain(SPECIAL_FORMS.iter().map(|form| form.name))
+            .chain(ctx.function_names())
+            .filter(|name| name.starts_with(builtin_word.as_ref()))
+            .map(|name| format!("{}(", name));
+        let constants = CONSTANTS
+            .iter()
+            .map(|(name, _)| *name)
+            .filter(|name| name.starts_with(builtin_word.as_ref()))
+            .map(String::from);
+        let variables = ctx
+            .vars()
+            .into_iter()
+            .map(|(name, _)| name)
+            .filter(|name| name.starts_with(word))
+            .map(String::from);
+        functions.chain(constants).chain(variables).collect()
+    };
+    candidates.sort();
+    candidates.dedup();
+    Completion { start, candidates }
+}
+
+#[cfg(test)]
+mod completion_tests {
+    use super::*;
+    use crate::builtins::{Arity, Builtin};
+
+    fn do_test(line: &str, ctx: &Context, expect: &[&str]) {
+        println!("Line = {}", line);
+
+        let completion = complete(line, line.len(), ctx);
+        assert_eq!(completion.candidates, expect);
+    }
+
+    #[test]
+    fn complete_names_test() {
+        let mut ctx = Context::new();
+        do_test("sq", &ctx, &["sqrt("]);
+        do_test("1 + si", &ctx, &["sigfig(", "sin(", "sinh("]);
+        do_test("2*p", &ctx, &["pi", "prod("]);
+        do_test("1 + ", &ctx, &[]);
+        do_test("xyz", &ctx, &[]);
+
+        // Variables are completed once they're defined.
+        do_test("rad", &ctx, &[]);
+        ctx.set_var("radius", 2.);
+        ctx.set_var("rate", 0.5);
+        do_test("rad", &ctx, &["radius"]);
+        do_test("ra", &ctx, &["radius", "random(", "rate"]);
+        // A variable named like a constant is listed once.
+        ctx.set_var("pi", 3.);
+        do_test("p", &ctx, &["pi", "prod("]);
+
+        ctx.set_case_insensitive(true);
+        do_test("SQ", &ctx, &["sqrt("]);
+
+        // So are registered functions.
+        let hyp = Builtin {
+            name: "hyp",
+            arity: Arity::Exact(2),
+            pure: true,
+            func: |args| Ok(args[0].hypot(args[1])),
+        };
+        ctx.add_function(hyp).unwrap();
+        do_test("hy", &ctx, &["hyp("]);
+    }
+
+    #[test]
+    fn complete_commands_test() {
+        let ctx = Context::new();
+        do_test(":v", &ctx, &["vars"]);
+        do_test(" : r", &ctx, &["redo", "reset"]);
+        do_test(":", &ctx, &[]);
+        // Only the first word after ':' is a command.
+        do_test(":ast sq", &ctx, &["sqrt("]);
+    }
+
+    #[test]
+    fn completion_position_test() {
+        let ctx = Context::new();
+        let completion = complete("sq + 1", 2, &ctx);
+        assert_eq!(completion.start, 0);
+        assert_eq!(completion.candidates, ["sqrt("]);
+
+        let completion = complete("1 + co", 6, &ctx);
+        assert_eq!(completion.start, 4);
+        assert_eq!(completion.common_prefix(), "cos");
+
+        // Digits are part of names, but not in front of them.
+        let completion = complete("2 * x_1 + 2si", 100, &ctx);
+        assert_eq!(completion.start, 11);
+        assert_eq!(completion.common_prefix(), "si");
+        assert_eq!(complete("log1", 4, &ctx).start, 0);
+
+        let completion = complete("2πs", 100, &ctx);
+        assert_eq!(completion.start, 1);
+        assert_eq!(completion.common_prefix(), "");
+    }
+}
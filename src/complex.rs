@@ -0,0 +1,195 @@
+use std::fmt;
+use std::ops;
+
+use super::printer::format_number;
+
+/// A complex number of floats.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub const I: Complex = Complex { re: 0., im: 1. };
+
+    pub fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.re == 0. && self.im == 0.
+    }
+
+    /// The modulus, `|3 + 4i|` is 5.
+    pub fn abs(&self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    /// The principal square root, `sqrt(-4)` is `2i`.
+    pub fn sqrt(&self) -> Self {
+        let modulus = self.abs();
+        let im = ((modulus - self.re) / 2.).sqrt();
+        Complex::new(
+            ((modulus + self.re) / 2.).sqrt(),
+            if self.im.is_sign_negative() { -im } else { im },
+        )
+    }
+
+    pub fn exp(&self) -> Self {
+        let modulus = self.re.exp();
+        Complex::new(modulus * self.im.cos(), modulus * self.im.sin())
+    }
+
+    /// The principal natural logarithm, `ln(-1)` is `pi i`.
+    pub fn ln(&self) -> Self {
+        Complex::new(self.abs().ln(), self.im.atan2(self.re))
+    }
+
+    /// `self` to an integer power by repeated squaring, so that `i^2` is
+    /// exactly -1.
+    pub fn powi(&self, exponent: i64) -> Self {
+        let mut result = Complex::new(1., 0.);
+        let mut base = *self;
+        let mut n = exponent.unsigned_abs();
+        while n > 0 {
+            if n % 2 == 1 {
+                result = result * base;
+            }
+            base = base * base;
+            n /= 2;
+        }
+        if exponent < 0 {
+            Complex::new(1., 0.) / result
+        } else {
+            result
+        }
+    }
+
+    /// The principal value of `self` to the power `exponent`. Integer powers
+    /// and square roots are computed exactly where possible.
+    pub fn pow(&self, exponent: Complex) -> Self {
+        if exponent.im == 0. && exponent.re.fract() == 0. && exponent.re.abs() <= i32::MAX as f64 {
+            self.powi(exponent.re as i64)
+        } else if exponent == Complex::from(0.5) {
+            self.sqrt()
+        } else if self.is_zero() && exponent.re > 0. {
+            Complex::new(0., 0.)
+        } else {
+            (exponent * self.ln()).exp()
+        }
+    }
+
+    /// Prints like it's written, `2i`, `-i` or `-1 - 2.5i`, with the parts
+    /// formatted by `format`.
+    pub fn format_with(&self, format: impl Fn(f64) -> String) -> String {
+        let imaginary = |im: f64| match format(im).as_str() {
+            "1" => String::from("i"),
+            "-1" => String::from("-i"),
+            im => format!("{}i", im),
+        };
+        if self.im == 0. {
+            format(self.re)
+        } else if self.re == 0. {
+            imaginary(self.im)
+        } else if self.im < 0. {
+            format!("{} - {}", format(self.re), imaginary(-self.im))
+        } else {
+            format!("{} + {}", format(self.re), imaginary(self.im))
+        }
+    }
+}
+
+impl From<f64> for Complex {
+    fn from(re: f64) -> Self {
+        Complex::new(re, 0.)
+    }
+}
+
+impl ops::Neg for Complex {
+    type Output = Complex;
+
+    fn neg(self) -> Complex {
+        Complex::new(-self.re, -self.im)
+    }
+}
+
+impl ops::Add for Complex {
+    type Output = Complex;
+
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl ops::Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl ops::Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+impl ops::Div for Complex {
+    type Output = Complex;
+
+    fn div(self, other: Complex) -> Complex {
+        let denominator = other.re * other.re + other.im * other.im;
+        Complex::new(
+            (self.re * other.re + self.im * other.im) / denominator,
+            (self.im * other.re - self.re * other.im) / denominator,
+        )
+    }
+}
+
+impl fmt::Display for Complex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.format_with(format_number))
+    }
+}
+
+#[cfg(test)]
+mod complex_tests {
+    use super::*;
+
+    #[test]
+    fn complex_arithmetic_test() {
+        let z = Complex::new(1., 2.);
+        let w = Complex::new(3., -1.);
+        assert_eq!(z + w, Complex::new(4., 1.));
+        assert_eq!(z - w, Complex::new(-2., 3.));
+        assert_eq!(z * w, Complex::new(5., 5.));
+        assert_eq!(Complex::new(5., 5.) / w, z);
+        assert_eq!(Complex::I * Complex::I, Complex::from(-1.));
+        assert_eq!(Complex::I.powi(3), -Complex::I);
+        assert_eq!(Complex::new(0., 2.).powi(-1), Complex::new(0., -0.5));
+        assert_eq!(Complex::new(3., 4.).abs(), 5.);
+        assert_eq!(Complex::from(-4.).sqrt(), Complex::new(0., 2.));
+        assert_eq!(Complex::new(3., 4.).sqrt(), Complex::new(2., 1.));
+        assert_eq!(
+            Complex::from(-1.).ln(),
+            Complex::new(0., std::f64::consts::PI)
+        );
+    }
+
+    #[test]
+    fn complex_display_test() {
+        assert_eq!(Complex::new(0., 2.).to_string(), "2i");
+        assert_eq!(Complex::new(0., -1.).to_string(), "-i");
+        assert_eq!(Complex::new(-1., -1.).to_string(), "-1 - i");
+        assert_eq!(Complex::new(5., 5.).to_string(), "5 + 5i");
+        assert_eq!(Complex::new(0.5, -2.5).to_string(), "0.5 - 2.5i");
+        assert_eq!(Complex::new(3., 0.).to_string(), "3");
+    }
+}
@@ -0,0 +1,206 @@
+use super::ast::TokenType;
+use super::complex::Complex;
+use super::context::Context;
+use super::printer::binary_symbol;
+use super::value::Value;
+
+/// A complex result as a real number if its imaginary part is zero.
+fn complex_value(z: Complex) -> Value {
+    if z.im == 0. {
+        Value::from_f64(z.re)
+    } else {
+        Value::Complex(z)
+    }
+}
+
+/// An operand in an error message, in parentheses if it's a sum like `1 + i`.
+fn complex_operand(value: &Value) -> String {
+    match value {
+        Value::Complex(z) if z.re != 0. => format!("({})", z),
+        _ => value.to_string(),
+    }
+}
+
+fn to_complex(value: &Value) -> Complex {
+    match value {
+        Value::Complex(z) => *z,
+        _ => Complex::from(value.as_f64()),
+    }
+}
+
+/// Whether an operation needs complex numbers, because of a complex operand
+/// or because it's a root of a negative number, like `(-8)^(1/3)`.
+pub fn needs_complex(op: &TokenType, left: &Value, right: &Value) -> bool {
+    let complex = |value: &Value| matches!(value, Value::Complex(_));
+    let root_of_negative = *op == TokenType::Power
+        && left.as_f64() < 0.
+        && right.as_f64().fract() != 0.
+        && right.as_f64().is_finite();
+    complex(left) || complex(right) || root_of_negative
+}
+
+pub fn complex_unary(op: &TokenType, z: Complex) -> Result<Value, String> {
+    match op {
+        TokenType::PrefixPlus => Ok(Value::Complex(z)),
+        TokenType::PrefixMinus => Ok(Value::Complex(-z)),
+        TokenType::Not => Ok(Value::Bool(z.is_zero())),
+        TokenType::Percent => Ok(complex_value(z / Complex::from(100.))),
+        // The modulus, `|3 + 4i|` is 5.
+        TokenType::Bar => Ok(Value::from_f64(z.abs())),
+        _ => Err(format!(
+            "The factorial of {} isn't defined for complex numbers!",
+            z
+        )),
+    }
+}
+
+pub fn complex_binary(op: &TokenType, left: &Value, right: &Value) -> Result<Value, String> {
+    let (l, r) = (to_complex(left), to_complex(right));
+    let operation = || {
+        format!(
+            "{} {} {}",
+            complex_operand(left),
+            binary_symbol(op),
+            complex_operand(right)
+        )
+    };
+    let result = match op {
+        TokenType::Plus => l + r,
+        TokenType::Minus => l - r,
+        TokenType::Multiply => l * r,
+        TokenType::Divide if r.is_zero() => {
+            return Err(format!("Division by zero in {}!", operation()))
+        }
+        TokenType::Divide => l / r,
+        TokenType::Power => l.pow(r),
+        TokenType::Equal => return Ok(Value::Bool(l == r)),
+        TokenType::NotEqual => return Ok(Value::Bool(l != r)),
+        _ if op.is_comparison() => {
+            return Err(format!(
+                "Complex numbers can't be ordered in {}!",
+                operation()
+            ))
+        }
+        _ => {
+            return Err(format!(
+                "{} isn't defined for complex numbers in {}!",
+                binary_symbol(op),
+                operation()
+            ))
+        }
+    };
+    Ok(complex_value(result))
+}
+
+/// Functions with a complex definition, called on a complex number or
+/// outside of their real domain, like `sqrt(-4)`.
+pub fn complex_call(ctx: &Context, name: &str, args: &[Value]) -> Result<Value, String> {
+    let overloads = ctx.overloads(name);
+    let builtin = overloads
+        .first()
+        .ok_or_else(|| format!("Unknown function {}!", name))?;
+    builtin.arity.check_count(name, args.len())?;
+    let func: fn(&Complex) -> Complex = match builtin.name {
+        "sqrt" => Complex::sqrt,
+        "exp" => Complex::exp,
+        "ln" => Complex::ln,
+        "abs" => return Ok(Value::from_f64(to_complex(&args[0]).abs())),
+        _ => {
+            return Err(format!(
+                "Function {} isn't defined for complex numbers!",
+                name
+            ))
+        }
+    };
+    Ok(complex_value(func(&to_complex(&args[0]))))
+}
+
+#[cfg(test)]
+mod complex_mode_tests {
+    use super::*;
+    use crate::evaluator::{evaluate_value, evaluate_with};
+
+    fn complex_context() -> Context {
+        let mut ctx = Context::new();
+        ctx.set_complex_mode(true);
+        ctx
+    }
+
+    fn do_complex_test(expr: &str, expected: &str) {
+        println!("Expression = {}", expr);
+
+        let expr = expr.to_string();
+        let result = evaluate_value(&expr, &complex_context()).unwrap();
+        assert_eq!(result.to_string(), expected);
+    }
+
+    fn do_complex_error_test(expr: &str, expected: &str) {
+        println!("Expression = {}", expr);
+
+        let expr = expr.to_string();
+        assert_eq!(
+            evaluate_value(&expr, &complex_context()),
+            Err(String::from(expected))
+        );
+    }
+
+    #[test]
+    fn complex_arithmetic_test() {
+        do_complex_test("(1 + 2i) * (3 - i)", "5 + 5i");
+        do_complex_test("(5 + 5i) / (3 - i)", "1 + 2i");
+        do_complex_test("i^2", "-1");
+        do_complex_test("i^3", "-i");
+        do_complex_test("i^-1", "-i");
+        do_complex_test("i * i == -1", "true");
+        do_complex_test("-1 - i", "-1 - i");
+        do_complex_test("2i - 2i", "0");
+        do_complex_test("|3 + 4i|", "5");
+        do_complex_test("abs(3 + 4i)", "5");
+        do_complex_test("sqrt(-4)", "2i");
+        do_complex_test("sqrt(-4)^2", "-4");
+        do_complex_test("sqrt(2i)", "1 + i");
+        do_complex_test("(-4)^0.5", "2i");
+        do_complex_test("1 + 2", "3");
+        do_complex_test("25!", "15511210043330985984000000");
+        do_complex_test("sqrt(4)", "2");
+        assert_eq!(
+            evaluate_value(&"ln(-1)".to_string(), &complex_context()),
+            Ok(Value::Complex(Complex::new(0., std::f64::consts::PI)))
+        );
+    }
+
+    #[test]
+    fn complex_errors_test() {
+        do_complex_error_test("sin(i)", "Function sin isn't defined for complex numbers!");
+        do_complex_error_test(
+            "sqrt(i, 1)",
+            "Function sqrt at pos 0 expects 1 argument, but gets 2, the first extra one at pos 8!",
+        );
+        do_complex_error_test("i < 1", "Complex numbers can't be ordered in i < 1!");
+        do_complex_error_test("(1 + i) / 0", "Division by zero in (1 + i) / 0!");
+        do_complex_error_test("i % 2", "% isn't defined for complex numbers in i % 2!");
+        do_complex_error_test(
+            "i!",
+            "The factorial of i isn't defined for complex numbers!",
+        );
+        assert_eq!(
+            evaluate_with(&"1 + i".to_string(), &complex_context()),
+            Err(String::from("1 + i isn't a real number!"))
+        );
+        assert_eq!(
+            evaluate_with(&"i^2".to_string(), &complex_context()),
+            Ok(-1.)
+        );
+
+        // Off by default, and a variable named `i` hides the imaginary unit.
+        assert!(evaluate_value(&"sqrt(-4)".to_string(), &Context::new()).is_err());
+        assert!(evaluate_value(&"i".to_string(), &Context::new()).is_err());
+        let mut ctx = complex_context();
+        ctx.set_var("i", 3.);
+        assert_eq!(evaluate_value(&"2i".to_string(), &ctx), Ok(Value::Int(6)));
+        assert_eq!(
+            evaluate_value(&"sum(i, 1, 3, i)".to_string(), &complex_context()),
+            Ok(Value::Int(6))
+        );
+    }
+}
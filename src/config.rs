@@ -0,0 +1,213 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use super::command::{run_line, Session};
+use super::error::ColorChoice;
+
+/// The settings a config file changes, `None` for those it leaves at their
+/// defaults.
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    /// `Some(None)` for `precision = full`.
+    pub precision: Option<Option<usize>>,
+    pub strict_floats: Option<bool>,
+    pub history_size: Option<usize>,
+    /// The colors of `color = always` or `never`, `None` for `auto`.
+    pub color: Option<ColorChoice>,
+    /// The lines to evaluate at startup and the numbers of their lines in
+    /// the file.
+    pub startup: Vec<(usize, String)>,
+}
+
+/// The config file read when there is no `--config`:
+/// `$XDG_CONFIG_HOME/ls_calc/config`, or `~/.config/ls_calc/config`.
+pub fn default_path() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("ls_calc").join("config"))
+}
+
+/// Reads the `key = value` lines of a config file. Blank lines and comments
+/// are skipped, and of a key given twice the last one counts, except that
+/// every `startup` line is kept. A line that can't be read doesn't stop the
+/// others, it gives a warning with its number instead.
+pub fn parse(text: &str) -> (Config, Vec<String>) {
+    let mut config = Config::default();
+    let mut warnings = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Err(err) = set(&mut config, i + 1, line) {
+            warnings.push(format!("Line {}: {}", i + 1, err));
+        }
+    }
+    (config, warnings)
+}
+
+/// Sets the setting of the line `number`.
+fn set(config: &mut Config, number: usize, line: &str) -> Result<(), String> {
+    let (key, value) = match line.split_once('=') {
+        Some((key, value)) => (key.trim(), value.trim()),
+        None => return Err(format!("{} isn't a setting like key = value!", line)),
+    };
+    let invalid = || format!("Invalid {} {}!", key, value);
+    match key {
+        "precision" if value == "full" => config.precision = Some(None),
+        "precision" => match value.parse::<usize>() {
+            Ok(digits) if digits > 0 => config.precision = Some(Some(digits)),
+            _ => return Err(invalid()),
+        },
+        "strict_floats" => match value {
+            "true" => config.strict_floats = Some(true),
+            "false" => config.strict_floats = Some(false),
+            _ => return Err(invalid()),
+        },
+        "history_size" => match value.parse::<usize>() {
+            Ok(size) if size > 0 => config.history_size = Some(size),
+            _ => return Err(invalid()),
+        },
+        "color" => match value {
+            "always" => config.color = Some(ColorChoice::Always),
+            "never" => config.color = Some(ColorChoice::Never),
+            "auto" => config.color = None,
+            _ => return Err(invalid()),
+        },
+        "startup" => config.startup.push((number, value.to_string())),
+        _ => return Err(format!("Unknown setting {}!", key)),
+    }
+    Ok(())
+}
+
+/// Reads the config file `path`, `Ok(None)` if it doesn't exist.
+pub fn read(path: &str) -> Result<Option<(Config, Vec<String>)>, String> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(Some(parse(&text))),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(format!("Can't read the config file {}! {}", path, err)),
+    }
+}
+
+impl Config {
+    /// Changes the settings of `session` and then evaluates the `startup`
+    /// lines in order, so that they see the settings. A line that fails
+    /// gives a warning with its number and the others are still evaluated.
+    pub fn apply(&self, session: &mut Session) -> Vec<String> {
+        let ctx = &mut session.ctx;
+        if let Some(precision) = self.precision {
+            ctx.set_precision(precision);
+        }
+        if let Some(strict) = self.strict_floats {
+            ctx.set_strict_floats(strict);
+        }
+        if let Some(size) = self.history_size {
+            ctx.history_mut().set_size(size);
+        }
+        let mut warnings = Vec::new();
+        for (number, line) in &self.startup {
+            if let Err(err) = run_line(line, session) {
+                warnings.push(format!("Line {}: {}", number, err));
+            }
+        }
+        // What the config file evaluates isn't a result of the user's.
+        session.last_expr = None;
+        session.last_report = None;
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod config_tests {
+    use super::*;
+
+    fn fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    #[test]
+    fn parse_test() {
+        let (config, warnings) = read(&fixture("config")).unwrap().unwrap();
+        assert_eq!(
+            config,
+            Config {
+                precision: Some(Some(6)),
+                strict_floats: Some(false),
+                history_size: Some(20),
+                color: Some(ColorChoice::Never),
+                startup: vec![
+                    (9, String::from("tau = 2 * pi")),
+                    (10, String::from("g = 9.81; c = 299792458")),
+                ],
+            }
+        );
+        assert!(warnings.is_empty());
+
+        assert_eq!(
+            parse("precision = full\ncolor = always\ncolor = auto\n"),
+            (
+                Config {
+                    precision: Some(None),
+                    ..Config::default()
+                },
+                vec![]
+            )
+        );
+        assert_eq!(parse(""), (Config::default(), vec![]));
+    }
+
+    #[test]
+    fn parse_warnings_test() {
+        let (config, warnings) = read(&fixture("bad_config")).unwrap().unwrap();
+        assert_eq!(
+            config,
+            Config {
+                history_size: Some(5),
+                startup: vec![(7, String::from("half = 1 / 2"))],
+                ..Config::default()
+            }
+        );
+        assert_eq!(
+            warnings,
+            [
+                "Line 2: Invalid precision 0!",
+                "Line 3: strict_floats isn't a setting like key = value!",
+                "Line 4: Unknown setting angle!",
+                "Line 6: Invalid color red!",
+            ]
+        );
+
+        assert_eq!(read("no/such/config"), Ok(None));
+        assert!(read("src")
+            .unwrap_err()
+            .starts_with("Can't read the config file src!"));
+    }
+
+    #[test]
+    fn apply_test() {
+        let (config, _) = read(&fixture("config")).unwrap().unwrap();
+        let mut session = Session::default();
+        session.ctx.set_strict_floats(true);
+        assert!(config.apply(&mut session).is_empty());
+
+        let ctx = &session.ctx;
+        assert!(!ctx.strict_floats());
+        assert_eq!(ctx.format_options().precision, Some(6));
+        assert_eq!(ctx.history().size(), 20);
+        assert_eq!(ctx.get_var("tau"), Some(2. * std::f64::consts::PI));
+        assert_eq!(ctx.get_var("c"), Some(299792458.));
+        assert_eq!(session.last_expr, None);
+
+        let (config, _) = parse("startup = a = 1\nstartup = b = a +\nstartup = c = a + 1\n");
+        let mut session = Session::default();
+        assert_eq!(
+            config.apply(&mut session),
+            ["Line 2: Ast build error! Operator Plus at pos 6 expects an operand, but gets End!"]
+        );
+        assert_eq!(session.ctx.get_var("c"), Some(2.));
+    }
+}
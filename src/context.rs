@@ -0,0 +1,327 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use super::ast::Node;
+use super::builtins::{builtin_overloads, resolve, Builtin};
+use super::format::{Base, FormatOptions, Notation};
+use super::history::History;
+use super::journal::{Journal, Mutation};
+use super::plot::{PLOT_HEIGHT, PLOT_WIDTH};
+use super::scanner::Scanner;
+use super::special_forms::find_special_form;
+use super::table::TABLE_ROWS;
+
+/// Variable bindings an expression is evaluated against.
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    variables: HashMap<String, f64>,
+    case_insensitive: bool,
+    strict_floats: bool,
+    integer_mode: bool,
+    rational_mode: bool,
+    complex_mode: bool,
+    decimal_comma: bool,
+    si_suffixes: bool,
+    env_access: bool,
+    format: FormatOptions,
+    history: History,
+    journal: Journal,
+    table_rows: Option<usize>,
+    plot_size: Option<(usize, usize)>,
+    functions: Vec<Builtin>,
+}
+
+impl Context {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_var(&mut self, name: &str, value: f64) {
+        self.variables.insert(name.to_string(), value);
+    }
+
+    pub fn get_var(&self, name: &str) -> Option<f64> {
+        self.variables.get(name).copied()
+    }
+
+    /// Removes a variable, returning its value if it was set.
+    pub fn remove_var(&mut self, name: &str) -> Option<f64> {
+        self.variables.remove(name)
+    }
+
+    /// Sets a variable like `set_var` and records the change for `undo`.
+    pub fn define_var(&mut self, name: &str, value: f64) {
+        let before = self.variables.insert(name.to_string(), value);
+        self.journal.record(Mutation {
+            name: name.to_string(),
+            before,
+            after: Some(value),
+        });
+    }
+
+    /// Removes a variable like `remove_var` and records the change for
+    /// `undo`, if it was set.
+    pub fn undefine_var(&mut self, name: &str) -> Option<f64> {
+        let before = self.variables.remove(name)?;
+        self.journal.record(Mutation {
+            name: name.to_string(),
+            before: Some(before),
+            after: None,
+        });
+        Some(before)
+    }
+
+    /// Reverts the newest change of `define_var` or `undefine_var`: a new
+    /// variable is removed, and one that had a value gets it back.
+    pub fn undo(&mut self) -> Option<Mutation> {
+        let mutation = self.journal.undo()?;
+        self.put_var(&mutation.name, mutation.before);
+        Some(mutation)
+    }
+
+    /// Applies the newest change that `undo` reverted again.
+    pub fn redo(&mut self) -> Option<Mutation> {
+        let mutation = self.journal.redo()?;
+        self.put_var(&mutation.name, mutation.after);
+        Some(mutation)
+    }
+
+    fn put_var(&mut self, name: &str, value: Option<f64>) {
+        match value {
+            Some(value) => self.variables.insert(name.to_string(), value),
+            None => self.variables.remove(name),
+        };
+    }
+
+    /// Removes every variable, settings stay as they are. The changes that
+    /// could be undone are forgotten too.
+    pub fn clear_vars(&mut self) {
+        self.variables.clear();
+        self.journal.clear();
+    }
+
+    /// The variables sorted by name.
+    pub fn vars(&self) -> Vec<(&str, f64)> {
+        let mut vars: Vec<(&str, f64)> = self
+            .variables
+            .iter()
+            .map(|(name, value)| (name.as_str(), *value))
+            .collect();
+        vars.sort_by(|a, b| a.0.cmp(b.0));
+        vars
+    }
+
+    /// Lets function and constant names be written in any case, so `SIN(PI)`
+    /// is `sin(pi)`. Variables set with `set_var` stay case-sensitive.
+    pub fn set_case_insensitive(&mut self, enabled: bool) {
+        self.case_insensitive = enabled;
+    }
+
+    /// Makes operations that turn numbers into NaN or infinity errors, like
+    /// `0/0`, `inf - inf` or `exp(1000)`, instead of passing the value on.
+    /// It's off by default, the REPL turns it on.
+    pub fn set_strict_floats(&mut self, enabled: bool) {
+        self.strict_floats = enabled;
+    }
+
+    pub fn strict_floats(&self) -> bool {
+        self.strict_floats
+    }
+
+    /// Evaluates whole numbers as exact 64-bit integers that report overflow
+    /// instead of losing precision. See `ExactEvaluator`.
+    pub fn set_integer_mode(&mut self, enabled: bool) {
+        self.integer_mode = enabled;
+    }
+
+    pub fn integer_mode(&self) -> bool {
+        self.integer_mode
+    }
+
+    /// Keeps divisions and decimal literals exact as fractions, so
+    /// `1/3 + 1/6` is `1/2`. Integer mode takes precedence.
+    pub fn set_rational_mode(&mut self, enabled: bool) {
+        self.rational_mode = enabled;
+    }
+
+    pub fn rational_mode(&self) -> bool {
+        self.rational_mode
+    }
+
+    /// Makes `i` the imaginary unit and lets operations and some functions
+    /// give complex results, so `sqrt(-4)` is `2i` instead of an error.
+    pub fn set_complex_mode(&mut self, enabled: bool) {
+        self.complex_mode = enabled;
+    }
+
+    pub fn complex_mode(&self) -> bool {
+        self.complex_mode
+    }
+
+    /// Reads expressions with `,` as the decimal separator and `;` between
+    /// function arguments, `max(1,5; 2,5)`. See `Scanner::set_decimal_comma`.
+    pub fn set_decimal_comma(&mut self, enabled: bool) {
+        self.decimal_comma = enabled;
+    }
+
+    pub fn decimal_comma(&self) -> bool {
+        self.decimal_comma
+    }
+
+    /// Reads numbers ending in an SI prefix like `4.7k` as multiples of its
+    /// power of ten. See `Scanner::set_si_suffixes`.
+    pub fn set_si_suffixes(&mut self, enabled: bool) {
+        self.si_suffixes = enabled;
+    }
+
+    pub fn si_suffixes(&self) -> bool {
+        self.si_suffixes
+    }
+
+    /// A scanner of `expr` that reads numbers like expressions of the
+    /// context are read, with its decimal separator and SI suffixes.
+    pub fn scanner<'a>(&self, expr: &'a String) -> Scanner<'a> {
+        let mut scanner = Scanner::new(expr);
+        scanner.set_decimal_comma(self.decimal_comma);
+        scanner.set_si_suffixes(self.si_suffixes);
+        scanner
+    }
+
+    /// Lets `env("NAME")` read environment variables. It's off by default,
+    /// so an expression from elsewhere can't probe the environment of an
+    /// embedding. The command line turns it on.
+    pub fn set_env_access(&mut self, enabled: bool) {
+        self.env_access = enabled;
+    }
+
+    pub fn env_access(&self) -> bool {
+        self.env_access
+    }
+
+    /// The significant digits results are displayed with, 15 by default, or
+    /// `None` to display the exact f64. Evaluation is unaffected.
+    pub fn set_precision(&mut self, precision: Option<usize>) {
+        self.format.precision = precision;
+    }
+
+    /// Displays results as plain decimals, `1234567`, in scientific
+    /// notation, `1.234567e6`, or engineering notation, `1.234567e6` and
+    /// `66.2e3`. Integers are rounded like floats in the latter two.
+    pub fn set_notation(&mut self, notation: Notation) {
+        self.format.notation = notation;
+    }
+
+    /// Floats below `below` or from `from` on are displayed in scientific
+    /// notation even in normal notation, by default below 1e-6 and from 1e15.
+    pub fn set_scientific_thresholds(&mut self, below: f64, from: f64) {
+        self.format.scientific_below = below;
+        self.format.scientific_from = from;
+    }
+
+    /// Displays integer results in another base, `255` as `0xFF`. Other
+    /// numbers, like `2.5`, are still displayed in decimal.
+    pub fn set_base(&mut self, base: Base) {
+        self.format.base = base;
+    }
+
+    /// Displays negative integers in other bases as two's complement of
+    /// `width` bits, `-1` is `0xFF` with 8 bits, instead of with a minus.
+    pub fn set_width(&mut self, width: Option<u32>) {
+        self.format.width = width;
+    }
+
+    /// Groups the digits of displayed results by thousands, `1,234,567.89`
+    /// with a comma, or `None` not to. Scientific notation isn't grouped.
+    pub fn set_grouping(&mut self, separator: Option<char>) {
+        self.format.grouping = separator;
+    }
+
+    pub fn format_options(&self) -> &FormatOptions {
+        &self.format
+    }
+
+    /// The results evaluated so far, which `$1` and `out(1)` refer to.
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    pub fn history_mut(&mut self) -> &mut History {
+        &mut self.history
+    }
+
+    /// How many rows a table has at most, see `table::tabulate`.
+    pub fn table_rows(&self) -> usize {
+        self.table_rows.unwrap_or(TABLE_ROWS)
+    }
+
+    pub fn set_table_rows(&mut self, rows: usize) {
+        self.table_rows = Some(rows);
+    }
+
+    /// The width and the height of a plot in chars, see `plot::plot`.
+    pub fn plot_size(&self) -> (usize, usize) {
+        self.plot_size.unwrap_or((PLOT_WIDTH, PLOT_HEIGHT))
+    }
+
+    pub fn set_plot_size(&mut self, width: usize, height: usize) {
+        self.plot_size = Some((width, height));
+    }
+
+    /// Registers a function of the embedder, or another overload of a
+    /// function. It fails if one of the builtin or registered overloads of
+    /// its name takes the same number of arguments.
+    pub fn add_function(&mut self, function: Builtin) -> Result<(), String> {
+        if find_special_form(function.name).is_some() {
+            return Err(format!(
+                "Function {} binds a variable and can't be overloaded!",
+                function.name
+            ));
+        }
+        let builtins = builtin_overloads(function.name).map(|builtin| (builtin, "builtin "));
+        let added = self.functions.iter().filter(|added| added.name == function.name);
+        let overlapping = builtins
+            .chain(added.map(|added| (added, "")))
+            .find(|(overload, _)| overload.arity.overlaps(&function.arity));
+        if let Some((overload, kind)) = overlapping {
+            return Err(format!(
+                "Function {} taking {} overlaps its {}overload taking {}!",
+                function.name, function.arity, kind, overload.arity
+            ));
+        }
+        self.functions.push(function);
+        Ok(())
+    }
+
+    /// The names of the registered functions.
+    pub fn function_names(&self) -> impl Iterator<Item = &str> {
+        self.functions.iter().map(|function| function.name)
+    }
+
+    /// The overloads of the function `name`, the builtin ones first.
+    pub fn overloads(&self, name: &str) -> Vec<&Builtin> {
+        let name = self.builtin_name(name);
+        let mut overloads: Vec<&Builtin> = builtin_overloads(&name).collect();
+        overloads.extend(self.functions.iter().filter(|function| *function.name == *name));
+        overloads
+    }
+
+    /// The overload of `name` that a call at `pos` with `args` resolves to.
+    pub fn function(&self, name: &str, args: &[Node], pos: usize) -> Result<&Builtin, String> {
+        let positions: Vec<usize> = args.iter().map(|arg| arg.pos).collect();
+        self.function_at(name, &positions, pos)
+    }
+
+    /// Like `function`, for a call with its arguments at `arg_positions`.
+    pub fn function_at(&self, name: &str, arg_positions: &[usize], pos: usize) -> Result<&Builtin, String> {
+        resolve(name, &self.overloads(name), arg_positions, pos)
+    }
+
+    /// The name to look up a function or constant by.
+    pub fn builtin_name<'n>(&self, name: &'n str) -> Cow<'n, str> {
+        if self.case_insensitive {
+            Cow::Owned(name.to_lowercase())
+        } else {
+            Cow::Borrowed(name)
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use std::io::Write;
+use std::mem;
+
+use super::ast::parse_expr;
+use super::context::Context;
+use super::error::Diagnostic;
+use super::evaluator::evaluate_tree;
+use super::format::format_value;
+use super::scanner::is_name;
+use super::stream::StreamStats;
+
+/// The header of the column the results are written to.
+pub const RESULT_COLUMN: &str = "result";
+
+/// The records of `csv` as RFC 4180 has them: fields are separated by commas
+/// and records by line breaks, and a field in double quotes can hold both,
+/// with `""` for a quote. Blank lines are left out.
+pub fn records(csv: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    // Whether the field is in quotes, and whether it has started.
+    let mut quoted = false;
+    let mut started = false;
+    let mut chars = csv.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => quoted = false,
+                c => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' if !started => quoted = true,
+            ',' => record.push(mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => continue,
+            '\n' => {
+                record.push(mem::take(&mut field));
+                records.push(mem::take(&mut record));
+            }
+            c => field.push(c),
+        }
+        started = !matches!(c, ',' | '\n');
+    }
+    if quoted {
+        return Err(format!(
+            "Malformed CSV! Record {} has an unclosed quote!",
+            records.len() + 1
+        ));
+    }
+    if started || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records.retain(|record| record.len() > 1 || !record[0].is_empty());
+    Ok(records)
+}
+
+/// A field as CSV writes it, in quotes if it holds a comma, a quote or a
+/// line break.
+pub fn field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+fn write_record<W: Write>(out: &mut W, fields: &[String]) -> Result<(), String> {
+    let fields: Vec<String> = fields.iter().map(|text| field(text)).collect();
+    writeln!(out, "{}", fields.join(",")).map_err(|err| format!("Output writing error! {}", err))
+}
+
+/// Evaluates `formula` for each row of `csv`, whose header names the
+/// variables, and writes the rows to `out` with a `result` column. A column
+/// is bound with the settings of `ctx` to the number in its cell, and a cell
+/// that isn't a number leaves its variable undefined for the row. A row the
+/// formula fails for gets `marker` as its result and a warning in
+/// `warnings` with its number, counting from the first row after the header.
+pub fn eval_csv<W: Write, E: Write>(
+    csv: &str,
+    formula: &str,
+    ctx: &Context,
+    marker: &str,
+    mut out: W,
+    mut warnings: E,
+) -> Result<StreamStats, Diagnostic> {
+    let tree = parse_expr(formula, ctx)?;
+    let mut records = records(csv)?.into_iter();
+    let mut header = match records.next() {
+        Some(header) => header,
+        None => return Err(Diagnostic::from(String::from("Malformed CSV! It has no header!"))),
+    };
+    if let Some(first) = header.first_mut() {
+        // The byte order mark spreadsheets start a file with.
+        *first = first.trim_start_matches('\u{feff}').to_string();
+    }
+    let names: Vec<&str> = header.iter().map(|name| name.trim()).collect();
+    for (i, name) in names.iter().enumerate() {
+        if !is_name(name) {
+            let _ = writeln!(
+                warnings,
+                "Column {} {:?} isn't a variable name, it's left out!",
+                i + 1,
+                name
+            );
+        }
+    }
+    write_record(
+        &mut out,
+        &[&header[..], &[String::from(RESULT_COLUMN)]].concat(),
+    )?;
+
+    let mut row_ctx = ctx.clone();
+    let mut stats = StreamStats::default();
+    for (i, mut row) in records.enumerate() {
+        for (j, name) in names.iter().enumerate().filter(|(_, name)| is_name(name)) {
+            match row.get(j).and_then(|cell| cell.trim().parse::<f64>().ok()) {
+                Some(value) if value.is_finite() => row_ctx.set_var(name, value),
+                _ => {
+                    row_ctx.remove_var(name);
+                }
+            }
+        }
+        let result = match evaluate_tree(&tree, formula, &row_ctx) {
+            Ok(value) => {
+                stats.succeeded += 1;
+                format_value(&value, ctx.format_options())
+            }
+            Err(err) => {
+                stats.failed += 1;
+                let _ = writeln!(warnings, "Row {}: {}", i + 1, err);
+                marker.to_string()
+            }
+        };
+        // Short rows are filled up, so the results stay in their column.
+        if row.len() < header.len() {
+            row.resize(header.len(), String::new());
+        }
+        row.push(result);
+        write_record(&mut out, &row)?;
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod csv_tests {
+    use super::*;
+
+    fn do_test(csv: &str, formula: &str, expect: &str, expect_warnings: &str) -> StreamStats {
+        println!("CSV = {:?}, formula = {:?}", csv, formula);
+
+        let (mut out, mut warnings) = (Vec::new(), Vec::new());
+        let stats = eval_csv(
+            csv,
+            formula,
+            &Context::new(),
+            "#ERR",
+            &mut out,
+            &mut warnings,
+        );
+        assert_eq!(String::from_utf8(out).unwrap(), expect);
+        assert_eq!(String::from_utf8(warnings).unwrap(), expect_warnings);
+        stats.unwrap()
+    }
+
+    #[test]
+    fn eval_csv_test() {
+        let csv = "\
+item,price,qty,tax
+pen,1.5,4,0.2
+\"paper, A4\",5,2,0.1
+ink,n/a,1,0.2
+\"a \"\"big\"\" box\",10,1,0
+";
+        let stats = do_test(
+            csv,
+            "price * qty * (1 + tax)",
+            "item,price,qty,tax,result\n\
+             pen,1.5,4,0.2,7.2\n\
+             \"paper, A4\",5,2,0.1,11\n\
+             ink,n/a,1,0.2,#ERR\n\
+             \"a \"\"big\"\" box\",10,1,0,10\n",
+            "Row 3: Unknown variable price!\n",
+        );
+        assert_eq!(
+            stats,
+            StreamStats {
+                succeeded: 3,
+                failed: 1
+            }
+        );
+    }
+
+    #[test]
+    fn rows_test() {
+        // A value of a row doesn't stay for the next one, where the cell
+        // isn't a number.
+        do_test(
+            "x,y\r\n1,2\r\n\r\n3\r\n",
+            "x + y",
+            "x,y,result\n1,2,3\n3,,#ERR\n",
+            "Row 2: Unknown variable y!\n",
+        );
+        do_test(
+            "\u{feff}x,unit price\n4",
+            "x^2",
+            "x,unit price,result\n4,,16\n",
+            "Column 2 \"unit price\" isn't a variable name, it's left out!\n",
+        );
+
+        let mut out = Vec::new();
+        let ctx = Context::new();
+        assert_eq!(
+            eval_csv("x\n1", "x +", &ctx, "", &mut out, Vec::new()).map_err(String::from),
+            Err(String::from(
+                "Ast build error! Operator Plus at pos 2 expects an operand, but gets End!"
+            ))
+        );
+        assert_eq!(
+            eval_csv("", "1", &ctx, "", &mut out, Vec::new()).map_err(String::from),
+            Err(String::from("Malformed CSV! It has no header!"))
+        );
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn records_test() {
+        assert_eq!(
+            records("a,\"b,\nc\"\n\"\",d"),
+            Ok(vec![
+                vec![String::from("a"), String::from("b,\nc")],
+                vec![String::new(), String::from("d")],
+            ])
+        );
+        assert_eq!(
+            records("a\n\"b"),
+            Err(String::from(
+                "Malformed CSV! Record 2 has an unclosed quote!"
+            ))
+        );
+        assert_eq!(records("\n\n"), Ok(Vec::new()));
+        for text in ["plain", "a,b", "say \"hi\"", "two\nlines", ""] {
+            assert_eq!(
+                records(&field(text)).unwrap_or_default().concat().concat(),
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,355 @@
+use std::fmt;
+
+use super::ast::{Node, NodePtr, TokenType};
+use super::special_forms::find_special_form;
+use super::visitor::{walk, walk_node, Visitor};
+
+#[derive(PartialEq, Debug)]
+pub enum DiffError {
+    /// The node has no derivative rule, e.g. a factorial or modulo that
+    /// depends on the variable.
+    Unsupported(String),
+}
+
+impl fmt::Display for DiffError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiffError::Unsupported(node) => write!(f, "Can't differentiate {}!", node),
+        }
+    }
+}
+
+/// Checks whether a tree mentions the variable `var`.
+struct DependsOn<'a> {
+    var: &'a str,
+}
+
+impl<'a> Visitor for DependsOn<'a> {
+    type Output = bool;
+
+    fn visit_number(&mut self, _n: f64) -> bool {
+        false
+    }
+
+    fn visit_variable(&mut self, name: &str) -> bool {
+        name == self.var
+    }
+
+    fn visit_history(&mut self, _n: usize) -> bool {
+        false
+    }
+
+    fn visit_text(&mut self, _text: &str) -> bool {
+        false
+    }
+
+    fn visit_call(&mut self, name: &str, args: &[Node]) -> bool {
+        let scoped = find_special_form(name).and_then(|form| form.scoped_args(args));
+        match scoped {
+            Some((var, body)) if args[var].token == TokenType::Variable(self.var.to_string()) => {
+                args.iter()
+                    .enumerate()
+                    .any(|(i, arg)| i != var && i != body && walk_node(self, arg))
+            }
+            _ => args.iter().any(|arg| walk_node(self, arg)),
+        }
+    }
+
+    fn visit_lambda(&mut self, param: &str, body: &NodePtr) -> bool {
+        param != self.var && walk(self, body)
+    }
+
+    fn visit_unary(&mut self, _op: &TokenType, operand: &NodePtr) -> bool {
+        walk(self, operand)
+    }
+
+    fn visit_binary(&mut self, _op: &TokenType, left: &NodePtr, right: &NodePtr) -> bool {
+        walk(self, left) || walk(self, right)
+    }
+
+    fn visit_none(&mut self) -> bool {
+        false
+    }
+}
+
+fn num(n: f64) -> Node {
+    Node::new(TokenType::Number(n), None, None)
+}
+
+fn unary(op: TokenType, operand: Node) -> Node {
+    Node::new(op, Some(Box::new(operand)), None)
+}
+
+fn binary(op: TokenType, left: Node, right: Node) -> Node {
+    Node::new(op, Some(Box::new(left)), Some(Box::new(right)))
+}
+
+fn call(name: &str, arg: Node) -> Node {
+    Node::new(TokenType::Call(name.to_string(), vec![arg]), None, None)
+}
+
+fn operand(node: &NodePtr) -> Result<&Node, DiffError> {
+    node.as_deref()
+        .ok_or_else(|| DiffError::Unsupported(String::from("incomplete node")))
+}
+
+/// Derivative of the outer function of `name(u)` with respect to `u`.
+pub fn outer_derivative(name: &str, u: &Node) -> Option<Node> {
+    let u = u.clone();
+    let square = |u: Node| binary(TokenType::Power, u, num(2.));
+    let reciprocal = |u: Node| binary(TokenType::Divide, num(1.), u);
+
+    let d = match name {
+        "sin" => call("cos", u),
+        "cos" => unary(TokenType::PrefixMinus, call("sin", u)),
+        "tan" => reciprocal(square(call("cos", u))),
+        "asin" => reciprocal(call("sqrt", binary(TokenType::Minus, num(1.), square(u)))),
+        "acos" => unary(
+            TokenType::PrefixMinus,
+            reciprocal(call("sqrt", binary(TokenType::Minus, num(1.), square(u)))),
+        ),
+        "atan" => reciprocal(binary(TokenType::Plus, num(1.), square(u))),
+        "sinh" => call("cosh", u),
+        "cosh" => call("sinh", u),
+        "tanh" => reciprocal(square(call("cosh", u))),
+        "exp" => call("exp", u),
+        "ln" | "log" => reciprocal(u),
+        "sqrt" | "root" => reciprocal(binary(TokenType::Multiply, num(2.), call("sqrt", u))),
+        "abs" => binary(TokenType::Divide, u.clone(), call("abs", u)),
+        _ => return None,
+    };
+    Some(d)
+}
+
+fn derive(node: &Node, var: &str) -> Result<Node, DiffError> {
+    if !node.depends_on(var) {
+        return Ok(num(0.));
+    }
+
+    let d = match &node.token {
+        TokenType::Variable(_) => num(1.),
+        TokenType::PrefixPlus => derive(operand(&node.left)?, var)?,
+        TokenType::PrefixMinus => unary(TokenType::PrefixMinus, derive(operand(&node.left)?, var)?),
+        TokenType::Percent => binary(
+            TokenType::Divide,
+            derive(operand(&node.left)?, var)?,
+            num(100.),
+        ),
+        TokenType::Plus | TokenType::Minus => {
+            let du = derive(operand(&node.left)?, var)?;
+            let dv = derive(operand(&node.right)?, var)?;
+            binary(node.token.clone(), du, dv)
+        }
+        TokenType::Multiply => {
+            let (u, v) = (operand(&node.left)?, operand(&node.right)?);
+            binary(
+                TokenType::Plus,
+                binary(TokenType::Multiply, derive(u, var)?, v.clone()),
+                binary(TokenType::Multiply, u.clone(), derive(v, var)?),
+            )
+        }
+        TokenType::Divide => {
+            let (u, v) = (operand(&node.left)?, operand(&node.right)?);
+            binary(
+                TokenType::Divide,
+                binary(
+                    TokenType::Minus,
+                    binary(TokenType::Multiply, derive(u, var)?, v.clone()),
+                    binary(TokenType::Multiply, u.clone(), derive(v, var)?),
+                ),
+                binary(TokenType::Power, v.clone(), num(2.)),
+            )
+        }
+        TokenType::Power => {
+            let (u, v) = (operand(&node.left)?, operand(&node.right)?);
+            if !v.depends_on(var) {
+                // n * u^(n - 1) * u'
+                let lowered = binary(TokenType::Minus, v.clone(), num(1.));
+                binary(
+                    TokenType::Multiply,
+                    binary(
+                        TokenType::Multiply,
+                        v.clone(),
+                        binary(TokenType::Power, u.clone(), lowered),
+                    ),
+                    derive(u, var)?,
+                )
+            } else if !u.depends_on(var) {
+                // a^v * ln(a) * v'
+                binary(
+                    TokenType::Multiply,
+                    binary(TokenType::Multiply, node.clone(), call("ln", u.clone())),
+                    derive(v, var)?,
+                )
+            } else {
+                // u^v * (v' * ln(u) + v * u' / u)
+                binary(
+                    TokenType::Multiply,
+                    node.clone(),
+                    binary(
+                        TokenType::Plus,
+                        binary(TokenType::Multiply, derive(v, var)?, call("ln", u.clone())),
+                        binary(
+                            TokenType::Divide,
+                            binary(TokenType::Multiply, v.clone(), derive(u, var)?),
+                            u.clone(),
+                        ),
+                    ),
+                )
+            }
+        }
+        TokenType::Bar => {
+            let u = operand(&node.left)?;
+            binary(
+                TokenType::Multiply,
+                binary(TokenType::Divide, u.clone(), node.clone()),
+                derive(u, var)?,
+            )
+        }
+        TokenType::Call(name, args) if args.len() == 1 => {
+            let outer = outer_derivative(name, &args[0])
+                .ok_or_else(|| DiffError::Unsupported(format!("function {}", name)))?;
+            binary(TokenType::Multiply, outer, derive(&args[0], var)?)
+        }
+        TokenType::Call(name, _) => {
+            return Err(DiffError::Unsupported(format!("function {}", name)))
+        }
+        TokenType::Lambda(_) => return Err(DiffError::Unsupported(String::from("lambda"))),
+        TokenType::Factorial => return Err(DiffError::Unsupported(String::from("factorial"))),
+        TokenType::DoubleFactorial => {
+            return Err(DiffError::Unsupported(String::from("double factorial")))
+        }
+        TokenType::Modulo => return Err(DiffError::Unsupported(String::from("modulo"))),
+        TokenType::Less
+        | TokenType::LessEqual
+        | TokenType::Greater
+        | TokenType::GreaterEqual
+        | TokenType::Equal
+        | TokenType::NotEqual => return Err(DiffError::Unsupported(String::from("comparison"))),
+        TokenType::And | TokenType::Or | TokenType::Not => {
+            return Err(DiffError::Unsupported(String::from("logical operator")))
+        }
+        TokenType::FloorDivide => {
+            return Err(DiffError::Unsupported(String::from("floor division")))
+        }
+        TokenType::Number(_) | TokenType::History(_) | TokenType::Text(_) => num(0.),
+    };
+    Ok(d)
+}
+
+impl Node {
+    pub fn depends_on(&self, var: &str) -> bool {
+        walk_node(&mut DependsOn { var }, self)
+    }
+
+    /// Returns the constant folded derivative of the tree with respect to
+    /// `var`. Any other variable is treated as a constant.
+    pub fn differentiate(&self, var: &str) -> Result<Node, DiffError> {
+        Ok(derive(self, var)?.fold_constants())
+    }
+}
+
+#[cfg(test)]
+mod derivative_tests {
+    use super::*;
+    use crate::ast::parse;
+    use crate::context::Context;
+    use crate::evaluator::Evaluator;
+
+    fn build(expr: &str) -> Node {
+        *parse(expr).unwrap().unwrap()
+    }
+
+    fn eval_at(node: &Node, x: f64) -> f64 {
+        let mut ctx = Context::new();
+        ctx.set_var("x", x);
+        ctx.set_var("y", 2.);
+        walk_node(&mut Evaluator::new(&ctx), node).unwrap()
+    }
+
+    fn do_test(expr: &str, points: &[f64]) {
+        println!("Expression = {}", expr);
+
+        let tree = build(expr);
+        let derivative = tree.differentiate("x").unwrap();
+
+        let h = 1e-6;
+        for &x in points {
+            let expected = (eval_at(&tree, x + h) - eval_at(&tree, x - h)) / (2. * h);
+            let result = eval_at(&derivative, x);
+
+            println!("x = {}, expected = {}, result = {}", x, expected, result);
+            assert!((result - expected).abs() <= 1e-4 * expected.abs().max(1.));
+        }
+    }
+
+    #[test]
+    fn polynomial_derivative_test() {
+        let points = [-2., -0.5, 0.3, 1.7];
+        do_test("x", &points);
+        do_test("x^3 - 2*x + 7", &points);
+        do_test("-(x^2) * (x - 1)", &points);
+        do_test("(x^2)% + 2", &points);
+        do_test("(x^2 + 1) / (x^2 + 2)", &points);
+    }
+
+    #[test]
+    fn function_derivative_test() {
+        let points = [0.3, 0.7, 1.5, 2.5];
+        do_test("x * sin(x)", &points);
+        do_test("exp(2*x) / x", &points);
+        do_test("ln(x^2 + 1)", &points);
+        do_test("sqrt(x) * cos(x)", &points);
+        do_test("tan(x / 2) + atan(x)", &points);
+        do_test("asin(x / 3) - acos(x / 3)", &points);
+        do_test("sinh(x) * tanh(x) - cosh(x)", &points);
+        do_test("ln(x) * log(x)", &points);
+    }
+
+    #[test]
+    fn power_derivative_test() {
+        let points = [0.3, 0.7, 1.5, 2.5];
+        do_test("2^x", &points);
+        do_test("x^x", &points);
+        do_test("x^y + y", &points);
+        do_test("|x - 1| + abs(x - 2)", &points);
+    }
+
+    #[test]
+    fn folded_derivative_test() {
+        assert_eq!(build("3*x").differentiate("x"), Ok(build("3")));
+        assert_eq!(build("x^2").differentiate("x"), Ok(build("2*x")));
+        assert_eq!(build("y * 5 + 3!").differentiate("x"), Ok(build("0")));
+        assert_eq!(build("3! * x").differentiate("x"), Ok(build("6")));
+    }
+
+    #[test]
+    fn bound_variable_derivative_test() {
+        assert_eq!(build("sum(x, 1, 3, x)").differentiate("x"), Ok(build("0")));
+        assert_eq!(
+            build("sum(x -> x, 1, 3) + y").differentiate("x"),
+            Ok(build("0"))
+        );
+        assert!(build("sum(i, 1, x, i)").differentiate("x").is_err());
+    }
+
+    #[test]
+    fn unsupported_derivative_test() {
+        assert_eq!(
+            build("x!").differentiate("x"),
+            Err(DiffError::Unsupported(String::from("factorial")))
+        );
+        assert_eq!(
+            build("x % 2").differentiate("x"),
+            Err(DiffError::Unsupported(String::from("modulo")))
+        );
+        assert_eq!(
+            build("floor(x)").differentiate("x"),
+            Err(DiffError::Unsupported(String::from("function floor")))
+        );
+        assert_eq!(
+            DiffError::Unsupported(String::from("factorial")).to_string(),
+            "Can't differentiate factorial!"
+        );
+    }
+}
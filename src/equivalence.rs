@@ -0,0 +1,133 @@
+use super::ast::{parse, NodePtr};
+use super::context::Context;
+use super::error::CalcError;
+use super::evaluator::Evaluator;
+use super::random::Random;
+use super::visitor::walk;
+
+/// Values every variable takes in turn. They're shifted per variable so
+/// that `x` and `y` don't always get the same value.
+const SAMPLES: &[f64] = &[-3.7, -2., -1., -0.5, -0.1, 0., 0.1, 0.5, 1., 2., 3.3, 10.];
+const RANDOM_POINTS: usize = 16;
+const RANDOM_RANGE: f64 = 20.;
+const MIN_COMPARISONS: usize = 5;
+const EPSILON: f64 = 1e-9;
+
+fn sample_points(vars: &[&str]) -> Vec<Vec<f64>> {
+    let mut points: Vec<Vec<f64>> = (0..SAMPLES.len())
+        .map(|i| {
+            (0..vars.len())
+                .map(|j| SAMPLES[(i + 5 * j) % SAMPLES.len()])
+                .collect()
+        })
+        .collect();
+
+    let mut rng = Random::new(0x9E37_79B9_7F4A_7C15);
+    for _ in 0..RANDOM_POINTS {
+        let point = vars
+            .iter()
+            .map(|_| (rng.next_f64() - 0.5) * RANDOM_RANGE)
+            .collect();
+        points.push(point);
+    }
+    points
+}
+
+fn eval_at(tree: &NodePtr, ctx: &Context) -> Option<f64> {
+    match walk(&mut Evaluator::new(ctx), tree) {
+        Ok(n) if n.is_finite() => Some(n),
+        _ => None,
+    }
+}
+
+fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() <= EPSILON * a.abs().max(b.abs()).max(1.)
+}
+
+/// Checks numerically whether `a` and `b` give the same value for every
+/// assignment of `vars`. Points where either side fails or isn't finite are
+/// skipped, but at least a few points have to be comparable.
+pub fn equivalent(a: &str, b: &str, vars: &[&str]) -> Result<bool, CalcError> {
+    let a = parse(a).map_err(CalcError::Parse)?;
+    let b = parse(b).map_err(CalcError::Parse)?;
+
+    let mut comparisons = 0;
+    for point in sample_points(vars) {
+        let mut ctx = Context::new();
+        for (var, value) in vars.iter().zip(point) {
+            ctx.set_var(var, value);
+        }
+
+        if let (Some(a), Some(b)) = (eval_at(&a, &ctx), eval_at(&b, &ctx)) {
+            if !close(a, b) {
+                return Ok(false);
+            }
+            comparisons += 1;
+        }
+    }
+
+    if comparisons < MIN_COMPARISONS {
+        return Err(CalcError::Eval(format!(
+            "Expressions can be compared at {} point(s) only, but need {}!",
+            comparisons, MIN_COMPARISONS
+        )));
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod equivalence_tests {
+    use super::*;
+
+    fn do_test(a: &str, b: &str, vars: &[&str], expected: bool) {
+        println!("Expressions = {}, {}", a, b);
+
+        assert_eq!(equivalent(a, b, vars), Ok(expected));
+        assert_eq!(equivalent(b, a, vars), Ok(expected));
+    }
+
+    #[test]
+    fn equivalent_test() {
+        do_test("(x+1)^2", "x^2 + 2*x + 1", &["x"], true);
+        do_test("sin(x)^2 + cos(x)^2", "1", &["x"], true);
+        do_test("x*y - y", "(x - 1)*y", &["x", "y"], true);
+        do_test("exp(a + b)", "exp(a) * exp(b)", &["a", "b"], true);
+        do_test("2 * pi", "pi + pi", &[], true);
+    }
+
+    #[test]
+    fn not_equivalent_test() {
+        do_test("x/2", "x*2", &["x"], false);
+        do_test("|x|", "x", &["x"], false);
+        do_test("x - y", "y - x", &["x", "y"], false);
+        do_test("x^2", "x^2 + 0.001", &["x"], false);
+    }
+
+    #[test]
+    fn singular_test() {
+        do_test("x / x", "1", &["x"], true);
+        do_test("ln(x) + ln(x)", "2 * ln(x)", &["x"], true);
+        do_test("sqrt(x)^2", "x", &["x"], true);
+        do_test("1 / (x - 1)", "1 / (x + 1)", &["x"], false);
+    }
+
+    #[test]
+    fn errors_test() {
+        assert_eq!(
+            equivalent("sqrt(-1 - x^2)", "0", &["x"]),
+            Err(CalcError::Eval(String::from(
+                "Expressions can be compared at 0 point(s) only, but need 5!"
+            )))
+        );
+        assert_eq!(
+            equivalent("x + y", "y + x", &["x"]),
+            Err(CalcError::Eval(String::from(
+                "Expressions can be compared at 0 point(s) only, but need 5!"
+            )))
+        );
+        assert!(matches!(
+            equivalent("x +", "x", &["x"]),
+            Err(CalcError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,366 @@
+use std::fmt;
+
+use super::scanner::ScanError;
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum CalcError {
+    /// The expression couldn't be parsed.
+    Parse(String),
+    /// The expression was parsed, but couldn't be evaluated.
+    Eval(String),
+}
+
+impl fmt::Display for CalcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CalcError::Parse(err) => write!(f, "Ast build error! {}", err),
+            CalcError::Eval(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// The kind of an error, which the CLI exits with and JSON reports.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ErrorCode {
+    /// The expression couldn't be parsed.
+    Parse,
+    /// Anything else, like an error of evaluation.
+    Eval,
+}
+
+impl ErrorCode {
+    /// `E001` for a parse error and `E002` for any other.
+    pub fn id(self) -> &'static str {
+        match self {
+            ErrorCode::Parse => "E001",
+            ErrorCode::Eval => "E002",
+        }
+    }
+}
+
+/// Chars of the input an error points at, or that a token or a node of a
+/// tree covers.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub len: usize,
+}
+
+impl Span {
+    /// The chars from `start` up to `end`.
+    pub fn between(start: usize, end: usize) -> Self {
+        Self {
+            start,
+            len: end.saturating_sub(start),
+        }
+    }
+
+    pub fn end(&self) -> usize {
+        self.start + self.len
+    }
+
+    /// The smallest span that covers both.
+    pub fn cover(self, other: Span) -> Self {
+        Span::between(self.start.min(other.start), self.end().max(other.end()))
+    }
+}
+
+/// An error of an expression with its code and what of the input it points
+/// at, which are set where it's raised: the token a parse error names, or
+/// the node of the tree an evaluation fails at, like all of `0 / 0` for a
+/// float error. Errors that aren't about a part of the input point at
+/// nothing.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Diagnostic {
+    pub code: ErrorCode,
+    pub message: String,
+    /// The position the error is at, the operator of `7 % 0` or the name of
+    /// the call `sqrt(-1)`, in chars of the input.
+    pub pos: Option<usize>,
+    pub span: Option<Span>,
+}
+
+impl Diagnostic {
+    /// A parse error at the token of `span`.
+    pub fn parse(message: String, span: Option<Span>) -> Self {
+        Self {
+            code: ErrorCode::Parse,
+            message,
+            pos: span.map(|span| span.start),
+            span,
+        }
+    }
+
+    /// Any other error, at the position and the span of a node, if any.
+    pub fn eval(message: String, at: Option<(usize, Span)>) -> Self {
+        Self {
+            code: ErrorCode::Eval,
+            message,
+            pos: at.map(|(pos, _)| pos),
+            span: at.map(|(_, span)| span),
+        }
+    }
+
+    /// The error of a part of a longer input that starts at `offset`, with
+    /// its span and the positions its message names moved by it.
+    pub fn shifted(self, offset: usize) -> Self {
+        Self {
+            code: self.code,
+            message: shift_positions(&self.message, offset),
+            pos: self.pos.map(|pos| pos + offset),
+            span: self.span.map(|span| Span {
+                start: span.start + offset,
+                ..span
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<String> for Diagnostic {
+    fn from(message: String) -> Self {
+        Diagnostic::eval(message, None)
+    }
+}
+
+impl From<ScanError> for Diagnostic {
+    fn from(err: ScanError) -> Self {
+        Diagnostic::parse(err.message, Some(err.span))
+    }
+}
+
+impl From<Diagnostic> for String {
+    fn from(err: Diagnostic) -> Self {
+        err.message
+    }
+}
+
+/// Whether diagnostics are colored.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum ColorChoice {
+    Always,
+    #[default]
+    Never,
+}
+
+impl ColorChoice {
+    /// The choice of `--color=auto`: colors on a terminal, unless `NO_COLOR`
+    /// is set.
+    pub fn auto(terminal: bool, no_color: bool) -> Self {
+        if terminal && !no_color {
+            ColorChoice::Always
+        } else {
+            ColorChoice::Never
+        }
+    }
+}
+
+const RED: &str = "\x1b[31m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// Shows `source` with `^^^` under the span of `err` above its message. In
+/// color the carets are red, the message is bold and the code of the error
+/// follows it dimmed, without color the code is left out, so plain output
+/// stays the same for scripts. Without a span only the message is shown.
+pub fn render_diagnostic(source: &str, err: &Diagnostic, color: ColorChoice) -> String {
+    let message = &err.message;
+    let mut shown = String::new();
+    if let Some(span) = err.span {
+        let carets = "^".repeat(span.len);
+        shown.push_str(source.trim_end());
+        shown.push('\n');
+        shown.push_str(&" ".repeat(span.start));
+        match color {
+            ColorChoice::Always => shown.push_str(&format!("{}{}{}", RED, carets, RESET)),
+            ColorChoice::Never => shown.push_str(&carets),
+        }
+        shown.push('\n');
+    }
+    match color {
+        ColorChoice::Always => shown.push_str(&format!(
+            "{}{}{} {}[{}]{}",
+            BOLD,
+            message,
+            RESET,
+            DIM,
+            err.code.id(),
+            RESET
+        )),
+        ColorChoice::Never => shown.push_str(message),
+    }
+    shown
+}
+
+/// Shows `expr` with carets under what `err` points at above the error
+/// itself. Errors without a span are left as they are.
+pub fn with_caret(expr: &str, err: &Diagnostic) -> String {
+    render_diagnostic(expr, err, ColorChoice::Never)
+}
+
+/// Moves the positions an error names by `offset`, for an error of a part of
+/// a longer input that starts at `offset`.
+pub fn shift_positions(err: &str, offset: usize) -> String {
+    let mut parts = err.split(" at pos ");
+    let mut shifted = parts.next().unwrap_or("").to_string();
+    for part in parts {
+        let digits: String = part.chars().take_while(char::is_ascii_digit).collect();
+        shifted.push_str(" at pos ");
+        match digits.parse::<usize>() {
+            Ok(pos) => {
+                shifted.push_str(&(pos + offset).to_string());
+                shifted.push_str(&part[digits.len()..]);
+            }
+            Err(_) => shifted.push_str(part),
+        }
+    }
+    shifted
+}
+
+#[cfg(test)]
+mod error_tests {
+    use super::*;
+    use crate::ast::parse_expr;
+    use crate::context::Context;
+    use crate::evaluator::evaluate_timed;
+
+    fn parse_error(expr: &str) -> Diagnostic {
+        parse_expr(expr, &Context::new()).unwrap_err()
+    }
+
+    #[test]
+    fn with_caret_test() {
+        let shown = |expr| with_caret(expr, &parse_error(expr));
+        assert_eq!(
+            shown("1 + * 2\n"),
+            "1 + * 2\n    ^\nAst build error! Unknown prefix operator Multiplication at pos 4!"
+        );
+        assert_eq!(
+            shown("2 × *"),
+            "2 × *\n    ^\nAst build error! Unknown prefix operator Multiplication at pos 4!"
+        );
+        assert_eq!(
+            with_caret("(1", &Diagnostic::from(String::from("Missing ')'!"))),
+            "Missing ')'!"
+        );
+        // Positions count chars, so the carets are under `0x1G` after the
+        // two-byte `π`, `×` and `é`.
+        let shown = shown("π × é + 0x1G");
+        assert_eq!(
+            shown,
+            "π × é + 0x1G\n        ^^^^\nAst build error! Number 0x1G at pos 8 is malformed!"
+        );
+        let lines: Vec<&str> = shown.lines().collect();
+        let column = lines[1].find('^').unwrap();
+        assert_eq!(lines[0].chars().nth(column), Some('0'));
+        assert_eq!(
+            with_caret("1 + 0x", &parse_error("1 + 0x")),
+            "1 + 0x\n    ^^\nAst build error! Number 0x at pos 4 is malformed!"
+        );
+    }
+
+    #[test]
+    fn render_diagnostic_test() {
+        let span = Span { start: 4, len: 3 };
+        let err = Diagnostic::eval(String::from("Unknown function foo!"), Some((4, span)));
+        assert_eq!(
+            render_diagnostic("2 * foo(1)", &err, ColorChoice::Never),
+            "2 * foo(1)\n    ^^^\nUnknown function foo!"
+        );
+        assert_eq!(
+            render_diagnostic("2 * foo(1)", &err, ColorChoice::Always),
+            "2 * foo(1)\n    \x1b[31m^^^\x1b[0m\n\x1b[1mUnknown function foo!\x1b[0m \x1b[2m[E002]\x1b[0m"
+        );
+        let err = Diagnostic::parse(String::from("Ast build error! End!"), None);
+        assert_eq!(
+            render_diagnostic("1 +", &err, ColorChoice::Always),
+            "\x1b[1mAst build error! End!\x1b[0m \x1b[2m[E001]\x1b[0m"
+        );
+        assert_eq!(
+            render_diagnostic("1 +", &err, ColorChoice::Never),
+            "Ast build error! End!"
+        );
+    }
+
+    #[test]
+    fn diagnostic_span_test() {
+        let span = |start, len| Some(Span { start, len });
+        let error_span =
+            |expr: &str, ctx: &Context| evaluate_timed(&expr.to_string(), ctx).0.unwrap_err().span;
+        let ctx = Context::new();
+        assert_eq!(parse_error("2 + * 1").span, span(4, 1));
+        assert_eq!(parse_error("2 + 1.2.3").span, span(4, 5));
+        assert_eq!(parse_error("1 +").span, span(2, 1));
+        assert_eq!(parse_error("(1 + 2").span, span(0, 1));
+        // Errors of evaluation point at the node they're raised at.
+        let mut strict = Context::new();
+        strict.set_strict_floats(true);
+        assert_eq!(error_span("1 + 0/0 * 2", &strict), span(4, 3));
+        let mut integer = Context::new();
+        integer.set_integer_mode(true);
+        assert_eq!(error_span("(2^62) * (4 + 0)", &integer), span(0, 16));
+        assert_eq!(error_span("1 + sin", &ctx), span(4, 3));
+        assert_eq!(error_span("1 + sqrt(-1)", &integer), span(4, 8));
+        // With the decimal comma `1,5` is a number, not two arguments.
+        strict.set_decimal_comma(true);
+        assert_eq!(error_span("1 + 1,5 / 0", &strict), span(4, 7));
+        assert_eq!(
+            Diagnostic::from(String::from("Unknown variable w!")).span,
+            None
+        );
+        let shifted = Diagnostic::parse(String::from("w at pos 2!"), span(2, 1)).shifted(3);
+        assert_eq!(
+            shifted,
+            Diagnostic::parse(String::from("w at pos 5!"), span(5, 1))
+        );
+        assert_eq!(ColorChoice::auto(true, false), ColorChoice::Always);
+        assert_eq!(ColorChoice::auto(true, true), ColorChoice::Never);
+        assert_eq!(ColorChoice::auto(false, false), ColorChoice::Never);
+    }
+
+    #[test]
+    fn diagnostic_pos_test() {
+        let error_at = |expr: &str, ctx: &Context| {
+            let err = evaluate_timed(&expr.to_string(), ctx).0.unwrap_err();
+            (err.code, err.pos)
+        };
+        let ctx = Context::new();
+        assert_eq!(error_at("1 + * 2", &ctx), (ErrorCode::Parse, Some(4)));
+        assert_eq!(error_at("1 +", &ctx), (ErrorCode::Parse, Some(2)));
+        assert_eq!(error_at("", &ctx), (ErrorCode::Parse, None));
+        // Domain errors are at their call or operator.
+        assert_eq!(error_at("2 * sqrt(-1)", &ctx), (ErrorCode::Eval, Some(4)));
+        let mut integer = Context::new();
+        integer.set_integer_mode(true);
+        assert_eq!(error_at("7 % 0", &integer), (ErrorCode::Eval, Some(2)));
+        assert_eq!(error_at("1 + 2^64", &integer), (ErrorCode::Eval, Some(5)));
+        // A message that looks like a parse error or names a position
+        // doesn't make the error one.
+        let err = Diagnostic::from(String::from("Ast build error! at pos 3"));
+        assert_eq!((err.code, err.pos), (ErrorCode::Eval, None));
+        assert_eq!(ErrorCode::Parse.id(), "E001");
+        assert_eq!(ErrorCode::Eval.id(), "E002");
+    }
+
+    #[test]
+    fn shift_positions_test() {
+        assert_eq!(
+            shift_positions("Operator Plus at pos 2 expects an operand at pos 4!", 10),
+            "Operator Plus at pos 12 expects an operand at pos 14!"
+        );
+        assert_eq!(
+            shift_positions("Unknown variable w!", 3),
+            "Unknown variable w!"
+        );
+        assert_eq!(
+            shift_positions("at pos x at pos 0!", 1),
+            "at pos x at pos 1!"
+        );
+    }
+}
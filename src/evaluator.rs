@@ -1,68 +1,505 @@
-use crate::ast::NodePtr;
+use std::env::{self, VarError};
+use std::time::Instant;
+
+use crate::ast::{check_operands, validate, Node, NodePtr};
 
 use super::ast::{Ast, TokenType};
-use super::scanner::Scanner;
+use super::builtins::{find_constant, Builtin};
+use super::context::Context;
+use super::error::Diagnostic;
+use super::format::{format_float, FormatOptions};
+use super::exact::ExactEvaluator;
+use super::printer::format_number;
+use super::special_forms::find_special_form;
+use super::timing::EvalReport;
+use super::value::Value;
+use super::visitor::{walk, walk_node, Visitor};
+
+/// 1 for true and 0 for false.
+pub fn truth(holds: bool) -> f64 {
+    if holds {
+        1.
+    } else {
+        0.
+    }
+}
+
+/// The largest arguments of `!` and `!!` whose results are finite floats.
+pub const MAX_FACTORIAL: f64 = 170.;
+pub const MAX_DOUBLE_FACTORIAL: f64 = 300.;
 
 fn factorial(n: f64) -> f64 {
     let mut f = 1.;
     for i in 2..=(n as u64) {
         f *= i as f64;
+        if f.is_infinite() {
+            break;
+        }
     }
     f
 }
 
-fn recursion<'a>(node: NodePtr) -> f64 {
-    match node {
-        Some(ptr) => match ptr.token {
-            TokenType::Modulo => {
-                let left = recursion(ptr.left);
-                let right = recursion(ptr.right);
-                (left % right) as f64
-            },
-            TokenType::Power => {
-                let base = recursion(ptr.left);
-                let exp = recursion(ptr.right);
-                base.powf(exp)
-            },
-            TokenType::PrefixMinus => -recursion(ptr.left),
-            TokenType::PrefixPlus => recursion(ptr.left),
-            TokenType::Plus => recursion(ptr.left) + recursion(ptr.right),
-            TokenType::Minus => recursion(ptr.left) - recursion(ptr.right),
-            TokenType::Factorial => factorial(recursion(ptr.left)),
-            TokenType::Bar => recursion(ptr.left).abs(),
-            TokenType::Multiply => recursion(ptr.left) * recursion(ptr.right),
-            TokenType::Divide => recursion(ptr.left) / recursion(ptr.right),
-            TokenType::Number(n) => n,
-            _ => panic!("Unknown token!"),
-        },
-        None => 0.,
+fn double_factorial(n: f64) -> f64 {
+    let mut f = 1f64;
+    let mut i = n as u64;
+    while i > 1 && f.is_finite() {
+        f *= i as f64;
+        i -= 2;
+    }
+    f
+}
+
+/// Writes an operand of a float error, in scientific notation if it's large
+/// or small, so that 1E308 doesn't take 309 digits.
+fn format_operand(n: f64) -> String {
+    let options = FormatOptions {
+        precision: None,
+        ..FormatOptions::default()
+    };
+    format_float(n, &options)
+}
+
+/// Computes the numeric value of a tree. A node that misses an operand or
+/// has one it shouldn't is a "Malformed tree!" error that names it.
+pub struct Evaluator<'a> {
+    ctx: &'a Context,
+    scope: Vec<(String, f64)>,
+    /// Position of the node being evaluated.
+    pos: usize,
+    /// Whether strict mode is left out for the samples of a special form,
+    /// which names the point of a non-finite one itself.
+    sampling: bool,
+}
+
+impl<'a> Evaluator<'a> {
+    pub fn new(ctx: &'a Context) -> Self {
+        Self {
+            ctx,
+            scope: Vec::new(),
+            pos: 0,
+            sampling: false,
+        }
+    }
+
+    /// Whether the function `name` takes its arguments as trees rather than
+    /// their values: the special forms, which bind variables, and `env`,
+    /// which takes a string.
+    pub fn takes_trees(&self, name: &str) -> bool {
+        let builtin_name = self.ctx.builtin_name(name);
+        find_special_form(&builtin_name).is_some() || builtin_name == "env"
+    }
+
+    /// The overload of `name` that a call at `pos` with its arguments at
+    /// `arg_positions` resolves to.
+    pub fn resolve(&self, name: &str, arg_positions: &[usize], pos: usize) -> Result<Builtin, String> {
+        self.ctx.function_at(name, arg_positions, pos).copied()
+    }
+
+    /// Applies `builtin`, the overload of `name` that a call at `pos`
+    /// resolved to, to the values of its arguments.
+    pub fn apply(&self, builtin: &Builtin, name: &str, args: &[f64], pos: usize) -> Result<f64, String> {
+        if self.ctx.builtin_name(name) == "out" {
+            return self.ctx.history().get_index(args[0], pos).map(Value::as_f64);
+        }
+
+        let result = (builtin.func)(args)?;
+        if result.is_nan() && !args.iter().any(|arg| arg.is_nan()) {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            Err(format!("Domain error! {}({}) is undefined!", name, args.join(", ")))
+        } else {
+            self.check_float(result, args, pos, || {
+                let args = args.iter().map(|arg| Node::new(TokenType::Number(*arg), None, None));
+                Node::new(TokenType::Call(name.to_string(), args.collect()), None, None)
+            })
+        }
+    }
+
+    /// In strict mode, reports a NaN `result` of operands that aren't NaN,
+    /// and an infinite one of finite operands. So an explicit `nan` or `inf`
+    /// still passes through. The error names the operation at `pos`.
+    fn check_float(
+        &self,
+        result: f64,
+        operands: &[f64],
+        pos: usize,
+        operation: impl FnOnce() -> Node,
+    ) -> Result<f64, String> {
+        let made_nan = result.is_nan() && !operands.iter().any(|x| x.is_nan());
+        let made_infinite = result.is_infinite() && operands.iter().all(|x| x.is_finite());
+        if self.ctx.strict_floats() && !self.sampling && (made_nan || made_infinite) {
+            Err(format!(
+                "Float error! {} at pos {} is {}!",
+                operation().format_with(format_operand),
+                pos,
+                format_number(result)
+            ))
+        } else {
+            Ok(result)
+        }
+    }
+
+    /// Binds `name` to `value` like the variable of an enclosing special
+    /// form, for as long as the evaluator lives.
+    pub fn bind(&mut self, name: &str, value: f64) {
+        self.scope.push((name.to_string(), value));
+    }
+
+    /// Changes the value of the innermost binding of `name`, to evaluate
+    /// again for another value without binding it anew.
+    pub fn rebind(&mut self, name: &str, value: f64) {
+        if let Some(binding) = self.scope.iter_mut().rev().find(|(local, _)| local == name) {
+            binding.1 = value;
+        }
+    }
+
+    /// Evaluates `body` with `name` bound to `value`. The binding shadows
+    /// context variables and is gone once the body is evaluated.
+    pub fn eval_with_binding(
+        &mut self,
+        name: &str,
+        value: f64,
+        body: &Node,
+    ) -> Result<f64, String> {
+        self.scope.push((name.to_string(), value));
+        let result = walk_node(self, body);
+        self.scope.pop();
+        result
+    }
+
+    /// Like `eval_with_binding`, but a NaN or infinite value isn't a float
+    /// error of strict mode, so that `integrate` can report it with the
+    /// point it was sampled at.
+    pub fn eval_sample(&mut self, name: &str, value: f64, body: &Node) -> Result<f64, String> {
+        let sampling = std::mem::replace(&mut self.sampling, true);
+        let result = self.eval_with_binding(name, value, body);
+        self.sampling = sampling;
+        result
+    }
+
+    /// Applies a unary operator at `pos` to its value.
+    pub fn unary(&self, op: &TokenType, operand: f64, pos: usize) -> Result<f64, String> {
+        let operation = || {
+            Node::new(op.clone(), Node::new_ptr(TokenType::Number(operand), None, None), None)
+        };
+        let max = match op {
+            TokenType::Factorial => MAX_FACTORIAL,
+            _ => MAX_DOUBLE_FACTORIAL,
+        };
+        // `!` and `!!` are only defined for whole numbers that aren't
+        // negative, `inf!` overflows below.
+        let factorial_op = matches!(op, TokenType::Factorial | TokenType::DoubleFactorial);
+        let whole = operand >= 0. && (operand.fract() == 0. || operand == f64::INFINITY);
+        if factorial_op && !whole {
+            return Err(format!(
+                "Domain error! {} at pos {} is undefined!",
+                operation().format_with(format_operand),
+                pos
+            ));
+        }
+        // Checked before the loop, which would take forever for `1E18!`.
+        if factorial_op && operand.trunc() > max {
+            return Err(format!(
+                "Overflow! {} at pos {} exceeds the representable range!",
+                operation().format_with(format_operand),
+                pos
+            ));
+        }
+        let result = match op {
+            TokenType::PrefixMinus => -operand,
+            TokenType::PrefixPlus => operand,
+            TokenType::Factorial => factorial(operand),
+            TokenType::DoubleFactorial => double_factorial(operand),
+            TokenType::Percent => operand / 100.,
+            TokenType::Bar => operand.abs(),
+            TokenType::Not => truth(operand == 0.),
+            _ => return Err(format!("Unknown operator {:?} at pos {}!", op, pos)),
+        };
+        self.check_float(result, &[operand], pos, operation)
+    }
+
+    /// Applies a binary operator at `pos` to the values of its operands.
+    /// `and` and `or` are left to the caller, which may not evaluate the
+    /// right operand.
+    pub fn binary(&self, op: &TokenType, left: f64, right: f64, pos: usize) -> Result<f64, String> {
+        let operation = || {
+            let number = |n: f64| Node::new_ptr(TokenType::Number(n), None, None);
+            Node::new(op.clone(), number(left), number(right))
+        };
+        let result = match op {
+            // Like `mod` and `rem`, rather than nan.
+            TokenType::Modulo if right == 0. => {
+                return Err(format!(
+                    "Division by zero in {} at pos {}!",
+                    operation().format_with(format_operand),
+                    pos
+                ))
+            }
+            TokenType::Modulo => left % right,
+            TokenType::Power => power(left, right),
+            TokenType::Plus => left + right,
+            TokenType::Minus => left - right,
+            TokenType::Multiply => left * right,
+            TokenType::Divide => left / right,
+            // Rounds down rather than toward zero, so -7 // 2 is -4.
+            TokenType::FloorDivide => (left / right).floor(),
+            // Equality is exact, approx() compares with a tolerance.
+            TokenType::Less => truth(left < right),
+            TokenType::LessEqual => truth(left <= right),
+            TokenType::Greater => truth(left > right),
+            TokenType::GreaterEqual => truth(left >= right),
+            TokenType::Equal => truth(left == right),
+            TokenType::NotEqual => truth(left != right),
+            _ => return Err(format!("Unknown operator {:?} at pos {}!", op, pos)),
+        };
+        self.check_float(result, &[left, right], pos, operation)
+    }
+}
+
+impl<'a> Visitor for Evaluator<'a> {
+    type Output = Result<f64, String>;
+
+    fn visit_number(&mut self, n: f64) -> Self::Output {
+        Ok(n)
+    }
+
+    fn visit_variable(&mut self, name: &str) -> Self::Output {
+        let local = self.scope.iter().rev().find(|(local, _)| local == name);
+        match local {
+            Some((_, value)) => Ok(*value),
+            None => self
+                .ctx
+                .get_var(name)
+                .or_else(|| find_constant(&self.ctx.builtin_name(name)))
+                .ok_or_else(|| format!("Unknown variable {}!", name)),
+        }
+    }
+
+    fn visit_history(&mut self, n: usize) -> Self::Output {
+        self.ctx.history().get(n, self.pos).map(Value::as_f64)
+    }
+
+    fn visit_text(&mut self, text: &str) -> Self::Output {
+        Err(format!(
+            "String \"{}\" at pos {} isn't a number!",
+            text, self.pos
+        ))
+    }
+
+    fn visit_call(&mut self, name: &str, args: &[Node]) -> Self::Output {
+        let pos = self.pos;
+        trace!("calls {} at pos {} with {} arguments", name, pos, args.len());
+        let builtin_name = self.ctx.builtin_name(name);
+        if let Some(form) = find_special_form(&builtin_name) {
+            return form.call(self, args, pos);
+        }
+
+        let builtin = *self.ctx.function(name, args, pos)?;
+        if builtin_name == "env" {
+            return env_var(self.ctx, &args[0], pos);
+        }
+
+        let args = args
+            .iter()
+            .map(|arg| walk_node(self, arg))
+            .collect::<Result<Vec<f64>, String>>()?;
+        self.apply(&builtin, name, &args, pos)
+    }
+
+    fn visit_lambda(&mut self, param: &str, _body: &NodePtr) -> Self::Output {
+        Err(format!(
+            "Lambda {} -> ... at pos {} can't be used as a number!",
+            param, self.pos
+        ))
+    }
+
+    fn visit_unary(&mut self, op: &TokenType, operand: &NodePtr) -> Self::Output {
+        let pos = self.pos;
+        check_operands(op, pos, operand.is_some(), false)?;
+        let operand = walk(self, operand)?;
+        let result = self.unary(op, operand, pos);
+        trace!("{:?} at pos {} gives {:?}", op, pos, result);
+        result
+    }
+
+    fn visit_binary(&mut self, op: &TokenType, left: &NodePtr, right: &NodePtr) -> Self::Output {
+        let pos = self.pos;
+        check_operands(op, pos, left.is_some(), right.is_some())?;
+        match op {
+            TokenType::And if walk(self, left)? == 0. => return Ok(0.),
+            TokenType::Or if walk(self, left)? != 0. => return Ok(1.),
+            TokenType::And | TokenType::Or => return Ok(truth(walk(self, right)? != 0.)),
+            _ => (),
+        }
+
+        let left = walk(self, left)?;
+        let right = walk(self, right)?;
+        let result = self.binary(op, left, right, pos);
+        trace!("{:?} at pos {} gives {:?}", op, pos, result);
+        result
+    }
+
+    fn visit_none(&mut self) -> Self::Output {
+        // The operators check for missing operands, so it's the whole tree.
+        Err(String::from("Empty expression!"))
+    }
+
+    fn visit_pos(&mut self, pos: usize) {
+        self.pos = pos;
+    }
+}
+
+/// The value of `env("NAME")` at `pos`: the environment variable `NAME`
+/// read as a number, if `ctx` lets expressions read them.
+pub fn env_var(ctx: &Context, arg: &Node, pos: usize) -> Result<f64, String> {
+    if !ctx.env_access() {
+        return Err(format!(
+            "Function env at pos {} can't read environment variables in this context!",
+            pos
+        ));
+    }
+    let name = match &arg.token {
+        TokenType::Text(name) if !name.is_empty() && !name.contains(['=', '\0']) => name,
+        _ => {
+            return Err(format!(
+                "Function env at pos {} expects the name of an environment variable in quotes, like env(\"HOME\")!",
+                pos
+            ))
+        }
+    };
+    let value = env::var(name).map_err(|err| match err {
+        VarError::NotPresent => format!("Environment variable {} isn't set!", name),
+        VarError::NotUnicode(_) => format!("Environment variable {} isn't valid unicode!", name),
+    })?;
+    match value.trim().parse::<f64>() {
+        Ok(number) if number.is_finite() => Ok(number),
+        _ => Err(format!(
+            "Environment variable {} is {:?}, which isn't a number!",
+            name, value
+        )),
     }
 }
 
+/// `base ^ exponent`, by repeated squaring for exponents that are integers
+/// in the i32 range, so `2^10` is exactly 1024.
+fn power(base: f64, exponent: f64) -> f64 {
+    if exponent.fract() == 0. && (i32::MIN as f64..=i32::MAX as f64).contains(&exponent) {
+        base.powi(exponent as i32)
+    } else {
+        base.powf(exponent)
+    }
+}
+
+/// Evaluates `expr` without variables. Like every function that evaluates
+/// an expression it never panics, whatever the input: it's `Ok` or `Err`.
 pub fn evaluate(expr: &String) -> Result<f64, String> {
-    let mut scanner = Scanner::new(expr);
+    evaluate_with(expr, &Context::new())
+}
+
+pub fn evaluate_with(expr: &String, ctx: &Context) -> Result<f64, String> {
+    evaluate_diagnosed(expr, ctx).map_err(String::from)
+}
+
+/// Like `evaluate_with`, with an error that keeps its code and what it's at.
+pub fn evaluate_diagnosed(expr: &String, ctx: &Context) -> Result<f64, Diagnostic> {
+    let root = parse_timed(expr, ctx, &mut EvalReport::default())?;
+    let value = evaluate_tree(&root, expr, ctx)?;
+    let number = value.as_f64();
+    let at = root.as_ref().map(|node| (node.pos, node.span));
+    match (value, &root) {
+        (Value::Complex(z), _) => Err(Diagnostic::eval(format!("{} isn't a real number!", z), at)),
+        // An exact integer like `171!` or `2^1024` beyond the range of f64.
+        (Value::Big(_), Some(node)) | (Value::Ratio(_), Some(node)) if number.is_infinite() => {
+            let err = format!(
+                "Overflow! {} at pos {} exceeds the representable range!",
+                node.format_with(format_operand),
+                node.pos
+            );
+            Err(Diagnostic::eval(err, at))
+        }
+        _ => Ok(number),
+    }
+}
+
+/// Like `evaluate_with`, but keeps integers exact, e.g. `25!`. In integer
+/// mode they're 64-bit and report overflow, in rational mode divisions are
+/// exact fractions, and in complex mode results can be complex numbers.
+pub fn evaluate_value(expr: &String, ctx: &Context) -> Result<Value, String> {
+    evaluate_timed(expr, ctx).0.map_err(String::from)
+}
+
+/// Like `evaluate_value`, with how long scanning, parsing and evaluating
+/// took, and an error with the span it points at.
+pub fn evaluate_timed(expr: &String, ctx: &Context) -> (Result<Value, Diagnostic>, EvalReport) {
+    let mut report = EvalReport::default();
+    let root = match parse_timed(expr, ctx, &mut report) {
+        Ok(root) => root,
+        Err(err_msg) => return (Err(err_msg), report),
+    };
+
+    let start = Instant::now();
+    let value = evaluate_tree(&root, expr, ctx);
+    report.eval = start.elapsed();
+    (value, report)
+}
+
+/// Scans and parses `expr` into `report`'s times.
+fn parse_timed(expr: &String, ctx: &Context, report: &mut EvalReport) -> Result<NodePtr, Diagnostic> {
+    let start = Instant::now();
+    let mut scanner = ctx.scanner(expr);
     scanner.scan();
+    report.scan = start.elapsed();
 
+    let start = Instant::now();
     let mut ast = Ast::new(&mut scanner);
-    if let Err(err_msg) = ast.build() {
-        Err(format!("Ast build error! {}", err_msg))
-    } else {
-        Ok(recursion(ast.root))
+    let built = ast.build();
+    report.parse = start.elapsed();
+    built.map_err(|err_msg| Diagnostic::parse(format!("Ast build error! {}", err_msg), ast.failed))?;
+    Ok(ast.root)
+}
+
+/// Evaluates a tree parsed from `source` like `evaluate_value`, so that a
+/// tree parsed once can be evaluated again and again. An error has the span
+/// of the node it comes from.
+pub fn evaluate_tree(root: &NodePtr, source: &str, ctx: &Context) -> Result<Value, Diagnostic> {
+    if let Some(root) = root {
+        validate(root)?;
     }
+    let mut evaluator = if ctx.integer_mode() {
+        ExactEvaluator::integer(ctx)
+    } else if ctx.rational_mode() {
+        ExactEvaluator::rational(ctx)
+    } else {
+        ExactEvaluator::exact(ctx)
+    };
+    evaluator.set_source(source);
+    let value = walk(&mut evaluator, root);
+    debug!("evaluates {:?} to {:?}", source, value);
+    value.map_err(|err| Diagnostic::eval(err, evaluator.failed()))
 }
 
 #[cfg(test)]
 mod evaluator_tests {
     use super::*;
+    use crate::arena::parse_arena;
+    use crate::builtins::{builtin_overloads, Arity, Builtin, BUILTINS};
+    use crate::scanner::Scanner;
+    #[cfg(feature = "log")]
+    use log::{LevelFilter, Log, Metadata, Record};
+    #[cfg(feature = "log")]
+    use std::cell::RefCell;
+    use std::time::Duration;
 
     fn do_test(expr: &str, expect: f64) {
         println!("Expression = {}", expr);
 
-        let mut expr = expr.to_string();
-        let mut result = evaluate(&expr).unwrap();
+        let expr = expr.to_string();
+        let result = evaluate(&expr).unwrap();
 
         println!("Expected result = {}", expect);
         assert_eq!(result, expect);
+        // The same floats over an arena.
+        let arena = parse_arena(&expr).unwrap().evaluate(&Context::new()).unwrap();
+        assert!((arena - expect).abs() <= 1e-12 * expect.abs() || arena == expect);
     }
 
     #[test]
@@ -104,6 +541,65 @@ mod evaluator_tests {
         do_test("3! * 3!", 36.);
     }
 
+    #[test]
+    fn double_factorial_tests() {
+        do_test("7!!", 105.);
+        do_test("8!!", 384.);
+        do_test("1!!", 1.);
+        do_test("0!!", 1.);
+        do_test("-5!!", -15.);
+
+        do_test("(3!)!", 720.);
+        do_test("3! !", 720.);
+        do_test("3!!!", 6.);
+        do_test("2 * 5!! + 1", 31.);
+
+        // Like `!`, only of whole numbers that aren't negative.
+        let domain = |expr: &str, message: &str| {
+            assert_eq!(evaluate(&expr.to_string()), Err(format!("Domain error! {}", message)));
+        };
+        domain("(-3)!!", "(-3)!! at pos 4 is undefined!");
+        domain("2.5!!", "2.5!! at pos 3 is undefined!");
+        domain("(-3)!", "(-3)! at pos 4 is undefined!");
+        domain("1 + 0.5!", "0.5! at pos 7 is undefined!");
+        domain("nan!", "nan! at pos 3 is undefined!");
+        domain("(-inf)!", "(-inf)! at pos 6 is undefined!");
+    }
+
+    #[test]
+    fn factorial_overflow_test() {
+        // Exact integers beyond the bound of the float path, 170, don't fit
+        // into the f64 result either.
+        assert!(evaluate(&"170!".to_string()).unwrap().is_finite());
+        assert!(evaluate(&"300!!".to_string()).unwrap().is_finite());
+        assert_eq!(
+            evaluate(&"171!".to_string()),
+            Err(String::from("Overflow! 171! at pos 3 exceeds the representable range!"))
+        );
+        assert_eq!(
+            evaluate(&"2^1024".to_string()),
+            Err(String::from("Overflow! 2 ^ 1024 at pos 1 exceeds the representable range!"))
+        );
+        assert_eq!(
+            evaluate(&"2 * (301.5 + 0.5)!!".to_string()),
+            Err(String::from("Overflow! 302!! at pos 17 exceeds the representable range!"))
+        );
+
+        let start = Instant::now();
+        assert_eq!(
+            evaluate(&"1E18!".to_string()),
+            Err(String::from("Overflow! 1e18! at pos 4 exceeds the representable range!"))
+        );
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        // Integers are exact beyond it, until they get too large as well.
+        assert_eq!(evaluate(&"171! - 171 * 170!".to_string()), Ok(0.));
+        assert_eq!(
+            evaluate(&"6000!".to_string()),
+            Err(String::from("Overflow! 6000! at pos 4 exceeds the representable range!"))
+        );
+    }
+
     #[test]
     fn prefixes_tests() {
         do_test("-1", -1.);
@@ -137,6 +633,97 @@ mod evaluator_tests {
         do_test("(5 + 2) % 9", 7.);
     }
 
+    #[test]
+    fn percent_operator_tests() {
+        do_test("50%", 0.5);
+        do_test("200 * 10%", 20.);
+        do_test("10% * 5", 0.5);
+        do_test("10 % 3", 1.);
+        do_test("200 + 10%", 200.1);
+        do_test("(20 + 30)% * 4", 2.);
+        do_test("-50%", -0.5);
+        do_test("10% - 3", -2.9);
+        do_test("7 % -3", 1.);
+        do_test("-7 % -3", -1.);
+        do_test("10 %-3", 1.);
+        do_test("10 % +3", 1.);
+    }
+
+    #[test]
+    fn floor_division_operator_tests() {
+        do_test("7 // 2", 3.);
+        do_test("-7 // 2", -4.);
+        do_test("7 // -2", -4.);
+        do_test("-7 // -2", 3.);
+        do_test("6 // 3", 2.);
+        do_test("7.5 // 2.5", 3.);
+
+        do_test("1 + 7 // 2", 4.);
+        do_test("2 * 7 // 2", 7.);
+        do_test("2 ^ 3 // 3", 2.);
+
+        let zero = evaluate(&"7 // 0".to_string());
+        assert_eq!(zero, evaluate(&"7 / 0".to_string()));
+    }
+
+    #[test]
+    fn comparison_operator_tests() {
+        do_test("3 < 5", 1.);
+        do_test("5 < 3", 0.);
+        do_test("3 <= 3", 1.);
+        do_test("3 > 3", 0.);
+        do_test("3 >= 3", 1.);
+        do_test("3 != 3", 0.);
+        do_test("(2+2 == 4) * 10", 10.);
+        do_test("1 + 2 == 3", 1.);
+        do_test("-1 < 0", 1.);
+        do_test("(1 < 2) < 3", 1.);
+
+        do_test("0.1 + 0.2 == 0.3", 0.);
+        do_test("approx(0.1 + 0.2, 0.3)", 1.);
+        do_test("approx(1, 1.001)", 0.);
+    }
+
+    #[test]
+    fn logical_operator_tests() {
+        do_test("1 and 2", 1.);
+        do_test("1 and 0", 0.);
+        do_test("0 or -3", 1.);
+        do_test("0 or 0", 0.);
+        do_test("not 0", 1.);
+        do_test("not 2 + 3", 0.);
+        do_test("not (1 < 2) * 10", 0.);
+
+        do_test("1 < 2 and 3 > 4 or 5 > 1", 1.);
+        do_test("1 < 2 and (3 > 4 or 5 < 1)", 0.);
+        do_test("0 or 1 and 0", 0.);
+        do_test("not 0 and 0", 0.);
+    }
+
+    #[test]
+    fn short_circuit_tests() {
+        do_test("1 == 1 or sqrt(-1) > 0", 1.);
+        do_test("1 == 2 and unknown > 0", 0.);
+
+        let expr = "1 == 2 or sqrt(-1) > 0".to_string();
+        assert_eq!(
+            evaluate(&expr),
+            Err(String::from("Domain error! sqrt(-1) is undefined!"))
+        );
+    }
+
+    #[test]
+    fn radix_numbers_tests() {
+        do_test("0xFF", 255.);
+        do_test("0b1010", 10.);
+        do_test("0o17", 15.);
+        do_test("0xFF % 0x10", 15.);
+        do_test("band(0xFF, 0x0F)", 15.);
+        do_test("2 * 0b11 + 0o10", 14.);
+        do_test("1_234 + 1", 1235.);
+        do_test("0xFF_FF - 1_000.000_5", 64535.0 - 0.0005);
+    }
+
     #[test]
     fn exponent_operator_tests() {
         do_test("4 ^ 2", 16.);
@@ -148,4 +735,731 @@ mod evaluator_tests {
         do_test("(2 + 3) ^ 2", 25.);
         do_test("(2 + 3) ^ 2 - (1 + 2) ^ 3", -2.);
     }
+
+    #[test]
+    fn implicit_multiplication_test() {
+        do_test("2(3+4)", 14.);
+        do_test("(1+2)(3+4)", 21.);
+        do_test("2pi", 2. * std::f64::consts::PI);
+        do_test("3|-2|", 6.);
+        do_test("1/2(4)", 2.);
+        do_test("|-2|3", 6.);
+    }
+
+    #[test]
+    fn unicode_operators_test() {
+        do_test("6 ÷ 2 × (1 + 2)", 9.);
+        do_test("2 · 3 − 10", -4.);
+        do_test("−2 + 5", 3.);
+        do_test("−(2 − 5)", 3.);
+    }
+
+    #[test]
+    fn root_and_superscript_test() {
+        do_test("√9", 3.);
+        do_test("√9 + 1", 4.);
+        do_test("√(9+16)", 5.);
+        do_test("5²", 25.);
+        do_test("2³ + 1", 9.);
+        do_test("√4²", 4.);
+        do_test("-3²", 9.);
+        do_test("2^3²", 64.);
+        do_test("2√16", 8.);
+
+        let expr = "√-4".to_string();
+        assert_eq!(
+            evaluate(&expr),
+            Err(String::from("Domain error! sqrt(-4) is undefined!"))
+        );
+    }
+
+    #[test]
+    fn whitespace_test() {
+        do_test("2 * 3\r\n", 6.);
+        do_test("\t2\t*\t3\n", 6.);
+        do_test("1 +\u{a0}2", 3.);
+        do_test("\u{3000}max(1,\u{3000}2)\r\n", 2.);
+    }
+
+    #[test]
+    fn comments_test() {
+        do_test("2 * 3  # six", 6.);
+        do_test("1 + 2 # ) 3 (", 3.);
+
+        let expr = "# nothing".to_string();
+        assert_eq!(evaluate(&expr), Err(String::from("Ast build error! Empty expression!")));
+    }
+
+    #[test]
+    fn case_insensitive_test() {
+        let mut ctx = Context::new();
+        ctx.set_var("R", 2.);
+
+        let exprs = ["sin(pi/2)", "SIN(PI/2)", "Sin(Pi/2)", "SUM(i, 1, 3, i) - 5", "Max(R, 1) / 2"];
+        for (i, expr) in exprs.iter().enumerate() {
+            let expr = expr.to_string();
+            ctx.set_case_insensitive(false);
+            assert_eq!(evaluate_with(&expr, &ctx).is_ok(), i == 0);
+
+            ctx.set_case_insensitive(true);
+            assert_eq!(evaluate_with(&expr, &ctx), Ok(1.));
+        }
+
+        // Variables still need their own case.
+        let expr = "r * 2".to_string();
+        assert_eq!(evaluate_with(&expr, &ctx), Err(String::from("Unknown variable r!")));
+        let expr = "FOO(1)".to_string();
+        assert_eq!(evaluate_with(&expr, &ctx), Err(String::from("Unknown function FOO!")));
+    }
+
+    #[test]
+    fn literals_test() {
+        do_test("true", 1.);
+        do_test("false", 0.);
+        do_test("true and not false", 1.);
+        do_test("inf > 1E308", 1.);
+        do_test("-inf < -1E308", 1.);
+        do_test("nan == nan", 0.);
+        do_test("nan != nan", 1.);
+        do_test("1 / inf", 0.);
+
+        // An explicit nan isn't a domain error, only a NaN made from numbers.
+        let expr = "sqrt(nan) + 1".to_string();
+        assert!(evaluate(&expr).unwrap().is_nan());
+        let expr = "sqrt(-1) + nan".to_string();
+        assert_eq!(evaluate(&expr), Err(String::from("Domain error! sqrt(-1) is undefined!")));
+    }
+
+    #[test]
+    fn strict_floats_test() {
+        let mut ctx = Context::new();
+        let cases = [
+            ("0/0", "0 / 0 at pos 1 is nan"),
+            ("inf - inf", "inf - inf at pos 4 is nan"),
+            ("1.5^2000 + 1", "1.5 ^ 2000 at pos 3 is inf"),
+            ("exp(1000)", "exp(1000) at pos 0 is inf"),
+            ("-ln(0)", "ln(0) at pos 1 is -inf"),
+            ("1 / -0", "1 / -0 at pos 2 is -inf"),
+            ("1E308 * 10", "1e308 * 10 at pos 6 is inf"),
+            ("-1E200 * 1E200", "-1e200 * 1e200 at pos 7 is -inf"),
+        ];
+
+        for (expr, operation) in cases.iter() {
+            let expr = expr.to_string();
+            ctx.set_strict_floats(false);
+            assert!(!evaluate_with(&expr, &ctx).unwrap().is_finite());
+
+            ctx.set_strict_floats(true);
+            assert_eq!(evaluate_with(&expr, &ctx), Err(format!("Float error! {}!", operation)));
+        }
+
+        // Explicit nan and inf still pass through.
+        for expr in ["inf + 1", "-inf", "nan * 2", "sqrt(nan)", "1 / inf"].iter() {
+            let expr = expr.to_string();
+            assert!(evaluate_with(&expr, &ctx).is_ok());
+        }
+
+        // Functions report undefined results either way.
+        for &strict in [false, true].iter() {
+            ctx.set_strict_floats(strict);
+            let expr = "sqrt(-1)".to_string();
+            assert_eq!(evaluate_with(&expr, &ctx), Err(String::from("Domain error! sqrt(-1) is undefined!")));
+        }
+    }
+
+    #[test]
+    fn si_suffixes_test() {
+        let eval = |expr: &str| {
+            let expr = expr.to_string();
+            let mut scanner = Scanner::new(&expr);
+            scanner.set_si_suffixes(true);
+            scanner.scan();
+
+            let mut ast = Ast::new(&mut scanner);
+            ast.build().unwrap();
+            walk(&mut Evaluator::new(&Context::new()), &ast.root)
+        };
+
+        assert_eq!(eval("4.7k * 2"), Ok(9400.));
+        assert_eq!(eval("1k / 4"), Ok(250.));
+        assert_eq!(eval("2m * 3"), Ok(0.006));
+        assert_eq!(eval("2 max(1, 2)"), Ok(4.));
+
+        let mut ctx = Context::new();
+        assert_eq!(evaluate_with(&String::from("2m"), &ctx), Err(String::from("Unknown variable m!")));
+        ctx.set_si_suffixes(true);
+        assert_eq!(evaluate_with(&String::from("2m * 3"), &ctx), Ok(0.006));
+    }
+
+    #[test]
+    fn decimal_comma_test() {
+        let mut comma = Context::new();
+        comma.set_decimal_comma(true);
+        let same = [
+            ("3.14 * 2", "3,14 * 2"),
+            ("max(1.5, 2.5)", "max(1,5; 2,5)"),
+            ("max(1, 5)", "max(1; 5)"),
+            ("mod(7.5, 2)", "mod(7,5; 2)"),
+            ("sum(i, 1, 4, i * 0.5)", "sum(i; 1; 4; i * 0,5)"),
+            ("1_000.25 + .5", "1_000,25 + 0,5"),
+        ];
+        for (point, decimal_comma) in same.iter() {
+            println!("Expression = {}, {}", point, decimal_comma);
+
+            let point = evaluate_with(&point.to_string(), &Context::new());
+            assert!(point.is_ok());
+            assert_eq!(evaluate_with(&decimal_comma.to_string(), &comma), point);
+        }
+
+        let error = |expr: &str, ctx: &Context| evaluate_with(&expr.to_string(), ctx);
+        assert_eq!(
+            error("max(1, 5)", &comma),
+            Err(String::from(
+                "Ast build error! ',' at pos 5 is the decimal separator, arguments are separated by ';'!"
+            ))
+        );
+        assert_eq!(
+            error("3.14", &comma),
+            Err(String::from(
+                "Ast build error! Number 3.14 at pos 0 has a '.', but the decimal separator is ','!"
+            ))
+        );
+        assert_eq!(
+            error("max(1; 5)", &Context::new()),
+            Err(String::from(
+                "Ast build error! ';' at pos 5 separates statements, arguments are separated by ','!"
+            ))
+        );
+    }
+
+    #[test]
+    fn unknown_variable_test() {
+        let expr = "x + 1".to_string();
+        assert_eq!(evaluate(&expr), Err(String::from("Unknown variable x!")));
+    }
+
+    #[test]
+    fn variables_test() {
+        let mut ctx = Context::new();
+        ctx.set_var("x", 3.);
+        ctx.set_var("y", -2.);
+
+        let expr = "x^2 + |y| * x".to_string();
+        assert_eq!(evaluate_with(&expr, &ctx), Ok(15.));
+    }
+
+    #[test]
+    fn constants_test() {
+        do_test("pi", std::f64::consts::PI);
+        do_test("2 * e", 2. * std::f64::consts::E);
+        do_test("cos(pi)", -1.);
+    }
+
+    #[test]
+    fn functions_test() {
+        do_test("sqrt(16)", 4.);
+        do_test("max(2, 3) * min(2, 3)", 6.);
+        do_test("max(1, 7, 3, 5) - min(4, -2, 9)", 9.);
+        do_test("abs(-3) + floor(2.5)!", 5.);
+        do_test("ln(exp(2))", 2.);
+        do_test("sin(0) + cos(0)", 1.);
+    }
+
+    #[test]
+    fn modulus_functions_test() {
+        do_test("mod(7, 3)", 1.);
+        do_test("mod(-7, 3)", 2.);
+        do_test("mod(7, -3)", 1.);
+        do_test("mod(-7, -3)", 2.);
+
+        do_test("rem(7, 3)", 1.);
+        do_test("rem(-7, 3)", -1.);
+        do_test("rem(7, -3)", 1.);
+        do_test("rem(-7, -3)", -1.);
+        do_test("rem(-7, 3) - -7 % 3", 0.);
+
+        do_test("div(7, 3)", 2.);
+        do_test("div(-7, 3)", -3.);
+        do_test("div(7, -3)", -2.);
+        do_test("div(-7, -3)", 3.);
+        do_test("-3 * div(-7, -3) + mod(-7, -3)", -7.);
+
+        for name in &["mod", "rem", "div"] {
+            let expr = format!("{}(7, 0)", name);
+            assert_eq!(
+                evaluate(&expr),
+                Err(format!("Domain error! {}(7, 0) is undefined!", name))
+            );
+        }
+    }
+
+    #[test]
+    fn integer_power_test() {
+        assert_eq!(evaluate(&"2^10".to_string()), Ok(1024.));
+        assert_eq!(evaluate(&"(-2)^3".to_string()), Ok(-8.));
+        assert_eq!(evaluate(&"2^-2".to_string()), Ok(0.25));
+        assert_eq!(evaluate(&"1.0000001^8".to_string()), Ok(1.0000001f64.powi(8)));
+        assert_eq!(evaluate(&"2^0.5".to_string()), Ok(2f64.powf(0.5)));
+        assert_eq!(evaluate(&"2^3E9".to_string()), Ok(f64::INFINITY));
+        assert_eq!(power(3., 1e10), 3f64.powf(1e10));
+
+        let mut ctx = Context::new();
+        ctx.set_strict_floats(true);
+        assert_eq!(
+            evaluate_with(&"(-2)^0.5".to_string(), &ctx),
+            Err(String::from("Float error! -2 ^ 0.5 at pos 4 is nan!"))
+        );
+    }
+
+    #[test]
+    fn modulus_spellings_test() {
+        // a, b, a % b, mod(a, b) and rem(a, b).
+        let table = [
+            (7, 3, 1, 1, 1),
+            (-7, 3, -1, 2, -1),
+            (7, -3, 1, 1, 1),
+            (-7, -3, -1, 2, -1),
+        ];
+        let mut integer = Context::new();
+        integer.set_integer_mode(true);
+        for ctx in &[Context::new(), integer] {
+            for &(a, b, percent, modulus, rem) in table.iter() {
+                let eval = |expr: String| evaluate_with(&expr, ctx);
+                assert_eq!(eval(format!("({}) % ({})", a, b)), Ok(percent as f64));
+                assert_eq!(eval(format!("{} % {}", a, b)), Ok(percent as f64));
+                assert_eq!(eval(format!("({}) mod ({})", a, b)), Ok(modulus as f64));
+                assert_eq!(eval(format!("{} mod {}", a, b)), Ok(modulus as f64));
+                assert_eq!(eval(format!("mod({}, {})", a, b)), Ok(modulus as f64));
+                assert_eq!(eval(format!("rem({}, {})", a, b)), Ok(rem as f64));
+            }
+        }
+        assert_eq!(
+            evaluate(&"1 + 7 % 0".to_string()),
+            Err(String::from("Division by zero in 7 % 0 at pos 6!"))
+        );
+        assert_eq!(
+            evaluate(&"-7.5 % (1 - 1)".to_string()),
+            Err(String::from("Division by zero in -7.5 % 0 at pos 5!"))
+        );
+    }
+
+    #[test]
+    fn rounding_functions_test() {
+        do_test("round(2.5)", 3.);
+        do_test("round(-2.5)", -3.);
+        do_test("round(2.71828, 2)", 2.72);
+        do_test("round(0.125, 2)", 0.13);
+        do_test("round(1234.5, 0)", 1235.);
+        do_test("round(1234, -2)", 1200.);
+        do_test("round(-1250, -2)", -1300.);
+        do_test("round(1 / 1E300, 400)", 1e-300);
+
+        do_test("sigfig(123456, 3)", 123000.);
+        do_test("sigfig(0.0012345, 2)", 0.0012);
+        do_test("sigfig(999.9, 3)", 1000.);
+        do_test("sigfig(999.4, 3)", 999.);
+        do_test("sigfig(1000, 1)", 1000.);
+        do_test("sigfig(0.000999, 1)", 0.001);
+        do_test("sigfig(-98765, 2)", -99000.);
+        do_test("sigfig(-0.00045678, 3)", -0.000457);
+        do_test("sigfig(1.5 / 1E300, 1)", 2e-300);
+        do_test("sigfig(1.23456E300, 3)", 1.23e300);
+        do_test("sigfig(0, 3)", 0.);
+        do_test("sigfig(pi, 30)", std::f64::consts::PI);
+        do_test("sigfig(5, 1)", 5.);
+    }
+
+    #[test]
+    fn rounding_functions_errors_test() {
+        let errors = [
+            ("sigfig(123, 0)", "Function sigfig expects at least 1 significant figure, but gets 0!"),
+            ("sigfig(123, -2)", "Function sigfig expects at least 1 significant figure, but gets -2!"),
+            ("sigfig(123, 1.5)", "Function sigfig expects integer arguments, but gets 1.5!"),
+            ("round(1, 0.5)", "Function round expects integer arguments, but gets 0.5!"),
+            ("sigfig(123)", "Function sigfig at pos 0 expects 2 arguments, but gets 1!"),
+            ("round(1, 2, 3)", "Function round at pos 0 expects 1 to 2 arguments, but gets 3, the first extra one at pos 12!"),
+        ];
+
+        for (expr, error) in errors.iter() {
+            assert_eq!(evaluate(&expr.to_string()), Err(error.to_string()));
+        }
+    }
+
+    #[test]
+    fn bitwise_functions_test() {
+        do_test("band(255, 15)", 15.);
+        do_test("bor(240, 15)", 255.);
+        do_test("bxor(5, 3)", 6.);
+        do_test("bnot(0)", -1.);
+        do_test("bnot(-6)", 5.);
+        do_test("band(-1, 9007199254740991)", 9007199254740991.);
+
+        do_test("shl(1, 10)", 1024.);
+        do_test("shl(1, 62)", 4611686018427387904.);
+        do_test("shl(-1, 63)", -9223372036854775808.);
+        do_test("shr(1024, 3)", 128.);
+        do_test("shr(-8, 1)", -4.);
+        do_test("shr(1, 63)", 0.);
+        do_test("band(0.1 * 30, 1) + bor(3 - 0.1 - 0.9, 0)", 3.);
+    }
+
+    #[test]
+    fn bitwise_functions_errors_test() {
+        let errors = [
+            ("band(1.5, 1)", "Function band expects integer arguments, but gets 1.5!"),
+            ("bnot(0/0)", "Function bnot expects integer arguments, but gets NaN!"),
+            (
+                "bor(2^64, 1)",
+                "Function bor expects arguments in the 64-bit integer range, but gets 18446744073709552000!",
+            ),
+            ("shl(1, 63)", "Function shl overflows shifting 1 by 63 bit(s)!"),
+            ("shl(3, 62)", "Function shl overflows shifting 3 by 62 bit(s)!"),
+            ("shr(1, 64)", "Function shr expects a shift by 0 to 63 bits, but gets 64!"),
+            ("shl(1, -1)", "Function shl expects a shift by 0 to 63 bits, but gets -1!"),
+        ];
+
+        for (expr, error) in errors.iter() {
+            assert_eq!(evaluate(&expr.to_string()), Err(error.to_string()));
+        }
+    }
+
+    #[test]
+    fn word_operators_test() {
+        do_test("10 mod 3", 1.);
+        do_test("-7 mod 3", 2.);
+        do_test("10 div 3", 3.);
+        do_test("-7 div 2", -4.);
+        do_test("5 xor 3", 6.);
+        do_test("10 mod 3 * 2", 2.);
+        do_test("2 * 10 div 3", 6.);
+        do_test("1 + 4 xor 1", 4.);
+        do_test("5 xor 3 == 6", 1.);
+    }
+
+    #[test]
+    fn functions_errors_test() {
+        let expr = "foo(1)".to_string();
+        assert_eq!(evaluate(&expr), Err(String::from("Unknown function foo!")));
+
+        let expr = "sin(1, 2)".to_string();
+        assert_eq!(
+            evaluate(&expr),
+            Err(String::from("Function sin at pos 0 expects 1 argument, but gets 2, the first extra one at pos 7!"))
+        );
+
+        let expr = "sqrt(-4)".to_string();
+        assert_eq!(
+            evaluate(&expr),
+            Err(String::from("Domain error! sqrt(-4) is undefined!"))
+        );
+    }
+
+    #[test]
+    fn default_arguments_test() {
+        let defaults = [
+            ("log(5)", "log(5, e)"),
+            ("ln(5)", "log(5, e)"),
+            ("round(2.567)", "round(2.567, 0)"),
+            ("root(10)", "root(10, 2)"),
+            ("root(2)", "sqrt(2)"),
+        ];
+        for (implicit, explicit) in defaults.iter() {
+            assert_eq!(evaluate(&implicit.to_string()), evaluate(&explicit.to_string()));
+        }
+        do_test("log(1024, 2) + log(1000, 10)", 13.);
+        do_test("log(81, 3)", 4.);
+        do_test("root(27, 3) + root(-32, 5)", 1.);
+        do_test("root(64, 3) * root(16, -2)", 1.);
+        assert_eq!(
+            evaluate(&"root(8, 0)".to_string()),
+            Err(String::from("Function root expects a nonzero index, but gets 0!"))
+        );
+        assert_eq!(
+            evaluate(&"root(-4)".to_string()),
+            Err(String::from("Domain error! root(-4) is undefined!"))
+        );
+        assert_eq!(
+            evaluate(&"log(8, 2, 1)".to_string()),
+            Err(String::from(
+                "Function log at pos 0 expects 1 to 2 arguments, but gets 3, the first extra one at pos 10!"
+            ))
+        );
+    }
+
+    #[test]
+    fn overloads_test() {
+        let mut ctx = Context::new();
+        let eval = |expr: &str, ctx: &Context| evaluate_with(&expr.to_string(), ctx);
+        assert_eq!(eval("random(3, 3)", &ctx), Ok(3.));
+        assert_eq!(
+            eval("1 + random(2)", &ctx),
+            Err(String::from(
+                "No overload of function random at pos 4 accepts 1 argument, it takes 0 arguments or 2 arguments!"
+            ))
+        );
+
+        let function = |name, arity, func| Builtin { name, arity, pure: true, func };
+        let hyp: fn(&[f64]) -> Result<f64, String> =
+            |args| Ok(args.iter().map(|x| x * x).sum::<f64>().sqrt());
+        assert_eq!(ctx.add_function(function("hyp", Arity::Exact(2), hyp)), Ok(()));
+        assert_eq!(ctx.add_function(function("hyp", Arity::Exact(3), hyp)), Ok(()));
+        assert_eq!(ctx.add_function(function("random", Arity::Exact(1), |args| Ok(args[0]))), Ok(()));
+        assert_eq!(eval("hyp(3, 4) + hyp(2, 3, 6)", &ctx), Ok(12.));
+        assert_eq!(eval("random(2)", &ctx), Ok(2.));
+        assert_eq!(
+            eval("hyp(1)", &ctx),
+            Err(String::from(
+                "No overload of function hyp at pos 0 accepts 1 argument, it takes 2 arguments or 3 arguments!"
+            ))
+        );
+
+        // Overloads can't share a number of arguments.
+        assert_eq!(
+            ctx.add_function(function("hyp", Arity::AtLeast(3), hyp)),
+            Err(String::from("Function hyp taking at least 3 arguments overlaps its overload taking 3 arguments!"))
+        );
+        assert_eq!(
+            ctx.add_function(function("max", Arity::Exact(3), hyp)),
+            Err(String::from(
+                "Function max taking 3 arguments overlaps its builtin overload taking at least 2 arguments!"
+            ))
+        );
+        assert_eq!(
+            ctx.add_function(function("round", Arity::Range(0, 1), hyp)),
+            Err(String::from(
+                "Function round taking 0 to 1 arguments overlaps its builtin overload taking 1 to 2 arguments!"
+            ))
+        );
+        assert_eq!(
+            ctx.add_function(function("sum", Arity::Exact(1), hyp)),
+            Err(String::from("Function sum binds a variable and can't be overloaded!"))
+        );
+        assert_eq!(eval("hyp(3, 4)", &ctx), Ok(5.));
+        assert_eq!(eval("hyp(3, 4)", &Context::new()), Err(String::from("Unknown function hyp!")));
+
+        // Builtin overloads don't overlap either.
+        for (i, builtin) in BUILTINS.iter().enumerate() {
+            for other in BUILTINS[i + 1..].iter().filter(|other| other.name == builtin.name) {
+                assert!(!builtin.arity.overlaps(&other.arity), "{} overlaps", builtin.name);
+            }
+        }
+    }
+
+    #[test]
+    fn arity_errors_test() {
+        // Overloaded functions fail as in `overloads_test`.
+        let single = |builtin: &&Builtin| builtin_overloads(builtin.name).count() == 1;
+        for builtin in BUILTINS.iter().filter(single) {
+            let call = |n: usize| format!("{}({})", builtin.name, vec!["1"; n].join(", "));
+            if builtin.arity.min() > 0 {
+                let given = builtin.arity.min() - 1;
+                assert_eq!(
+                    evaluate(&call(given)),
+                    Err(format!(
+                        "Function {} at pos 0 expects {}, but gets {}!",
+                        builtin.name, builtin.arity, given
+                    ))
+                );
+            }
+            if let Some(max) = builtin.arity.max() {
+                let extra = builtin.name.len() + 1 + 3 * max;
+                assert_eq!(
+                    evaluate(&call(max + 1)),
+                    Err(format!(
+                        "Function {} at pos 0 expects {}, but gets {}, the first extra one at pos {}!",
+                        builtin.name, builtin.arity, max + 1, extra
+                    ))
+                );
+            }
+        }
+        assert_eq!(
+            evaluate(&"max(1)".to_string()),
+            Err(String::from("Function max at pos 0 expects at least 2 arguments, but gets 1!"))
+        );
+        assert_eq!(
+            evaluate(&"2 * round()".to_string()),
+            Err(String::from("Function round at pos 4 expects 1 to 2 arguments, but gets 0!"))
+        );
+        // Exact mode checks before the arguments are evaluated.
+        let mut ctx = Context::new();
+        ctx.set_integer_mode(true);
+        assert_eq!(
+            evaluate_with(&"1 + sqrt(4, 1/0)".to_string(), &ctx),
+            Err(String::from(
+                "Function sqrt at pos 4 expects 1 argument, but gets 2, the first extra one at pos 13!"
+            ))
+        );
+    }
+
+    #[test]
+    fn evaluate_timed_test() {
+        let ctx = Context::new();
+        let start = Instant::now();
+        let (value, report) = evaluate_timed(&"sum(i, 1, 10000, i)".to_string(), &ctx);
+        let elapsed = start.elapsed();
+        assert_eq!(value, Ok(Value::Int(50005000)));
+        assert!(report.scan > Duration::ZERO);
+        assert!(report.parse > Duration::ZERO);
+        assert!(report.eval > Duration::ZERO);
+        assert!(report.total() <= elapsed);
+
+        let (value, report) = evaluate_timed(&"1 +".to_string(), &ctx);
+        assert!(value.is_err());
+        assert!(report.parse > Duration::ZERO);
+        assert_eq!(report.eval, Duration::ZERO);
+    }
+
+    #[test]
+    fn evaluate_tree_test() {
+        let source = "x ^ 2 + sin(x * pi)";
+        let root = crate::ast::parse(source).unwrap();
+        let mut ctx = Context::new();
+        ctx.set_var("x", 3.);
+        let value = evaluate_tree(&root, source, &ctx).map(|value| value.as_f64());
+        assert_eq!(value, Ok(9. + (3. * std::f64::consts::PI).sin()));
+        // Evaluating borrows the tree, which can be evaluated again.
+        ctx.set_var("x", 0.5);
+        assert_eq!(evaluate_tree(&root, source, &ctx), Ok(Value::Float(1.25)));
+        assert_eq!(root.as_ref().unwrap().to_string(), source);
+
+        // Its subtrees too.
+        let sum = root.as_ref().unwrap();
+        assert_eq!(evaluate_tree(&sum.left, source, &ctx), Ok(Value::Float(0.25)));
+        ctx.remove_var("x");
+        assert_eq!(
+            evaluate_tree(&sum.right, source, &ctx).map_err(String::from),
+            Err(String::from("Unknown variable x!"))
+        );
+    }
+
+    #[test]
+    fn env_test() {
+        // Names of their own, as tests run in parallel.
+        env::set_var("LS_CALC_TEST_THREADS", " 8 ");
+        env::set_var("LS_CALC_TEST_WORD", "eight");
+        env::remove_var("LS_CALC_TEST_UNSET");
+        let expr = String::from("env(\"LS_CALC_TEST_THREADS\") * 2");
+
+        // An embedding doesn't let expressions read the environment.
+        let mut ctx = Context::new();
+        let denied = Err(String::from(
+            "Function env at pos 0 can't read environment variables in this context!",
+        ));
+        assert_eq!(evaluate_with(&expr, &ctx), denied);
+        assert_eq!(parse_arena(&expr).unwrap().evaluate(&ctx), denied);
+
+        ctx.set_env_access(true);
+        assert_eq!(evaluate_with(&expr, &ctx), Ok(16.));
+        assert_eq!(parse_arena(&expr).unwrap().evaluate(&ctx), Ok(16.));
+        ctx.set_integer_mode(true);
+        assert_eq!(evaluate_value(&expr, &ctx), Ok(Value::Int(16)));
+
+        let errors = [
+            (
+                "env(\"LS_CALC_TEST_UNSET\")",
+                "Environment variable LS_CALC_TEST_UNSET isn't set!",
+            ),
+            (
+                "env(\"LS_CALC_TEST_WORD\")",
+                "Environment variable LS_CALC_TEST_WORD is \"eight\", which isn't a number!",
+            ),
+            (
+                "1 + env(LS_CALC_TEST_THREADS)",
+                "Function env at pos 4 expects the name of an environment variable in quotes, like env(\"HOME\")!",
+            ),
+            ("env(\"\")", "Function env at pos 0 expects the name of an environment variable in quotes, like env(\"HOME\")!"),
+            ("sqrt(\"LS_CALC_TEST_THREADS\")", "String \"LS_CALC_TEST_THREADS\" at pos 5 isn't a number!"),
+        ];
+        for (expr, err) in errors.iter() {
+            let expr = expr.to_string();
+            assert_eq!(evaluate_with(&expr, &ctx), Err(err.to_string()), "{}", expr);
+        }
+
+        env::remove_var("LS_CALC_TEST_THREADS");
+        assert_eq!(
+            evaluate_with(&expr, &ctx),
+            Err(String::from(
+                "Environment variable LS_CALC_TEST_THREADS isn't set!"
+            ))
+        );
+        env::remove_var("LS_CALC_TEST_WORD");
+    }
+
+    #[test]
+    fn malformed_tree_test() {
+        let number = |n: f64| Node::new_ptr(TokenType::Number(n), None, None);
+        let missing = |op: &str, side: &str| format!("Malformed tree! {} at pos 0 is missing its {} operand!", op, side);
+        let ctx = Context::new();
+
+        // Not 1 + 0.
+        let tree = Node::new_ptr(TokenType::Plus, number(1.), None);
+        assert_eq!(walk(&mut Evaluator::new(&ctx), &tree), Err(missing("Plus", "right")));
+        let tree = Node::new_ptr(TokenType::Multiply, None, number(2.));
+        assert_eq!(walk(&mut Evaluator::new(&ctx), &tree), Err(missing("Multiply", "left")));
+        let tree = Node::new_ptr(TokenType::Bar, None, None);
+        assert_eq!(evaluate_tree(&tree, "", &ctx).map_err(String::from), Err(missing("Bar", "left")));
+        let tree = Node::new_ptr(TokenType::Minus, number(1.), None);
+        assert_eq!(evaluate_tree(&tree, "", &ctx).map_err(String::from), Err(missing("Minus", "right")));
+        // Deep in the tree, before anything is evaluated.
+        let call = Node::new(TokenType::Call(String::from("max"), vec![*tree.unwrap()]), None, None);
+        let tree = Node::new_ptr(TokenType::Divide, number(1.), Some(Box::new(call)));
+        assert_eq!(evaluate_tree(&tree, "", &ctx).map_err(String::from), Err(missing("Minus", "right")));
+        assert_eq!(walk(&mut Evaluator::new(&ctx), &None), Err(String::from("Empty expression!")));
+    }
+
+    #[cfg(feature = "log")]
+    thread_local! {
+        static RECORDS: RefCell<Option<Vec<(String, String)>>> = const { RefCell::new(None) };
+    }
+
+    /// Keeps the records of the thread that captures them, so the tests
+    /// running at the same time don't mix theirs in.
+    #[cfg(feature = "log")]
+    struct Capture;
+
+    #[cfg(feature = "log")]
+    impl Log for Capture {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            RECORDS.with(|records| {
+                if let Some(records) = records.borrow_mut().as_mut() {
+                    let target = record.target().to_string();
+                    records.push((target, record.args().to_string()));
+                }
+            });
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[cfg(feature = "log")]
+    static CAPTURE: Capture = Capture;
+
+    #[cfg(feature = "log")]
+    fn capture(f: impl FnOnce()) -> Vec<(String, String)> {
+        let _ = log::set_logger(&CAPTURE);
+        log::set_max_level(LevelFilter::Trace);
+        RECORDS.with(|records| *records.borrow_mut() = Some(Vec::new()));
+        f();
+        RECORDS.with(|records| records.borrow_mut().take().unwrap())
+    }
+
+    #[test]
+    #[cfg(feature = "log")]
+    fn log_test() {
+        let records = capture(|| assert_eq!(evaluate(&String::from("1+2*3")), Ok(7.)));
+        let count = |target: &str| records.iter().filter(|(t, _)| t == target).count();
+        // A scan and 5 tokens taken, 2 operators bound and 5 nodes built,
+        // 2 operations and their result.
+        assert_eq!(count("ls_calc::scanner"), 6);
+        assert_eq!(count("ls_calc::ast"), 7);
+        assert_eq!(count("ls_calc::exact"), 2);
+        assert_eq!(count("ls_calc::evaluator"), 1);
+        let has = |message: &str| records.iter().any(|(_, m)| m == message);
+        assert!(has("scans 6 tokens of \"1+2*3\""));
+        assert!(has("evaluates \"1+2*3\" to Ok(Int(7))"));
+    }
 }
@@ -0,0 +1,926 @@
+use std::convert::TryFrom;
+
+use super::ast::{check_operands, Node, NodePtr, TokenType};
+use super::bigint::BigInt;
+use super::complex::Complex;
+use super::complex_mode::{complex_binary, complex_call, complex_unary, needs_complex};
+use super::context::Context;
+use super::error::Span;
+use super::evaluator::{env_var, Evaluator};
+use super::fraction::Fraction;
+use super::printer::{binary_symbol, format_number};
+use super::rational_mode::{exact_fraction, frac, rational_binary};
+use super::special_forms::{find_special_form, integer_bound, range_steps, Binding, SpecialForm};
+use super::value::{Value, MAX_EXACT_INTEGER};
+use super::visitor::{walk, walk_node, Visitor};
+
+/// Integers beyond this many bits are left to floats, which make them
+/// infinite, so that `2^(10^9)` doesn't take up all memory.
+pub const MAX_BITS: u64 = 1 << 16;
+
+fn number(n: f64) -> NodePtr {
+    Node::new_ptr(TokenType::Number(n), None, None)
+}
+
+fn overflow(op: &TokenType, left: i64, right: i64, pos: usize) -> String {
+    format!(
+        "Integer overflow in {} {} {} at pos {}!",
+        left,
+        binary_symbol(op),
+        right,
+        pos
+    )
+}
+
+/// `op` of i64 operands, or `None` if the result doesn't fit into i64.
+/// Divisions expect a nonzero `right` and powers a non-negative one.
+fn int_binary(op: &TokenType, left: i64, right: i64) -> Option<Value> {
+    let truth = |holds: bool| Some(Value::Bool(holds));
+    let result = match op {
+        TokenType::Plus => left.checked_add(right),
+        TokenType::Minus => left.checked_sub(right),
+        TokenType::Multiply => left.checked_mul(right),
+        // Stays an integer only when it's exact, `7 / 2` is the float 3.5.
+        TokenType::Divide => match left.checked_rem(right)? {
+            0 => left.checked_div(right),
+            _ => return Some(Value::Float(left as f64 / right as f64)),
+        },
+        // Rounds down rather than toward zero, like `//` on floats.
+        TokenType::FloorDivide => {
+            let quotient = left.checked_div(right)?;
+            if left % right != 0 && (left < 0) != (right < 0) {
+                Some(quotient - 1)
+            } else {
+                Some(quotient)
+            }
+        }
+        // The sign follows the dividend, like `%` on floats.
+        TokenType::Modulo => left.checked_rem(right),
+        TokenType::Power => match u32::try_from(right) {
+            Ok(exponent) => left.checked_pow(exponent),
+            // Only 0, 1 and -1 survive exponents beyond u32.
+            Err(_) => match left {
+                0 | 1 => Some(left),
+                -1 => Some(if right % 2 == 0 { 1 } else { -1 }),
+                _ => None,
+            },
+        },
+        TokenType::Less => return truth(left < right),
+        TokenType::LessEqual => return truth(left <= right),
+        TokenType::Greater => return truth(left > right),
+        TokenType::GreaterEqual => return truth(left >= right),
+        TokenType::Equal => return truth(left == right),
+        TokenType::NotEqual => return truth(left != right),
+        // Floats report it.
+        _ => None,
+    };
+    result.map(Value::Int)
+}
+
+/// `op` of big integers, or `None` if it's left to floats.
+fn big_binary(op: &TokenType, left: &BigInt, right: &BigInt) -> Option<Value> {
+    let truth = |holds: bool| Some(Value::Bool(holds));
+    let minus_one = BigInt::from(-1);
+    let result = match op {
+        TokenType::Plus => left + right,
+        TokenType::Minus => left - right,
+        TokenType::Multiply => left * right,
+        TokenType::Power => {
+            let exponent = right.to_i64()? as u64;
+            if left.bits().saturating_mul(exponent) > MAX_BITS {
+                return None;
+            }
+            left.pow(exponent as u32)
+        }
+        // Of the divisions of i64, only `i64::MIN / -1` overflows.
+        TokenType::Divide | TokenType::FloorDivide if *right == minus_one => -left.clone(),
+        TokenType::Modulo if *right == minus_one => BigInt::from(0),
+        TokenType::Less => return truth(left < right),
+        TokenType::LessEqual => return truth(left <= right),
+        TokenType::Greater => return truth(left > right),
+        TokenType::GreaterEqual => return truth(left >= right),
+        TokenType::Equal => return truth(left == right),
+        TokenType::NotEqual => return truth(left != right),
+        _ => return None,
+    };
+    exact(result)
+}
+
+/// An i64 if `n` fits, a big integer up to `MAX_BITS`, otherwise `None`.
+pub fn exact(n: BigInt) -> Option<Value> {
+    if n.bits() > MAX_BITS {
+        return None;
+    }
+    match n.to_i64() {
+        Some(i) => Some(Value::Int(i)),
+        None => Some(Value::Big(n)),
+    }
+}
+
+fn factorial_step(op: &TokenType) -> usize {
+    match op {
+        TokenType::DoubleFactorial => 2,
+        _ => 1,
+    }
+}
+
+/// `n!` or `n!!` of a non-negative `n`, with every `step`-th factor.
+fn factorial(n: i64, step: usize) -> Option<i64> {
+    (1..=n)
+        .rev()
+        .step_by(step)
+        .try_fold(1i64, |f, i| f.checked_mul(i))
+}
+
+fn big_factorial(n: i64, step: usize) -> Option<BigInt> {
+    let mut f = BigInt::from(1);
+    for i in (1..=n).rev().step_by(step) {
+        f = &f * &BigInt::from(i);
+        if f.bits() > MAX_BITS {
+            return None;
+        }
+    }
+    Some(f)
+}
+
+/// Comparisons and logical operators give a boolean even of floats.
+fn truth_value(op: &TokenType, result: Value) -> Value {
+    if op.is_comparison() || *op == TokenType::Not {
+        Value::Bool(result.is_true())
+    } else {
+        result
+    }
+}
+
+/// Evaluates a tree like `Evaluator`, but keeps whole numbers as exact
+/// integers. A float operand makes an operation use floats, and so do
+/// functions of float arguments. Fractions and complex numbers are left to
+/// `rational_mode` and `complex_mode`.
+pub struct ExactEvaluator<'a> {
+    ctx: &'a Context,
+    big: bool,
+    rational: bool,
+    complex: bool,
+    /// Variables bound by `sum` and `prod`, innermost last.
+    scope: Vec<(String, Value)>,
+    /// Position of the node being evaluated.
+    pos: usize,
+    /// The expression the tree was parsed from, if known.
+    source: Option<&'a str>,
+    /// The position and the span of the node the error being returned comes
+    /// from.
+    failed: Option<(usize, Span)>,
+}
+
+impl<'a> ExactEvaluator<'a> {
+    /// Integer mode, which reports integer overflow and division by zero.
+    pub fn integer(ctx: &'a Context) -> Self {
+        Self {
+            ctx,
+            big: false,
+            rational: false,
+            complex: ctx.complex_mode(),
+            scope: Vec::new(),
+            pos: 0,
+            source: None,
+            failed: None,
+        }
+    }
+
+    /// Integers that outgrow i64 become big integers instead, and what
+    /// integers can't do, like dividing by zero, is left to floats. So the
+    /// results only differ from `Evaluator` where floats lose precision, e.g.
+    /// `25!` is exact.
+    pub fn exact(ctx: &'a Context) -> Self {
+        Self {
+            ctx,
+            big: true,
+            rational: false,
+            complex: ctx.complex_mode(),
+            scope: Vec::new(),
+            pos: 0,
+            source: None,
+            failed: None,
+        }
+    }
+
+    /// Like `exact`, but divisions give exact fractions, `1/3 + 1/6` is
+    /// `1/2`, and so do number literals, `0.1` is `1/10`. Dividing by zero
+    /// is an error, and functions still use floats.
+    pub fn rational(ctx: &'a Context) -> Self {
+        Self {
+            ctx,
+            big: true,
+            rational: true,
+            complex: ctx.complex_mode(),
+            scope: Vec::new(),
+            pos: 0,
+            source: None,
+            failed: None,
+        }
+    }
+
+    /// Sets the expression the tree was parsed from, so that integer literals
+    /// beyond 2^53, which floats round, are read exactly from their digits.
+    pub fn set_source(&mut self, source: &'a str) {
+        self.source = Some(source);
+    }
+
+    /// The position and the span of the node the last error comes from,
+    /// which is the node that raised it, like the call of `sqrt(-1)`.
+    pub fn failed(&self) -> Option<(usize, Span)> {
+        self.failed
+    }
+
+    /// The exact value of the integer literal `n` at the current position,
+    /// or `None` if it's below 2^53 or it isn't a whole literal, like
+    /// `1E20`. In integer mode a literal beyond i64 is an overflow.
+    fn integer_literal(&self, n: f64) -> Option<Result<Value, String>> {
+        let source = self.source.filter(|_| n >= MAX_EXACT_INTEGER)?;
+        let mut chars = source.chars().skip(self.pos).peekable();
+        let mut text = String::new();
+        while let Some(c) = chars.next_if(|&c| c.is_ascii_digit() || c == '_') {
+            text.push(c);
+        }
+        let fractional = match chars.next() {
+            Some('.') | Some('E') => true,
+            // An exponent rather than the constant `e`.
+            Some('e') => matches!(chars.next(), Some('0'..='9') | Some('+') | Some('-')),
+            Some(',') => self.ctx.decimal_comma() && matches!(chars.next(), Some('0'..='9')),
+            _ => false,
+        };
+        let digits = text.replace('_', "");
+        if fractional || digits.parse() != Ok(n) {
+            return None;
+        }
+        match digits.parse() {
+            Ok(i) => Some(Ok(Value::Int(i))),
+            Err(_) if !self.big => Some(Err(format!(
+                "Integer overflow in the literal {} at pos {}!",
+                text, self.pos
+            ))),
+            Err(_) => BigInt::parse(&digits).and_then(exact).map(Ok),
+        }
+    }
+
+    /// Evaluates a tree of plain numbers with floats, as the node at the
+    /// current position, so that float errors report it.
+    fn float(&self, node: &Node) -> Result<Value, String> {
+        let node = Node {
+            pos: self.pos,
+            ..node.clone()
+        };
+        let mut evaluator = Evaluator::new(self.ctx);
+        for (name, value) in &self.scope {
+            evaluator.bind(name, value.as_f64());
+        }
+        walk_node(&mut evaluator, &node).map(Value::Float)
+    }
+
+    /// `sum` and `prod` of exact values, `sum(i, 1, 3, i)` is the integer 6
+    /// and `sum(i, 1, 3, 1/i)` is `11/6` in rational mode.
+    fn fold_range(&mut self, form: &SpecialForm, args: &[Node]) -> Result<Value, String> {
+        let pos = self.pos;
+        let (op, init) = match form.name {
+            "sum" => (TokenType::Plus, 0),
+            _ => (TokenType::Multiply, 1),
+        };
+        let Binding { var, body, args } = form.split(args, pos)?;
+        let from = integer_bound(form.name, walk_node(self, &args[0])?.as_f64())?;
+        let to = integer_bound(form.name, walk_node(self, &args[1])?.as_f64())?;
+
+        let mut acc = Value::Int(init);
+        for step in 0..range_steps(form.name, from, to)? {
+            let i = Value::from_f64(from + step as f64);
+            self.scope.push((var.to_string(), i));
+            let value = walk_node(self, body);
+            self.scope.pop();
+            self.pos = pos;
+            acc = self.binary(&op, acc, value?)?;
+        }
+        Ok(acc)
+    }
+
+    /// Applies `op` to the operands as floats.
+    fn float_op(
+        &self,
+        op: &TokenType,
+        left: &Value,
+        right: Option<&Value>,
+    ) -> Result<Value, String> {
+        let right = match right {
+            Some(right) => number(self.float_operand(right)?),
+            None => None,
+        };
+        let left = number(self.float_operand(left)?);
+        let result = self.float(&Node::new(op.clone(), left, right))?;
+        Ok(truth_value(op, result))
+    }
+
+    /// `value` as a float operand of the operation at the current position.
+    /// In strict mode an exact number beyond the range of floats is a float
+    /// error rather than `inf`, like the results of float operations.
+    fn float_operand(&self, value: &Value) -> Result<f64, String> {
+        let x = value.as_f64();
+        if self.ctx.strict_floats() && x.is_infinite() && matches!(value, Value::Big(_) | Value::Ratio(_)) {
+            return Err(format!(
+                "Float error! The exact operand at pos {} is {} as a float!",
+                self.pos,
+                format_number(x)
+            ));
+        }
+        Ok(x)
+    }
+
+    fn unary(&self, op: &TokenType, operand: Value) -> Result<Value, String> {
+        let operand = operand.to_number();
+        let result = match (op, &operand) {
+            (_, Value::Complex(z)) => return complex_unary(op, *z),
+            (_, Value::Float(_)) => return self.float_op(op, &operand, None),
+            (TokenType::PrefixPlus, _) => return Ok(operand),
+            (TokenType::Not, _) => return Ok(Value::Bool(!operand.is_true())),
+            (TokenType::Percent, _) => {
+                return self.binary(&TokenType::Divide, operand, Value::Int(100))
+            }
+            // -0 keeps its sign like a float, so `1 / -0` is still -inf.
+            (TokenType::PrefixMinus, Value::Int(0)) if self.big && !self.rational => {
+                return Ok(Value::Float(-0.))
+            }
+            (TokenType::PrefixMinus, Value::Ratio(fraction)) => {
+                return Ok(Value::Ratio(-fraction.clone()))
+            }
+            (TokenType::Bar, Value::Ratio(fraction)) => return Ok(Value::Ratio(fraction.abs())),
+            (_, Value::Ratio(_)) => return self.float_op(op, &operand, None),
+            (TokenType::PrefixMinus, Value::Int(i)) => i.checked_neg().map(Value::Int),
+            (TokenType::Bar, Value::Int(i)) => i.checked_abs().map(Value::Int),
+            (TokenType::Factorial | TokenType::DoubleFactorial, Value::Int(i)) if *i >= 0 => {
+                factorial(*i, factorial_step(op)).map(Value::Int)
+            }
+            (_, Value::Int(_)) => return self.float_op(op, &operand, None),
+            (_, Value::Big(_)) => None,
+            (_, Value::Bool(_)) => unreachable!(),
+        };
+        if let Some(result) = result {
+            return Ok(result);
+        }
+
+        let n = match &operand {
+            Value::Int(i) if !self.big => {
+                let operation = match op {
+                    TokenType::PrefixMinus => format!("-({})", i),
+                    TokenType::Bar => format!("|{}|", i),
+                    TokenType::Factorial => format!("the factorial of {}", i),
+                    _ => format!("the double factorial of {}", i),
+                };
+                return Err(format!(
+                    "Integer overflow in {} at pos {}!",
+                    operation, self.pos
+                ));
+            }
+            Value::Int(i) => BigInt::from(*i),
+            Value::Big(n) => n.clone(),
+            Value::Bool(_) | Value::Ratio(_) | Value::Float(_) | Value::Complex(_) => {
+                unreachable!()
+            }
+        };
+        let result = match op {
+            TokenType::PrefixMinus => exact(-n),
+            TokenType::Bar => exact(n.abs()),
+            TokenType::Factorial | TokenType::DoubleFactorial if n >= BigInt::from(0) => n
+                .to_i64()
+                .and_then(|n| big_factorial(n, factorial_step(op)))
+                .and_then(exact),
+            _ => None,
+        };
+        match result {
+            Some(result) => Ok(result),
+            None => self.float_op(op, &operand, None),
+        }
+    }
+
+    fn binary(&self, op: &TokenType, left: Value, right: Value) -> Result<Value, String> {
+        let (left, right) = (left.to_number(), right.to_number());
+        if self.complex && needs_complex(op, &left, &right) {
+            return complex_binary(op, &left, &right);
+        }
+
+        if self.rational {
+            if let (Some(fraction_left), Some(fraction_right)) =
+                (left.to_fraction(), right.to_fraction())
+            {
+                return match rational_binary(op, &left, &right, &fraction_left, &fraction_right)? {
+                    Some(result) => Ok(result),
+                    None => self.float_op(op, &left, Some(&right)),
+                };
+            }
+        }
+
+        let (big_left, big_right) = match (left.to_big(), right.to_big()) {
+            (Some(big_left), Some(big_right)) => (big_left, big_right),
+            _ => return self.float_op(op, &left, Some(&right)),
+        };
+
+        let division = matches!(
+            op,
+            TokenType::Divide | TokenType::FloorDivide | TokenType::Modulo
+        );
+        if division && big_right.is_zero() && !self.big {
+            return Err(format!(
+                "Division by zero in {} {} {}!",
+                left,
+                binary_symbol(op),
+                right
+            ));
+        } else if division && big_right.is_zero()
+            || *op == TokenType::Power && big_right < BigInt::from(0)
+        {
+            return self.float_op(op, &left, Some(&right));
+        }
+
+        if let (Value::Int(l), Value::Int(r)) = (&left, &right) {
+            match int_binary(op, *l, *r) {
+                Some(result) => return Ok(result),
+                None if !self.big => return Err(overflow(op, *l, *r, self.pos)),
+                None => (),
+            }
+        }
+        match big_binary(op, &big_left, &big_right) {
+            Some(result) => Ok(result),
+            None => self.float_op(op, &left, Some(&right)),
+        }
+    }
+}
+
+impl<'a> Visitor for ExactEvaluator<'a> {
+    type Output = Result<Value, String>;
+
+    fn visit_number(&mut self, n: f64) -> Self::Output {
+        if let Some(value) = self.integer_literal(n) {
+            return value;
+        }
+        // A literal prints as itself, so its digits are the exact value.
+        match Fraction::parse(&n.to_string()).and_then(exact_fraction) {
+            Some(value) if self.rational => Ok(value),
+            _ => Ok(Value::from_f64(n)),
+        }
+    }
+
+    fn visit_variable(&mut self, name: &str) -> Self::Output {
+        if let Some((_, value)) = self.scope.iter().rev().find(|(local, _)| local == name) {
+            return Ok(value.clone());
+        }
+        // Variables hide constants, including the imaginary unit `i`.
+        if self.ctx.get_var(name).is_none() {
+            match self.ctx.builtin_name(name).as_ref() {
+                "i" if self.complex => return Ok(Value::Complex(Complex::I)),
+                "true" => return Ok(Value::Bool(true)),
+                "false" => return Ok(Value::Bool(false)),
+                _ => (),
+            }
+        }
+        Evaluator::new(self.ctx)
+            .visit_variable(name)
+            .map(Value::from_f64)
+    }
+
+    fn visit_history(&mut self, n: usize) -> Self::Output {
+        self.ctx.history().get(n, self.pos).cloned()
+    }
+
+    /// Functions compute with floats. Their result is an integer again if
+    /// all arguments are i64 and it's a whole number, e.g. `band(12, 10)`.
+    fn visit_call(&mut self, name: &str, args: &[Node]) -> Self::Output {
+        let pos = self.pos;
+        trace!("calls {} at pos {} with {} arguments", name, pos, args.len());
+        let call = |args: Vec<Node>| {
+            let mut call = Node::new(TokenType::Call(name.to_string(), args), None, None);
+            call.pos = pos;
+            call
+        };
+        let builtin_name = self.ctx.builtin_name(name);
+        match find_special_form(&builtin_name) {
+            Some(form) if form.name == "sum" || form.name == "prod" => {
+                return self.fold_range(form, args)
+            }
+            Some(_) => return self.float(&call(args.to_vec())),
+            None => (),
+        }
+        // Before the arguments are evaluated, while they still have their
+        // positions.
+        self.ctx.function(name, args, pos)?;
+        if builtin_name == "env" {
+            return env_var(self.ctx, &args[0], pos).map(Value::from_f64);
+        }
+
+        let args = args
+            .iter()
+            .map(|arg| walk_node(self, arg).map(Value::to_number))
+            .collect::<Result<Vec<Value>, String>>()?;
+        self.pos = pos;
+        let integers = args.iter().all(|arg| matches!(arg, Value::Int(_)));
+        let real_domain = |arg: &Value| match builtin_name.as_ref() {
+            "sqrt" | "ln" => arg.as_f64() >= 0. || arg.as_f64().is_nan(),
+            _ => true,
+        };
+        let complex = args.iter().any(|arg| matches!(arg, Value::Complex(_)));
+        if self.complex && (complex || !args.iter().all(real_domain)) {
+            return complex_call(self.ctx, name, &args);
+        }
+        if builtin_name == "frac" {
+            return frac(name, &args);
+        }
+        if builtin_name == "out" && args.len() == 1 {
+            return self.ctx.history().get_index(args[0].as_f64(), pos).cloned();
+        }
+
+        let args = args
+            .iter()
+            .map(|arg| self.float_operand(arg).map(|x| *number(x).unwrap()))
+            .collect::<Result<Vec<Node>, String>>()?;
+        match self.float(&call(args))? {
+            Value::Float(result) if integers => Ok(Value::from_f64(result)),
+            result => Ok(result),
+        }
+    }
+
+    fn visit_text(&mut self, text: &str) -> Self::Output {
+        let mut evaluator = Evaluator::new(self.ctx);
+        evaluator.visit_pos(self.pos);
+        evaluator.visit_text(text).map(Value::Float)
+    }
+
+    fn visit_lambda(&mut self, param: &str, body: &NodePtr) -> Self::Output {
+        let mut evaluator = Evaluator::new(self.ctx);
+        evaluator.visit_pos(self.pos);
+        evaluator.visit_lambda(param, body).map(Value::Float)
+    }
+
+    fn visit_unary(&mut self, op: &TokenType, operand: &NodePtr) -> Self::Output {
+        let pos = self.pos;
+        check_operands(op, pos, operand.is_some(), false)?;
+        let operand = walk(self, operand)?;
+        self.pos = pos;
+        let result = self.unary(op, operand);
+        trace!("{:?} at pos {} gives {:?}", op, pos, result);
+        result
+    }
+
+    fn visit_binary(&mut self, op: &TokenType, left: &NodePtr, right: &NodePtr) -> Self::Output {
+        let pos = self.pos;
+        check_operands(op, pos, left.is_some(), right.is_some())?;
+        match op {
+            TokenType::And if !walk(self, left)?.is_true() => return Ok(Value::Bool(false)),
+            TokenType::Or if walk(self, left)?.is_true() => return Ok(Value::Bool(true)),
+            TokenType::And | TokenType::Or => return Ok(Value::Bool(walk(self, right)?.is_true())),
+            _ => (),
+        }
+
+        let left = walk(self, left)?;
+        let right = walk(self, right)?;
+        self.pos = pos;
+        let result = self.binary(op, left, right);
+        trace!("{:?} at pos {} gives {:?}", op, pos, result);
+        result
+    }
+
+    fn visit_none(&mut self) -> Self::Output {
+        // The operators check for missing operands, so it's the whole tree.
+        Err(String::from("Empty expression!"))
+    }
+
+    fn visit_pos(&mut self, pos: usize) {
+        self.pos = pos;
+    }
+
+    /// An error is kept for the node it ends first, a value forgets one the
+    /// node recovered from.
+    fn visit_end(&mut self, node: &Node, output: &Self::Output) {
+        match output {
+            Ok(_) => self.failed = None,
+            Err(_) if self.failed.is_none() => self.failed = Some((node.pos, node.span)),
+            Err(_) => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod exact_tests {
+    use super::*;
+    use crate::ast::parse;
+    use crate::evaluator::{evaluate_value, evaluate_with};
+
+    fn integer_context() -> Context {
+        let mut ctx = Context::new();
+        ctx.set_integer_mode(true);
+        ctx
+    }
+
+    fn do_test(expr: &str, expected: Value) {
+        println!("Expression = {}", expr);
+
+        let expr = expr.to_string();
+        assert_eq!(evaluate_value(&expr, &integer_context()), Ok(expected));
+    }
+
+    fn do_error_test(expr: &str, expected: &str) {
+        println!("Expression = {}", expr);
+
+        let expr = expr.to_string();
+        assert_eq!(
+            evaluate_value(&expr, &integer_context()),
+            Err(String::from(expected))
+        );
+    }
+
+    #[test]
+    fn integer_arithmetic_test() {
+        do_test("2 * 3 + 1", Value::Int(7));
+        do_test("-7 % 3", Value::Int(-1));
+        do_test("6 / 2", Value::Int(3));
+        do_test("7 // 2", Value::Int(3));
+        do_test("-7 // 2", Value::Int(-4));
+        do_test("7 // -2", Value::Int(-4));
+        do_test("|-5| + 20!", Value::Int(2_432_902_008_176_640_005));
+        do_test("9!!", Value::Int(945));
+        do_test("not 0 and 3 > 2", Value::Bool(true));
+    }
+
+    #[test]
+    fn exact_integer_test() {
+        let doubled = vec!["2"; 62].join(" * ");
+        do_test(&doubled, Value::Int(1 << 62));
+        do_test("2^62", Value::Int(1 << 62));
+        do_test("(2^62 - 1) * 2 + 1", Value::Int(i64::MAX));
+        // Floats can't tell these apart.
+        do_test("2^62 + 1 > 2^62", Value::Bool(true));
+
+        let tree = parse("2^62 + 1 > 2^62").unwrap();
+        assert_eq!(walk(&mut Evaluator::new(&Context::new()), &tree), Ok(0.));
+
+        // Literals are read from their digits, not rounded to floats.
+        do_test("9223372036854775807", Value::Int(i64::MAX));
+        do_test(
+            "9_007_199_254_740_993 - 1",
+            Value::Int(9_007_199_254_740_992),
+        );
+        do_test("1E20 > 2^62", Value::Bool(true));
+        do_test("10000000000000000000e0 > 2^62", Value::Bool(true));
+    }
+
+    #[test]
+    fn integer_overflow_test() {
+        do_error_test(
+            "(2^62 - 1) * 2 + 1 + 1",
+            "Integer overflow in 9223372036854775807 + 1 at pos 19!",
+        );
+        do_error_test(
+            "-(2^62) * 2 - 1",
+            "Integer overflow in -9223372036854775808 - 1 at pos 12!",
+        );
+        do_error_test(
+            "2^62 * 2",
+            "Integer overflow in 4611686018427387904 * 2 at pos 5!",
+        );
+        do_error_test("2^63", "Integer overflow in 2 ^ 63 at pos 1!");
+        do_error_test(
+            "-(-(2^62) * 2)",
+            "Integer overflow in -(-9223372036854775808) at pos 0!",
+        );
+        do_error_test("21!", "Integer overflow in the factorial of 21 at pos 2!");
+        do_error_test("(-3)!", "Domain error! (-3)! at pos 4 is undefined!");
+        do_error_test("1 + (-3)!!", "Domain error! (-3)!! at pos 8 is undefined!");
+        do_error_test("2.5!!", "Domain error! 2.5!! at pos 3 is undefined!");
+        do_error_test(
+            "|-(2^62) * 2|",
+            "Integer overflow in |-9223372036854775808| at pos 0!",
+        );
+        do_error_test("7 / 0", "Division by zero in 7 / 0!");
+        do_error_test("7 % 0", "Division by zero in 7 % 0!");
+        do_error_test(
+            "-(2^62) * 2 // -1",
+            "Integer overflow in -9223372036854775808 // -1 at pos 12!",
+        );
+        do_error_test(
+            "9223372036854775807 + 1",
+            "Integer overflow in 9223372036854775807 + 1 at pos 20!",
+        );
+        do_error_test(
+            "1 + 9_223_372_036_854_775_808",
+            "Integer overflow in the literal 9_223_372_036_854_775_808 at pos 4!",
+        );
+    }
+
+    #[test]
+    fn float_promotion_test() {
+        do_test("7 / 2", Value::Float(3.5));
+        do_test("1.5 + 2", Value::Float(3.5));
+        do_test("2^-1", Value::Float(0.5));
+        do_test("50%", Value::Float(0.5));
+        do_test("200%", Value::Int(2));
+        do_test("2.5 * 2", Value::Float(5.));
+        do_test("pi > 3", Value::Bool(true));
+        do_test("not 0.5", Value::Bool(false));
+        do_test("1^5000000000 + (-1)^5000000001", Value::Int(0));
+    }
+
+    #[test]
+    fn integer_functions_test() {
+        do_test("band(12, 10)", Value::Int(8));
+        do_test("max(2, 7)", Value::Int(7));
+        do_test("sqrt(16)", Value::Int(4));
+        do_test("sqrt(2)", Value::Float(2f64.sqrt()));
+        do_test("floor(2.5)", Value::Float(2.));
+        do_test("sum(i, 1, 3, i)", Value::Int(6));
+        do_error_test("sqrt(-4)", "Domain error! sqrt(-4) is undefined!");
+        do_error_test(
+            "x -> x",
+            "Lambda x -> ... at pos 0 can't be used as a number!",
+        );
+        do_error_test(
+            "1 + (y -> y)",
+            "Lambda y -> ... at pos 5 can't be used as a number!",
+        );
+    }
+
+    #[test]
+    fn exact_special_forms_test() {
+        do_test("sum(i, 1, 3, sum(j, 1, i, j))", Value::Int(10));
+        do_test("prod(i -> 2 * i, 1, 3)", Value::Int(48));
+        do_test("sum(i, 1, 0, i)", Value::Int(0));
+        do_test("sum(k, 1, 2, integrate(x -> k, 0, 1))", Value::Float(3.));
+        do_error_test(
+            "prod(i, 1, 21, i)",
+            "Integer overflow in 2432902008176640000 * 21 at pos 0!",
+        );
+        do_exact_test("prod(i, 1, 25, i)", "15511210043330985984000000");
+        do_exact_test("sum(i, 2^53 - 1, 2^53, i)", "18014398509481983");
+
+        let expr = "sum(i, 1, 3, 1/i)".to_string();
+        let mut ctx = Context::new();
+        ctx.set_rational_mode(true);
+        assert_eq!(evaluate_value(&expr, &ctx).unwrap().to_string(), "11/6");
+    }
+
+    #[test]
+    fn strict_float_position_test() {
+        let mut ctx = integer_context();
+        ctx.set_strict_floats(true);
+        let cases = [
+            (
+                "1 + 2.5 * 1E308",
+                "Float error! 2.5 * 1e308 at pos 8 is inf!",
+            ),
+            ("2 * exp(1000)", "Float error! exp(1000) at pos 4 is inf!"),
+        ];
+        for (expr, expected) in cases.iter() {
+            let expr = expr.to_string();
+            assert_eq!(evaluate_value(&expr, &ctx), Err(expected.to_string()));
+        }
+
+        // Exact integers too large for a float don't slip in as `inf`.
+        let mut ctx = Context::new();
+        ctx.set_strict_floats(true);
+        let cases = [
+            (
+                "1 + 2^1024 * 1.5",
+                "Float error! The exact operand at pos 11 is inf as a float!",
+            ),
+            (
+                "sqrt(171!)",
+                "Float error! The exact operand at pos 0 is inf as a float!",
+            ),
+        ];
+        for (expr, expected) in cases.iter() {
+            let expr = expr.to_string();
+            assert_eq!(evaluate_value(&expr, &ctx), Err(expected.to_string()));
+        }
+        ctx.set_strict_floats(false);
+        assert_eq!(evaluate_value(&"2^1024 * 1.5".to_string(), &ctx), Ok(Value::Float(f64::INFINITY)));
+    }
+
+    #[test]
+    fn integer_mode_evaluate_with_test() {
+        let expr = "7 / 2 + 2 * 3".to_string();
+        assert_eq!(evaluate_with(&expr, &integer_context()), Ok(9.5));
+        let expr = "2^63".to_string();
+        assert_eq!(
+            evaluate_with(&expr, &integer_context()),
+            Err(String::from("Integer overflow in 2 ^ 63 at pos 1!"))
+        );
+    }
+
+    fn do_exact_test(expr: &str, expected: &str) {
+        println!("Expression = {}", expr);
+
+        let expr = expr.to_string();
+        let result = evaluate_value(&expr, &Context::new()).unwrap();
+        assert_eq!(result.to_string(), expected);
+    }
+
+    #[test]
+    fn big_integer_test() {
+        do_exact_test("25!", "15511210043330985984000000");
+        do_exact_test("2^100", "1267650600228229401496703205376");
+        do_exact_test("2^53 + 1", "9007199254740993");
+        do_exact_test(
+            "123456789012345678901234567890 + 1",
+            "123456789012345678901234567891",
+        );
+        do_exact_test("-2^100", "1267650600228229401496703205376");
+        do_exact_test("-(2^100) + 1", "-1267650600228229401496703205375");
+        do_exact_test("2^64 * 2^64 - 1", "340282366920938463463374607431768211455");
+        do_exact_test("2^100 - 2^100 + 7", "7");
+        do_exact_test("2^100 > 2^100 - 1", "true");
+        do_exact_test("|-(2^70)|", "1180591620717411303424");
+        do_exact_test("31!!", "191898783962510625");
+        do_exact_test("(2^62 - 1) * 2 + 2", "9223372036854775808");
+
+        let expr = "200!".to_string();
+        let result = evaluate_value(&expr, &Context::new()).unwrap().to_string();
+        assert_eq!(result.len(), 375);
+        assert!(result.starts_with("788657867364790503552363213932185062295135977687"));
+        assert!(result.ends_with(&"0".repeat(49)));
+    }
+
+    #[test]
+    fn big_integer_fallback_test() {
+        assert_eq!(
+            evaluate_value(&"-(2^63)".to_string(), &Context::new()),
+            Ok(Value::Int(i64::MIN))
+        );
+        assert_eq!(
+            evaluate_value(&"2^64 / 2^60".to_string(), &Context::new()),
+            Ok(Value::Float(16.))
+        );
+        assert_eq!(
+            evaluate_value(&"2^100 / 3".to_string(), &Context::new()),
+            Ok(Value::Float(2f64.powi(100) / 3.))
+        );
+        assert_eq!(
+            evaluate_value(&"2^100 * 0.5".to_string(), &Context::new()),
+            Ok(Value::Float(2f64.powi(99)))
+        );
+        assert_eq!(
+            evaluate_value(&"2^(2^20)".to_string(), &Context::new()),
+            Ok(Value::Float(f64::INFINITY))
+        );
+        assert_eq!(
+            evaluate_with(&"2^100".to_string(), &Context::new()),
+            Ok(2f64.powi(100))
+        );
+    }
+
+    #[test]
+    fn small_results_unchanged_test() {
+        let cases = [
+            "1 + 2",
+            "0.1 + 0.2",
+            "7 / 2",
+            "-7 // 2",
+            "-7 % 3",
+            "2^-2",
+            "1 / 0",
+            "1 / -0",
+            "0 / 0",
+            "5!",
+            "50%",
+            "sqrt(2)",
+            "3 > 2",
+            "sum(i, 1, 4, i^2)",
+        ];
+        for expr in cases.iter() {
+            println!("Expression = {}", expr);
+
+            let tree = parse(expr).unwrap();
+            let float = walk(&mut Evaluator::new(&Context::new()), &tree).unwrap();
+            let exact = evaluate_value(&expr.to_string(), &Context::new()).unwrap();
+            // Only the type of comparisons changes, `3 > 2` is `true`.
+            assert_eq!(
+                Value::Float(exact.as_f64()).to_string(),
+                Value::Float(float).to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn bool_value_test() {
+        let ctx = Context::new();
+        let value = |expr: &str| evaluate_value(&expr.to_string(), &ctx);
+        assert_eq!(value("3 > 2"), Ok(Value::Bool(true)));
+        assert_eq!(value("true and false"), Ok(Value::Bool(false)));
+        assert_eq!(value("not 0"), Ok(Value::Bool(true)));
+        assert_eq!(value("true"), Ok(Value::Bool(true)));
+        assert_eq!(value("1 + true"), Ok(Value::Int(2)));
+        assert_eq!(value("(1 < 2) * 10"), Ok(Value::Int(10)));
+        assert_eq!(value("-true"), Ok(Value::Int(-1)));
+        assert_eq!(value("true == 1"), Ok(Value::Bool(true)));
+        assert_eq!(value("max(true, 0.5)"), Ok(Value::Float(1.)));
+        assert_eq!(evaluate_with(&"2 > 1".to_string(), &ctx), Ok(1.));
+
+        assert_eq!(Value::Bool(false).to_string(), "false");
+        assert_eq!(Value::Int(-3).to_string(), "-3");
+        assert_eq!(Value::Float(2.).to_string(), "2");
+        assert_eq!(Value::Float(0.5).to_string(), "0.5");
+        assert_eq!(Value::Bool(true).as_f64(), 1.);
+    }
+}
@@ -0,0 +1,263 @@
+//! A C interface for applications in other languages. A context is made by
+//! `lr_calc_ctx_new` and owned by the caller until it passes it to
+//! `lr_calc_ctx_free`. Strings are UTF-8 and NUL-terminated, those passed in
+//! stay owned by the caller and are only read during the call. No panic
+//! crosses the interface, one is reported as `LR_CALC_PANIC`.
+
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use super::context::Context;
+use super::error::ErrorCode;
+use super::evaluator::evaluate_diagnosed;
+
+pub const LR_CALC_OK: i32 = 0;
+/// The expression couldn't be parsed.
+pub const LR_CALC_PARSE_ERROR: i32 = 1;
+/// The expression was parsed, but couldn't be evaluated.
+pub const LR_CALC_EVAL_ERROR: i32 = 2;
+pub const LR_CALC_INVALID_UTF8: i32 = 3;
+pub const LR_CALC_NULL_POINTER: i32 = 4;
+/// A bug of the calculator, which panicked.
+pub const LR_CALC_PANIC: i32 = 5;
+
+/// The context of the C interface: the variables, and the message of the
+/// last error.
+pub struct LrCalcContext {
+    ctx: Context,
+    last_error: Option<CString>,
+}
+
+impl LrCalcContext {
+    /// Keeps `message` for `lr_calc_last_error_message` and returns `code`.
+    fn fail(&mut self, code: i32, message: &str) -> i32 {
+        self.last_error = CString::new(message.replace('\0', "")).ok();
+        code
+    }
+}
+
+/// Runs `f` with the context and the strings behind `ctx` and `strings`,
+/// and turns a null pointer, invalid UTF-8 or a panic into its error code.
+unsafe fn with_context(
+    ctx: *mut LrCalcContext,
+    strings: &[*const c_char],
+    f: impl FnOnce(&mut LrCalcContext, &[&str]) -> i32,
+) -> i32 {
+    let ctx = match ctx.as_mut() {
+        Some(ctx) => ctx,
+        None => return LR_CALC_NULL_POINTER,
+    };
+    ctx.last_error = None;
+    let mut texts = Vec::with_capacity(strings.len());
+    for &string in strings {
+        if string.is_null() {
+            return ctx.fail(LR_CALC_NULL_POINTER, "A string is a null pointer!");
+        }
+        match CStr::from_ptr(string).to_str() {
+            Ok(text) => texts.push(text),
+            Err(err) => {
+                let message = format!("A string isn't UTF-8 from byte {} on!", err.valid_up_to());
+                return ctx.fail(LR_CALC_INVALID_UTF8, &message);
+            }
+        }
+    }
+    match panic::catch_unwind(AssertUnwindSafe(|| f(&mut *ctx, &texts))) {
+        Ok(code) => code,
+        Err(_) => ctx.fail(LR_CALC_PANIC, "The calculator panicked!"),
+    }
+}
+
+/// A new context without variables, null if it can't be made. It's freed
+/// with `lr_calc_ctx_free`.
+#[no_mangle]
+pub extern "C" fn lr_calc_ctx_new() -> *mut LrCalcContext {
+    panic::catch_unwind(|| {
+        Box::into_raw(Box::new(LrCalcContext {
+            ctx: Context::new(),
+            last_error: None,
+        }))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Frees a context, null is ignored.
+///
+/// # Safety
+///
+/// `ctx` is null or was returned by `lr_calc_ctx_new` and isn't used after.
+#[no_mangle]
+pub unsafe extern "C" fn lr_calc_ctx_free(ctx: *mut LrCalcContext) {
+    if !ctx.is_null() {
+        drop(Box::from_raw(ctx));
+    }
+}
+
+/// Evaluates `expr` with the variables of `ctx` and writes its value to
+/// `out`. Returns `LR_CALC_OK` or the code of the error, whose message is
+/// `lr_calc_last_error_message`.
+///
+/// # Safety
+///
+/// `ctx` is null or a live context, `expr` null or a NUL-terminated string
+/// and `out` null or valid for a write.
+#[no_mangle]
+pub unsafe extern "C" fn lr_calc_eval(
+    ctx: *mut LrCalcContext,
+    expr: *const c_char,
+    out: *mut f64,
+) -> i32 {
+    with_context(ctx, &[expr], |ctx, strings| {
+        if out.is_null() {
+            return ctx.fail(LR_CALC_NULL_POINTER, "The output is a null pointer!");
+        }
+        match evaluate_diagnosed(&strings[0].to_string(), &ctx.ctx) {
+            Ok(value) => {
+                *out = value;
+                LR_CALC_OK
+            }
+            Err(err) if err.code == ErrorCode::Parse => ctx.fail(LR_CALC_PARSE_ERROR, &err.message),
+            Err(err) => ctx.fail(LR_CALC_EVAL_ERROR, &err.message),
+        }
+    })
+}
+
+/// Sets the variable `name` of `ctx` to `value`.
+///
+/// # Safety
+///
+/// `ctx` is null or a live context and `name` null or a NUL-terminated
+/// string.
+#[no_mangle]
+pub unsafe extern "C" fn lr_calc_set_var(
+    ctx: *mut LrCalcContext,
+    name: *const c_char,
+    value: f64,
+) -> i32 {
+    with_context(ctx, &[name], |ctx, strings| {
+        ctx.ctx.set_var(strings[0], value);
+        LR_CALC_OK
+    })
+}
+
+/// The message of the error of the last call with `ctx`, null if it
+/// succeeded. The string belongs to the context and lives until its next
+/// call or until it's freed.
+///
+/// # Safety
+///
+/// `ctx` is null or a live context.
+#[no_mangle]
+pub unsafe extern "C" fn lr_calc_last_error_message(ctx: *const LrCalcContext) -> *const c_char {
+    match ctx.as_ref().and_then(|ctx| ctx.last_error.as_ref()) {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+#[cfg(test)]
+mod ffi_tests {
+    use super::*;
+
+    unsafe fn last_error(ctx: *const LrCalcContext) -> Option<String> {
+        let message = lr_calc_last_error_message(ctx);
+        if message.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(message).to_str().unwrap().to_string())
+        }
+    }
+
+    #[test]
+    fn eval_test() {
+        unsafe {
+            let ctx = lr_calc_ctx_new();
+            let mut out = 0.;
+            let x = CString::new("x").unwrap();
+            assert_eq!(lr_calc_set_var(ctx, x.as_ptr(), 4.), LR_CALC_OK);
+            let expr = CString::new("sqrt(x) + 2^10").unwrap();
+            assert_eq!(lr_calc_eval(ctx, expr.as_ptr(), &mut out), LR_CALC_OK);
+            assert_eq!(out, 1026.);
+            assert_eq!(last_error(ctx), None);
+
+            let expr = CString::new("1 +").unwrap();
+            assert_eq!(
+                lr_calc_eval(ctx, expr.as_ptr(), &mut out),
+                LR_CALC_PARSE_ERROR
+            );
+            assert_eq!(
+                last_error(ctx),
+                Some(String::from(
+                    "Ast build error! Operator Plus at pos 2 expects an operand, but gets End!"
+                ))
+            );
+            let expr = CString::new("y").unwrap();
+            assert_eq!(
+                lr_calc_eval(ctx, expr.as_ptr(), &mut out),
+                LR_CALC_EVAL_ERROR
+            );
+            assert_eq!(last_error(ctx), Some(String::from("Unknown variable y!")));
+            // The value of the last success stays.
+            assert_eq!(out, 1026.);
+            lr_calc_ctx_free(ctx);
+        }
+    }
+
+    #[test]
+    fn invalid_input_test() {
+        unsafe {
+            let ctx = lr_calc_ctx_new();
+            let mut out = 0.;
+            let invalid = b"1 + \xff\0";
+            assert_eq!(
+                lr_calc_eval(ctx, invalid.as_ptr() as *const c_char, &mut out),
+                LR_CALC_INVALID_UTF8
+            );
+            assert_eq!(
+                last_error(ctx),
+                Some(String::from("A string isn't UTF-8 from byte 4 on!"))
+            );
+            assert_eq!(
+                lr_calc_set_var(ctx, invalid.as_ptr() as *const c_char, 1.),
+                LR_CALC_INVALID_UTF8
+            );
+
+            let expr = CString::new("1").unwrap();
+            assert_eq!(
+                lr_calc_eval(ptr::null_mut(), expr.as_ptr(), &mut out),
+                LR_CALC_NULL_POINTER
+            );
+            assert_eq!(
+                lr_calc_eval(ctx, ptr::null(), &mut out),
+                LR_CALC_NULL_POINTER
+            );
+            assert_eq!(
+                lr_calc_eval(ctx, expr.as_ptr(), ptr::null_mut()),
+                LR_CALC_NULL_POINTER
+            );
+            assert_eq!(
+                last_error(ctx),
+                Some(String::from("The output is a null pointer!"))
+            );
+            assert_eq!(lr_calc_set_var(ctx, ptr::null(), 1.), LR_CALC_NULL_POINTER);
+            assert!(lr_calc_last_error_message(ptr::null()).is_null());
+            lr_calc_ctx_free(ptr::null_mut());
+            lr_calc_ctx_free(ctx);
+        }
+    }
+
+    #[test]
+    fn panic_test() {
+        unsafe {
+            let ctx = lr_calc_ctx_new();
+            let code = with_context(ctx, &[], |_, _| panic!("A bug!"));
+            assert_eq!(code, LR_CALC_PANIC);
+            assert_eq!(
+                last_error(ctx),
+                Some(String::from("The calculator panicked!"))
+            );
+            lr_calc_ctx_free(ctx);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use super::ast::{Node, NodePtr, TokenType};
+use super::context::Context;
+use super::evaluator::Evaluator;
+use super::visitor::{transform, transform_args, walk_node, Transformer};
+
+/// Replaces every subtree that doesn't depend on a variable with its value
+/// and drops neutral operands (`x + 0`, `x * 1`, `x ^ 1`, ...).
+struct ConstantFolder;
+
+fn number(node: &NodePtr) -> Option<f64> {
+    match node {
+        Some(node) => match node.token {
+            TokenType::Number(n) => Some(n),
+            _ => None,
+        },
+        None => None,
+    }
+}
+
+/// Evaluates a node whose operands are all numbers. Operations that fail or
+/// don't produce a finite value are kept as they are.
+fn fold(node: Node) -> NodePtr {
+    match walk_node(&mut Evaluator::new(&Context::new()), &node) {
+        Ok(n) if n.is_finite() => Node::new_ptr(TokenType::Number(n), None, None),
+        _ => Some(Box::new(node)),
+    }
+}
+
+impl Transformer for ConstantFolder {
+    fn transform_call(&mut self, name: String, args: Vec<Node>) -> NodePtr {
+        let args = transform_args(self, args);
+        let constant = args
+            .iter()
+            .all(|arg| matches!(arg.token, TokenType::Number(_)));
+
+        let node = Node::new(TokenType::Call(name, args), None, None);
+        let constant = constant && node.is_pure();
+        if constant {
+            fold(node)
+        } else {
+            Some(Box::new(node))
+        }
+    }
+
+    fn transform_unary(&mut self, op: TokenType, operand: NodePtr) -> NodePtr {
+        if number(&operand).is_some() {
+            return fold(Node::new(op, operand, None));
+        }
+
+        match op {
+            TokenType::PrefixPlus => operand,
+            _ => Node::new_ptr(op, operand, None),
+        }
+    }
+
+    fn transform_binary(&mut self, op: TokenType, left: NodePtr, right: NodePtr) -> NodePtr {
+        let zero = Node::new_ptr(TokenType::Number(0.), None, None);
+        let one = Node::new_ptr(TokenType::Number(1.), None, None);
+
+        match (&op, number(&left), number(&right)) {
+            (_, Some(_), Some(_)) => fold(Node::new(op, left, right)),
+            (TokenType::Plus, Some(0.), _) => right,
+            (TokenType::Plus, _, Some(0.)) => left,
+            (TokenType::Minus, _, Some(0.)) => left,
+            (TokenType::Minus, Some(0.), _) => Node::new_ptr(TokenType::PrefixMinus, right, None),
+            (TokenType::Multiply, Some(0.), _) => zero,
+            (TokenType::Multiply, _, Some(0.)) => zero,
+            (TokenType::Multiply, Some(1.), _) => right,
+            (TokenType::Multiply, _, Some(1.)) => left,
+            (TokenType::Divide, _, Some(1.)) => left,
+            (TokenType::Divide, Some(0.), _) => zero,
+            (TokenType::Power, _, Some(0.)) => one,
+            (TokenType::Power, _, Some(1.)) => left,
+            (TokenType::Power, Some(1.), _) => one,
+            _ => Node::new_ptr(op, left, right),
+        }
+    }
+}
+
+impl Node {
+    /// Returns a simplified copy of the tree with all constant subtrees
+    /// evaluated.
+    pub fn fold_constants(&self) -> Node {
+        let tree = transform(&mut ConstantFolder, Some(Box::new(self.clone())));
+        *tree.expect("Folding a node always yields a node")
+    }
+}
+
+#[cfg(test)]
+mod folding_tests {
+    use super::*;
+    use crate::ast::parse;
+
+    fn build(expr: &str) -> Node {
+        *parse(expr).unwrap().unwrap()
+    }
+
+    fn do_test(expr: &str, expected: &str) {
+        println!("Expression = {}", expr);
+
+        assert_eq!(build(expr).fold_constants(), build(expected));
+    }
+
+    #[test]
+    fn fold_numbers_test() {
+        do_test("1 + 2", "3");
+        do_test("2 * 3 + x", "6 + x");
+        do_test("x * (4 - 1)!", "x * 6");
+        do_test("sqrt(16) + x", "4 + x");
+        do_test("max(x, 2 ^ 3)", "max(x, 8)");
+    }
+
+    #[test]
+    fn fold_negative_number_test() {
+        let tree = build("-(2 + 3)").fold_constants();
+        assert_eq!(tree, Node::new(TokenType::Number(-5.), None, None));
+    }
+
+    #[test]
+    fn fold_neutral_operands_test() {
+        do_test("x * 1 + 0", "x");
+        do_test("0 - x", "-x");
+        do_test("x ^ (2 - 1)", "x");
+        do_test("x ^ 0 + 0 * y", "1");
+        do_test("+x / 1", "x");
+        do_test("sin(0) + x", "x");
+    }
+
+    #[test]
+    fn fold_keeps_undefined_test() {
+        do_test("1 / 0 + x", "1 / 0 + x");
+        do_test("ln(0) * x", "ln(0) * x");
+        do_test("asin(2) * 2", "asin(2) * 2");
+        do_test("random() + 1", "random() + 1");
+    }
+}
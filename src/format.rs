@@ -0,0 +1,573 @@
+use super::bigint::BigInt;
+use super::printer::format_number;
+use super::value::Value;
+
+/// Significant digits results are shown with, unless set otherwise.
+pub const DEFAULT_PRECISION: usize = 15;
+
+/// How the magnitude of a number is written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Notation {
+    /// Plain decimals like `1234.5`, scientific beyond the thresholds.
+    Normal,
+    /// `1.2345e3`
+    Scientific,
+    /// The exponent is a multiple of 3, `1.2345e3`, `66.2e3`, `12e-6`.
+    Engineering,
+}
+
+/// The base integers are displayed in, with the prefix they're written with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Base {
+    Decimal,
+    /// `0b1010`
+    Binary,
+    /// `0o17`
+    Octal,
+    /// `0xFF`
+    Hexadecimal,
+}
+
+impl Base {
+    fn radix(self) -> u32 {
+        match self {
+            Base::Decimal => 10,
+            Base::Binary => 2,
+            Base::Octal => 8,
+            Base::Hexadecimal => 16,
+        }
+    }
+
+    fn prefix(self) -> &'static str {
+        match self {
+            Base::Decimal => "",
+            Base::Binary => "0b",
+            Base::Octal => "0o",
+            Base::Hexadecimal => "0x",
+        }
+    }
+}
+
+/// How results are displayed. It never changes the values themselves.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatOptions {
+    /// Significant digits of floats, or `None` for the exact f64.
+    pub precision: Option<usize>,
+    pub notation: Notation,
+    /// Floats below this magnitude are scientific in normal notation.
+    pub scientific_below: f64,
+    /// Floats from this magnitude on are scientific in normal notation.
+    pub scientific_from: f64,
+    /// Integers are shown in this base, other numbers stay decimal.
+    pub base: Base,
+    /// Shows negative integers in other bases as two's complement of this
+    /// many bits, and pads the digits to it.
+    pub width: Option<u32>,
+    /// Groups the integer digits of plain decimals by thousands with this
+    /// separator, `1,234,567.89`.
+    pub grouping: Option<char>,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            precision: Some(DEFAULT_PRECISION),
+            notation: Notation::Normal,
+            scientific_below: 1e-6,
+            scientific_from: 1e15,
+            base: Base::Decimal,
+            width: None,
+            grouping: None,
+        }
+    }
+}
+
+/// The significant digits of a nonzero decimal without trailing zeros and
+/// the exponent of the first one, `0.0125` is `("125", -2)`.
+fn decimal_digits(n: f64) -> (String, i64) {
+    let scientific = format!("{:e}", n.abs());
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    (mantissa.replace('.', ""), exponent.parse().unwrap())
+}
+
+/// Rounds `digits` half up to `precision` digits, which may carry into a new
+/// first digit, `("996", 0)` to 2 digits is `("1", 1)`.
+fn round_digits(digits: &str, exponent: i64, precision: Option<usize>) -> (String, i64) {
+    let precision = match precision {
+        Some(precision) if precision.max(1) < digits.len() => precision.max(1),
+        _ => return (digits.trim_end_matches('0').to_string(), exponent),
+    };
+    let mut kept: Vec<u8> = digits.bytes().take(precision).collect();
+    if digits.as_bytes()[precision] >= b'5' {
+        match kept.iter().rposition(|&digit| digit != b'9') {
+            Some(i) => {
+                kept[i] += 1;
+                kept.truncate(i + 1);
+            }
+            None => return (String::from("1"), exponent + 1),
+        }
+    }
+    let kept = String::from_utf8(kept).unwrap();
+    (kept.trim_end_matches('0').to_string(), exponent)
+}
+
+/// Writes `digits` with the exponent `exponent`, moving `shift` digits in
+/// front of the point, `("662", 4)` with a shift of 1 is `66.2e3`.
+fn with_exponent(negative: bool, digits: &str, exponent: i64, shift: usize) -> String {
+    let digits = format!("{:0<width$}", digits, width = shift + 1);
+    let (whole, fraction) = digits.split_at(shift + 1);
+    let sign = if negative { "-" } else { "" };
+    let point = if fraction.is_empty() { "" } else { "." };
+    format!(
+        "{}{}{}{}e{}",
+        sign,
+        whole,
+        point,
+        fraction,
+        exponent - shift as i64
+    )
+}
+
+/// Writes a number given by its sign, digits and exponent in scientific or
+/// engineering notation.
+fn format_exponent(negative: bool, digits: &str, exponent: i64, options: &FormatOptions) -> String {
+    let (digits, exponent) = round_digits(digits, exponent, options.precision);
+    let shift = match options.notation {
+        Notation::Engineering => exponent.rem_euclid(3) as usize,
+        _ => 0,
+    };
+    with_exponent(negative, &digits, exponent, shift)
+}
+
+/// Separates the integer digits of a plain decimal by thousands,
+/// `-1234567.891` is `-1,234,567.891` with a comma.
+fn group_digits(n: String, options: &FormatOptions) -> String {
+    let separator = match options.grouping {
+        Some(separator) => separator,
+        None => return n,
+    };
+    let (sign, digits) = n.split_at(n.starts_with('-') as usize);
+    let (whole, fraction) = digits.split_at(digits.find('.').unwrap_or(digits.len()));
+    let mut grouped = String::from(sign);
+    for (i, digit) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i) % 3 == 0 {
+            grouped.push(separator);
+        }
+        grouped.push(digit);
+    }
+    grouped + fraction
+}
+
+/// Rounds `n` to the significant digits of `options`, without trailing
+/// zeros, so `0.1 + 0.2` shows as `0.3` and 2 stays `2`. It has an exponent
+/// in scientific and engineering notation, and beyond the thresholds.
+pub fn format_float(n: f64, options: &FormatOptions) -> String {
+    if !n.is_finite() {
+        return format_number(n);
+    }
+    // Rounded first, so that a number that rounds up to the threshold is
+    // scientific too.
+    let n = match options.precision {
+        Some(digits) => format!("{:.*e}", digits.max(1) - 1, n).parse().unwrap(),
+        None => n,
+    };
+    let magnitude = n.abs();
+    let scientific = match options.notation {
+        Notation::Normal => {
+            magnitude >= options.scientific_from
+                || magnitude < options.scientific_below && magnitude != 0.
+        }
+        _ => magnitude != 0.,
+    };
+    if scientific {
+        let (digits, exponent) = decimal_digits(n);
+        format_exponent(n < 0., &digits, exponent, options)
+    } else {
+        group_digits(format_number(n), options)
+    }
+}
+
+/// Formats an integer, in full and grouped in normal notation.
+fn format_integer(n: String, options: &FormatOptions) -> String {
+    let digits = n.trim_start_matches('-');
+    if options.notation == Notation::Normal || digits == "0" {
+        return group_digits(n, options);
+    }
+    let exponent = digits.len() as i64 - 1;
+    format_exponent(n.starts_with('-'), digits, exponent, options)
+}
+
+/// Writes an integer in a base other than decimal, `-255` is `-0xFF`, or
+/// `0xFF01` with a width of 16 bits.
+fn format_in_base(n: &BigInt, options: &FormatOptions) -> String {
+    let radix = options.base.radix();
+    let width = options.width.filter(|&width| width > 0);
+    let mut n = n.clone();
+    if let Some(width) = width {
+        let range = BigInt::from(2).pow(width);
+        if n.is_negative() && n >= -BigInt::from(2).pow(width - 1) {
+            n = &n + &range;
+        }
+    }
+
+    let sign = if n.is_negative() { "-" } else { "" };
+    let mut digits = n.abs().to_str_radix(radix).to_uppercase();
+    if let Some(width) = width {
+        let length = width.div_ceil(radix.trailing_zeros()) as usize;
+        digits = format!("{:0>length$}", digits, length = length);
+    }
+    format!("{}{}{}", sign, options.base.prefix(), digits)
+}
+
+/// An integer-valued result as an integer, `2.0` is 2, otherwise `None`.
+fn integer_value(value: &Value) -> Option<BigInt> {
+    match value {
+        Value::Float(x) => BigInt::from_f64(*x),
+        _ => value.to_big(),
+    }
+}
+
+/// Formats a result for display. Exact integers and fractions are shown in
+/// full, floats and complex numbers with the precision of `options`. In
+/// scientific and engineering notation integers are rounded like floats.
+///
+/// In another base than decimal integer-valued results are shown in that
+/// base, and anything else, like 2.5 or `1/2`, falls back to decimal.
+pub fn format_value(value: &Value, options: &FormatOptions) -> String {
+    let integer = match value {
+        Value::Bool(_) => None,
+        _ => integer_value(value),
+    };
+    if let (Some(n), true) = (integer, options.base != Base::Decimal) {
+        return format_in_base(&n, options);
+    }
+    match value {
+        Value::Int(_) | Value::Big(_) => format_integer(value.to_string(), options),
+        Value::Float(x) => format_float(*x, options),
+        Value::Complex(z) => z.format_with(|part| format_float(part, options)),
+        _ => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod format_tests {
+    use super::*;
+    use crate::complex::Complex;
+    use crate::context::Context;
+    use crate::evaluator::evaluate_value;
+
+    fn do_test(n: f64, precision: Option<usize>, expected: &str) {
+        println!("Number = {}, precision = {:?}", n, precision);
+
+        let options = FormatOptions {
+            precision,
+            ..FormatOptions::default()
+        };
+        assert_eq!(format_value(&Value::Float(n), &options), expected);
+    }
+
+    #[test]
+    fn precision_test() {
+        do_test(0.1 + 0.2, Some(12), "0.3");
+        do_test(0.1 + 0.2, None, "0.30000000000000004");
+        do_test(2., Some(12), "2");
+        do_test(2., None, "2");
+        do_test(1. / 3., Some(12), "0.333333333333");
+        do_test(1. / 3., Some(3), "0.333");
+        do_test(2. / 3., Some(3), "0.667");
+        do_test(2. / 3., Some(1), "0.7");
+        do_test(2. / 3., Some(0), "0.7");
+        do_test(123456.789, Some(4), "123500");
+        do_test(123456.789, Some(12), "123456.789");
+        do_test(-0.000123456, Some(2), "-0.00012");
+        do_test(9.99, Some(2), "10");
+        do_test(-0., Some(12), "-0");
+        do_test(f64::INFINITY, Some(3), "inf");
+        do_test(f64::NAN, Some(3), "nan");
+    }
+
+    #[test]
+    fn exact_values_unrounded_test() {
+        let options = FormatOptions {
+            precision: Some(3),
+            ..FormatOptions::default()
+        };
+        assert_eq!(format_value(&Value::Int(123456), &options), "123456");
+        assert_eq!(format_value(&Value::Bool(true), &options), "true");
+
+        let z = Value::Complex(Complex::new(1. / 3., -2. / 3.));
+        assert_eq!(format_value(&z, &options), "0.333 - 0.667i");
+        let z = Value::Complex(Complex::new(0.1 + 0.2, 1. + 1e-15));
+        assert_eq!(format_value(&z, &FormatOptions::default()), "0.3 + i");
+    }
+
+    fn do_notation_test(
+        value: Value,
+        notation: Notation,
+        precision: Option<usize>,
+        expected: &str,
+    ) {
+        println!("Value = {:?}, notation = {:?}", value, notation);
+
+        let options = FormatOptions {
+            precision,
+            notation,
+            ..FormatOptions::default()
+        };
+        assert_eq!(format_value(&value, &options), expected);
+    }
+
+    #[test]
+    fn scientific_notation_test() {
+        let scientific =
+            |value, expected| do_notation_test(value, Notation::Scientific, Some(12), expected);
+        scientific(Value::Int(1234567), "1.234567e6");
+        scientific(Value::Float(1.234567e6), "1.234567e6");
+        scientific(Value::Float(6.022e23), "6.022e23");
+        scientific(Value::Float(-0.00125), "-1.25e-3");
+        scientific(Value::Int(5), "5e0");
+        scientific(Value::Int(0), "0");
+        scientific(Value::Float(0.), "0");
+        scientific(Value::Int(-1000), "-1e3");
+        scientific(Value::Float(f64::INFINITY), "inf");
+        scientific(Value::Float(0.1 + 0.2), "3e-1");
+        do_notation_test(
+            Value::Float(0.1 + 0.2),
+            Notation::Scientific,
+            None,
+            "3.0000000000000004e-1",
+        );
+        do_notation_test(Value::Int(1234567), Notation::Scientific, Some(3), "1.23e6");
+        do_notation_test(Value::Int(9996), Notation::Scientific, Some(3), "1e4");
+        do_notation_test(Value::Int(1255), Notation::Scientific, Some(3), "1.26e3");
+        do_notation_test(
+            Value::Big(crate::bigint::BigInt::from(2).pow(100)),
+            Notation::Scientific,
+            None,
+            "1.267650600228229401496703205376e30",
+        );
+    }
+
+    #[test]
+    fn engineering_notation_test() {
+        let engineering =
+            |value, expected| do_notation_test(value, Notation::Engineering, Some(12), expected);
+        engineering(Value::Float(1.234567e6), "1.234567e6");
+        engineering(Value::Float(66200.), "66.2e3");
+        engineering(Value::Int(123456), "123.456e3");
+        engineering(Value::Float(0.000012), "12e-6");
+        engineering(Value::Float(-0.5), "-500e-3");
+        engineering(Value::Int(1000), "1e3");
+        engineering(Value::Int(100), "100e0");
+        engineering(Value::Float(6.022e23), "602.2e21");
+        do_notation_test(Value::Int(999999), Notation::Engineering, Some(3), "1e6");
+    }
+
+    #[test]
+    fn notation_reads_back_test() {
+        // Results in either notation can be entered again as they are.
+        let values = [
+            Value::Int(1234567),
+            Value::Float(6.022e23),
+            Value::Float(-0.00125),
+            Value::Float(0.000012),
+            Value::Int(-1000),
+            Value::Big(crate::bigint::BigInt::from(2).pow(100)),
+        ];
+        for notation in [Notation::Scientific, Notation::Engineering].iter() {
+            let options = FormatOptions {
+                precision: None,
+                notation: *notation,
+                ..FormatOptions::default()
+            };
+            for value in values.iter() {
+                let printed = format_value(value, &options);
+                let read = evaluate_value(&printed, &Context::new()).unwrap();
+                assert_eq!(read.as_f64(), value.as_f64(), "{}", printed);
+            }
+        }
+    }
+
+    #[test]
+    fn automatic_scientific_test() {
+        let normal =
+            |value, expected| do_notation_test(value, Notation::Normal, Some(12), expected);
+        normal(Value::Float(1e300), "1e300");
+        normal(Value::Float(1.2e-13), "1.2e-13");
+        normal(Value::Float(-2.5e20), "-2.5e20");
+        normal(Value::Float(1e14), "100000000000000");
+        normal(Value::Float(0.000001), "0.000001");
+        normal(Value::Float(0.), "0");
+        normal(Value::Int(i64::MAX), "9223372036854775807");
+
+        let options = FormatOptions {
+            scientific_below: 1e-3,
+            scientific_from: 1e4,
+            ..FormatOptions::default()
+        };
+        assert_eq!(format_value(&Value::Float(12345.), &options), "1.2345e4");
+        assert_eq!(format_value(&Value::Float(1234.5), &options), "1234.5");
+        assert_eq!(format_value(&Value::Float(0.0005), &options), "5e-4");
+    }
+
+    #[test]
+    fn automatic_scientific_reads_back_test() {
+        // What the formatter prints evaluates to the same value again.
+        let options = FormatOptions {
+            precision: None,
+            ..FormatOptions::default()
+        };
+        for &n in [1.2e-7, 6.022e23, -2.5e20, 1e300, 1.2e-13, 5e-324].iter() {
+            let printed = format_value(&Value::Float(n), &options);
+            assert_eq!(
+                evaluate_value(&printed, &Context::new()),
+                Ok(Value::Float(n)),
+                "{}",
+                printed
+            );
+        }
+    }
+
+    fn do_base_test(value: Value, base: Base, width: Option<u32>, expected: &str) {
+        println!(
+            "Value = {:?}, base = {:?}, width = {:?}",
+            value, base, width
+        );
+
+        let options = FormatOptions {
+            base,
+            width,
+            ..FormatOptions::default()
+        };
+        assert_eq!(format_value(&value, &options), expected);
+    }
+
+    #[test]
+    fn base_test() {
+        do_base_test(Value::Int(255), Base::Hexadecimal, None, "0xFF");
+        do_base_test(Value::Int(10), Base::Binary, None, "0b1010");
+        do_base_test(Value::Int(15), Base::Octal, None, "0o17");
+        do_base_test(Value::Int(0), Base::Hexadecimal, None, "0x0");
+        do_base_test(Value::Int(0), Base::Binary, None, "0b0");
+        do_base_test(Value::Int(-255), Base::Hexadecimal, None, "-0xFF");
+        do_base_test(Value::Int(-10), Base::Binary, None, "-0b1010");
+        do_base_test(Value::Float(4096.), Base::Hexadecimal, None, "0x1000");
+        do_base_test(Value::Float(-8.), Base::Octal, None, "-0o10");
+        do_base_test(
+            Value::Big(BigInt::from(2).pow(64)),
+            Base::Hexadecimal,
+            None,
+            "0x10000000000000000",
+        );
+        do_base_test(Value::Int(255), Base::Decimal, None, "255");
+    }
+
+    #[test]
+    fn base_width_test() {
+        do_base_test(Value::Int(-1), Base::Hexadecimal, Some(8), "0xFF");
+        do_base_test(Value::Int(-1), Base::Hexadecimal, Some(16), "0xFFFF");
+        do_base_test(Value::Int(-128), Base::Binary, Some(8), "0b10000000");
+        do_base_test(Value::Int(-2), Base::Octal, Some(6), "0o76");
+        do_base_test(Value::Int(255), Base::Hexadecimal, Some(16), "0x00FF");
+        do_base_test(Value::Int(5), Base::Binary, Some(8), "0b00000101");
+        // Beyond the width it's shown with a minus again.
+        do_base_test(Value::Int(-129), Base::Hexadecimal, Some(8), "-0x81");
+    }
+
+    #[test]
+    fn base_fallback_test() {
+        do_base_test(Value::Float(2.5), Base::Hexadecimal, None, "2.5");
+        do_base_test(Value::Float(-0.5), Base::Binary, None, "-0.5");
+        do_base_test(Value::Float(f64::INFINITY), Base::Hexadecimal, None, "inf");
+        do_base_test(Value::Bool(true), Base::Hexadecimal, None, "true");
+        do_base_test(
+            Value::Complex(Complex::new(0., 2.)),
+            Base::Hexadecimal,
+            None,
+            "2i",
+        );
+    }
+
+    fn do_grouping_test(value: Value, grouping: char, expected: &str) {
+        println!("Value = {:?}, grouping = {:?}", value, grouping);
+
+        let options = FormatOptions {
+            grouping: Some(grouping),
+            ..FormatOptions::default()
+        };
+        assert_eq!(format_value(&value, &options), expected);
+    }
+
+    #[test]
+    fn grouping_test() {
+        do_grouping_test(Value::Int(1234567), ',', "1,234,567");
+        do_grouping_test(Value::Float(1234567.89), ',', "1,234,567.89");
+        do_grouping_test(Value::Float(-1234567.891234), ',', "-1,234,567.891234");
+        do_grouping_test(Value::Int(-123456), '_', "-123_456");
+        do_grouping_test(Value::Int(999), ',', "999");
+        do_grouping_test(Value::Int(1000), '\u{2009}', "1\u{2009}000");
+        do_grouping_test(Value::Float(0.000123456), ',', "0.000123456");
+        do_grouping_test(
+            Value::Big(BigInt::from(2).pow(70)),
+            ',',
+            "1,180,591,620,717,411,303,424",
+        );
+        do_grouping_test(
+            Value::Complex(Complex::new(12345., -6789.5)),
+            ',',
+            "12,345 - 6,789.5i",
+        );
+        do_grouping_test(Value::Float(f64::NAN), ',', "nan");
+        do_grouping_test(Value::Float(f64::NEG_INFINITY), ',', "-inf");
+    }
+
+    #[test]
+    fn grouping_scientific_test() {
+        // From the threshold on the number is scientific and isn't grouped.
+        do_grouping_test(Value::Float(123456789012345.), ',', "123,456,789,012,345");
+        do_grouping_test(Value::Float(999999999999999.9), ',', "1e15");
+        do_grouping_test(Value::Float(1e15), ',', "1e15");
+        do_grouping_test(Value::Float(-1.5e-7), ',', "-1.5e-7");
+        let options = FormatOptions {
+            grouping: Some(','),
+            notation: Notation::Scientific,
+            ..FormatOptions::default()
+        };
+        assert_eq!(format_value(&Value::Int(1234567), &options), "1.234567e6");
+        let options = FormatOptions {
+            grouping: Some(','),
+            base: Base::Hexadecimal,
+            ..FormatOptions::default()
+        };
+        assert_eq!(format_value(&Value::Int(0xFFFFFF), &options), "0xFFFFFF");
+    }
+
+    #[test]
+    fn float_artifacts_test() {
+        let ugly = [
+            ("0.1 + 0.2", "0.3", 0.30000000000000004),
+            ("1.005 * 1000", "1005", 1004.9999999999999),
+            ("1.1 * 1.1", "1.21", 1.2100000000000002),
+            ("0.1 * 3", "0.3", 0.30000000000000004),
+            ("1 - 0.9", "0.1", 0.09999999999999998),
+            ("4.35 * 100", "435", 434.99999999999994),
+            ("0.7 + 0.1", "0.8", 0.7999999999999999),
+            ("sqrt(2)^2", "2", 2.0000000000000004),
+            ("1 / 3", "0.333333333333333", 1. / 3.),
+        ];
+        let ctx = Context::new();
+        for (expr, displayed, value) in ugly.iter() {
+            println!("Expression = {}", expr);
+
+            let result = evaluate_value(&expr.to_string(), &ctx).unwrap();
+            assert_eq!(format_value(&result, ctx.format_options()), *displayed);
+            // Only the display is rounded.
+            assert_eq!(result, Value::Float(*value));
+            let full = FormatOptions {
+                precision: None,
+                ..FormatOptions::default()
+            };
+            assert_eq!(format_value(&result, &full), value.to_string());
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops;
+
+use super::bigint::BigInt;
+
+/// An exact fraction in lowest terms with a positive denominator.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Fraction {
+    numerator: BigInt,
+    denominator: BigInt,
+}
+
+impl Fraction {
+    /// Reduces `numerator / denominator`, or `None` if the denominator is
+    /// zero.
+    pub fn new(numerator: BigInt, denominator: BigInt) -> Option<Self> {
+        if denominator.is_zero() {
+            return None;
+        }
+        let mut gcd = numerator.gcd(&denominator);
+        if denominator.is_negative() {
+            gcd = -gcd;
+        }
+        Some(Self {
+            numerator: numerator.div_rem(&gcd).0,
+            denominator: denominator.div_rem(&gcd).0,
+        })
+    }
+
+    /// Reads a decimal number without exponent like `-1.25` as `-5/4`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (whole, decimals) = s.split_once('.').unwrap_or((s, ""));
+        let numerator = BigInt::parse(&format!("{}{}", whole, decimals))?;
+        let denominator = BigInt::from(10).pow(decimals.len() as u32);
+        Fraction::new(numerator, denominator)
+    }
+
+    pub fn numerator(&self) -> &BigInt {
+        &self.numerator
+    }
+
+    pub fn denominator(&self) -> &BigInt {
+        &self.denominator
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.denominator == BigInt::from(1)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.numerator.is_zero()
+    }
+
+    pub fn abs(&self) -> Self {
+        Self {
+            numerator: self.numerator.abs(),
+            denominator: self.denominator.clone(),
+        }
+    }
+
+    /// The quotient rounded toward zero.
+    pub fn trunc(&self) -> BigInt {
+        self.numerator.div_rem(&self.denominator).0
+    }
+
+    pub fn floor(&self) -> BigInt {
+        let (quotient, remainder) = self.numerator.div_rem(&self.denominator);
+        if remainder.is_negative() {
+            &quotient - &BigInt::from(1)
+        } else {
+            quotient
+        }
+    }
+
+    /// `self / other`, or `None` if `other` is zero.
+    pub fn checked_div(&self, other: &Fraction) -> Option<Self> {
+        Fraction::new(
+            &self.numerator * &other.denominator,
+            &self.denominator * &other.numerator,
+        )
+    }
+
+    /// `self` to an integer power, or `None` for a negative power of zero.
+    pub fn pow(&self, exponent: i64) -> Option<Self> {
+        let n = exponent.unsigned_abs() as u32;
+        let (numerator, denominator) = (self.numerator.pow(n), self.denominator.pow(n));
+        if exponent < 0 {
+            Fraction::new(denominator, numerator)
+        } else {
+            Fraction::new(numerator, denominator)
+        }
+    }
+
+    /// The fraction closest to `x` with a denominator up to `max_denominator`,
+    /// found with continued fractions, `355/113` for pi and 1000. `None` if
+    /// `x` isn't finite.
+    pub fn approximate(x: f64, max_denominator: i64) -> Option<Self> {
+        if !x.is_finite() {
+            return None;
+        } else if x.fract() == 0. {
+            return BigInt::from_f64(x).map(Fraction::from);
+        }
+
+        // The last two convergents p0/q0 and p1/q1.
+        let (mut p0, mut q0, mut p1, mut q1) = (0i64, 1i64, 1i64, 0i64);
+        let mut y = x.abs();
+        loop {
+            let a = y.floor();
+            if a >= (1u64 << 53) as f64 {
+                break;
+            }
+            let a = a as i64;
+            let next = a
+                .checked_mul(q1)
+                .and_then(|q| q.checked_add(q0))
+                .zip(a.checked_mul(p1).and_then(|p| p.checked_add(p0)));
+            match next {
+                Some((q2, p2)) if q2 <= max_denominator => {
+                    (p0, q0, p1, q1) = (p1, q1, p2, q2);
+                }
+                _ => break,
+            }
+            if y == a as f64 {
+                break;
+            }
+            y = 1. / (y - a as f64);
+        }
+
+        // Between the last convergent and the next one, the semiconvergent
+        // with the largest denominator in bounds may be closer.
+        let k = (max_denominator - q0) / q1;
+        let (p, q) = match (p0 + k * p1, q0 + k * q1) {
+            (p, q)
+                if (p as f64 / q as f64 - x.abs()).abs()
+                    < (p1 as f64 / q1 as f64 - x.abs()).abs() =>
+            {
+                (p, q)
+            }
+            _ => (p1, q1),
+        };
+        let p = if x < 0. { -p } else { p };
+        Fraction::new(BigInt::from(p), BigInt::from(q))
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        // Scale both down first, so that large ones don't turn into inf / inf.
+        let shift = self
+            .numerator
+            .bits()
+            .max(self.denominator.bits())
+            .saturating_sub(1000);
+        self.numerator.shr(shift).to_f64() / self.denominator.shr(shift).to_f64()
+    }
+}
+
+impl From<BigInt> for Fraction {
+    fn from(n: BigInt) -> Self {
+        Self {
+            numerator: n,
+            denominator: BigInt::from(1),
+        }
+    }
+}
+
+impl Ord for Fraction {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (&self.numerator * &other.denominator).cmp(&(&other.numerator * &self.denominator))
+    }
+}
+
+impl PartialOrd for Fraction {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl ops::Neg for Fraction {
+    type Output = Fraction;
+
+    fn neg(self) -> Fraction {
+        Fraction {
+            numerator: -self.numerator,
+            denominator: self.denominator,
+        }
+    }
+}
+
+impl<'a> ops::Add for &'a Fraction {
+    type Output = Fraction;
+
+    fn add(self, other: &'a Fraction) -> Fraction {
+        let numerator =
+            &(&self.numerator * &other.denominator) + &(&other.numerator * &self.denominator);
+        Fraction::new(numerator, &self.denominator * &other.denominator).unwrap()
+    }
+}
+
+impl<'a> ops::Sub for &'a Fraction {
+    type Output = Fraction;
+
+    fn sub(self, other: &'a Fraction) -> Fraction {
+        self + &-other.clone()
+    }
+}
+
+impl<'a> ops::Mul for &'a Fraction {
+    type Output = Fraction;
+
+    fn mul(self, other: &'a Fraction) -> Fraction {
+        Fraction::new(
+            &self.numerator * &other.numerator,
+            &self.denominator * &other.denominator,
+        )
+        .unwrap()
+    }
+}
+
+impl fmt::Display for Fraction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_integer() {
+            write!(f, "{}", self.numerator)
+        } else {
+            write!(f, "{}/{}", self.numerator, self.denominator)
+        }
+    }
+}
+
+#[cfg(test)]
+mod fraction_tests {
+    use super::*;
+
+    fn fraction(numerator: i64, denominator: i64) -> Fraction {
+        Fraction::new(BigInt::from(numerator), BigInt::from(denominator)).unwrap()
+    }
+
+    #[test]
+    fn fraction_normalization_test() {
+        assert_eq!(fraction(2, 4).to_string(), "1/2");
+        assert_eq!(fraction(1, -3).to_string(), "-1/3");
+        assert_eq!(fraction(-2, -4).to_string(), "1/2");
+        assert_eq!(fraction(6, 3).to_string(), "2");
+        assert_eq!(fraction(0, -5).to_string(), "0");
+        assert_eq!(Fraction::new(BigInt::from(1), BigInt::from(0)), None);
+        assert_eq!(Fraction::parse("-1.25"), Some(fraction(-5, 4)));
+        assert_eq!(Fraction::parse("0.1"), Some(fraction(1, 10)));
+        assert_eq!(Fraction::parse("42"), Some(fraction(42, 1)));
+    }
+
+    #[test]
+    fn fraction_arithmetic_test() {
+        assert_eq!(&fraction(1, 3) + &fraction(1, 6), fraction(1, 2));
+        assert_eq!(&fraction(1, 3) - &fraction(1, 2), fraction(-1, 6));
+        assert_eq!(&fraction(1, 3) * &fraction(3, 1), fraction(1, 1));
+        assert_eq!(
+            fraction(1, 3).checked_div(&fraction(-2, 3)),
+            Some(fraction(-1, 2))
+        );
+        assert_eq!(fraction(1, 3).checked_div(&fraction(0, 1)), None);
+        assert_eq!(fraction(2, 3).pow(-2), Some(fraction(9, 4)));
+        assert_eq!(fraction(0, 1).pow(-1), None);
+        assert_eq!(fraction(-7, 2).floor(), BigInt::from(-4));
+        assert_eq!(fraction(-7, 2).trunc(), BigInt::from(-3));
+        assert!(fraction(1, 3) < fraction(34, 100));
+        assert!(fraction(-1, 2) < fraction(-1, 3));
+        assert_eq!(fraction(1, 4).to_f64(), 0.25);
+
+        let huge = Fraction::new(
+            BigInt::from(2).pow(2000),
+            &BigInt::from(2).pow(2000) * &BigInt::from(3),
+        )
+        .unwrap();
+        assert_eq!(huge, fraction(1, 3));
+        let huge = Fraction::new(
+            &BigInt::from(2).pow(2000) + &BigInt::from(1),
+            BigInt::from(2).pow(2001),
+        )
+        .unwrap();
+        assert_eq!(huge.to_f64(), 0.5);
+    }
+}
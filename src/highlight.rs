@@ -0,0 +1,208 @@
+use super::ast::is_reserved;
+use super::error::Span;
+use super::scanner::{Scanner, TokenType};
+
+/// What a span of the input is, for an editor to color it.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TokenClass {
+    Number,
+    /// Operators, including the words `and`, `or`, `not`, `mod`, `div` and
+    /// `xor`, `=`, `->` and the separators `,` and `;`.
+    Operator,
+    /// A name followed by `(`, which is called.
+    Function,
+    /// Any other name, including constants and `$1`.
+    Variable,
+    /// `(`, `)`, `[` and `]`.
+    Paren,
+    /// A string in double quotes, with them.
+    Text,
+    Bar,
+    /// From `#` up to the end of the line.
+    Comment,
+    Whitespace,
+    /// A char the scanner doesn't know, a malformed number, a misplaced
+    /// separator or a string without its closing quote.
+    Error,
+}
+
+/// The spans of `expr` and what they are. They cover all of it, whitespace
+/// included, and follow each other without a gap. Nothing is an error but
+/// the spans of class `Error`, so input that is still being typed is
+/// classified too.
+pub fn classify(expr: &str) -> Vec<(Span, TokenClass)> {
+    classify_with(expr, false)
+}
+
+/// Like `classify`, with `,` as the decimal separator if `decimal_comma`.
+pub fn classify_with(expr: &str, decimal_comma: bool) -> Vec<(Span, TokenClass)> {
+    let source = expr.to_string();
+    let mut scanner = Scanner::new(&source);
+    scanner.set_decimal_comma(decimal_comma);
+    scanner.scan();
+
+    let chars: Vec<char> = expr.chars().collect();
+    let tokens = scanner.get_tokens();
+    let mut spans = Vec::with_capacity(2 * tokens.len());
+    let mut pos = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        if token.t == TokenType::End {
+            break;
+        }
+        classify_gap(&chars[pos..token.pos], pos, &mut spans);
+        let next = tokens.get(i + 1).map(|token| token.t);
+        spans.push((Span::between(token.pos, token.end), class(token.t, next)));
+        pos = token.end;
+    }
+    classify_gap(&chars[pos..], pos, &mut spans);
+    spans
+}
+
+fn class(token: TokenType, next: Option<TokenType>) -> TokenClass {
+    match token {
+        TokenType::Number(_) => TokenClass::Number,
+        TokenType::History(_) => TokenClass::Variable,
+        TokenType::Str(_) if is_reserved(token) => TokenClass::Operator,
+        TokenType::Str(_) if next == Some(TokenType::Lparen) => TokenClass::Function,
+        TokenType::Str(_) => TokenClass::Variable,
+        TokenType::Lparen | TokenType::Rparen => TokenClass::Paren,
+        TokenType::Text(_) => TokenClass::Text,
+        TokenType::Bar => TokenClass::Bar,
+        TokenType::Invalid(..) | TokenType::Misplaced(..) => TokenClass::Error,
+        TokenType::End | TokenType::None => TokenClass::Error,
+        _ => TokenClass::Operator,
+    }
+}
+
+/// Classifies the chars the scanner skips between two tokens, which start
+/// at `pos`: whitespace, comments and chars it doesn't know.
+fn classify_gap(chars: &[char], pos: usize, spans: &mut Vec<(Span, TokenClass)>) {
+    let mut start = 0;
+    while start < chars.len() {
+        let rest = &chars[start..];
+        let (class, len) = match rest[0] {
+            '#' => (
+                TokenClass::Comment,
+                rest.iter().position(|&c| c == '\n').unwrap_or(rest.len()),
+            ),
+            c if c.is_whitespace() => (
+                TokenClass::Whitespace,
+                rest.iter()
+                    .position(|c| !c.is_whitespace())
+                    .unwrap_or(rest.len()),
+            ),
+            _ => (
+                TokenClass::Error,
+                rest.iter()
+                    .position(|&c| c.is_whitespace() || c == '#')
+                    .unwrap_or(rest.len()),
+            ),
+        };
+        spans.push((Span::between(pos + start, pos + start + len), class));
+        start += len;
+    }
+}
+
+#[cfg(test)]
+mod highlight_tests {
+    use super::*;
+    use TokenClass::*;
+
+    fn do_test(expr: &str, expected: &[(usize, usize, TokenClass)]) {
+        println!("Expression = {:?}", expr);
+
+        let expected: Vec<(Span, TokenClass)> = expected
+            .iter()
+            .map(|&(start, len, class)| (Span { start, len }, class))
+            .collect();
+        assert_eq!(classify(expr), expected);
+    }
+
+    #[test]
+    fn classify_test() {
+        do_test(
+            "max(x, 2) # the larger",
+            &[
+                (0, 3, Function),
+                (3, 1, Paren),
+                (4, 1, Variable),
+                (5, 1, Operator),
+                (6, 1, Whitespace),
+                (7, 1, Number),
+                (8, 1, Paren),
+                (9, 1, Whitespace),
+                (10, 12, Comment),
+            ],
+        );
+        do_test(
+            "|pi| × $1 mod 3",
+            &[
+                (0, 1, Bar),
+                (1, 2, Variable),
+                (3, 1, Bar),
+                (4, 1, Whitespace),
+                (5, 1, Operator),
+                (6, 1, Whitespace),
+                (7, 2, Variable),
+                (9, 1, Whitespace),
+                (10, 3, Operator),
+                (13, 1, Whitespace),
+                (14, 1, Number),
+            ],
+        );
+        // Input that is still being typed: an unknown char and a trailing
+        // malformed number.
+        do_test(
+            "sin (x°) +\n0x",
+            &[
+                (0, 3, Function),
+                (3, 1, Whitespace),
+                (4, 1, Paren),
+                (5, 1, Variable),
+                (6, 1, Error),
+                (7, 1, Paren),
+                (8, 1, Whitespace),
+                (9, 1, Operator),
+                (10, 1, Whitespace),
+                (11, 2, Error),
+            ],
+        );
+        do_test(
+            "env(\"N\") \"x",
+            &[
+                (0, 3, Function),
+                (3, 1, Paren),
+                (4, 3, Text),
+                (7, 1, Paren),
+                (8, 1, Whitespace),
+                (9, 2, Error),
+            ],
+        );
+        do_test("", &[]);
+        do_test("  ", &[(0, 2, Whitespace)]);
+    }
+
+    #[test]
+    fn coverage_test() {
+        for expr in [
+            "1,5 + $ # x\n y",
+            "°°x²√2 ->",
+            "\"a\" 1__0 [2]",
+            "not x or y; f(1) = 3",
+        ] {
+            let spans = classify(expr);
+            let mut end = 0;
+            for (span, _) in &spans {
+                assert_eq!(span.start, end, "{:?} has a gap in {:?}", expr, spans);
+                assert!(span.len > 0);
+                end = span.end();
+            }
+            assert_eq!(end, expr.chars().count());
+        }
+        assert_eq!(
+            classify_with("1,5", true),
+            [(Span { start: 0, len: 3 }, Number)]
+        );
+        assert_eq!(classify("1,5")[1], (Span { start: 1, len: 1 }, Operator));
+    }
+}
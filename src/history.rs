@@ -0,0 +1,163 @@
+use std::collections::VecDeque;
+
+use super::value::Value;
+
+/// How many results are kept by default.
+pub const HISTORY_SIZE: usize = 100;
+
+/// The results of the expressions evaluated so far, numbered from 1 in the
+/// order they were evaluated, which `$3` or `out(3)` refer to.
+#[derive(Debug, Clone)]
+pub struct History {
+    /// The expressions and their results, oldest first.
+    entries: VecDeque<(String, Value)>,
+    /// The number of results that were dropped, so the first entry is
+    /// `dropped + 1`.
+    dropped: usize,
+    size: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History {
+            entries: VecDeque::new(),
+            dropped: 0,
+            size: HISTORY_SIZE,
+        }
+    }
+}
+
+impl History {
+    /// Adds the result of `expr`, dropping the oldest one if the history is
+    /// full.
+    pub fn push(&mut self, expr: &str, value: Value) {
+        self.entries.push_back((expr.to_string(), value));
+        self.shrink();
+    }
+
+    /// Keeps at most `size` results, which is at least 1, dropping the
+    /// oldest ones beyond it. Their numbers stay the same.
+    pub fn set_size(&mut self, size: usize) {
+        self.size = size.max(1);
+        self.shrink();
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    fn shrink(&mut self) {
+        while self.entries.len() > self.size {
+            self.entries.pop_front();
+            self.dropped += 1;
+        }
+    }
+
+    /// The result number `n`, the error of `$n` at `pos` if there is none.
+    pub fn get(&self, n: usize, pos: usize) -> Result<&Value, String> {
+        if n == 0 {
+            return Err(format!(
+                "Result $0 at pos {} doesn't exist, the first one is $1!",
+                pos
+            ));
+        }
+        if n <= self.dropped {
+            return Err(format!(
+                "Result ${} at pos {} was dropped, the history keeps the last {}!",
+                n, pos, self.size
+            ));
+        }
+        match self.entries.get(n - self.dropped - 1) {
+            Some((_, value)) => Ok(value),
+            None => Err(format!("Result ${} at pos {} doesn't exist yet!", n, pos)),
+        }
+    }
+
+    /// The result `out(index)` refers to, where `index` has to be a positive
+    /// integer.
+    pub fn get_index(&self, index: f64, pos: usize) -> Result<&Value, String> {
+        if index.fract() != 0. || index < 1. {
+            return Err(format!(
+                "Result index {} at pos {} isn't a positive integer!",
+                index, pos
+            ));
+        }
+        self.get(index.min(usize::MAX as f64) as usize, pos)
+    }
+
+    /// The kept results with their numbers and expressions, oldest first.
+    pub fn entries(&self) -> impl Iterator<Item = (usize, &str, &Value)> {
+        let first = self.dropped + 1;
+        self.entries
+            .iter()
+            .enumerate()
+            .map(move |(i, (expr, value))| (first + i, expr.as_str(), value))
+    }
+}
+
+#[cfg(test)]
+mod history_tests {
+    use super::*;
+
+    #[test]
+    fn get_test() {
+        let mut history = History::default();
+        assert_eq!(
+            history.get(1, 0),
+            Err(String::from("Result $1 at pos 0 doesn't exist yet!"))
+        );
+
+        history.push("1 + 2", Value::Int(3));
+        history.push("1 / 4", Value::Float(0.25));
+        assert_eq!(history.get(1, 0), Ok(&Value::Int(3)));
+        assert_eq!(history.get(2, 0), Ok(&Value::Float(0.25)));
+        assert_eq!(
+            history.get(3, 4),
+            Err(String::from("Result $3 at pos 4 doesn't exist yet!"))
+        );
+        assert_eq!(
+            history.get(0, 2),
+            Err(String::from(
+                "Result $0 at pos 2 doesn't exist, the first one is $1!"
+            ))
+        );
+
+        assert_eq!(history.get_index(2., 0), Ok(&Value::Float(0.25)));
+        assert_eq!(
+            history.get_index(-1., 4),
+            Err(String::from(
+                "Result index -1 at pos 4 isn't a positive integer!"
+            ))
+        );
+        assert_eq!(
+            history.get_index(1.5, 4),
+            Err(String::from(
+                "Result index 1.5 at pos 4 isn't a positive integer!"
+            ))
+        );
+    }
+
+    #[test]
+    fn size_test() {
+        let mut history = History::default();
+        history.set_size(2);
+        for i in 1..=3 {
+            history.push(&i.to_string(), Value::Int(i));
+        }
+        assert_eq!(
+            history.get(1, 0),
+            Err(String::from(
+                "Result $1 at pos 0 was dropped, the history keeps the last 2!"
+            ))
+        );
+        assert_eq!(history.get(3, 0), Ok(&Value::Int(3)));
+        let numbers: Vec<usize> = history.entries().map(|(n, _, _)| n).collect();
+        assert_eq!(numbers, [2, 3]);
+
+        history.set_size(1);
+        assert!(history.get(2, 0).is_err());
+        assert_eq!(history.get(3, 0), Ok(&Value::Int(3)));
+        history.set_size(0);
+        assert_eq!(history.size(), 1);
+    }
+}
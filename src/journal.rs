@@ -0,0 +1,97 @@
+use std::collections::VecDeque;
+
+/// How many changes of variables can be undone.
+pub const UNDO_LIMIT: usize = 20;
+
+/// A change of the variable `name`, `None` where it isn't defined.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mutation {
+    pub name: String,
+    pub before: Option<f64>,
+    pub after: Option<f64>,
+}
+
+/// The changes of variables that `:undo` reverts, newest last, and those
+/// that `:redo` applies again.
+#[derive(Debug, Clone, Default)]
+pub struct Journal {
+    undo: VecDeque<Mutation>,
+    redo: Vec<Mutation>,
+}
+
+impl Journal {
+    /// Records a new change, dropping the oldest one beyond `UNDO_LIMIT`. The
+    /// changes that were undone can't be redone after it.
+    pub fn record(&mut self, mutation: Mutation) {
+        self.undo.push_back(mutation);
+        if self.undo.len() > UNDO_LIMIT {
+            self.undo.pop_front();
+        }
+        self.redo.clear();
+    }
+
+    /// The newest change to revert, which can be redone then.
+    pub fn undo(&mut self) -> Option<Mutation> {
+        let mutation = self.undo.pop_back()?;
+        self.redo.push(mutation.clone());
+        Some(mutation)
+    }
+
+    /// The newest undone change to apply again, which can be undone again.
+    pub fn redo(&mut self) -> Option<Mutation> {
+        let mutation = self.redo.pop()?;
+        self.undo.push_back(mutation.clone());
+        Some(mutation)
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+#[cfg(test)]
+mod journal_tests {
+    use super::*;
+
+    fn mutation(name: &str, before: Option<f64>, after: Option<f64>) -> Mutation {
+        Mutation {
+            name: name.to_string(),
+            before,
+            after,
+        }
+    }
+
+    #[test]
+    fn undo_redo_test() {
+        let mut journal = Journal::default();
+        assert_eq!(journal.undo(), None);
+        journal.record(mutation("x", None, Some(1.)));
+        journal.record(mutation("x", Some(1.), Some(2.)));
+
+        assert_eq!(journal.undo(), Some(mutation("x", Some(1.), Some(2.))));
+        assert_eq!(journal.undo(), Some(mutation("x", None, Some(1.))));
+        assert_eq!(journal.undo(), None);
+        assert_eq!(journal.redo(), Some(mutation("x", None, Some(1.))));
+
+        // A new change drops the rest of what was undone.
+        journal.record(mutation("y", None, Some(3.)));
+        assert_eq!(journal.redo(), None);
+        assert_eq!(journal.undo(), Some(mutation("y", None, Some(3.))));
+        assert_eq!(journal.undo(), Some(mutation("x", None, Some(1.))));
+    }
+
+    #[test]
+    fn limit_test() {
+        let mut journal = Journal::default();
+        for i in 0..UNDO_LIMIT + 5 {
+            journal.record(mutation("x", Some(i as f64), Some(i as f64 + 1.)));
+        }
+        let mut undone = 0;
+        while let Some(mutation) = journal.undo() {
+            undone += 1;
+            assert_eq!(mutation.before, Some((UNDO_LIMIT + 5 - undone) as f64));
+        }
+        assert_eq!(undone, UNDO_LIMIT);
+    }
+}
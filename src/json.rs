@@ -0,0 +1,219 @@
+use std::fmt;
+
+use super::ast::{Node, NodePtr, TokenType};
+use super::printer::binary_symbol;
+use super::value::Value;
+use super::visitor::{walk, walk_node, Visitor};
+
+/// `s` as a JSON string, quoted and escaped.
+pub fn string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// `x` as a JSON number with all its digits. JSON has no infinity or NaN,
+/// they're `null`.
+pub fn number(x: f64) -> String {
+    if x.is_finite() {
+        format!("{:?}", x)
+    } else {
+        String::from("null")
+    }
+}
+
+/// A value as JSON. Integers and floats are numbers, even the ones beyond
+/// i64, and booleans are booleans. Fractions and complex numbers have no JSON
+/// form, they're strings like `"1/3"` and `"1+2i"`.
+pub fn value(value: &Value) -> String {
+    match value {
+        Value::Bool(b) => b.to_string(),
+        Value::Int(_) | Value::Big(_) => value.to_string(),
+        Value::Float(x) => number(*x),
+        Value::Ratio(_) | Value::Complex(_) => string(&value.to_string()),
+    }
+}
+
+/// A JSON object, whose fields are written in the order they're added.
+#[derive(Debug, Default)]
+pub struct Object {
+    fields: Vec<(&'static str, String)>,
+}
+
+impl Object {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the field `key` with a value that is already JSON.
+    pub fn field(mut self, key: &'static str, json: String) -> Self {
+        self.fields.push((key, json));
+        self
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{{")?;
+        for (i, (key, json)) in self.fields.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}:{}", string(key), json)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+/// Renders a tree as nested objects, each with its `type` and its position
+/// in the input.
+struct TreeJson {
+    pos: usize,
+}
+
+impl TreeJson {
+    /// An object of the node whose position was visited last, which has to
+    /// be taken before walking its children.
+    fn node(&self, kind: &str) -> Object {
+        Object::new()
+            .field("type", string(kind))
+            .field("pos", self.pos.to_string())
+    }
+}
+
+impl Visitor for TreeJson {
+    type Output = String;
+
+    fn visit_number(&mut self, n: f64) -> String {
+        self.node("number").field("value", number(n)).to_string()
+    }
+
+    fn visit_variable(&mut self, name: &str) -> String {
+        self.node("variable")
+            .field("name", string(name))
+            .to_string()
+    }
+
+    fn visit_history(&mut self, n: usize) -> String {
+        self.node("history")
+            .field("index", n.to_string())
+            .to_string()
+    }
+
+    fn visit_text(&mut self, text: &str) -> String {
+        self.node("text").field("value", string(text)).to_string()
+    }
+
+    fn visit_call(&mut self, name: &str, args: &[Node]) -> String {
+        let object = self.node("call").field("name", string(name));
+        let args: Vec<String> = args.iter().map(|arg| walk_node(self, arg)).collect();
+        object
+            .field("args", format!("[{}]", args.join(",")))
+            .to_string()
+    }
+
+    fn visit_lambda(&mut self, param: &str, body: &NodePtr) -> String {
+        let object = self.node("lambda").field("param", string(param));
+        object.field("body", walk(self, body)).to_string()
+    }
+
+    fn visit_unary(&mut self, op: &TokenType, operand: &NodePtr) -> String {
+        let symbol = match op {
+            TokenType::PrefixMinus => "-",
+            TokenType::PrefixPlus => "+",
+            TokenType::Factorial => "!",
+            TokenType::DoubleFactorial => "!!",
+            TokenType::Percent => "%",
+            TokenType::Bar => "||",
+            TokenType::Not => "not",
+            _ => panic!("Unknown unary token! {:?}", op),
+        };
+        let object = self.node("unary").field("op", string(symbol));
+        object.field("operand", walk(self, operand)).to_string()
+    }
+
+    fn visit_binary(&mut self, op: &TokenType, left: &NodePtr, right: &NodePtr) -> String {
+        let object = self.node("binary").field("op", string(binary_symbol(op)));
+        let left = walk(self, left);
+        let right = walk(self, right);
+        object.field("left", left).field("right", right).to_string()
+    }
+
+    fn visit_none(&mut self) -> String {
+        String::from("null")
+    }
+
+    fn visit_pos(&mut self, pos: usize) {
+        self.pos = pos;
+    }
+}
+
+/// The tree of `root` as JSON, `null` if it's empty.
+pub fn tree(root: &NodePtr) -> String {
+    walk(&mut TreeJson { pos: 0 }, root)
+}
+
+#[cfg(test)]
+mod json_tests {
+    use super::*;
+    use crate::ast::parse;
+    use crate::fraction::Fraction;
+
+    #[test]
+    fn string_test() {
+        assert_eq!(string("1 + 2"), r#""1 + 2""#);
+        assert_eq!(string("a \"b\" \\ c\n"), r#""a \"b\" \\ c\n""#);
+        assert_eq!(string("\u{1}é"), r#""\u0001é""#);
+    }
+
+    #[test]
+    fn value_test() {
+        assert_eq!(value(&Value::Int(-3)), "-3");
+        assert_eq!(value(&Value::Float(1. / 3.)), "0.3333333333333333");
+        assert_eq!(value(&Value::Float(1e300)), "1e300");
+        assert_eq!(value(&Value::Float(f64::INFINITY)), "null");
+        assert_eq!(value(&Value::Float(f64::NAN)), "null");
+        assert_eq!(value(&Value::Bool(true)), "true");
+        assert_eq!(
+            value(&Value::Ratio(Fraction::new(1.into(), 3.into()).unwrap())),
+            r#""1/3""#
+        );
+    }
+
+    #[test]
+    fn tree_test() {
+        let root = parse("-x + max(2, 3!)").unwrap();
+        assert_eq!(
+            tree(&root),
+            "{\"type\":\"binary\",\"pos\":3,\"op\":\"+\",\
+             \"left\":{\"type\":\"unary\",\"pos\":0,\"op\":\"-\",\
+             \"operand\":{\"type\":\"variable\",\"pos\":1,\"name\":\"x\"}},\
+             \"right\":{\"type\":\"call\",\"pos\":5,\"name\":\"max\",\"args\":[\
+             {\"type\":\"number\",\"pos\":9,\"value\":2.0},\
+             {\"type\":\"unary\",\"pos\":13,\"op\":\"!\",\
+             \"operand\":{\"type\":\"number\",\"pos\":12,\"value\":3.0}}]}}"
+        );
+        assert_eq!(tree(&None), "null");
+    }
+
+    #[test]
+    fn object_test() {
+        assert_eq!(Object::new().to_string(), "{}");
+        let object = Object::new()
+            .field("expr", string("1/3"))
+            .field("ok", String::from("true"));
+        assert_eq!(object.to_string(), r#"{"expr":"1/3","ok":true}"#);
+    }
+}
@@ -0,0 +1,86 @@
+// The `log` macros with the `log` feature. Without it a step logs nothing,
+// though its arguments are still checked.
+#[cfg(feature = "log")]
+macro_rules! debug {
+    ($($arg:tt)*) => { log::debug!($($arg)*) };
+}
+#[cfg(feature = "log")]
+macro_rules! trace {
+    ($($arg:tt)*) => { log::trace!($($arg)*) };
+}
+#[cfg(not(feature = "log"))]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format_args!($($arg)*);
+        }
+    };
+}
+#[cfg(not(feature = "log"))]
+macro_rules! trace {
+    ($($arg:tt)*) => { debug!($($arg)*) };
+}
+
+pub mod analysis;
+pub mod arena;
+pub mod ast;
+pub mod bench;
+pub mod bigint;
+pub mod builtins;
+pub mod cache;
+pub mod canonical;
+#[cfg(feature = "cli")]
+pub mod cli;
+#[cfg(feature = "repl")]
+pub mod command;
+#[cfg(feature = "repl")]
+pub mod completion;
+pub mod complex;
+pub mod complex_mode;
+#[cfg(feature = "repl")]
+pub mod config;
+pub mod context;
+#[cfg(feature = "cli")]
+pub mod csv;
+pub mod derivative;
+pub mod equivalence;
+pub mod error;
+pub mod evaluator;
+pub mod exact;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod folding;
+pub mod format;
+pub mod fraction;
+pub mod highlight;
+pub mod history;
+pub mod journal;
+pub mod json;
+pub mod partial;
+pub mod plot;
+pub mod printer;
+#[cfg(feature = "repl")]
+pub mod prompt;
+pub mod random;
+pub mod rational_mode;
+#[cfg(feature = "repl")]
+pub mod recall;
+#[cfg(feature = "repl")]
+pub mod repl;
+#[cfg(any(test, feature = "roundtrip"))]
+pub mod roundtrip;
+pub mod scanner;
+#[cfg(feature = "repl")]
+pub mod session_file;
+pub mod special_forms;
+#[cfg(feature = "repl")]
+pub mod stream;
+pub mod substitution;
+pub mod table;
+pub mod template;
+pub mod timing;
+pub mod trace;
+pub mod value;
+pub mod visitor;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -1,29 +1,152 @@
-use std::io::Write;
-
-mod ast;
-mod evaluator;
-mod scanner;
-use evaluator::evaluate;
-
-fn main() {
-    loop {
-        print!(">>> ");
-        std::io::stdout().flush().expect("Reading error!");
-
-        let mut exp = String::new();
-        match std::io::stdin().read_line(&mut exp) {
-            Ok(_) => {
-                if exp.starts_with("q") || exp.starts_with("exit") {
-                    break;
-                }
-                match evaluate(&exp) {
-                    Ok(result) => println!("<<< {}", result),
-                    Err(err) => println!("Error happened: {}", err),
-                }
-            }
-            Err(_) => println!("Input reading error!"),
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, IsTerminal};
+use std::process;
+
+use ls_calc::cli::{
+    chosen_color, define_vars, install_logger, load_config, load_session, parse_args, run_batch,
+    run_bench, run_csv, run_dump, run_exprs, stdin_mode, watch, Mode, EXIT_OK, EXIT_USAGE, USAGE,
+};
+use ls_calc::command::Session;
+use ls_calc::error::ColorChoice;
+use ls_calc::repl;
+
+/// Ctrl-C cancels the line being typed instead of ending the calculator. The
+/// terminal discards the line, the handler only shows a new prompt.
+#[cfg(unix)]
+mod interrupt {
+    use std::sync::atomic::Ordering;
+
+    use ls_calc::repl::INTERRUPTED;
+
+    const SIGINT: i32 = 2;
+    const STDOUT: i32 = 1;
+    const PROMPT: &[u8] = b"\n>>> ";
+
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+        fn write(fd: i32, buf: *const u8, count: usize) -> isize;
+    }
+
+    extern "C" fn on_interrupt(_: i32) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        // Only async-signal-safe calls are allowed here, so no `print!`.
+        unsafe {
+            write(STDOUT, PROMPT.as_ptr(), PROMPT.len());
         }
+    }
+
+    pub fn install() {
+        unsafe {
+            signal(SIGINT, on_interrupt);
+        }
+    }
+}
+
+fn main() -> io::Result<()> {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            process::exit(EXIT_USAGE);
+        }
+    };
+    install_logger(options.verbosity);
+    if options.help {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+
+    let mut session = Session::default();
+    session.ctx.set_strict_floats(true);
+    session.ctx.set_env_access(true);
+    let config = match load_config(&options, &mut session, io::stderr()) {
+        Ok(config) => config,
+        Err(code) => process::exit(code),
+    };
+    // Errors go to stderr, except in the REPL.
+    let no_color = env::var_os("NO_COLOR").is_some();
+    let color =
+        |terminal| chosen_color(&options, &config).unwrap_or(ColorChoice::auto(terminal, no_color));
+    session.color = color(io::stderr().is_terminal());
 
-        println!("exp = {}", exp);
+    if let Some(dump) = &options.dump {
+        process::exit(run_dump(dump, &session, io::stdout(), io::stderr()));
     }
+
+    if let Some(path) = &options.load {
+        let code = load_session(path, &mut session, io::stderr());
+        if code != EXIT_OK {
+            process::exit(code);
+        }
+    }
+    let code = define_vars(&options.vars, &mut session, io::stderr());
+    if code != EXIT_OK {
+        process::exit(code);
+    }
+
+    if options.bench.is_some() {
+        process::exit(run_bench(&options, &session, io::stdout(), io::stderr()));
+    }
+
+    if options.csv.is_some() {
+        process::exit(run_csv(&options, &session, io::stdout(), io::stderr()));
+    }
+
+    if let Some(path) = &options.watch {
+        if let Err(err) = File::open(path) {
+            eprintln!("Can't open the file {}! {}", path, err);
+            process::exit(EXIT_USAGE);
+        }
+        // Errors are shown among the results.
+        let terminal = io::stdout().is_terminal();
+        session.color = color(terminal);
+        return watch(
+            path,
+            &session,
+            options.json,
+            terminal && !options.json,
+            io::stdout(),
+        );
+    }
+
+    if !options.exprs.is_empty() {
+        let code = run_exprs(&options, &mut session, io::stdout(), io::stderr());
+        if code != EXIT_OK || options.file.is_none() {
+            process::exit(code);
+        }
+    }
+    let (input, keep_going): (Box<dyn BufRead>, bool) = match &options.file {
+        Some(path) => match File::open(path) {
+            Ok(file) => (Box::new(BufReader::new(file)), options.keep_going),
+            Err(err) => {
+                eprintln!("Can't open the file {}! {}", path, err);
+                process::exit(EXIT_USAGE);
+            }
+        },
+        None => {
+            let stdin_tty = io::stdin().is_terminal();
+            match stdin_mode(&options, stdin_tty, io::stdout().is_terminal()) {
+                Mode::Batch { keep_going } => (Box::new(io::stdin().lock()), keep_going),
+                Mode::Repl => {
+                    session.color = color(io::stdout().is_terminal());
+                    if stdin_tty {
+                        #[cfg(unix)]
+                        interrupt::install();
+                    }
+                    return repl::run(io::stdin().lock(), io::stdout(), &mut session);
+                }
+            }
+        }
+    };
+    let json = options.json;
+    let code = run_batch(
+        input,
+        &mut session,
+        keep_going,
+        json,
+        io::stdout(),
+        io::stderr(),
+    );
+    process::exit(code);
 }
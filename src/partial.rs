@@ -0,0 +1,169 @@
+use super::ast::{Node, TokenType};
+use super::context::Context;
+use super::evaluator::Evaluator;
+use super::special_forms::find_special_form;
+use super::visitor::walk_node;
+
+/// Evaluates the subtrees whose variables are all set in the context.
+struct PartialEvaluator<'a> {
+    ctx: &'a Context,
+    bound: Vec<String>,
+}
+
+impl<'a> PartialEvaluator<'a> {
+    fn is_known(&self, node: &Node) -> bool {
+        node.is_pure()
+            && node
+                .free_variables()
+                .iter()
+                .all(|var| !self.bound.contains(var) && self.ctx.get_var(var).is_some())
+    }
+
+    fn eval_bound(&mut self, var: &str, body: &Node) -> Node {
+        self.bound.push(var.to_string());
+        let body = self.eval(body);
+        self.bound.pop();
+        body
+    }
+
+    fn eval(&mut self, node: &Node) -> Node {
+        let leaf = matches!(node.token, TokenType::Number(_) | TokenType::Lambda(_));
+        if !leaf && self.is_known(node) {
+            if let Ok(n) = walk_node(&mut Evaluator::new(self.ctx), node) {
+                if n.is_finite() {
+                    return Node::new(TokenType::Number(n), None, None);
+                }
+            }
+        }
+
+        match node.token {
+            TokenType::Lambda(ref param) => {
+                let body = node
+                    .left
+                    .as_ref()
+                    .map(|body| Box::new(self.eval_bound(param, body)));
+                Node::new(node.token.clone(), body, None)
+            }
+            TokenType::Call(ref name, ref args) => {
+                let scoped = find_special_form(name).and_then(|form| form.scoped_args(args));
+                let args = args
+                    .iter()
+                    .enumerate()
+                    .map(|(i, arg)| match (scoped, &args[..]) {
+                        (Some((var, _)), _) if i == var => arg.clone(),
+                        (Some((var, body)), args) if i == body => match args[var].token {
+                            TokenType::Variable(ref var) => self.eval_bound(var, arg),
+                            _ => self.eval(arg),
+                        },
+                        _ => self.eval(arg),
+                    })
+                    .collect();
+                Node::new(TokenType::Call(name.clone(), args), None, None)
+            }
+            _ => {
+                let left = node.left.as_ref().map(|left| Box::new(self.eval(left)));
+                let right = node.right.as_ref().map(|right| Box::new(self.eval(right)));
+                Node::new(node.token.clone(), left, right)
+            }
+        }
+    }
+}
+
+/// Returns a copy of the tree where every subtree that only depends on
+/// variables set in `ctx` is replaced by its value. Unlike constant folding
+/// this consults the variables, and unlike evaluation it leaves everything
+/// depending on unknown variables, as well as calls to impure functions like
+/// `random()`, in place.
+pub fn partial_eval(node: &Node, ctx: &Context) -> Node {
+    PartialEvaluator {
+        ctx,
+        bound: Vec::new(),
+    }
+    .eval(node)
+}
+
+#[cfg(test)]
+mod partial_tests {
+    use super::*;
+    use crate::ast::parse;
+
+    fn build(expr: &str) -> Node {
+        *parse(expr).unwrap().unwrap()
+    }
+
+    fn do_test(expr: &str, ctx: &Context, expected: &str) {
+        println!("Expression = {}", expr);
+
+        let result = partial_eval(&build(expr), ctx);
+        assert_eq!(result, build(expected));
+    }
+
+    fn eval(node: &Node, ctx: &Context) -> f64 {
+        walk_node(&mut Evaluator::new(ctx), node).unwrap()
+    }
+
+    #[test]
+    fn unknown_variables_test() {
+        let ctx = Context::new();
+        do_test("2*3 + x*(4-1)", &ctx, "6 + x*3");
+        do_test("x", &ctx, "x");
+        do_test("sin(pi / 2) * x!", &ctx, "1 * x!");
+        do_test("max(x, 2 + 3)", &ctx, "max(x, 5)");
+    }
+
+    #[test]
+    fn known_variables_test() {
+        let mut ctx = Context::new();
+        ctx.set_var("y", 2.);
+        ctx.set_var("z", 3.);
+
+        do_test("x*y + y^z", &ctx, "x*2 + 8");
+        do_test("y * z", &ctx, "6");
+        do_test("(x + y) * z", &ctx, "(x + 2) * 3");
+    }
+
+    #[test]
+    fn bound_variables_test() {
+        let mut ctx = Context::new();
+        ctx.set_var("i", 100.);
+        ctx.set_var("n", 3.);
+
+        do_test("sum(i, 1, n, i) + x", &ctx, "6 + x");
+        do_test("sum(i, 1, x, i * n)", &ctx, "sum(i, 1, x, i * 3)");
+        do_test("sum(i -> i + n, 1, x)", &ctx, "sum(i -> i + 3, 1, x)");
+        do_test(
+            "integrate(x -> x * n, 0, x) + i",
+            &ctx,
+            "integrate(x -> x * 3, 0, x) + 100",
+        );
+    }
+
+    #[test]
+    fn impure_test() {
+        let ctx = Context::new();
+        do_test("random() * (1 + 1) + x", &ctx, "random() * 2 + x");
+        do_test("random() * 0", &ctx, "random() * 0");
+    }
+
+    #[test]
+    fn finish_evaluation_test() {
+        let mut known = Context::new();
+        known.set_var("a", 1.5);
+        known.set_var("b", -2.);
+
+        for expr in &[
+            "a * x^2 + b * x + 3",
+            "sin(a * x) / (b - x)",
+            "sum(i, 1, 4, x^i * a)",
+        ] {
+            let tree = build(expr);
+            let partial = partial_eval(&tree, &known);
+
+            let mut all = known.clone();
+            all.set_var("x", 0.7);
+
+            println!("Expression = {}", expr);
+            assert_eq!(eval(&partial, &all), eval(&tree, &all));
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use super::arena::{eval_vec, Arena};
+use super::ast::parse_with;
+use super::context::Context;
+use super::error::CalcError;
+
+/// The size of a plot in chars by default.
+pub const PLOT_WIDTH: usize = 70;
+pub const PLOT_HEIGHT: usize = 20;
+
+/// The row of `y` on a y axis from `min` at the bottom to `max` at the top.
+fn row(y: f64, min: f64, max: f64, height: usize) -> usize {
+    ((max - y) / (max - min) * (height - 1) as f64).round() as usize
+}
+
+/// Plots `expr` over `var` from `from` to `to` as `height` rows of `width`
+/// chars. The expression is sampled once per column and the y axis is
+/// scaled to its values, a constant gets a line in the middle. The axes are
+/// drawn with `-` and `|` where they're in the plot, and the samples with
+/// `*`. Samples that fail or aren't finite are left out. Trailing spaces of
+/// the rows are left out too.
+pub fn plot(
+    expr: &str,
+    var: &str,
+    from: f64,
+    to: f64,
+    (width, height): (usize, usize),
+    ctx: &Context,
+) -> Result<Vec<String>, CalcError> {
+    let root = parse_with(expr, ctx)
+        .map_err(CalcError::Parse)?
+        .ok_or_else(|| CalcError::Parse(String::from("Empty expression!")))?;
+    if width < 2 || height < 2 {
+        return Err(CalcError::Eval(format!(
+            "A plot of {}x{} chars is too small!",
+            width, height
+        )));
+    }
+    if !from.is_finite() || !to.is_finite() || from >= to {
+        return Err(CalcError::Eval(format!(
+            "A plot can't go from {} to {}!",
+            from, to
+        )));
+    }
+
+    let x = |column: usize| from + (to - from) * column as f64 / (width - 1) as f64;
+    let xs: Vec<f64> = (0..width).map(x).collect();
+    let ys = eval_vec(&Arena::from_tree(Some(root)), var, &xs, ctx);
+    let samples: Vec<(usize, f64)> = ys
+        .into_iter()
+        .enumerate()
+        .filter_map(|(column, y)| y.ok().filter(|y| y.is_finite()).map(|y| (column, y)))
+        .collect();
+    if samples.is_empty() {
+        return Err(CalcError::Eval(format!(
+            "{} has no value from {} to {}!",
+            expr, from, to
+        )));
+    }
+    let mut min = samples
+        .iter()
+        .map(|(_, y)| *y)
+        .fold(f64::INFINITY, f64::min);
+    let mut max = samples
+        .iter()
+        .map(|(_, y)| *y)
+        .fold(f64::NEG_INFINITY, f64::max);
+    if min == max {
+        min -= 1.;
+        max += 1.;
+    }
+
+    let mut grid = vec![vec![' '; width]; height];
+    if min <= 0. && 0. <= max {
+        grid[row(0., min, max, height)] = vec!['-'; width];
+    }
+    if from <= 0. && 0. <= to {
+        let column = ((0. - from) / (to - from) * (width - 1) as f64).round() as usize;
+        for line in grid.iter_mut() {
+            line[column] = if line[column] == '-' { '+' } else { '|' };
+        }
+    }
+    for (column, y) in samples {
+        grid[row(y, min, max, height)][column] = '*';
+    }
+
+    Ok(grid
+        .iter()
+        .map(|line| line.iter().collect::<String>().trim_end().to_string())
+        .collect())
+}
+
+#[cfg(test)]
+mod plot_tests {
+    use super::*;
+
+    #[test]
+    fn plot_line_test() {
+        let ctx = Context::new();
+        assert_eq!(
+            plot("x", "x", -1., 1., (5, 5), &ctx).unwrap(),
+            ["  | *", "  |*", "--*--", " *|", "* |"]
+        );
+        assert_eq!(
+            plot("0", "t", -2., 2., (5, 3), &ctx).unwrap(),
+            ["  |", "*****", "  |"]
+        );
+        // Without zero in the plot there are no axes.
+        assert_eq!(
+            plot("2 * x + 1", "x", 1., 4., (4, 4), &ctx).unwrap(),
+            ["   *", "  *", " *", "*"]
+        );
+    }
+
+    #[test]
+    fn plot_skips_test() {
+        let mut ctx = Context::new();
+        ctx.set_strict_floats(true);
+        assert_eq!(
+            plot("1 / x", "x", -1., 1., (5, 3), &ctx).unwrap(),
+            ["  |*", "--+-*", "**|"]
+        );
+        assert_eq!(
+            plot("sqrt(x)", "x", -4., -1., (4, 4), &ctx),
+            Err(CalcError::Eval(String::from(
+                "sqrt(x) has no value from -4 to -1!"
+            )))
+        );
+    }
+
+    #[test]
+    fn plot_errors_test() {
+        let ctx = Context::new();
+        assert_eq!(
+            plot("x", "x", 1., 1., (5, 5), &ctx),
+            Err(CalcError::Eval(String::from(
+                "A plot can't go from 1 to 1!"
+            )))
+        );
+        assert_eq!(
+            plot("x", "x", 0., 1., (1, 5), &ctx),
+            Err(CalcError::Eval(String::from(
+                "A plot of 1x5 chars is too small!"
+            )))
+        );
+        assert_eq!(
+            plot("x *", "x", 0., 1., (5, 5), &ctx),
+            Err(CalcError::Parse(String::from(
+                "Operator Multiplication at pos 2 expects an operand, but gets End!"
+            )))
+        );
+    }
+}
@@ -0,0 +1,379 @@
+use std::fmt;
+
+use super::ast::{Node, NodePtr, TokenType};
+use super::visitor::{walk, walk_node, Visitor};
+
+/// Binding powers of the infix operators, the same as the parser uses.
+fn infix_binding_power(op: &TokenType) -> (u32, u32) {
+    match op {
+        TokenType::Or => (1, 2),
+        TokenType::And => (3, 4),
+        op if op.is_comparison() => (7, 8),
+        TokenType::Plus | TokenType::Minus => (11, 12),
+        TokenType::Multiply | TokenType::Divide | TokenType::FloorDivide | TokenType::Modulo => {
+            (13, 14)
+        }
+        TokenType::Power => (14, 15),
+        _ => panic!("Unknown binary token! {:?}", op),
+    }
+}
+
+const PREFIX_BINDING_POWER: u32 = 15;
+
+/// Formats a number so that it reads back as the same number, e.g. NaN is
+/// printed as the constant `nan` and infinity as `inf`.
+pub fn format_number(n: f64) -> String {
+    if n.is_nan() {
+        String::from("nan")
+    } else {
+        n.to_string()
+    }
+}
+
+fn is_prefix(node: &Node) -> bool {
+    match node.token {
+        TokenType::PrefixMinus | TokenType::PrefixPlus => true,
+        TokenType::Number(n) => n.is_sign_negative(),
+        _ => false,
+    }
+}
+
+fn is_logical(op: &TokenType) -> bool {
+    matches!(op, TokenType::And | TokenType::Or)
+}
+
+/// Whether `child` reads back as the operand of a `not`, or whether it can
+/// take `not` as an operand. `not` takes everything up to the next `and` or
+/// `or` as its operand, so elsewhere it needs parentheses.
+fn fits_not(op: &TokenType, child: &Node) -> bool {
+    match (op, &child.token) {
+        (TokenType::Not, token) => !is_logical(token) && !is_lambda(child),
+        (op, TokenType::Not) => is_logical(op),
+        _ => true,
+    }
+}
+
+fn is_lambda(node: &Node) -> bool {
+    matches!(node.token, TokenType::Lambda(_))
+}
+
+fn is_binary(node: &Node) -> bool {
+    !node.token.is_unary()
+        && !matches!(
+            node.token,
+            TokenType::Number(_)
+                | TokenType::Variable(_)
+                | TokenType::Call(..)
+                | TokenType::Lambda(_)
+        )
+}
+
+/// Whether `child` reads back as the left operand of `op` without
+/// parentheses.
+fn fits_left(op: &TokenType, child: &Node) -> bool {
+    let (l_bp, _) = infix_binding_power(op);
+    // Comparisons can't be chained.
+    if !fits_not(op, child) || op.is_comparison() && child.token.is_comparison() {
+        false
+    } else if is_binary(child) {
+        l_bp < infix_binding_power(&child.token).1
+    } else if is_prefix(child) {
+        l_bp < PREFIX_BINDING_POWER
+    } else {
+        !is_lambda(child)
+    }
+}
+
+/// Whether `node` is a call of a function named like the operator words.
+fn is_word_call(node: &Node) -> bool {
+    matches!(&node.token, TokenType::Call(name, _) if name == "mod" || name == "div")
+}
+
+/// Whether `node` may be printed starting with a token that makes a `%`
+/// before it a percent. Its leftmost operands are printed first.
+fn starts_like_percent(node: &Node) -> bool {
+    match node.left.as_deref() {
+        _ if is_prefix(node) || is_word_call(node) => true,
+        _ if matches!(node.token, TokenType::Bar | TokenType::Not) => true,
+        Some(left) if !is_lambda(node) => starts_like_percent(left),
+        _ => false,
+    }
+}
+
+/// Whether `child` reads back as the right operand of `op` without
+/// parentheses.
+fn fits_right(op: &TokenType, child: &Node) -> bool {
+    let (_, r_bp) = infix_binding_power(op);
+    if !fits_not(op, child) || op.is_comparison() && child.token.is_comparison() {
+        false
+    } else if *op == TokenType::Modulo && starts_like_percent(child) {
+        // `10 % -3`, `|10 % |x||` and `10 % mod(7, 4)` would read as a
+        // percent.
+        false
+    } else if is_binary(child) {
+        infix_binding_power(&child.token).0 >= r_bp
+    } else {
+        !is_lambda(child)
+    }
+}
+
+/// Renders a tree back into an expression with as few parentheses as
+/// needed to parse into the same tree.
+struct ExprPrinter {
+    number: fn(f64) -> String,
+    /// Whether every operator gets parentheses around it and its operands
+    /// instead.
+    explicit: bool,
+}
+
+impl ExprPrinter {
+    fn child(&mut self, child: &NodePtr, fits: impl Fn(&Node) -> bool) -> String {
+        match child {
+            Some(node) if !self.explicit && !fits(node) => format!("({})", walk_node(self, node)),
+            _ => walk(self, child),
+        }
+    }
+
+    /// An operator with its operands, in parentheses if they're explicit.
+    fn group(&self, operation: String) -> String {
+        if self.explicit {
+            format!("({})", operation)
+        } else {
+            operation
+        }
+    }
+}
+
+impl Visitor for ExprPrinter {
+    type Output = String;
+
+    fn visit_number(&mut self, n: f64) -> String {
+        (self.number)(n)
+    }
+
+    fn visit_variable(&mut self, name: &str) -> String {
+        name.to_string()
+    }
+
+    fn visit_history(&mut self, n: usize) -> String {
+        format!("${}", n)
+    }
+
+    fn visit_text(&mut self, text: &str) -> String {
+        format!("\"{}\"", text)
+    }
+
+    fn visit_call(&mut self, name: &str, args: &[Node]) -> String {
+        let args: Vec<String> = args.iter().map(|arg| walk_node(self, arg)).collect();
+        format!("{}({})", name, args.join(", "))
+    }
+
+    fn visit_lambda(&mut self, param: &str, body: &NodePtr) -> String {
+        format!("{} -> {}", param, walk(self, body))
+    }
+
+    fn visit_unary(&mut self, op: &TokenType, operand: &NodePtr) -> String {
+        // Prefix operators bind tighter than any infix one and factorial binds
+        // tighter still, so it only takes atoms without parentheses.
+        let prefix_fits = |node: &Node| !is_binary(node) && !is_lambda(node) && fits_not(op, node);
+        // `(x!)!` can't lose its parentheses either, `x!!` is a double factorial.
+        let factorial_fits = |node: &Node| {
+            prefix_fits(node) && !is_prefix(node) && node.token != TokenType::Factorial
+        };
+        let operation = match op {
+            TokenType::PrefixMinus => format!("-{}", self.child(operand, prefix_fits)),
+            TokenType::PrefixPlus => format!("+{}", self.child(operand, prefix_fits)),
+            TokenType::Factorial => format!("{}!", self.child(operand, factorial_fits)),
+            TokenType::DoubleFactorial => format!("{}!!", self.child(operand, factorial_fits)),
+            TokenType::Percent => format!("{}%", self.child(operand, factorial_fits)),
+            // Bars group their operand already.
+            TokenType::Bar => return format!("|{}|", walk(self, operand)),
+            TokenType::Not => format!("not {}", self.child(operand, |node| fits_not(op, node))),
+            _ => panic!("Unknown unary token! {:?}", op),
+        };
+        self.group(operation)
+    }
+
+    fn visit_binary(&mut self, op: &TokenType, left: &NodePtr, right: &NodePtr) -> String {
+        let symbol = binary_symbol(op);
+        let left = self.child(left, |node| fits_left(op, node));
+        let right = match right {
+            // `x % |y|` would read as a percent.
+            Some(node)
+                if self.explicit
+                    && *op == TokenType::Modulo
+                    && (node.token == TokenType::Bar || is_word_call(node)) =>
+            {
+                format!("({})", walk_node(self, node))
+            }
+            _ => self.child(right, |node| fits_right(op, node)),
+        };
+        self.group(format!("{} {} {}", left, symbol, right))
+    }
+
+    fn visit_none(&mut self) -> String {
+        String::new()
+    }
+}
+
+/// How a binary operator is written.
+pub fn binary_symbol(op: &TokenType) -> &'static str {
+    match op {
+        TokenType::Plus => "+",
+        TokenType::Minus => "-",
+        TokenType::Multiply => "*",
+        TokenType::Divide => "/",
+        TokenType::FloorDivide => "//",
+        TokenType::And => "and",
+        TokenType::Or => "or",
+        TokenType::Less => "<",
+        TokenType::LessEqual => "<=",
+        TokenType::Greater => ">",
+        TokenType::GreaterEqual => ">=",
+        TokenType::Equal => "==",
+        TokenType::NotEqual => "!=",
+        TokenType::Modulo => "%",
+        TokenType::Power => "^",
+        _ => panic!("Unknown binary token! {:?}", op),
+    }
+}
+
+impl Node {
+    /// Renders the tree like `to_string`, but writes numbers with `number`.
+    pub fn format_with(&self, number: fn(f64) -> String) -> String {
+        let mut printer = ExprPrinter {
+            number,
+            explicit: false,
+        };
+        walk_node(&mut printer, self)
+    }
+
+    /// Renders the tree with every operator in parentheses together with its
+    /// operands, which shows how the expression was grouped: `-3!` is
+    /// `(-(3!))`. A prefix minus is written next to its operand, `(-x)`, an
+    /// infix one between spaces, `(x - y)`.
+    pub fn explain(&self) -> String {
+        let mut printer = ExprPrinter {
+            number: format_number,
+            explicit: true,
+        };
+        walk_node(&mut printer, self)
+    }
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.format_with(format_number))
+    }
+}
+
+#[cfg(test)]
+mod printer_tests {
+    use super::*;
+    use crate::ast::parse;
+
+    fn build(expr: &str) -> Node {
+        *parse(expr).unwrap().unwrap()
+    }
+
+    fn do_test(expr: &str, expected: &str) {
+        println!("Expression = {}", expr);
+
+        let tree = build(expr);
+        let printed = tree.to_string();
+        assert_eq!(printed, expected);
+        assert_eq!(build(&printed), tree);
+    }
+
+    #[test]
+    fn print_special_numbers_test() {
+        let num = |n: f64| Node::new(TokenType::Number(n), None, None);
+        assert_eq!(num(f64::NAN).to_string(), "nan");
+        assert_eq!(num(f64::INFINITY).to_string(), "inf");
+        assert_eq!(num(f64::NEG_INFINITY).to_string(), "-inf");
+        assert_eq!(format_number(-f64::NAN), "nan");
+        assert_eq!(format_number(2.5), "2.5");
+    }
+
+    #[test]
+    fn print_operators_test() {
+        do_test("1+2*3", "1 + 2 * 3");
+        do_test("(1+2)*3", "(1 + 2) * 3");
+        do_test("1-(2-3)", "1 - (2 - 3)");
+        do_test("(1-2)-3", "1 - 2 - 3");
+        do_test("2^(3^2)", "2 ^ (3 ^ 2)");
+        do_test("(2^3)^2", "2 ^ 3 ^ 2");
+        do_test("a*b^c", "a * b ^ c");
+        do_test("(a*b)^c", "(a * b) ^ c");
+        do_test("x % 2 / y", "x % 2 / y");
+        do_test("x // (2 // y)", "x // (2 // y)");
+        do_test("x + 1 < 2 * y", "x + 1 < 2 * y");
+        do_test(
+            "not x < 1 and y or not (x or y)",
+            "not x < 1 and y or not (x or y)",
+        );
+        do_test(
+            "(not x) * -(not y) + (not z)!",
+            "(not x) * -(not y) + (not z)!",
+        );
+        do_test("(x == 1) != (y >= 2) * 3", "(x == 1) != (y >= 2) * 3");
+    }
+
+    #[test]
+    fn print_unary_test() {
+        do_test("-(x + 1)", "-(x + 1)");
+        do_test("-x^2", "-x ^ 2");
+        do_test("-(x^2)", "-(x ^ 2)");
+        do_test("(-3)!", "(-3)!");
+        do_test("-3!", "-3!");
+        do_test("(3!)! + 3!!! + (3!)!!", "(3!)! + 3!!! + (3!)!!");
+        do_test("(x*2)! + |x - 1|", "(x * 2)! + |x - 1|");
+        do_test("2 * -x", "2 * -x");
+        do_test("(x + 1)% * 3 % 2", "(x + 1)% * 3 % 2");
+        do_test("10 % (-3) + |x % (|y|)|", "10 % (-3) + |x % (|y|)|");
+        do_test("10 % (-3 ^ 2) + 10 % (|y|!)", "10 % (-3 ^ 2) + 10 % (|y|!)");
+        do_test(
+            "10 % (mod(7, 4)) + 10 % (div(7, 4) * 2)",
+            "10 % (mod(7, 4)) + 10 % (div(7, 4) * 2)",
+        );
+    }
+
+    fn do_explain_test(expr: &str, expected: &str) {
+        println!("Expression = {}", expr);
+
+        let tree = build(expr);
+        let explained = tree.explain();
+        assert_eq!(explained, expected);
+        assert_eq!(build(&explained), tree);
+    }
+
+    #[test]
+    fn explain_test() {
+        do_explain_test("2 + 3 * 4 ^ 2", "(2 + (3 * (4 ^ 2)))");
+        do_explain_test("-3!", "(-(3!))");
+        do_explain_test("-3! / 3", "((-(3!)) / 3)");
+        do_explain_test("-5!!", "(-(5!!))");
+        do_explain_test("3!!!", "((3!!)!)");
+        do_explain_test("2 + -1 / 2", "(2 + ((-1) / 2))");
+        do_explain_test("-x^2", "((-x) ^ 2)");
+        do_explain_test("2^3^2", "((2 ^ 3) ^ 2)");
+        do_explain_test("10 - 2! + 3!", "((10 - (2!)) + (3!))");
+        do_explain_test("200 + 10%", "(200 + (10%))");
+        do_explain_test("-50%", "(-(50%))");
+        do_explain_test("2 ^ 3 // 3", "((2 ^ 3) // 3)");
+        do_explain_test("not 2 + 3", "(not (2 + 3))");
+        do_explain_test("not 0 and 0", "((not 0) and 0)");
+        do_explain_test("x % |y - 1|", "(x % (|(y - 1)|))");
+        do_explain_test("max(1 + 2, -x)", "max((1 + 2), (-x))");
+        do_explain_test("sum(i -> i^2, 1, n)", "sum(i -> (i ^ 2), 1, n)");
+        do_explain_test("7", "7");
+    }
+
+    #[test]
+    fn print_calls_test() {
+        do_test("max(x,2)+sin(pi/2)", "max(x, 2) + sin(pi / 2)");
+        do_test("sum(i->i^2,1,n)", "sum(i -> i ^ 2, 1, n)");
+        do_test("(x -> x) + 1", "(x -> x) + 1");
+        do_test("random()", "random()");
+    }
+}
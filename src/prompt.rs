@@ -0,0 +1,185 @@
+use super::context::Context;
+use super::format::Base;
+
+/// The prompt of the REPL unless `:prompt` changes it. It's `>>> ` until a
+/// mode differs from its default, like `[int,hex] >>> `.
+pub const DEFAULT_PROMPT: &str = "{modes}>>> ";
+
+/// The placeholders a prompt template can have.
+pub const PLACEHOLDERS: &[&str] = &["base", "complex", "mode", "modes", "precision", "strict"];
+
+/// `float`, `int` or `rational`.
+fn arithmetic_mode(ctx: &Context) -> &'static str {
+    if ctx.integer_mode() {
+        "int"
+    } else if ctx.rational_mode() {
+        "rational"
+    } else {
+        "float"
+    }
+}
+
+fn base(ctx: &Context) -> &'static str {
+    match ctx.format_options().base {
+        Base::Decimal => "dec",
+        Base::Binary => "bin",
+        Base::Octal => "oct",
+        Base::Hexadecimal => "hex",
+    }
+}
+
+fn complex(ctx: &Context) -> &'static str {
+    if ctx.complex_mode() {
+        "complex"
+    } else {
+        "real"
+    }
+}
+
+/// The arithmetic mode, complex mode and base that differ from their
+/// defaults in brackets and followed by a space, or nothing if none does.
+fn modes(ctx: &Context) -> String {
+    let modes: Vec<&str> = [
+        (arithmetic_mode(ctx), "float"),
+        (complex(ctx), "real"),
+        (base(ctx), "dec"),
+    ]
+    .iter()
+    .filter(|(mode, default)| mode != default)
+    .map(|(mode, _)| *mode)
+    .collect();
+    if modes.is_empty() {
+        String::new()
+    } else {
+        format!("[{}] ", modes.join(","))
+    }
+}
+
+/// The text of the placeholder `name`, `None` if there is no such one.
+fn placeholder(name: &str, ctx: &Context) -> Option<String> {
+    let text = match name {
+        "base" => base(ctx).to_string(),
+        "complex" => complex(ctx).to_string(),
+        "mode" => arithmetic_mode(ctx).to_string(),
+        "modes" => modes(ctx),
+        "precision" => match ctx.format_options().precision {
+            Some(digits) => digits.to_string(),
+            None => String::from("full"),
+        },
+        "strict" if ctx.strict_floats() => String::from("strict"),
+        "strict" => String::from("loose"),
+        _ => return None,
+    };
+    Some(text)
+}
+
+/// Replaces the placeholders of `template` like `{base}` with the settings
+/// of `ctx`. Braces around anything else are kept as they are.
+pub fn render_prompt(template: &str, ctx: &Context) -> String {
+    let mut prompt = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        prompt.push_str(&rest[..open]);
+        rest = &rest[open..];
+        let shown = rest
+            .find('}')
+            .and_then(|close| Some((placeholder(&rest[1..close], ctx)?, close)));
+        match shown {
+            Some((text, close)) => {
+                prompt.push_str(&text);
+                rest = &rest[close + 1..];
+            }
+            None => {
+                prompt.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    prompt.push_str(rest);
+    prompt
+}
+
+/// Checks that every `{name}` of `template` whose name is a word is a
+/// placeholder, which catches typos that would be shown as they are.
+pub fn check_prompt(template: &str) -> Result<(), String> {
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        rest = &rest[open + 1..];
+        let name = rest.find('}').map(|close| &rest[..close]);
+        let word = name.filter(|name| !name.is_empty() && name.chars().all(char::is_alphabetic));
+        match word {
+            Some(name) if !PLACEHOLDERS.contains(&name) => {
+                return Err(format!(
+                    "Unknown placeholder {{{}}}, the placeholders are {{{}}}!",
+                    name,
+                    PLACEHOLDERS.join("}, {")
+                ))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod prompt_tests {
+    use super::*;
+
+    #[test]
+    fn placeholders_test() {
+        let mut ctx = Context::new();
+        let render = |template: &str, ctx: &Context| render_prompt(template, ctx);
+        assert_eq!(render("{mode}", &ctx), "float");
+        assert_eq!(render("{complex}", &ctx), "real");
+        assert_eq!(render("{base}", &ctx), "dec");
+        assert_eq!(render("{strict}", &ctx), "loose");
+        assert_eq!(render("{precision}", &ctx), "15");
+        assert_eq!(render("{modes}", &ctx), "");
+
+        ctx.set_integer_mode(true);
+        ctx.set_complex_mode(true);
+        ctx.set_base(Base::Hexadecimal);
+        ctx.set_strict_floats(true);
+        ctx.set_precision(None);
+        assert_eq!(render("{mode}", &ctx), "int");
+        assert_eq!(render("{complex}", &ctx), "complex");
+        assert_eq!(render("{base}", &ctx), "hex");
+        assert_eq!(render("{strict}", &ctx), "strict");
+        assert_eq!(render("{precision}", &ctx), "full");
+        assert_eq!(render("{modes}", &ctx), "[int,complex,hex] ");
+
+        ctx.set_integer_mode(false);
+        ctx.set_rational_mode(true);
+        assert_eq!(render("{mode}", &ctx), "rational");
+        ctx.set_rational_mode(false);
+        ctx.set_complex_mode(false);
+        assert_eq!(render("{modes}", &ctx), "[hex] ");
+    }
+
+    #[test]
+    fn render_prompt_test() {
+        let mut ctx = Context::new();
+        assert_eq!(render_prompt(DEFAULT_PROMPT, &ctx), ">>> ");
+        ctx.set_base(Base::Binary);
+        assert_eq!(render_prompt(DEFAULT_PROMPT, &ctx), "[bin] >>> ");
+
+        assert_eq!(render_prompt("{base}/{mode}> ", &ctx), "bin/float> ");
+        assert_eq!(render_prompt("{x} {base} {", &ctx), "{x} bin {");
+        assert_eq!(render_prompt("}{{base}}", &ctx), "}{bin}");
+        assert_eq!(render_prompt("", &ctx), "");
+    }
+
+    #[test]
+    fn check_prompt_test() {
+        assert_eq!(check_prompt(DEFAULT_PROMPT), Ok(()));
+        assert_eq!(check_prompt("{base} { > "), Ok(()));
+        assert_eq!(check_prompt("{{base}} {1} {}"), Ok(()));
+        assert_eq!(
+            check_prompt("{angle} > "),
+            Err(String::from(
+                "Unknown placeholder {angle}, the placeholders are \
+                 {base}, {complex}, {mode}, {modes}, {precision}, {strict}!"
+            ))
+        );
+    }
+}
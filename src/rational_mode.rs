@@ -0,0 +1,257 @@
+use super::ast::TokenType;
+use super::builtins::{find_builtin, max_denominator};
+use super::exact::{exact, MAX_BITS};
+use super::fraction::Fraction;
+use super::printer::binary_symbol;
+use super::value::Value;
+
+/// `op` of fractions, or `None` if it's left to floats. Divisions expect a
+/// nonzero `right`.
+fn fraction_binary(op: &TokenType, left: &Fraction, right: &Fraction) -> Option<Value> {
+    let truth = |holds: bool| Some(Value::Bool(holds));
+    let result = match op {
+        TokenType::Plus => left + right,
+        TokenType::Minus => left - right,
+        TokenType::Multiply => left * right,
+        TokenType::Divide => left.checked_div(right)?,
+        TokenType::FloorDivide => Fraction::from(left.checked_div(right)?.floor()),
+        // The sign follows the dividend, like `%` on floats.
+        TokenType::Modulo => {
+            let quotient = Fraction::from(left.checked_div(right)?.trunc());
+            left - &(right * &quotient)
+        }
+        // Only integer powers are exact.
+        TokenType::Power if right.is_integer() => {
+            let exponent = right.numerator().to_i64()?;
+            let bits = left.numerator().bits().max(left.denominator().bits());
+            if bits.saturating_mul(exponent.unsigned_abs()) > MAX_BITS {
+                return None;
+            }
+            left.pow(exponent)?
+        }
+        TokenType::Less => return truth(left < right),
+        TokenType::LessEqual => return truth(left <= right),
+        TokenType::Greater => return truth(left > right),
+        TokenType::GreaterEqual => return truth(left >= right),
+        TokenType::Equal => return truth(left == right),
+        TokenType::NotEqual => return truth(left != right),
+        _ => return None,
+    };
+    exact_fraction(result)
+}
+
+/// A fraction as an integer if it is one, or `None` beyond `MAX_BITS`.
+pub fn exact_fraction(fraction: Fraction) -> Option<Value> {
+    if fraction.is_integer() {
+        exact(fraction.numerator().clone())
+    } else if fraction.denominator().bits() > MAX_BITS || fraction.numerator().bits() > MAX_BITS {
+        None
+    } else {
+        Some(Value::Ratio(fraction))
+    }
+}
+
+/// `op` of the fractions of the operands `left` and `right`, or `None` if
+/// it's left to floats, like `(1/4)^0.5`. Dividing by zero is an error.
+pub fn rational_binary(
+    op: &TokenType,
+    left: &Value,
+    right: &Value,
+    fraction_left: &Fraction,
+    fraction_right: &Fraction,
+) -> Result<Option<Value>, String> {
+    let division = matches!(
+        op,
+        TokenType::Divide | TokenType::FloorDivide | TokenType::Modulo
+    );
+    if division && fraction_right.is_zero() {
+        return Err(format!(
+            "Division by zero in {} {} {}!",
+            left,
+            binary_symbol(op),
+            right
+        ));
+    }
+    Ok(fraction_binary(op, fraction_left, fraction_right))
+}
+
+/// The fraction closest to the first argument with a denominator up to
+/// the second one, 1000 by default, `frac(pi)` is `355/113`. Integers
+/// stay themselves.
+pub fn frac(name: &str, args: &[Value]) -> Result<Value, String> {
+    find_builtin("frac")
+        .unwrap()
+        .arity
+        .check_count(name, args.len())?;
+    let floats: Vec<f64> = args.iter().map(Value::as_f64).collect();
+    let max = max_denominator(&floats)?;
+    match &args[0] {
+        Value::Int(_) | Value::Big(_) => Ok(args[0].clone()),
+        arg => Ok(Fraction::approximate(arg.as_f64(), max)
+            .and_then(exact_fraction)
+            .unwrap_or_else(|| arg.clone())),
+    }
+}
+
+#[cfg(test)]
+mod rational_mode_tests {
+    use super::*;
+    use crate::ast::parse;
+    use crate::bigint::BigInt;
+    use crate::context::Context;
+    use crate::evaluator::{evaluate_value, evaluate_with, Evaluator};
+    use crate::visitor::walk;
+
+    fn rational_context() -> Context {
+        let mut ctx = Context::new();
+        ctx.set_rational_mode(true);
+        ctx
+    }
+
+    fn do_rational_test(expr: &str, expected: &str) {
+        println!("Expression = {}", expr);
+
+        let expr = expr.to_string();
+        let result = evaluate_value(&expr, &rational_context()).unwrap();
+        assert_eq!(result.to_string(), expected);
+    }
+
+    #[test]
+    fn rational_arithmetic_test() {
+        do_rational_test("1/3 + 1/6", "1/2");
+        do_rational_test("1/3 * 3", "1");
+        do_rational_test("2/4", "1/2");
+        do_rational_test("-1/3 - 1/3", "-2/3");
+        do_rational_test("1/-3", "-1/3");
+        do_rational_test("0.1 + 0.2", "3/10");
+        do_rational_test("0.1 + 0.2 == 0.3", "true");
+        do_rational_test("1/3 < 0.34", "true");
+        do_rational_test("50%", "1/2");
+        do_rational_test("(2/3)^-2", "9/4");
+        do_rational_test("|-1/2|", "1/2");
+        do_rational_test("-7/2 // 1", "-4");
+        do_rational_test("7/2 % 1", "1/2");
+        do_rational_test("-7/2 % 1", "-1/2");
+        do_rational_test("1/2^100", "1/1267650600228229401496703205376");
+        do_rational_test("0 * -1", "0");
+    }
+
+    #[test]
+    fn rational_fallback_test() {
+        let ctx = rational_context();
+        assert_eq!(
+            evaluate_value(&"sqrt(1/4)".to_string(), &ctx),
+            Ok(Value::Float(0.5))
+        );
+        assert_eq!(
+            evaluate_value(&"(1/4)^0.5".to_string(), &ctx),
+            Ok(Value::Float(0.5))
+        );
+        assert_eq!(
+            evaluate_value(&"1/3 + pi".to_string(), &ctx),
+            Ok(Value::Float(1. / 3. + std::f64::consts::PI))
+        );
+        assert_eq!(
+            evaluate_value(&"(1/2)!".to_string(), &ctx),
+            Err(String::from("Domain error! 0.5! at pos 5 is undefined!"))
+        );
+        assert_eq!(
+            evaluate_value(&"1/0".to_string(), &ctx),
+            Err(String::from("Division by zero in 1 / 0!"))
+        );
+        assert_eq!(
+            evaluate_value(&"1/2 % 0".to_string(), &ctx),
+            Err(String::from("Division by zero in 1/2 % 0!"))
+        );
+        assert_eq!(evaluate_with(&"1/4".to_string(), &ctx), Ok(0.25));
+
+        let mut ctx = rational_context();
+        ctx.set_integer_mode(true);
+        assert_eq!(
+            evaluate_value(&"1/4".to_string(), &ctx),
+            Ok(Value::Float(0.25))
+        );
+    }
+
+    #[test]
+    fn frac_test() {
+        let frac = |expr: &str, expected: &str| {
+            let result = evaluate_value(&expr.to_string(), &Context::new()).unwrap();
+            assert_eq!(result.to_string(), expected, "{}", expr);
+        };
+        frac("frac(0.25)", "1/4");
+        frac("frac(-0.75)", "-3/4");
+        frac("frac(0.333333333)", "1/3");
+        frac("frac(5)", "5");
+        frac("frac(2.0)", "2");
+        frac("frac(2^100)", "1267650600228229401496703205376");
+        frac("frac(e)", "1457/536");
+        frac("frac(sqrt(2))", "1393/985");
+        // The convergents of pi, and semiconvergents in between.
+        frac("frac(pi, 1)", "3");
+        frac("frac(pi, 10)", "22/7");
+        frac("frac(pi, 100)", "311/99");
+        frac("frac(pi, 106)", "333/106");
+        frac("frac(pi)", "355/113");
+        frac("frac(pi, 1000)", "355/113");
+        frac("frac(pi, 30000)", "94053/29938");
+        frac("frac(pi, 33102)", "103993/33102");
+        frac("frac(pi, 100000)", "312689/99532");
+        frac("frac(inf)", "inf");
+
+        for max in [1, 7, 50, 113, 999, 5000].iter() {
+            let expr = format!("frac(0.123456789, {})", max);
+            match evaluate_value(&expr, &Context::new()).unwrap() {
+                Value::Ratio(fraction) => assert!(*fraction.denominator() <= BigInt::from(*max)),
+                Value::Int(_) => (),
+                value => panic!("{} isn't a fraction!", value),
+            }
+        }
+        assert_eq!(
+            evaluate_with(&"frac(pi)".to_string(), &Context::new()),
+            Ok(355. / 113.)
+        );
+        assert_eq!(
+            walk(
+                &mut Evaluator::new(&Context::new()),
+                &parse("frac(0.25)").unwrap()
+            ),
+            Ok(0.25)
+        );
+    }
+
+    #[test]
+    fn frac_errors_test() {
+        let error = |expr: &str| evaluate_value(&expr.to_string(), &Context::new());
+        assert_eq!(
+            error("frac(pi, 0)"),
+            Err(String::from(
+                "Function frac expects a whole maximum denominator of at least 1, but gets 0!"
+            ))
+        );
+        assert_eq!(
+            error("frac(pi, 2.5)"),
+            Err(String::from(
+                "Function frac expects a whole maximum denominator of at least 1, but gets 2.5!"
+            ))
+        );
+        assert_eq!(
+            error("frac(1, 2, 3)"),
+            Err(String::from(
+                "Function frac at pos 0 expects 1 to 2 arguments, but gets 3, the first extra one at pos 11!"
+            ))
+        );
+        assert_eq!(
+            error("frac()"),
+            Err(String::from(
+                "Function frac at pos 0 expects 1 to 2 arguments, but gets 0!"
+            ))
+        );
+        assert_eq!(
+            error("sin(1, 2)"),
+            Err(String::from(
+                "Function sin at pos 0 expects 1 argument, but gets 2, the first extra one at pos 7!"
+            ))
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use super::history::History;
+
+/// A textual substitution of `:sub /old/new/`, with the flags after the last
+/// delimiter and the result whose expression it edits.
+#[derive(Debug, PartialEq)]
+pub struct Edit {
+    pub old: String,
+    pub new: String,
+    /// Whether every match is replaced rather than only the first, `g`.
+    pub global: bool,
+    /// The number of the result of `$n`, `None` for the last expression.
+    pub result: Option<usize>,
+}
+
+/// Reads `/old/new/`, where the first character is the delimiter like in
+/// sed, so `|1/2|1/3|` works too. It can be followed by `g` and by a result
+/// `$n`.
+pub fn parse_edit(arg: &str) -> Result<Edit, String> {
+    let usage = || format!("{} isn't a substitution like /old/new/!", arg);
+    let delimiter = match arg.chars().next() {
+        Some(c) if !c.is_alphanumeric() && !c.is_whitespace() && c != '$' => c,
+        _ => return Err(usage()),
+    };
+    let mut parts = arg[delimiter.len_utf8()..].splitn(3, delimiter);
+    let (old, new, rest) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(old), Some(new), Some(rest)) => (old, new, rest),
+        _ => return Err(usage()),
+    };
+    if old.is_empty() {
+        return Err(String::from("The text to replace can't be empty!"));
+    }
+
+    let mut edit = Edit {
+        old: old.to_string(),
+        new: new.to_string(),
+        global: false,
+        result: None,
+    };
+    let mut rest = rest.trim();
+    if let Some(after) = rest.strip_prefix('g') {
+        edit.global = true;
+        rest = after.trim_start();
+    }
+    if !rest.is_empty() {
+        let n = rest
+            .strip_prefix('$')
+            .and_then(|n| n.parse::<usize>().ok())
+            .ok_or_else(|| format!("{} isn't a result like $1!", rest))?;
+        edit.result = Some(n);
+    }
+    Ok(edit)
+}
+
+/// The expression `edit` applies to: that of the result `$n`, or else the
+/// last expression.
+pub fn recall<'a>(
+    edit: &Edit,
+    last: Option<&'a str>,
+    history: &'a History,
+) -> Result<&'a str, String> {
+    match edit.result {
+        Some(n) => history
+            .entries()
+            .find(|(number, _, _)| *number == n)
+            .map(|(_, expr, _)| expr)
+            .ok_or_else(|| format!("There is no result ${}!", n)),
+        None => last.ok_or_else(|| String::from("No expression was evaluated yet!")),
+    }
+}
+
+/// `expr` with the first match of `edit.old` replaced, or every one with
+/// `g`. No match is an error, so nothing is evaluated.
+pub fn substitute(expr: &str, edit: &Edit) -> Result<String, String> {
+    if !expr.contains(&edit.old) {
+        return Err(format!("{} doesn't contain {}!", expr, edit.old));
+    }
+    Ok(if edit.global {
+        expr.replace(&edit.old, &edit.new)
+    } else {
+        expr.replacen(&edit.old, &edit.new, 1)
+    })
+}
+
+#[cfg(test)]
+mod recall_tests {
+    use super::*;
+    use crate::value::Value;
+
+    fn edit(old: &str, new: &str, global: bool, result: Option<usize>) -> Edit {
+        Edit {
+            old: old.to_string(),
+            new: new.to_string(),
+            global,
+            result,
+        }
+    }
+
+    #[test]
+    fn parse_edit_test() {
+        assert_eq!(
+            parse_edit("/9.81/9.8/"),
+            Ok(edit("9.81", "9.8", false, None))
+        );
+        assert_eq!(parse_edit("|1/2||g"), Ok(edit("1/2", "", true, None)));
+        assert_eq!(parse_edit("/x/y/ $3"), Ok(edit("x", "y", false, Some(3))));
+        assert_eq!(parse_edit("/x/y/g $12"), Ok(edit("x", "y", true, Some(12))));
+        assert_eq!(
+            parse_edit("/x/y"),
+            Err(String::from("/x/y isn't a substitution like /old/new/!"))
+        );
+        assert_eq!(
+            parse_edit("x/y/"),
+            Err(String::from("x/y/ isn't a substitution like /old/new/!"))
+        );
+        assert_eq!(
+            parse_edit(""),
+            Err(String::from(" isn't a substitution like /old/new/!"))
+        );
+        assert_eq!(
+            parse_edit("//y/"),
+            Err(String::from("The text to replace can't be empty!"))
+        );
+        assert_eq!(
+            parse_edit("/x/y/ 3"),
+            Err(String::from("3 isn't a result like $1!"))
+        );
+    }
+
+    #[test]
+    fn substitute_test() {
+        let expr = "2 * pi * 9.81 + 9.81";
+        assert_eq!(
+            substitute(expr, &edit("9.81", "g", false, None)),
+            Ok(String::from("2 * pi * g + 9.81"))
+        );
+        assert_eq!(
+            substitute(expr, &edit("9.81", "g", true, None)),
+            Ok(String::from("2 * pi * g + g"))
+        );
+        assert_eq!(
+            substitute(expr, &edit("tau", "pi", false, None)),
+            Err(String::from("2 * pi * 9.81 + 9.81 doesn't contain tau!"))
+        );
+        // History references are text like the rest.
+        assert_eq!(
+            substitute("$1 * 2", &edit("$1", "$2", false, None)),
+            Ok(String::from("$2 * 2"))
+        );
+    }
+
+    #[test]
+    fn recall_test() {
+        let mut history = History::default();
+        history.push("1 + 2", Value::Int(3));
+        history.push("$1 * 10", Value::Int(30));
+        let last = Some("$1 * 10");
+        assert_eq!(
+            recall(&edit("1", "2", false, None), last, &history),
+            Ok("$1 * 10")
+        );
+        assert_eq!(
+            recall(&edit("1", "2", false, Some(1)), last, &history),
+            Ok("1 + 2")
+        );
+        assert_eq!(
+            recall(&edit("1", "2", false, Some(3)), last, &history),
+            Err(String::from("There is no result $3!"))
+        );
+        assert_eq!(
+            recall(&edit("1", "2", false, None), None, &history),
+            Err(String::from("No expression was evaluated yet!"))
+        );
+
+        history.set_size(1);
+        assert_eq!(
+            recall(&edit("1", "2", false, Some(1)), last, &history),
+            Err(String::from("There is no result $1!"))
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use std::io::{self, BufRead, ErrorKind, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::command::{
+    is_complete, join_lines, parse_line, run_command, run_line, show_evaluated, Line, Outcome,
+    Session,
+};
+use super::error::{ColorChoice, Diagnostic};
+
+/// Set by an interrupt handler when Ctrl-C is pressed, which cancels the
+/// input read so far.
+pub static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Reads a line, or `None` at the end of input. A line that isn't UTF-8 is
+/// reported and read as an empty line.
+fn read_line<R: BufRead, W: Write>(input: &mut R, output: &mut W) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    match input.read_line(&mut line) {
+        Ok(0) => Ok(None),
+        Ok(_) => Ok(Some(line)),
+        Err(err) if err.kind() == ErrorKind::InvalidData => {
+            writeln!(output, "Input reading error!")?;
+            Ok(Some(String::new()))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Reads lines after `exp` with a `...` prompt until it's complete, or until
+/// a blank line or the end of input. `None` if it's interrupted.
+fn read_continuation<R: BufRead, W: Write>(
+    mut exp: String,
+    session: &Session,
+    input: &mut R,
+    output: &mut W,
+) -> io::Result<Option<String>> {
+    while !is_complete(&exp, &session.ctx) {
+        write!(output, "... ")?;
+        output.flush()?;
+
+        let line = read_line(input, output)?;
+        if INTERRUPTED.swap(false, Ordering::SeqCst) {
+            return Ok(None);
+        }
+        match line {
+            Some(line) if !line.trim().is_empty() => exp = join_lines(&exp, &line),
+            _ => break,
+        }
+    }
+    Ok(Some(exp))
+}
+
+/// An error of the input `exp`. In color it's the diagnostic with carets
+/// under the token it names, the input is right above it.
+fn show_error(exp: &str, err: &Diagnostic, session: &Session) -> String {
+    match session.color {
+        ColorChoice::Always => session.diagnostic(exp, err),
+        ColorChoice::Never => format!("Error happened: {}", err),
+    }
+}
+
+/// Runs the REPL on the lines of `input` and writes the prompts and results
+/// to `output`, until `:quit` or the end of input, e.g. Ctrl-D.
+pub fn run<R: BufRead, W: Write>(
+    mut input: R,
+    mut output: W,
+    session: &mut Session,
+) -> io::Result<()> {
+    loop {
+        write!(output, "{}", session.prompt())?;
+        output.flush()?;
+
+        let exp = match read_line(&mut input, &mut output)? {
+            Some(exp) => exp,
+            None => {
+                // Ends the prompt's line, so the shell's prompt starts anew.
+                writeln!(output)?;
+                return Ok(());
+            }
+        };
+        INTERRUPTED.store(false, Ordering::SeqCst);
+
+        if let Line::Command(command) = parse_line(&exp) {
+            match run_command(command, session) {
+                Ok(Outcome::Quit) => return Ok(()),
+                Ok(Outcome::Print(text)) => writeln!(output, "{}", text)?,
+                Ok(Outcome::Continue) => {}
+                Err(err) => writeln!(output, "{}", show_error(&exp, &Diagnostic::from(err), session))?,
+            }
+            continue;
+        }
+
+        let exp = match read_continuation(exp, session, &mut input, &mut output)? {
+            Some(exp) => exp,
+            None => continue,
+        };
+        // Only the last statement is shown, blank lines and lines that are
+        // only a comment show nothing.
+        match run_line(&exp, session).map(|mut results| results.pop()) {
+            Ok(Some(last)) => {
+                let value = show_evaluated(&last, &session.ctx);
+                match session.last_report {
+                    Some(report) if session.time => writeln!(output, "<<< {} {}", value, report)?,
+                    _ => writeln!(output, "<<< {}", value)?,
+                }
+            }
+            Ok(None) => {}
+            Err(err) => writeln!(output, "{}", show_error(&exp, &err, session))?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod repl_tests {
+    use super::*;
+
+    fn do_test(input: &[u8], expect: &str) {
+        println!("Input = {:?}", String::from_utf8_lossy(input));
+
+        let mut output = Vec::new();
+        run(input, &mut output, &mut Session::default()).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), expect);
+    }
+
+    #[test]
+    fn end_of_input_test() {
+        do_test(b"", ">>> \n");
+        do_test(b"1 + 2\n", ">>> <<< 3\n>>> \n");
+        // A last line without a newline is still evaluated.
+        do_test(b"1 + 2", ">>> <<< 3\n>>> \n");
+        do_test(b"\n\n", ">>> >>> >>> \n");
+        do_test(b"x = 2\nx * 3\n", ">>> <<< x = 2\n>>> <<< 6\n>>> \n");
+        // An incomplete expression is evaluated once the input ends.
+        do_test(
+            b"1 +",
+            ">>> ... Error happened: Ast build error! Operator Plus at pos 2 expects an operand, but gets End!\n>>> \n",
+        );
+        do_test(b"(1 +\n2)\n", ">>> ... <<< 3\n>>> \n");
+    }
+
+    #[test]
+    fn quit_test() {
+        do_test(b":quit\n1 + 2\n", ">>> ");
+        do_test(b"1\nexit\n2\n", ">>> <<< 1\n>>> ");
+    }
+
+    #[test]
+    fn prompt_test() {
+        do_test(
+            b":base hex\n255\n:base dec\n",
+            ">>> [hex] >>> <<< 0xFF\n[hex] >>> >>> \n",
+        );
+        do_test(b":prompt \"{base}> \"\n1\n", ">>> dec> <<< 1\ndec> \n");
+    }
+
+    #[test]
+    fn color_test() {
+        let mut session = Session {
+            color: ColorChoice::Always,
+            ..Session::default()
+        };
+        let mut output = Vec::new();
+        run(&b"2 * 0x1G\n"[..], &mut output, &mut session).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            ">>> 2 * 0x1G\n    \x1b[31m^^^^\x1b[0m\n\x1b[1mAst build error! Number 0x1G at pos 4 is malformed!\x1b[0m \x1b[2m[E001]\x1b[0m\n>>> \n"
+        );
+    }
+
+    #[test]
+    fn time_test() {
+        let mut output = Vec::new();
+        let input = &b":time\n1 + 2\nx = 4\n:time\n1 + 2\n"[..];
+        run(input, &mut output, &mut Session::default()).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.split(">>> ").collect();
+        assert_eq!(lines[1], "Timing is on.\n");
+        for (line, value) in [(lines[2], "3"), (lines[3], "x = 4")].iter() {
+            let timing = line.strip_prefix(&format!("<<< {} (scan ", value)).unwrap();
+            assert!(timing.contains(", parse ") && timing.contains(", eval "));
+            assert!(timing.ends_with(")\n"));
+        }
+        assert_eq!(lines[4], "Timing is off.\n");
+        assert_eq!(lines[5], "<<< 3\n");
+    }
+
+    #[test]
+    fn invalid_input_test() {
+        do_test(
+            b"\xff\xfe\n1\n",
+            ">>> Input reading error!\n>>> <<< 1\n>>> \n",
+        );
+    }
+}
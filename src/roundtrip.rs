@@ -0,0 +1,336 @@
+use super::ast::{parse, Node, NodePtr, TokenType};
+use super::builtins::BUILTINS;
+use super::context::Context;
+use super::evaluator::evaluate_tree;
+use super::random::Random;
+
+/// How far apart the values of a tree and of its printed and parsed copy may
+/// be.
+pub const EPSILON: f64 = 1e-9;
+
+const BINARY: &[TokenType] = &[
+    TokenType::Plus,
+    TokenType::Minus,
+    TokenType::Multiply,
+    TokenType::Divide,
+    TokenType::FloorDivide,
+    TokenType::Modulo,
+    TokenType::Power,
+    TokenType::And,
+    TokenType::Or,
+    TokenType::Less,
+    TokenType::LessEqual,
+    TokenType::Greater,
+    TokenType::GreaterEqual,
+    TokenType::Equal,
+    TokenType::NotEqual,
+];
+
+const UNARY: &[TokenType] = &[
+    TokenType::PrefixMinus,
+    TokenType::PrefixPlus,
+    TokenType::Factorial,
+    TokenType::DoubleFactorial,
+    TokenType::Percent,
+    TokenType::Bar,
+    TokenType::Not,
+];
+
+const NUMBERS: &[f64] = &[0., 1., 2., 3., 7., 10., 0.5, 2.25, 1e-3, 12345.678];
+
+/// Builds random trees of every operator for round-trip tests of the
+/// printer. Lambdas and history references aren't generated, they evaluate
+/// only inside calls like `sum` and in a session.
+#[derive(Debug, Clone)]
+pub struct Generator {
+    random: Random,
+    /// How many operators deep a tree can be.
+    pub max_depth: usize,
+    /// Leaves that aren't numbers, which the context of `check_round_trip`
+    /// must define.
+    pub variables: Vec<String>,
+    /// The functions that calls are made to, with their numbers of
+    /// arguments. Embedders add their own functions here.
+    pub functions: Vec<(String, usize)>,
+}
+
+impl Generator {
+    /// A generator of trees up to 4 operators deep, of the variables `x` and
+    /// `y` and of the pure builtins. The same seed gives the same trees.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            random: Random::new(seed),
+            max_depth: 4,
+            variables: vec![String::from("x"), String::from("y")],
+            functions: BUILTINS
+                .iter()
+                .filter(|builtin| builtin.pure)
+                .map(|builtin| (builtin.name.to_string(), builtin.arity.min()))
+                .collect(),
+        }
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        self.random.below(n)
+    }
+
+    pub fn tree(&mut self) -> Node {
+        self.tree_of_depth(self.max_depth)
+    }
+
+    fn leaf(&mut self) -> Node {
+        let token = match self.below(3) {
+            0 if !self.variables.is_empty() => {
+                let i = self.below(self.variables.len());
+                TokenType::Variable(self.variables[i].clone())
+            }
+            _ => TokenType::Number(NUMBERS[self.below(NUMBERS.len())]),
+        };
+        Node::new(token, None, None)
+    }
+
+    fn operand(&mut self, depth: usize) -> NodePtr {
+        Some(Box::new(self.tree_of_depth(depth - 1)))
+    }
+
+    fn tree_of_depth(&mut self, depth: usize) -> Node {
+        if depth == 0 || self.below(4) == 0 {
+            return self.leaf();
+        }
+        match self.below(5) {
+            0 if !self.functions.is_empty() => {
+                let i = self.below(self.functions.len());
+                let (name, arity) = self.functions[i].clone();
+                let args = (0..arity).map(|_| self.tree_of_depth(depth - 1)).collect();
+                Node::new(TokenType::Call(name, args), None, None)
+            }
+            0 | 1 => {
+                let op = UNARY[self.below(UNARY.len())].clone();
+                let operand = self.operand(depth);
+                Node::new(op, operand, None)
+            }
+            _ => {
+                let op = BINARY[self.below(BINARY.len())].clone();
+                let left = self.operand(depth);
+                let right = self.operand(depth);
+                Node::new(op, left, right)
+            }
+        }
+    }
+}
+
+/// Smaller trees like `node`, to look for a smaller one with the same
+/// failure: its operands, a number instead of it, and the trees with one
+/// operand made smaller.
+pub fn shrink(node: &Node) -> Vec<Node> {
+    let mut smaller = Vec::new();
+    match &node.token {
+        TokenType::Number(n) => {
+            if *n != n.trunc() {
+                smaller.push(Node::new(TokenType::Number(n.trunc()), None, None));
+            }
+            if *n != 0. {
+                smaller.push(Node::new(TokenType::Number(0.), None, None));
+            }
+            if *n > 1. {
+                smaller.push(Node::new(TokenType::Number(1.), None, None));
+            }
+            return smaller;
+        }
+        TokenType::Call(name, args) => {
+            smaller.extend(args.iter().cloned());
+            for (i, arg) in args.iter().enumerate() {
+                for arg in shrink(arg) {
+                    let mut args = args.clone();
+                    args[i] = arg;
+                    smaller.push(Node::new(TokenType::Call(name.clone(), args), None, None));
+                }
+            }
+        }
+        _ => {
+            for child in node.left.iter().chain(node.right.iter()) {
+                smaller.push((**child).clone());
+            }
+            if let Some(left) = &node.left {
+                for left in shrink(left) {
+                    let left = Some(Box::new(left));
+                    smaller.push(Node::new(node.token.clone(), left, node.right.clone()));
+                }
+            }
+            if let Some(right) = &node.right {
+                for right in shrink(right) {
+                    let right = Some(Box::new(right));
+                    smaller.push(Node::new(node.token.clone(), node.left.clone(), right));
+                }
+            }
+        }
+    }
+    smaller.insert(0, Node::new(TokenType::Number(1.), None, None));
+    smaller
+}
+
+/// Shrinks a tree that `check` fails for as long as a smaller one fails too,
+/// and returns the smallest one found with its failure.
+pub fn minimize(
+    node: Node,
+    error: String,
+    check: impl Fn(&Node) -> Result<(), String>,
+) -> (Node, String) {
+    let (mut node, mut error) = (node, error);
+    'shrinking: loop {
+        for smaller in shrink(&node) {
+            if let Err(smaller_error) = check(&smaller) {
+                node = smaller;
+                error = smaller_error;
+                continue 'shrinking;
+            }
+        }
+        return (node, error);
+    }
+}
+
+/// Prints `node`, parses the expression back and fails unless it's the same
+/// tree with the same value in `ctx`. Trees that fail to evaluate are fine as
+/// long as their copies fail too.
+pub fn check_round_trip(node: &Node, ctx: &Context) -> Result<(), String> {
+    let printed = node.to_string();
+    let parsed = parse(&printed).map_err(|err| format!("{} doesn't parse: {}", printed, err))?;
+    let original = Some(Box::new(node.clone()));
+    if parsed != original {
+        let reprinted = parsed.map_or_else(String::new, |parsed| parsed.explain());
+        return Err(format!(
+            "{} parses as {} instead of {}!",
+            printed,
+            reprinted,
+            node.explain()
+        ));
+    }
+    let value = evaluate_tree(&original, &printed, ctx)
+        .map(|value| value.as_f64())
+        .map_err(String::from);
+    let reparsed = evaluate_tree(&parsed, &printed, ctx)
+        .map(|value| value.as_f64())
+        .map_err(String::from);
+    match (value, reparsed) {
+        (Ok(a), Ok(b)) if a == b || a.is_nan() && b.is_nan() => Ok(()),
+        (Ok(a), Ok(b)) if (a - b).abs() <= EPSILON * a.abs().max(b.abs()).max(1.) => Ok(()),
+        // Domain errors like 0!! of a fraction.
+        (Err(_), Err(_)) => Ok(()),
+        (a, b) => Err(format!(
+            "{} evaluates to {:?} and back to {:?}!",
+            printed, a, b
+        )),
+    }
+}
+
+/// Checks `cases` trees of `generator` and reports the first one `check`
+/// fails for, shrunk to a minimal counterexample.
+pub fn run_cases(
+    generator: &mut Generator,
+    cases: usize,
+    check: impl Fn(&Node) -> Result<(), String>,
+) -> Result<(), String> {
+    for case in 1..=cases {
+        let node = generator.tree();
+        if let Err(error) = check(&node) {
+            let (node, error) = minimize(node, error, &check);
+            return Err(format!(
+                "Case {} fails, at its smallest for {}: {}",
+                case, node, error
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod roundtrip_tests {
+    use super::*;
+
+    fn context() -> Context {
+        let mut ctx = Context::new();
+        ctx.set_var("x", 1.5);
+        ctx.set_var("y", -4.);
+        ctx
+    }
+
+    fn build(expr: &str) -> Node {
+        *parse(expr).unwrap().unwrap()
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let ctx = context();
+        let mut generator = Generator::new(0x5eed);
+        assert_eq!(
+            run_cases(&mut generator, 2000, |node| check_round_trip(node, &ctx)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn generator_test() {
+        let mut generator = Generator::new(7);
+        generator.max_depth = 0;
+        generator.variables.clear();
+        for _ in 0..100 {
+            assert!(matches!(generator.tree().token, TokenType::Number(_)));
+        }
+
+        let mut generator = Generator::new(7);
+        generator.functions = vec![(String::from("f"), 3)];
+        let calls = (0..1000)
+            .filter(|_| generator.tree().to_string().contains("f("))
+            .count();
+        assert!(calls > 0);
+        // The same seed gives the same trees.
+        assert_eq!(Generator::new(3).tree(), Generator::new(3).tree());
+    }
+
+    #[test]
+    fn minimize_test() {
+        // A printer that forgot the parentheses of `^` would fail for any
+        // power with an operator on its right, which shrinks to `0 ^ (0 ^ 0)`.
+        let check = |node: &Node| {
+            if node.to_string().contains("^ (") {
+                Err(String::from("Parentheses!"))
+            } else {
+                Ok(())
+            }
+        };
+        let node = build("sin(x + 2.5 ^ (y * 3 ^ 2)) - 7");
+        assert_eq!(
+            minimize(node, String::from("Parentheses!"), check),
+            (build("0 ^ (0 ^ 0)"), String::from("Parentheses!"))
+        );
+
+        let mut generator = Generator::new(1);
+        let error = run_cases(&mut generator, 1000, |node| match node.token {
+            TokenType::Bar => Err(String::from("Bars!")),
+            _ => Ok(()),
+        });
+        assert!(matches!(error, Err(error) if error.ends_with("at its smallest for |0|: Bars!")));
+    }
+
+    #[test]
+    fn check_round_trip_test() {
+        let ctx = context();
+        assert_eq!(check_round_trip(&build("-(x + 1)^2 // 3!"), &ctx), Ok(()));
+        // Both fail to evaluate.
+        assert_eq!(check_round_trip(&build("z + 1"), &ctx), Ok(()));
+
+        // A negative number prints like a prefix minus.
+        let negative = Node::new(TokenType::Number(-2.), None, None);
+        let power = Node::new(
+            TokenType::Power,
+            Some(Box::new(negative)),
+            Some(Box::new(build("2"))),
+        );
+        assert_eq!(
+            check_round_trip(&power, &ctx),
+            Err(String::from(
+                "-2 ^ 2 parses as ((-2) ^ 2) instead of (-2 ^ 2)!"
+            ))
+        );
+    }
+}
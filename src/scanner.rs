@@ -1,8 +1,80 @@
+use std::fmt;
+use std::num::IntErrorKind;
+
+use super::ast::Ast;
+use super::error::Span;
+#[cfg(feature = "json")]
+use super::json::{number, string, Object};
+
+/// 2^53, the largest integer up to which every integer is an exact f64.
+const MAX_EXACT_INTEGER: u64 = 1 << 53;
+
+/// SI prefixes numbers can end in, with their powers of ten. `M` is mega,
+/// `m` is milli.
+const SI_SUFFIXES: &[(char, i32)] = &[
+    ('G', 9),
+    ('M', 6),
+    ('k', 3),
+    ('m', -3),
+    ('u', -6),
+    ('n', -9),
+];
+
+const SEPARATOR_ERROR: &str = "has a misplaced _";
+const DECIMAL_POINT_ERROR: &str = "has a '.', but the decimal separator is ','";
+const DECIMAL_COMMA_ERROR: &str = "is the decimal separator, arguments are separated by ';'";
+const HISTORY_ERROR: &str = "needs the number of a result, like $1";
+const TEXT_ERROR: &str = "has no closing quote";
+const UNKNOWN_CHAR_ERROR: &str = "is an unknown character";
+pub const SEMICOLON_ERROR: &str = "separates statements, arguments are separated by ','";
+
+/// Removes the `_` separators from a number literal. Every `_` has to be
+/// between two digits, so `1_000` is fine, but `_1`, `1_` and `1__0` aren't.
+fn without_separators(s: &str, is_digit: impl Fn(char) -> bool) -> Option<String> {
+    let chars: Vec<char> = s.chars().collect();
+    for (i, &c) in chars.iter().enumerate() {
+        if c == '_' {
+            let before = i > 0 && is_digit(chars[i - 1]);
+            let after = i + 1 < chars.len() && is_digit(chars[i + 1]);
+            if !before || !after {
+                return None;
+            }
+        }
+    }
+    Some(s.replace('_', ""))
+}
+
+/// Whether `c` can be in a name after its first char: a letter, an ASCII
+/// digit or `_`.
+pub fn is_name_char(c: char) -> bool {
+    c.is_alphabetic() || c.is_ascii_digit() || c == '_'
+}
+
+/// Whether `name` can be a variable: a letter or `_` followed by letters,
+/// digits and `_`, like `x_1`. A `_` before a digit starts a number.
+pub fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    let first = match chars.next() {
+        Some('_') => !name[1..].starts_with(|c: char| c.is_ascii_digit()),
+        Some(c) => c.is_alphabetic(),
+        None => false,
+    };
+    first && chars.all(is_name_char)
+}
+
 pub struct Scanner<'a> {
     expr: &'a str,
     iterator: std::iter::Peekable<std::str::CharIndices<'a>>,
     tokens: Vec<Token<'a>>,
+    /// The byte offset each token starts at, where `retokenize` can scan
+    /// again from.
+    starts: Vec<usize>,
     iter_index: usize,
+    si_suffixes: bool,
+    decimal_comma: bool,
+    /// Byte offset and char count up to which positions are counted, so that
+    /// every char is counted once.
+    counted: (usize, usize),
 }
 
 #[derive(PartialEq, Debug, Copy, Clone)]
@@ -13,14 +85,41 @@ pub enum TokenType<'a> {
     Minus,
     Multiplication,
     Division,
+    FloorDivision,
     Modulo,
     Power,
     Factorial,
+    DoubleFactorial,
     Comma,
+    /// `;`, which separates statements, or arguments with a decimal comma.
+    Semicolon,
     Lparen,
     Rparen,
     Equals,
+    EqualEqual,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
     Bar,
+    Arrow,
+    /// `√`, the square root.
+    Root,
+    /// The superscripts `²` and `³`.
+    Squared,
+    Cubed,
+    /// A number literal that can't be read, with the reason why.
+    Invalid(&'a str, &'static str),
+    /// A separator of the other decimal convention, a `$` that isn't a
+    /// result of the history or a string without its closing quote, with
+    /// the reason why.
+    Misplaced(&'a str, &'static str),
+    /// `$3`, the third result of the history.
+    History(usize),
+    /// `"HOME"`, a string in double quotes without them. Strings are only
+    /// arguments of functions like `env`, and have no escapes.
+    Text(&'a str),
 
     End,
     None,
@@ -28,67 +127,419 @@ pub enum TokenType<'a> {
 #[derive(PartialEq, Debug, Copy, Clone)]
 pub struct Token<'a> {
     pub t: TokenType<'a>,
+    /// Counted in chars rather than bytes, so positions stay right after
+    /// operators like `×`.
     pub pos: usize,
+    /// The position right after the last char of the token, so it covers
+    /// the chars from `pos` up to `end`.
+    pub end: usize,
+}
+
+impl<'a> Token<'a> {
+    pub fn new(t: TokenType<'a>, pos: usize, end: usize) -> Self {
+        Self { t, pos, end }
+    }
+
+    /// The chars of `expr`, the expression the token was scanned from, that
+    /// it covers.
+    pub fn text<'e>(&self, expr: &'e str) -> &'e str {
+        let byte = |pos: usize| expr.char_indices().nth(pos).map_or(expr.len(), |(i, _)| i);
+        &expr[byte(self.pos)..byte(self.end.max(self.pos))]
+    }
+}
+
+/// A number or a separator that can't be read, with the error the parser
+/// gives for it and the chars it covers.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ScanError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl ScanError {
+    /// The error of `token` if it's a number or a separator that can't be
+    /// read.
+    pub fn of(token: &Token) -> Option<Self> {
+        let err = match token.t {
+            TokenType::Invalid(text, reason) => Ast::invalid_number(text, reason, token.pos),
+            TokenType::Misplaced(text, reason) => Ast::misplaced_separator(text, reason, token.pos),
+            _ => return None,
+        };
+        Some(Self {
+            message: format!("Ast build error! {}", err),
+            span: Span::between(token.pos, token.end),
+        })
+    }
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[cfg(feature = "json")]
+impl<'a> TokenType<'a> {
+    /// The name of the kind of token in JSON.
+    pub fn json_name(&self) -> &'static str {
+        match self {
+            TokenType::Number(_) => "number",
+            TokenType::Str(_) => "name",
+            TokenType::Plus => "plus",
+            TokenType::Minus => "minus",
+            TokenType::Multiplication => "multiply",
+            TokenType::Division => "divide",
+            TokenType::FloorDivision => "floor_divide",
+            TokenType::Modulo => "modulo",
+            TokenType::Power => "power",
+            TokenType::Factorial => "factorial",
+            TokenType::DoubleFactorial => "double_factorial",
+            TokenType::Comma => "comma",
+            TokenType::Semicolon => "semicolon",
+            TokenType::Lparen => "lparen",
+            TokenType::Rparen => "rparen",
+            TokenType::Equals => "equals",
+            TokenType::EqualEqual => "equal_equal",
+            TokenType::NotEqual => "not_equal",
+            TokenType::Less => "less",
+            TokenType::LessEqual => "less_equal",
+            TokenType::Greater => "greater",
+            TokenType::GreaterEqual => "greater_equal",
+            TokenType::Bar => "bar",
+            TokenType::Arrow => "arrow",
+            TokenType::Root => "root",
+            TokenType::Squared => "squared",
+            TokenType::Cubed => "cubed",
+            TokenType::Invalid(..) => "invalid",
+            TokenType::Misplaced(..) => "misplaced",
+            TokenType::History(_) => "history",
+            TokenType::Text(_) => "text",
+            TokenType::End => "end",
+            TokenType::None => "none",
+        }
+    }
 }
 
+#[cfg(feature = "json")]
 impl<'a> Token<'a> {
-    pub fn new(t: TokenType<'a>, pos: usize) -> Self {
-        Self { t, pos }
+    /// The token as an object with its `type`, the chars of `expr` from
+    /// `start` up to `end` that it covers and their `text`, and the `value`
+    /// of a number or a string or the `index` of a result of the history.
+    pub fn to_json(&self, expr: &str) -> String {
+        let object = Object::new()
+            .field("type", string(self.t.json_name()))
+            .field("start", self.pos.to_string())
+            .field("end", self.end.to_string())
+            .field("text", string(self.text(expr)));
+        match self.t {
+            TokenType::Number(x) => object.field("value", number(x)),
+            TokenType::History(n) => object.field("index", n.to_string()),
+            TokenType::Text(text) => object.field("value", string(text)),
+            _ => object,
+        }
+        .to_string()
+    }
+}
+
+/// The tokens of `expr` as a JSON array of the objects of `Token::to_json`,
+/// for editors and other tools. A number or a separator that can't be read
+/// is an error.
+#[cfg(feature = "json")]
+pub fn tokens_to_json(expr: &str) -> Result<String, ScanError> {
+    let source = expr.to_string();
+    let mut scanner = Scanner::new(&source);
+    scanner.scan();
+    let mut tokens = Vec::new();
+    for token in scanner.get_tokens() {
+        if let Some(err) = ScanError::of(token) {
+            return Err(err);
+        }
+        if token.t != TokenType::End {
+            tokens.push(token.to_json(expr));
+        }
+    }
+    Ok(format!("[{}]", tokens.join(",")))
+}
+
+/// An edit of an expression, as a text field makes them: the bytes from
+/// `start` up to `end` are replaced by `text`. Typing a char is an edit of
+/// an empty range, deleting one an edit with an empty text.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub struct TextEdit<'e> {
+    pub start: usize,
+    pub end: usize,
+    pub text: &'e str,
+}
+
+impl<'e> TextEdit<'e> {
+    pub fn new(start: usize, end: usize, text: &'e str) -> Self {
+        Self { start, end, text }
+    }
+
+    /// The expression `expr` becomes with the edit.
+    pub fn apply(&self, expr: &str) -> String {
+        let mut edited =
+            String::with_capacity(expr.len() - (self.end - self.start) + self.text.len());
+        edited.push_str(&expr[..self.start]);
+        edited.push_str(self.text);
+        edited.push_str(&expr[self.end..]);
+        edited
     }
 }
 
 impl<'a> Scanner<'a> {
     pub fn new(expr: &'a String) -> Self {
         Self {
-            expr: expr,
+            expr,
             iterator: expr.char_indices().peekable(),
             tokens: Vec::new(),
+            starts: Vec::new(),
             iter_index: 0,
+            si_suffixes: false,
+            decimal_comma: false,
+            counted: (0, 0),
         }
     }
 
-    pub fn get_tokens(&self) -> Vec<Token> {
-        self.tokens.clone()
+    /// Lets numbers end in an SI prefix: `4.7k` is 4700 and `2m` is 0.002.
+    /// It's off by default, since `2m` otherwise means `2 * m`. A suffix only
+    /// counts if no letter follows it, so `2 max(1, 2)` is still a product.
+    pub fn set_si_suffixes(&mut self, enabled: bool) {
+        self.si_suffixes = enabled;
+    }
+
+    /// Reads `,` as the decimal separator and `;` as the argument separator,
+    /// so `max(1,5; 2,5)` is 2.5. `.` and a `,` outside a number are errors
+    /// then, as is `;` otherwise.
+    pub fn set_decimal_comma(&mut self, enabled: bool) {
+        self.decimal_comma = enabled;
+    }
+
+    /// Whether the scanned input has no tokens, e.g. a blank line or one
+    /// that is only a comment.
+    pub fn is_empty(&self) -> bool {
+        self.tokens.iter().all(|token| token.t == TokenType::End)
+    }
+
+    pub fn get_tokens(&self) -> &[Token<'a>] {
+        &self.tokens
+    }
+
+    /// The chars of the scanned input that `token` covers.
+    pub fn text(&self, token: &Token) -> &'a str {
+        token.text(self.expr)
+    }
+
+    /// Starts over with `expr`, keeping the settings and the buffer of
+    /// tokens, so scanning many expressions allocates it only once.
+    pub fn reset(&mut self, expr: &'a str) {
+        self.expr = expr;
+        self.iterator = expr.char_indices().peekable();
+        self.tokens.clear();
+        self.starts.clear();
+        self.iter_index = 0;
+        self.counted = (0, 0);
+    }
+
+    /// Scans `expr`, which `edit` made of the expression scanned last, and
+    /// gives the same tokens as scanning it from scratch, with the same
+    /// settings. Only the tokens around the edit are scanned again: those
+    /// before it are kept, and those after it are kept with their positions
+    /// shifted once scanning reaches one of them again. Returns how many
+    /// tokens it scanned.
+    pub fn retokenize(&mut self, expr: &'a str, edit: &TextEdit) -> usize {
+        debug_assert_eq!(
+            expr.len() + edit.end - edit.start,
+            self.expr.len() + edit.text.len()
+        );
+        if self.tokens.is_empty() {
+            self.reset(expr);
+            self.scan();
+            return self.tokens.len();
+        }
+
+        // A token can depend on the 2 chars after it, like `1` in `1,5`
+        // with a decimal comma, so one is only kept if the one after the
+        // next starts before the edit.
+        let kept = self
+            .starts
+            .partition_point(|&start| start < edit.start)
+            .saturating_sub(2);
+        let (offset, chars) = match kept {
+            0 => (0, 0),
+            _ => (self.starts[kept], self.tokens[kept].pos),
+        };
+        let old_tokens = self.tokens.split_off(kept);
+        let old_starts = self.starts.split_off(kept);
+
+        self.expr = expr;
+        self.iterator = expr.char_indices().peekable();
+        while self.iterator.next_if(|&(i, _)| i < offset).is_some() {}
+        self.counted = (offset, chars);
+        self.iter_index = 0;
+        let edited_end = edit.start + edit.text.len();
+        let mut rescanned = 0;
+        loop {
+            let (start, token) = self.get_next_token();
+            match token.t {
+                TokenType::End => {
+                    self.push(start, token);
+                    break;
+                }
+                TokenType::None => continue,
+                _ => (),
+            }
+            // After the edit, a token that starts where an old one did is
+            // followed by the same tokens too.
+            if start >= edited_end {
+                let old_start = start - edited_end + edit.end;
+                if let Ok(i) = old_starts.binary_search(&old_start) {
+                    let old_pos = old_tokens[i].pos;
+                    for (&old_start, &old) in old_starts[i..].iter().zip(&old_tokens[i..]) {
+                        let shifted = match old.t {
+                            TokenType::End => old,
+                            t => Token::new(
+                                t,
+                                old.pos - old_pos + token.pos,
+                                old.end - old_pos + token.pos,
+                            ),
+                        };
+                        self.push(old_start - edit.end + edited_end, shifted);
+                    }
+                    self.iterator = expr[expr.len()..].char_indices().peekable();
+                    break;
+                }
+            }
+            self.push(start, token);
+            rescanned += 1;
+        }
+        debug!(
+            "rescans {} of {} tokens of {:?}",
+            rescanned,
+            self.tokens.len(),
+            self.expr
+        );
+        rescanned
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Token<'a> {
         if self.iter_index >= self.tokens.len() {
-            Token::new(TokenType::End, 0)
+            Token::new(TokenType::End, 0, 0)
         } else {
             self.iter_index += 1;
-            self.tokens[self.iter_index - 1]
+            let token = self.tokens[self.iter_index - 1];
+            trace!("takes {:?} at pos {}", token.t, token.pos);
+            token
         }
     }
 
     pub fn peek(&self) -> Token<'a> {
         if self.iter_index >= self.tokens.len() {
-            Token::new(TokenType::End, 0)
+            Token::new(TokenType::End, 0, 0)
         } else {
             self.tokens[self.iter_index]
         }
     }
 
+    /// The token after the one `peek` returns.
+    pub fn peek_next(&self) -> Token<'a> {
+        self.peek_nth(1)
+    }
+
+    /// The token `n` places after the one `peek` returns, `End` past the
+    /// last one.
+    pub fn peek_nth(&self, n: usize) -> Token<'a> {
+        match self.tokens.get(self.iter_index + n) {
+            Some(token) => *token,
+            None => Token::new(TokenType::End, 0, 0),
+        }
+    }
+
+    /// The token `next` returned last, or `None` before the first one.
+    pub fn last(&self) -> Token<'a> {
+        match self.iter_index {
+            0 => Token::new(TokenType::None, 0, 0),
+            i => self.tokens[i - 1],
+        }
+    }
+
     pub fn scan(&mut self) {
+        // Tokens are mostly a char or two and a space, a guess that spares
+        // most of the regrowing.
+        self.tokens.reserve(self.expr.len() / 2 + 1);
+        self.starts.reserve(self.expr.len() / 2 + 1);
         loop {
-            let token = self.get_next_token();
+            let (start, token) = self.get_next_token();
 
             if token.t == TokenType::End {
-                self.tokens.push(Token::new(TokenType::End, 0));
+                self.push(start, token);
+                debug!("scans {} tokens of {:?}", self.tokens.len(), self.expr);
                 break;
             } else if token.t != TokenType::None {
-                self.tokens.push(token);
+                self.push(start, token);
+            }
+        }
+    }
+
+    fn push(&mut self, start: usize, token: Token<'a>) {
+        self.tokens.push(token);
+        self.starts.push(start);
+    }
+
+    /// Reads a `0x`, `0b` or `0o` literal, whose prefix starts at `index`.
+    fn take_radix_number(&mut self, index: usize, radix: u32) -> TokenType<'a> {
+        self.iterator.next();
+
+        let mut end = index + 2;
+        while let Some(&(i, c)) = self.iterator.peek() {
+            if !c.is_alphanumeric() && c != '_' {
+                break;
+            }
+            end = i + c.len_utf8();
+            self.iterator.next();
+        }
+
+        let s = &self.expr[index..end];
+        let digits = match without_separators(&s[2..], |c| c.is_digit(radix)) {
+            Some(digits) => digits,
+            None => return TokenType::Invalid(s, SEPARATOR_ERROR),
+        };
+        match u64::from_str_radix(&digits, radix) {
+            Ok(n) if n <= MAX_EXACT_INTEGER => TokenType::Number(n as f64),
+            Ok(_) => TokenType::Invalid(s, "is too large to be exact"),
+            Err(err) if *err.kind() == IntErrorKind::PosOverflow => {
+                TokenType::Invalid(s, "is too large to be exact")
             }
+            Err(_) => TokenType::Invalid(s, "is malformed"),
         }
     }
 
     fn take_number(&mut self, index: usize) -> TokenType<'a> {
+        if self.expr[index..].starts_with('0') {
+            let radix = match self.iterator.peek() {
+                Some((_, 'x')) => 16,
+                Some((_, 'b')) => 2,
+                Some((_, 'o')) => 8,
+                _ => 0,
+            };
+            if radix != 0 {
+                return self.take_radix_number(index, radix);
+            }
+        }
+
+        let expr = self.expr;
         let start = index;
         let mut end = index;
         loop {
             match self.iterator.peek() {
                 Option::None => break,
                 Option::Some(d) => {
-                    if d.1.is_numeric() || d.1 == '.' || d.1 == 'E' {
+                    // A comma only belongs to the number if a digit follows.
+                    let comma = self.decimal_comma
+                        && d.1 == ','
+                        && matches!(expr[d.0 + 1..].chars().next(), Some('0'..='9'));
+                    if d.1.is_ascii_digit() || d.1 == '.' || d.1 == '_' || comma {
                         end = d.0;
                     } else {
                         break;
@@ -97,23 +548,104 @@ impl<'a> Scanner<'a> {
             };
             self.iterator.next();
         }
+        let exponent = self.exponent_len(end + 1);
+        for _ in 0..exponent {
+            self.iterator.next();
+        }
+        end += exponent;
 
         let s = &self.expr[start..(end + 1)];
-        match s.parse::<f64>() {
-            Result::Ok(n) => return TokenType::Number(n),
-            _ => panic!("Wrong format number!"),
+        let digits = match without_separators(s, |c| c.is_ascii_digit()) {
+            Some(_) if self.decimal_comma && s.contains('.') => {
+                return TokenType::Invalid(s, DECIMAL_POINT_ERROR)
+            }
+            Some(digits) => digits.replace(',', "."),
+            None => return TokenType::Invalid(s, SEPARATOR_ERROR),
+        };
+        let digits = match self.take_si_suffix(end + 1) {
+            // Parse the scaled number, 4.7E3 is exactly 4700 unlike 4.7 * 1000.
+            Some(exponent) if !digits.contains(['E', 'e']) => {
+                format!("{}E{}", digits, exponent)
+            }
+            Some(exponent) => {
+                return match digits.parse::<f64>() {
+                    Result::Ok(n) => TokenType::Number(n * 10f64.powi(exponent)),
+                    _ => TokenType::Invalid(s, "is malformed"),
+                }
+            }
+            None => digits,
+        };
+        match digits.parse::<f64>() {
+            Result::Ok(n) => TokenType::Number(n),
+            _ => TokenType::Invalid(s, "is malformed"),
+        }
+    }
+
+    /// The length in bytes of the exponent at byte `index`, like `E5`, `e-7`
+    /// or `E+1_0`, 0 if there's none. A lowercase `e` without digits after it
+    /// is the constant, so `2e` is `2 * e` and `2e_1` is `2 * e_1`.
+    fn exponent_len(&self, index: usize) -> usize {
+        let rest = &self.expr.as_bytes()[index..];
+        let upper = match rest.first() {
+            Some(b'E') => true,
+            Some(b'e') => false,
+            _ => return 0,
+        };
+        let sign = matches!(rest.get(1), Some(b'+') | Some(b'-')) as usize;
+        if !rest.get(1 + sign).is_some_and(u8::is_ascii_digit) {
+            // `1E` is still read as a malformed number.
+            return upper as usize;
+        }
+        1 + sign
+            + rest[1 + sign..]
+                .iter()
+                .take_while(|&&c| c.is_ascii_digit() || c == b'_')
+                .count()
+    }
+
+    /// Takes the SI suffix at byte `index` and returns its power of ten.
+    fn take_si_suffix(&mut self, index: usize) -> Option<i32> {
+        if !self.si_suffixes {
+            return None;
+        }
+
+        let mut rest = self.expr[index..].chars();
+        let exponent = rest
+            .next()
+            .and_then(|c| SI_SUFFIXES.iter().find(|(suffix, _)| *suffix == c))
+            .map(|(_, exponent)| *exponent)?;
+        match rest.next() {
+            Some(c) if c.is_alphanumeric() || c == '_' => None,
+            _ => {
+                self.iterator.next();
+                Some(exponent)
+            }
+        }
+    }
+
+    /// Skips a `#` comment up to the end of the line.
+    fn skip_comment(&mut self) {
+        while let Some(&(_, c)) = self.iterator.peek() {
+            if c == '\n' {
+                break;
+            }
+            self.iterator.next();
         }
     }
 
     fn take_str(&mut self, index: usize) -> TokenType<'a> {
         let start = index;
-        let mut end = index;
+        // Letters can take more than one byte, so `end` is exclusive here.
+        let mut end = index + self.expr[index..].chars().next().map_or(0, char::len_utf8);
         loop {
             match self.iterator.peek() {
                 Option::None => break,
                 Option::Some(d) => {
-                    if d.1.is_alphabetic() {
-                        end = d.0;
+                    // `_` joins words, like `pi_approx`, and digits can
+                    // follow the first char, like `log2`. `x²` is still a
+                    // square.
+                    if is_name_char(d.1) {
+                        end = d.0 + d.1.len_utf8();
                     } else {
                         break;
                     }
@@ -122,51 +654,155 @@ impl<'a> Scanner<'a> {
             self.iterator.next();
         }
 
-        let s = &self.expr[start..(end + 1)];
-        return TokenType::Str(s);
+        TokenType::Str(&self.expr[start..end])
+    }
+
+    /// Reads `$` and the number of a result, whose `$` is at `index`.
+    fn take_history(&mut self, index: usize) -> TokenType<'a> {
+        let mut end = index + 1;
+        while let Some(&(i, c)) = self.iterator.peek() {
+            if !c.is_ascii_digit() {
+                break;
+            }
+            end = i + 1;
+            self.iterator.next();
+        }
+
+        let s = &self.expr[index..end];
+        match s[1..].parse::<usize>() {
+            Ok(0) => TokenType::Misplaced(s, "isn't a result, the first one is $1"),
+            Ok(n) => TokenType::History(n),
+            Err(_) if end == index + 1 => TokenType::Misplaced(s, HISTORY_ERROR),
+            Err(_) => TokenType::Misplaced(s, "is too large"),
+        }
+    }
+
+    /// Reads a string up to its closing quote, whose opening `"` is at
+    /// `index`.
+    fn take_text(&mut self, index: usize) -> TokenType<'a> {
+        for (i, c) in self.iterator.by_ref() {
+            if c == '"' {
+                return TokenType::Text(&self.expr[index + 1..i]);
+            }
+        }
+        TokenType::Misplaced(&self.expr[index..], TEXT_ERROR)
     }
 
-    fn get_next_token(&mut self) -> Token<'a> {
+    /// The position in chars of the byte `offset`. Tokens are scanned from
+    /// left to right, so only the chars since the last token are counted.
+    fn char_pos(&mut self, offset: usize) -> usize {
+        let (counted_offset, counted_chars) = self.counted;
+        let chars = counted_chars + self.expr[counted_offset..offset].chars().count();
+        self.counted = (offset, chars);
+        chars
+    }
+
+    /// The next token and the byte offset it starts at.
+    fn get_next_token(&mut self) -> (usize, Token<'a>) {
         let oc = match self.iterator.next() {
-            Option::None => return Token::new(TokenType::End, 0),
+            Option::None => return (self.expr.len(), Token::new(TokenType::End, 0, 0)),
             Option::Some(c) => c,
         };
+        let pos = self.char_pos(oc.0);
 
-        let tokenType = match oc.1 {
+        let token_type = match oc.1 {
             '+' => TokenType::Plus,
-            '-' => TokenType::Minus,
-            '/' => TokenType::Division,
+            '-' => match self.iterator.peek() {
+                Option::Some((_, '>')) => {
+                    self.iterator.next();
+                    TokenType::Arrow
+                }
+                _ => TokenType::Minus,
+            },
+            '/' => match self.iterator.peek() {
+                Option::Some((_, '/')) => {
+                    self.iterator.next();
+                    TokenType::FloorDivision
+                }
+                _ => TokenType::Division,
+            },
             '%' => TokenType::Modulo,
             '^' => TokenType::Power,
-            '!' => TokenType::Factorial,
+            '$' => self.take_history(oc.0),
+            '"' => self.take_text(oc.0),
+            '!' => match self.iterator.peek() {
+                Option::Some((_, '!')) => {
+                    self.iterator.next();
+                    TokenType::DoubleFactorial
+                }
+                Option::Some((_, '=')) => {
+                    self.iterator.next();
+                    TokenType::NotEqual
+                }
+                _ => TokenType::Factorial,
+            },
+            ',' if self.decimal_comma => TokenType::Misplaced(",", DECIMAL_COMMA_ERROR),
             ',' => TokenType::Comma,
+            ';' if self.decimal_comma => TokenType::Comma,
+            ';' => TokenType::Semicolon,
             '(' => TokenType::Lparen,
             ')' => TokenType::Rparen,
             '[' => TokenType::Lparen,
             ']' => TokenType::Rparen,
-            '=' => TokenType::Equals,
+            '=' => match self.iterator.peek() {
+                Option::Some((_, '=')) => {
+                    self.iterator.next();
+                    TokenType::EqualEqual
+                }
+                _ => TokenType::Equals,
+            },
+            '<' => match self.iterator.peek() {
+                Option::Some((_, '=')) => {
+                    self.iterator.next();
+                    TokenType::LessEqual
+                }
+                _ => TokenType::Less,
+            },
+            '>' => match self.iterator.peek() {
+                Option::Some((_, '=')) => {
+                    self.iterator.next();
+                    TokenType::GreaterEqual
+                }
+                _ => TokenType::Greater,
+            },
             '|' => TokenType::Bar,
             '*' => match self.iterator.peek() {
-                Option::None => return Token::new(TokenType::Multiplication, oc.0),
-                Option::Some(d) => match d.1 {
-                    '*' => {
-                        self.iterator.next();
-                        TokenType::Power
-                    }
-                    _ => TokenType::Multiplication,
-                },
+                Option::Some((_, '*')) => {
+                    self.iterator.next();
+                    TokenType::Power
+                }
+                _ => TokenType::Multiplication,
             },
+            // Typographic operators, as pasted from documents.
+            '×' | '·' => TokenType::Multiplication,
+            '÷' => TokenType::Division,
+            '−' => TokenType::Minus,
+            // Any whitespace only separates tokens, including the `\r\n` a
+            // line is read with and no-break or full-width spaces.
+            c if c.is_whitespace() => TokenType::None,
+            '#' => {
+                self.skip_comment();
+                TokenType::None
+            }
+            '√' => TokenType::Root,
+            '²' => TokenType::Squared,
+            '³' => TokenType::Cubed,
             _ => {
-                if oc.1.is_numeric() || oc.1 == '.' {
+                // A leading `_` is read as part of the number to report it.
+                let separated = oc.1 == '_' && matches!(self.iterator.peek(), Some((_, '0'..='9')));
+                // Only ASCII digits, `²` is numeric too.
+                if oc.1.is_ascii_digit() || oc.1 == '.' || separated {
                     self.take_number(oc.0)
-                } else if oc.1.is_alphabetic() {
+                } else if oc.1.is_alphabetic() || oc.1 == '_' {
                     self.take_str(oc.0)
                 } else {
-                    TokenType::None
+                    let text = &self.expr[oc.0..oc.0 + oc.1.len_utf8()];
+                    TokenType::Misplaced(text, UNKNOWN_CHAR_ERROR)
                 }
             }
         };
-        Token::new(tokenType, oc.0)
+        let end = self.iterator.peek().map_or(self.expr.len(), |&(i, _)| i);
+        (oc.0, Token::new(token_type, pos, self.char_pos(end)))
     }
 }
 
@@ -191,134 +827,899 @@ mod scanner_tests {
     }
 
     #[test]
-    fn very_primitive_tests() {
-        do_test("", vec![TokenType::End]);
-
-        do_test("+", vec![TokenType::Plus, TokenType::End]);
-        do_test("-", vec![TokenType::Minus, TokenType::End]);
-        do_test("/", vec![TokenType::Division, TokenType::End]);
-        do_test("*", vec![TokenType::Multiplication, TokenType::End]);
-
-        do_test("1", vec![TokenType::Number(1.), TokenType::End]);
-        do_test("103", vec![TokenType::Number(103.), TokenType::End]);
+    fn radix_number_tests() {
+        do_test("0xFF", vec![TokenType::Number(255.), TokenType::End]);
         do_test(
-            "103 + 1",
+            "0xff + 0x1A",
             vec![
-                TokenType::Number(103.),
+                TokenType::Number(255.),
                 TokenType::Plus,
-                TokenType::Number(1.),
+                TokenType::Number(26.),
                 TokenType::End,
             ],
         );
+        do_test("0b1010", vec![TokenType::Number(10.), TokenType::End]);
+        do_test("0o17", vec![TokenType::Number(15.), TokenType::End]);
+        do_test("0", vec![TokenType::Number(0.), TokenType::End]);
+        do_test("0.5", vec![TokenType::Number(0.5), TokenType::End]);
         do_test(
-            "+103+1",
-            vec![
-                TokenType::Plus,
-                TokenType::Number(103.),
-                TokenType::Plus,
-                TokenType::Number(1.),
-                TokenType::End,
-            ],
+            "0x20000000000000",
+            vec![TokenType::Number(9007199254740992.), TokenType::End],
         );
     }
 
     #[test]
-    fn easy_tests() {
+    fn digit_separator_tests() {
         do_test(
-            "321.23 * 10.23",
+            "1_000_000 * 2",
             vec![
-                TokenType::Number(321.23),
+                TokenType::Number(1e6),
                 TokenType::Multiplication,
-                TokenType::Number(10.23),
+                TokenType::Number(2.),
                 TokenType::End,
             ],
         );
+        do_test("0.000_001", vec![TokenType::Number(1e-6), TokenType::End]);
+        do_test("1_000E1_0", vec![TokenType::Number(1e13), TokenType::End]);
+        do_test("1_000e1_0", vec![TokenType::Number(1e13), TokenType::End]);
+        do_test("2.5e-1_0", vec![TokenType::Number(2.5e-10), TokenType::End]);
+        do_test("0xFF_FF", vec![TokenType::Number(65535.), TokenType::End]);
+        do_test("0b1010_1010", vec![TokenType::Number(170.), TokenType::End]);
+    }
+
+    #[test]
+    fn exponent_tests() {
+        do_test("1.2e-7", vec![TokenType::Number(1.2e-7), TokenType::End]);
         do_test(
-            "+2 - 3 / 6 * 7",
+            "6.022e23",
+            vec![TokenType::Number(6.022e23), TokenType::End],
+        );
+        do_test("1E-7", vec![TokenType::Number(1e-7), TokenType::End]);
+        do_test("1E+2", vec![TokenType::Number(100.), TokenType::End]);
+        do_test(
+            "2e - 1",
             vec![
-                TokenType::Plus,
                 TokenType::Number(2.),
+                TokenType::Str("e"),
                 TokenType::Minus,
-                TokenType::Number(3.),
-                TokenType::Division,
-                TokenType::Number(6.),
-                TokenType::Multiplication,
-                TokenType::Number(7.),
+                TokenType::Number(1.),
                 TokenType::End,
             ],
         );
         do_test(
-            "(123.20 + 1.21) * 40",
+            "2e_1",
+            vec![TokenType::Number(2.), TokenType::Str("e_1"), TokenType::End],
+        );
+        do_test(
+            "1E",
+            vec![TokenType::Invalid("1E", "is malformed"), TokenType::End],
+        );
+    }
+
+    #[test]
+    fn unicode_operator_tests() {
+        do_test(
+            "6 ÷ 2 × (1 + 2)",
             vec![
+                TokenType::Number(6.),
+                TokenType::Division,
+                TokenType::Number(2.),
+                TokenType::Multiplication,
                 TokenType::Lparen,
-                TokenType::Number(123.20),
+                TokenType::Number(1.),
                 TokenType::Plus,
-                TokenType::Number(1.21),
+                TokenType::Number(2.),
                 TokenType::Rparen,
+                TokenType::End,
+            ],
+        );
+        do_test(
+            "−3·x - 1",
+            vec![
+                TokenType::Minus,
+                TokenType::Number(3.),
                 TokenType::Multiplication,
-                TokenType::Number(40.),
+                TokenType::Str("x"),
+                TokenType::Minus,
+                TokenType::Number(1.),
                 TokenType::End,
             ],
         );
     }
 
     #[test]
-    fn string_fetch_tests() {
+    fn text_tests() {
         do_test(
-            "max()",
+            "env(\"THREADS\") * 2",
             vec![
-                TokenType::Str("max"),
+                TokenType::Str("env"),
                 TokenType::Lparen,
+                TokenType::Text("THREADS"),
                 TokenType::Rparen,
+                TokenType::Multiplication,
+                TokenType::Number(2.),
                 TokenType::End,
             ],
         );
+        // Anything goes between the quotes, but another quote.
         do_test(
-            "max(1, 2)",
+            "\"\" \"a, # (b)\"",
             vec![
-                TokenType::Str("max"),
-                TokenType::Lparen,
-                TokenType::Number(1.),
-                TokenType::Comma,
-                TokenType::Number(2.),
-                TokenType::Rparen,
+                TokenType::Text(""),
+                TokenType::Text("a, # (b)"),
                 TokenType::End,
             ],
         );
         do_test(
-            "max()",
+            "f(\"x) + 1",
             vec![
-                TokenType::Str("max"),
+                TokenType::Str("f"),
                 TokenType::Lparen,
-                TokenType::Rparen,
-                TokenType::End,
+                TokenType::Misplaced("\"x) + 1", TEXT_ERROR),
             ],
         );
+    }
+
+    #[test]
+    fn history_tests() {
         do_test(
-            "max(min(3, 2), someFunc(4))",
+            "$1 + $23",
             vec![
-                TokenType::Str("max"),
-                TokenType::Lparen,
-                TokenType::Str("min"),
-                TokenType::Lparen,
-                TokenType::Number(3.),
-                TokenType::Comma,
-                TokenType::Number(2.),
-                TokenType::Rparen,
-                TokenType::Comma,
-                TokenType::Str("someFunc"),
-                TokenType::Lparen,
-                TokenType::Number(4.),
-                TokenType::Rparen,
-                TokenType::Rparen,
+                TokenType::History(1),
+                TokenType::Plus,
+                TokenType::History(23),
                 TokenType::End,
             ],
         );
-    }
-
+        do_test(
+            "$0",
+            vec![TokenType::Misplaced(
+                "$0",
+                "isn't a result, the first one is $1",
+            )],
+        );
+        do_test(
+            "$-1",
+            vec![TokenType::Misplaced("$", HISTORY_ERROR), TokenType::Minus],
+        );
+        do_test(
+            "$99999999999999999999999",
+            vec![TokenType::Misplaced(
+                "$99999999999999999999999",
+                "is too large",
+            )],
+        );
+    }
+
+    #[test]
+    fn underscore_name_tests() {
+        do_test(
+            "pi_approx * 2",
+            vec![
+                TokenType::Str("pi_approx"),
+                TokenType::Multiplication,
+                TokenType::Number(2.),
+                TokenType::End,
+            ],
+        );
+    }
+
+    #[test]
+    fn digit_name_tests() {
+        do_test(
+            "log2(8) + x_1 * _tmp - a1b2",
+            vec![
+                TokenType::Str("log2"),
+                TokenType::Lparen,
+                TokenType::Number(8.),
+                TokenType::Rparen,
+                TokenType::Plus,
+                TokenType::Str("x_1"),
+                TokenType::Multiplication,
+                TokenType::Str("_tmp"),
+                TokenType::Minus,
+                TokenType::Str("a1b2"),
+                TokenType::End,
+            ],
+        );
+        // A digit still starts a number, which a name can follow.
+        do_test(
+            "2a + 2pi",
+            vec![
+                TokenType::Number(2.),
+                TokenType::Str("a"),
+                TokenType::Plus,
+                TokenType::Number(2.),
+                TokenType::Str("pi"),
+                TokenType::End,
+            ],
+        );
+        do_test(
+            "x²",
+            vec![TokenType::Str("x"), TokenType::Squared, TokenType::End],
+        );
+        do_test(
+            "_1",
+            vec![
+                TokenType::Invalid("_1", "has a misplaced _"),
+                TokenType::End,
+            ],
+        );
+    }
+
+    #[test]
+    fn root_and_superscript_tests() {
+        do_test(
+            "√25 + 5²³",
+            vec![
+                TokenType::Root,
+                TokenType::Number(25.),
+                TokenType::Plus,
+                TokenType::Number(5.),
+                TokenType::Squared,
+                TokenType::Cubed,
+                TokenType::End,
+            ],
+        );
+    }
+
+    #[test]
+    fn whitespace_tests() {
+        let expected = vec![
+            TokenType::Number(1.),
+            TokenType::Plus,
+            TokenType::Number(2.),
+            TokenType::End,
+        ];
+        do_test("1 + 2\r\n", expected.clone());
+        do_test("\t1\t+\t2\n", expected.clone());
+        do_test("1 +\u{a0}2", expected.clone());
+        do_test("1\u{3000}+\u{2003}2\u{a0}", expected);
+
+        let s = String::from("\t1 +\u{a0}\u{3000}2\r\n");
+        let mut scanner = Scanner::new(&s);
+        scanner.scan();
+
+        let positions: Vec<usize> = scanner.get_tokens().iter().map(|token| token.pos).collect();
+        assert_eq!(&positions[..3], &[1, 3, 6]);
+
+        // Any other char is an error rather than skipped.
+        do_test(
+            "1 @ 2",
+            vec![
+                TokenType::Number(1.),
+                TokenType::Misplaced("@", UNKNOWN_CHAR_ERROR),
+                TokenType::Number(2.),
+                TokenType::End,
+            ],
+        );
+    }
+
+    #[test]
+    fn comment_tests() {
+        do_test(
+            "2 * pi * r  # circumference",
+            vec![
+                TokenType::Number(2.),
+                TokenType::Multiplication,
+                TokenType::Str("pi"),
+                TokenType::Multiplication,
+                TokenType::Str("r"),
+                TokenType::End,
+            ],
+        );
+        do_test(
+            "1 + 2 # ) 3 ( 0x",
+            vec![
+                TokenType::Number(1.),
+                TokenType::Plus,
+                TokenType::Number(2.),
+                TokenType::End,
+            ],
+        );
+        do_test(
+            "1 + # one\n2",
+            vec![
+                TokenType::Number(1.),
+                TokenType::Plus,
+                TokenType::Number(2.),
+                TokenType::End,
+            ],
+        );
+
+        for expr in ["# only a comment", "  #\r\n", ""].iter() {
+            let s = expr.to_string();
+            let mut scanner = Scanner::new(&s);
+            scanner.scan();
+            assert!(scanner.is_empty());
+        }
+
+        let s = String::from("x - 1 # y");
+        let mut scanner = Scanner::new(&s);
+        scanner.scan();
+        assert!(!scanner.is_empty());
+
+        let positions: Vec<usize> = scanner.get_tokens().iter().map(|token| token.pos).collect();
+        assert_eq!(&positions[..3], &[0, 2, 4]);
+    }
+
+    #[test]
+    fn unicode_position_tests() {
+        let s = String::from("2×÷ é + 3");
+        let mut scanner = Scanner::new(&s);
+        scanner.scan();
+
+        let positions: Vec<usize> = scanner.get_tokens().iter().map(|token| token.pos).collect();
+        assert_eq!(&positions[..6], &[0, 1, 2, 4, 6, 8]);
+        assert_eq!(scanner.get_tokens()[3].t, TokenType::Str("é"));
+    }
+
+    #[test]
+    fn span_tests() {
+        let s = String::from("sqrt(12.5E3) // x_y ** 2×3!!");
+        let mut scanner = Scanner::new(&s);
+        scanner.scan();
+
+        let spans: Vec<(usize, usize)> = scanner
+            .get_tokens()
+            .iter()
+            .map(|token| (token.pos, token.end))
+            .collect();
+        assert_eq!(
+            spans,
+            [
+                (0, 4),
+                (4, 5),
+                (5, 11),
+                (11, 12),
+                (13, 15),
+                (16, 19),
+                (20, 22),
+                (23, 24),
+                (24, 25),
+                (25, 26),
+                (26, 28),
+                (0, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn long_input_position_tests() {
+        let s = "1 × ".repeat(20_000) + "1";
+        let mut scanner = Scanner::new(&s);
+        scanner.scan();
+
+        let tokens = scanner.get_tokens();
+        assert_eq!(tokens.len(), 40_002);
+        assert_eq!(tokens[39_999].pos, 79_998);
+        assert_eq!(tokens[40_000].pos, 80_000);
+    }
+
+    fn do_si_test(expr: &str, expected: Vec<TokenType>) {
+        let s = expr.to_string();
+        let mut scanner = Scanner::new(&s);
+        scanner.set_si_suffixes(true);
+        scanner.scan();
+
+        let result: Vec<TokenType> = scanner.get_tokens().iter().map(|token| token.t).collect();
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn si_suffix_tests() {
+        do_si_test("4.7k", vec![TokenType::Number(4700.), TokenType::End]);
+        do_si_test("3M", vec![TokenType::Number(3e6), TokenType::End]);
+        do_si_test("2G", vec![TokenType::Number(2e9), TokenType::End]);
+        do_si_test("2m", vec![TokenType::Number(0.002), TokenType::End]);
+        do_si_test("220u", vec![TokenType::Number(2.2e-4), TokenType::End]);
+        do_si_test("5n", vec![TokenType::Number(5e-9), TokenType::End]);
+        do_si_test("1_5k", vec![TokenType::Number(15000.), TokenType::End]);
+        do_si_test("1E2k", vec![TokenType::Number(1e5), TokenType::End]);
+        do_si_test(
+            "4.7k * 2m",
+            vec![
+                TokenType::Number(4700.),
+                TokenType::Multiplication,
+                TokenType::Number(0.002),
+                TokenType::End,
+            ],
+        );
+        do_si_test(
+            "2m(3)",
+            vec![
+                TokenType::Number(0.002),
+                TokenType::Lparen,
+                TokenType::Number(3.),
+                TokenType::Rparen,
+                TokenType::End,
+            ],
+        );
+    }
+
+    fn do_decimal_comma_test(expr: &str, expected: Vec<TokenType>) {
+        let s = expr.to_string();
+        let mut scanner = Scanner::new(&s);
+        scanner.set_decimal_comma(true);
+        scanner.scan();
+
+        let result: Vec<TokenType> = scanner.get_tokens().iter().map(|token| token.t).collect();
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn decimal_comma_tests() {
+        do_decimal_comma_test("2,75", vec![TokenType::Number(2.75), TokenType::End]);
+        do_decimal_comma_test("1_000,5", vec![TokenType::Number(1000.5), TokenType::End]);
+        do_decimal_comma_test("2,5E3", vec![TokenType::Number(2500.), TokenType::End]);
+        do_decimal_comma_test(
+            "max(1,5; 2,5)",
+            vec![
+                TokenType::Str("max"),
+                TokenType::Lparen,
+                TokenType::Number(1.5),
+                TokenType::Comma,
+                TokenType::Number(2.5),
+                TokenType::Rparen,
+                TokenType::End,
+            ],
+        );
+        do_decimal_comma_test(
+            "1, 5",
+            vec![
+                TokenType::Number(1.),
+                TokenType::Misplaced(",", DECIMAL_COMMA_ERROR),
+                TokenType::Number(5.),
+                TokenType::End,
+            ],
+        );
+        do_decimal_comma_test(
+            "3.14",
+            vec![
+                TokenType::Invalid("3.14", DECIMAL_POINT_ERROR),
+                TokenType::End,
+            ],
+        );
+        do_decimal_comma_test(
+            "1,5,3",
+            vec![TokenType::Invalid("1,5,3", "is malformed"), TokenType::End],
+        );
+        do_test(
+            "1;2",
+            vec![
+                TokenType::Number(1.),
+                TokenType::Semicolon,
+                TokenType::Number(2.),
+                TokenType::End,
+            ],
+        );
+        do_test(
+            "1,5",
+            vec![
+                TokenType::Number(1.),
+                TokenType::Comma,
+                TokenType::Number(5.),
+                TokenType::End,
+            ],
+        );
+    }
+
+    #[test]
+    fn si_suffix_boundary_tests() {
+        do_si_test(
+            "2 max",
+            vec![TokenType::Number(2.), TokenType::Str("max"), TokenType::End],
+        );
+        do_si_test(
+            "2max",
+            vec![TokenType::Number(2.), TokenType::Str("max"), TokenType::End],
+        );
+        do_si_test(
+            "2mm",
+            vec![TokenType::Number(2.), TokenType::Str("mm"), TokenType::End],
+        );
+        do_si_test(
+            "2x",
+            vec![TokenType::Number(2.), TokenType::Str("x"), TokenType::End],
+        );
+        do_test(
+            "2m",
+            vec![TokenType::Number(2.), TokenType::Str("m"), TokenType::End],
+        );
+    }
+
+    #[test]
+    fn misplaced_digit_separator_tests() {
+        let misplaced = |s| TokenType::Invalid(s, "has a misplaced _");
+
+        do_test("_100", vec![misplaced("_100"), TokenType::End]);
+        do_test("100_", vec![misplaced("100_"), TokenType::End]);
+        do_test("1__0", vec![misplaced("1__0"), TokenType::End]);
+        do_test("1_.5", vec![misplaced("1_.5"), TokenType::End]);
+        do_test("1_E5", vec![misplaced("1_E5"), TokenType::End]);
+        do_test("1e1_", vec![misplaced("1e1_"), TokenType::End]);
+        do_test("0x_FF", vec![misplaced("0x_FF"), TokenType::End]);
+        do_test(
+            "2 + 3_",
+            vec![
+                TokenType::Number(2.),
+                TokenType::Plus,
+                misplaced("3_"),
+                TokenType::End,
+            ],
+        );
+    }
+
+    #[test]
+    fn invalid_number_tests() {
+        let malformed = |s| TokenType::Invalid(s, "is malformed");
+        let too_large = |s| TokenType::Invalid(s, "is too large to be exact");
+
+        do_test("0x", vec![malformed("0x"), TokenType::End]);
+        do_test("0b102", vec![malformed("0b102"), TokenType::End]);
+        do_test(
+            "0o8 + 1",
+            vec![
+                malformed("0o8"),
+                TokenType::Plus,
+                TokenType::Number(1.),
+                TokenType::End,
+            ],
+        );
+        do_test("0xFG", vec![malformed("0xFG"), TokenType::End]);
+        do_test("1.2.3", vec![malformed("1.2.3"), TokenType::End]);
+        do_test(
+            "0x20000000000001",
+            vec![too_large("0x20000000000001"), TokenType::End],
+        );
+        do_test(
+            "0x1FFFFFFFFFFFFFFFF",
+            vec![too_large("0x1FFFFFFFFFFFFFFFF"), TokenType::End],
+        );
+    }
+
+    #[test]
+    fn very_primitive_tests() {
+        do_test("", vec![TokenType::End]);
+
+        do_test("+", vec![TokenType::Plus, TokenType::End]);
+        do_test("-", vec![TokenType::Minus, TokenType::End]);
+        do_test("/", vec![TokenType::Division, TokenType::End]);
+        do_test("//", vec![TokenType::FloorDivision, TokenType::End]);
+        do_test("!!", vec![TokenType::DoubleFactorial, TokenType::End]);
+        do_test(
+            "< <= > >= == != =",
+            vec![
+                TokenType::Less,
+                TokenType::LessEqual,
+                TokenType::Greater,
+                TokenType::GreaterEqual,
+                TokenType::EqualEqual,
+                TokenType::NotEqual,
+                TokenType::Equals,
+                TokenType::End,
+            ],
+        );
+        do_test(
+            "3!=3!!",
+            vec![
+                TokenType::Number(3.),
+                TokenType::NotEqual,
+                TokenType::Number(3.),
+                TokenType::DoubleFactorial,
+                TokenType::End,
+            ],
+        );
+        do_test(
+            "! !",
+            vec![TokenType::Factorial, TokenType::Factorial, TokenType::End],
+        );
+        do_test(
+            "///",
+            vec![
+                TokenType::FloorDivision,
+                TokenType::Division,
+                TokenType::End,
+            ],
+        );
+        do_test("*", vec![TokenType::Multiplication, TokenType::End]);
+
+        do_test("1", vec![TokenType::Number(1.), TokenType::End]);
+        do_test("103", vec![TokenType::Number(103.), TokenType::End]);
+        do_test(
+            "103 + 1",
+            vec![
+                TokenType::Number(103.),
+                TokenType::Plus,
+                TokenType::Number(1.),
+                TokenType::End,
+            ],
+        );
+        do_test(
+            "+103+1",
+            vec![
+                TokenType::Plus,
+                TokenType::Number(103.),
+                TokenType::Plus,
+                TokenType::Number(1.),
+                TokenType::End,
+            ],
+        );
+    }
+
+    #[test]
+    fn easy_tests() {
+        do_test(
+            "321.23 * 10.23",
+            vec![
+                TokenType::Number(321.23),
+                TokenType::Multiplication,
+                TokenType::Number(10.23),
+                TokenType::End,
+            ],
+        );
+        do_test(
+            "+2 - 3 / 6 * 7",
+            vec![
+                TokenType::Plus,
+                TokenType::Number(2.),
+                TokenType::Minus,
+                TokenType::Number(3.),
+                TokenType::Division,
+                TokenType::Number(6.),
+                TokenType::Multiplication,
+                TokenType::Number(7.),
+                TokenType::End,
+            ],
+        );
+        do_test(
+            "(123.20 + 1.21) * 40",
+            vec![
+                TokenType::Lparen,
+                TokenType::Number(123.20),
+                TokenType::Plus,
+                TokenType::Number(1.21),
+                TokenType::Rparen,
+                TokenType::Multiplication,
+                TokenType::Number(40.),
+                TokenType::End,
+            ],
+        );
+    }
+
+    #[test]
+    fn string_fetch_tests() {
+        do_test(
+            "max()",
+            vec![
+                TokenType::Str("max"),
+                TokenType::Lparen,
+                TokenType::Rparen,
+                TokenType::End,
+            ],
+        );
+        do_test(
+            "max(1, 2)",
+            vec![
+                TokenType::Str("max"),
+                TokenType::Lparen,
+                TokenType::Number(1.),
+                TokenType::Comma,
+                TokenType::Number(2.),
+                TokenType::Rparen,
+                TokenType::End,
+            ],
+        );
+        do_test(
+            "max()",
+            vec![
+                TokenType::Str("max"),
+                TokenType::Lparen,
+                TokenType::Rparen,
+                TokenType::End,
+            ],
+        );
+        do_test(
+            "max(min(3, 2), someFunc(4))",
+            vec![
+                TokenType::Str("max"),
+                TokenType::Lparen,
+                TokenType::Str("min"),
+                TokenType::Lparen,
+                TokenType::Number(3.),
+                TokenType::Comma,
+                TokenType::Number(2.),
+                TokenType::Rparen,
+                TokenType::Comma,
+                TokenType::Str("someFunc"),
+                TokenType::Lparen,
+                TokenType::Number(4.),
+                TokenType::Rparen,
+                TokenType::Rparen,
+                TokenType::End,
+            ],
+        );
+    }
+
     #[test]
-    #[should_panic]
     fn wrong_number_format_parsing() {
-        do_test("123.45.3", vec![]);
+        do_test(
+            "123.45.3",
+            vec![
+                TokenType::Invalid("123.45.3", "is malformed"),
+                TokenType::End,
+            ],
+        );
+    }
+
+    #[test]
+    fn arrow_tests() {
+        do_test(
+            "x -> x - 1",
+            vec![
+                TokenType::Str("x"),
+                TokenType::Arrow,
+                TokenType::Str("x"),
+                TokenType::Minus,
+                TokenType::Number(1.),
+                TokenType::End,
+            ],
+        );
+        do_test(
+            "1 - -2",
+            vec![
+                TokenType::Number(1.),
+                TokenType::Minus,
+                TokenType::Minus,
+                TokenType::Number(2.),
+                TokenType::End,
+            ],
+        );
+    }
+
+    #[test]
+    fn reset_test() {
+        let piece = "12 * ab - max(3.5, 0x1F) ^ 2";
+        let expr = [piece; 1000].join(" + ");
+        let mut scanner = Scanner::new(&expr);
+        scanner.scan();
+        let tokens = scanner.get_tokens().to_vec();
+        // 12 tokens a piece, the pluses between them and the end.
+        assert_eq!(tokens.len(), 12 * 1000 + 999 + 1);
+        assert_eq!(tokens[8].t, TokenType::Number(31.));
+        assert_eq!(tokens[13].pos, piece.len() + 3);
+
+        let other = String::from("1 + 2");
+        scanner.reset(&other);
+        scanner.scan();
+        assert_eq!(scanner.get_tokens().len(), 4);
+        assert_eq!(scanner.next().t, TokenType::Number(1.));
+
+        // Scanning again and again reuses the buffer of tokens.
+        let buffer = (scanner.tokens.as_ptr(), scanner.tokens.capacity());
+        for _ in 0..50 {
+            scanner.reset(&expr);
+            scanner.scan();
+        }
+        assert_eq!(scanner.get_tokens(), &tokens[..]);
+        assert_eq!((scanner.tokens.as_ptr(), scanner.tokens.capacity()), buffer);
+    }
+
+    fn do_retokenize_test(expr: &str, edit: TextEdit, expect_rescanned: usize) {
+        println!("Expression = {:?}, edit = {:?}", expr, edit);
+
+        let old = expr.to_string();
+        let new = edit.apply(&old);
+        let mut scanner = Scanner::new(&old);
+        scanner.set_decimal_comma(true);
+        scanner.scan();
+        let rescanned = scanner.retokenize(&new, &edit);
+
+        let mut fresh = Scanner::new(&new);
+        fresh.set_decimal_comma(true);
+        fresh.scan();
+        assert_eq!(scanner.get_tokens(), fresh.get_tokens());
+        assert_eq!(scanner.starts, fresh.starts);
+        assert_eq!(rescanned, expect_rescanned);
+    }
+
+    #[test]
+    fn retokenize_tests() {
+        assert_eq!(
+            TextEdit::new(2, 3, "×").apply("2 * x"),
+            String::from("2 × x")
+        );
+        // Only `123`, the tokens from `+` on are shifted.
+        do_retokenize_test("12 + x^2 - sin(y)", TextEdit::new(2, 2, "3"), 1);
+        do_retokenize_test("12 + x^2 - sin(y)", TextEdit::new(5, 6, "×"), 3);
+        do_retokenize_test("12 + x^2 - sin(y)", TextEdit::new(0, 17, ""), 0);
+        do_retokenize_test("", TextEdit::new(0, 0, "1 + 2"), 3);
+        // `1` is scanned again, a digit after the comma makes it `1,5`.
+        do_retokenize_test("max(1,x; 2)", TextEdit::new(6, 7, "5"), 1);
+        do_retokenize_test("1 # 2 + 3\n4", TextEdit::new(2, 3, ""), 4);
+        do_retokenize_test("1 + 2\n# 3 + 4", TextEdit::new(6, 7, "-"), 6);
+        do_retokenize_test("y = x² + √x", TextEdit::new(5, 7, "³"), 3);
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn tokens_to_json_test() {
+        // The field names and types are a contract with tools, every kind of
+        // token is pinned here.
+        let expr = "$1 + x*2 - 3/4 // 5 % 6 ^ 7! + 8!!, (a = b == c != d < e <= f > g >= h) \
+                    |i| -> √9²³; π×2";
+        let tokens = [
+            ("history", 0, "$1", r#","index":1"#),
+            ("plus", 3, "+", ""),
+            ("name", 5, "x", ""),
+            ("multiply", 6, "*", ""),
+            ("number", 7, "2", r#","value":2.0"#),
+            ("minus", 9, "-", ""),
+            ("number", 11, "3", r#","value":3.0"#),
+            ("divide", 12, "/", ""),
+            ("number", 13, "4", r#","value":4.0"#),
+            ("floor_divide", 15, "//", ""),
+            ("number", 18, "5", r#","value":5.0"#),
+            ("modulo", 20, "%", ""),
+            ("number", 22, "6", r#","value":6.0"#),
+            ("power", 24, "^", ""),
+            ("number", 26, "7", r#","value":7.0"#),
+            ("factorial", 27, "!", ""),
+            ("plus", 29, "+", ""),
+            ("number", 31, "8", r#","value":8.0"#),
+            ("double_factorial", 32, "!!", ""),
+            ("comma", 34, ",", ""),
+            ("lparen", 36, "(", ""),
+            ("name", 37, "a", ""),
+            ("equals", 39, "=", ""),
+            ("name", 41, "b", ""),
+            ("equal_equal", 43, "==", ""),
+            ("name", 46, "c", ""),
+            ("not_equal", 48, "!=", ""),
+            ("name", 51, "d", ""),
+            ("less", 53, "<", ""),
+            ("name", 55, "e", ""),
+            ("less_equal", 57, "<=", ""),
+            ("name", 60, "f", ""),
+            ("greater", 62, ">", ""),
+            ("name", 64, "g", ""),
+            ("greater_equal", 66, ">=", ""),
+            ("name", 69, "h", ""),
+            ("rparen", 70, ")", ""),
+            ("bar", 72, "|", ""),
+            ("name", 73, "i", ""),
+            ("bar", 74, "|", ""),
+            ("arrow", 76, "->", ""),
+            ("root", 79, "√", ""),
+            ("number", 80, "9", r#","value":9.0"#),
+            ("squared", 81, "²", ""),
+            ("cubed", 82, "³", ""),
+            ("semicolon", 83, ";", ""),
+            ("name", 85, "π", ""),
+            ("multiply", 86, "×", ""),
+            ("number", 87, "2", r#","value":2.0"#),
+        ];
+        let objects: Vec<String> = tokens
+            .iter()
+            .map(|(kind, start, text, extra)| {
+                format!(
+                    r#"{{"type":"{}","start":{},"end":{},"text":"{}"{}}}"#,
+                    kind,
+                    start,
+                    start + text.chars().count(),
+                    text,
+                    extra
+                )
+            })
+            .collect();
+        assert_eq!(tokens_to_json(expr), Ok(format!("[{}]", objects.join(","))));
+
+        assert_eq!(tokens_to_json(""), Ok(String::from("[]")));
+        let err = tokens_to_json("1 + 0x").unwrap_err();
+        assert_eq!(
+            err.message,
+            "Ast build error! Number 0x at pos 4 is malformed!"
+        );
+        assert_eq!(err.span, Span::between(4, 6));
     }
 }
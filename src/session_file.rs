@@ -0,0 +1,119 @@
+use std::fs;
+
+use super::ast::{Node, TokenType};
+use super::command::{assign, parse_line, Line, Session};
+use super::context::Context;
+use super::error::Diagnostic;
+
+/// The first line of a saved session.
+pub const HEADER: &str = "# ls_calc session";
+
+/// The variables of `ctx` as assignments, one per line sorted by name. The
+/// values are written by the expression printer, so they read back as the
+/// same floats, `inf` and `nan` included.
+pub fn render(ctx: &Context) -> String {
+    let mut text = String::from(HEADER);
+    text.push('\n');
+    for (name, value) in ctx.vars() {
+        let value = Node::new(TokenType::Number(value), None, None);
+        text.push_str(&format!("{} = {}\n", name, value));
+    }
+    text
+}
+
+/// Runs the assignments of a saved session, of which there is one per line.
+/// Blank lines and comments are skipped. The first line that fails stops the
+/// loading, the variables before it stay set. Returns the number of variables
+/// that were set.
+pub fn restore(text: &str, session: &mut Session) -> Result<usize, Diagnostic> {
+    let mut count = 0;
+    for (i, line) in text.lines().enumerate() {
+        let result = match parse_line(line) {
+            Line::Assignment(name, expr) => assign(name, expr, session).map(|_| count += 1),
+            Line::Expression(expr) if expr.trim().is_empty() || expr.trim().starts_with('#') => {
+                Ok(())
+            }
+            _ => Err(Diagnostic::from(String::from("Only assignments can be loaded!"))),
+        };
+        if let Err(mut err) = result {
+            err.message = format!("Line {}: {}", i + 1, err.message);
+            return Err(err);
+        }
+    }
+    Ok(count)
+}
+
+/// Writes the variables of `ctx` to the file `path`.
+pub fn save(path: &str, ctx: &Context) -> Result<(), String> {
+    fs::write(path, render(ctx)).map_err(|err| format!("Can't save to {}! {}", path, err))
+}
+
+/// Loads the variables saved in the file `path` into `session`.
+pub fn load(path: &str, session: &mut Session) -> Result<usize, Diagnostic> {
+    let text = fs::read_to_string(path).map_err(|err| format!("Can't load {}! {}", path, err))?;
+    restore(&text, session).map_err(|mut err| {
+        err.message = format!("{} of {}", err.message, path);
+        err
+    })
+}
+
+#[cfg(test)]
+mod session_file_tests {
+    use super::*;
+
+    #[test]
+    fn render_test() {
+        let mut ctx = Context::new();
+        assert_eq!(render(&ctx), "# ls_calc session\n");
+
+        ctx.set_var("rate", 0.1);
+        ctx.set_var("debt", -2500.);
+        ctx.set_var("pi", 3.);
+        assert_eq!(
+            render(&ctx),
+            "# ls_calc session\ndebt = -2500\npi = 3\nrate = 0.1\n"
+        );
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let mut session = Session::default();
+        let ctx = &mut session.ctx;
+        ctx.set_var("third", 1. / 3.);
+        ctx.set_var("big", 1e300);
+        ctx.set_var("tiny", -5e-324);
+        ctx.set_var("huge", f64::INFINITY);
+        ctx.set_var("x_max", 42.);
+
+        let path =
+            std::env::temp_dir().join(format!("ls_calc_session_{}.calc", std::process::id()));
+        let path = path.to_str().unwrap();
+        save(path, &session.ctx).unwrap();
+        let mut loaded = Session::default();
+        assert_eq!(load(path, &mut loaded), Ok(5));
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(loaded.ctx.vars(), session.ctx.vars());
+    }
+
+    #[test]
+    fn restore_error_test() {
+        let mut session = Session::default();
+        assert_eq!(
+            restore("a = 1\n\n# note\nb = a +\nc = 3\n", &mut session).map_err(String::from),
+            Err(String::from(
+                "Line 4: Ast build error! Operator Plus at pos 2 expects an operand, but gets End!"
+            ))
+        );
+        assert_eq!(session.ctx.get_var("a"), Some(1.));
+        assert_eq!(session.ctx.get_var("c"), None);
+
+        assert_eq!(
+            restore("1 + 2\n", &mut session).map_err(String::from),
+            Err(String::from("Line 1: Only assignments can be loaded!"))
+        );
+        assert!(load("no/such/session.calc", &mut session)
+            .unwrap_err()
+            .message
+            .starts_with("Can't load no/such/session.calc!"));
+    }
+}
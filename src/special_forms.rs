@@ -0,0 +1,591 @@
+use super::ast::{Node, TokenType};
+use super::builtins::Arity;
+use super::evaluator::Evaluator;
+use super::visitor::walk_node;
+
+/// Upper bound on the number of times a special form evaluates its body.
+pub const MAX_STEPS: f64 = 1_000_000.;
+
+/// 2^53, bounds of `sum` and `prod` beyond it skip integers as f64s.
+const MAX_BOUND: f64 = 9_007_199_254_740_992.;
+
+/// Error tolerance and recursion limit of the adaptive Simpson integration.
+const INTEGRATION_EPS: f64 = 1e-10;
+const INTEGRATION_DEPTH: u32 = 30;
+
+/// Iteration limit and relative step tolerance of the root finder.
+pub const SOLVE_ITERATIONS: u32 = 100;
+const SOLVE_TOLERANCE: f64 = 1e-12;
+
+/// Where the bound variable and the body go in a call written without a
+/// lambda.
+pub enum Layout {
+    /// `name(var, ..., body)`
+    VarFirst,
+    /// `name(body, var, ...)`
+    BodyFirst,
+}
+
+/// The variable a special form binds, the expression it's bound in and the
+/// remaining arguments.
+pub struct Binding<'n> {
+    pub var: &'n str,
+    pub body: &'n Node,
+    pub args: &'n [Node],
+}
+
+/// A function that receives its arguments unevaluated, so it can bind a
+/// variable before evaluating an expression argument. The variable and the
+/// expression are given either as separate arguments (`sum(i, 1, 3, i^2)`)
+/// or as a lambda in the first argument (`sum(i -> i^2, 1, 3)`).
+pub struct SpecialForm {
+    pub name: &'static str,
+    /// Number of arguments without a lambda.
+    pub arity: usize,
+    pub layout: Layout,
+    pub func: fn(&mut Evaluator, Binding) -> Result<f64, String>,
+}
+
+pub const SPECIAL_FORMS: &[SpecialForm] = &[
+    SpecialForm {
+        name: "sum",
+        arity: 4,
+        layout: Layout::VarFirst,
+        func: |evaluator, binding| fold_range(evaluator, "sum", binding, 0., |acc, n| acc + n),
+    },
+    SpecialForm {
+        name: "prod",
+        arity: 4,
+        layout: Layout::VarFirst,
+        func: |evaluator, binding| fold_range(evaluator, "prod", binding, 1., |acc, n| acc * n),
+    },
+    SpecialForm {
+        name: "integrate",
+        arity: 4,
+        layout: Layout::BodyFirst,
+        func: integrate,
+    },
+    SpecialForm {
+        name: "solve",
+        arity: 3,
+        layout: Layout::BodyFirst,
+        func: solve,
+    },
+];
+
+pub fn find_special_form(name: &str) -> Option<&'static SpecialForm> {
+    SPECIAL_FORMS.iter().find(|form| form.name == name)
+}
+
+fn variable_name<'n>(name: &str, arg: &'n Node) -> Result<&'n str, String> {
+    match arg.token {
+        TokenType::Variable(ref var) => Ok(var),
+        _ => Err(format!(
+            "Function {} expects a variable name to bind!",
+            name
+        )),
+    }
+}
+
+impl SpecialForm {
+    /// Indices of the variable and body arguments, if the call binds its
+    /// variable without a lambda.
+    pub fn scoped_args(&self, args: &[Node]) -> Option<(usize, usize)> {
+        if args.len() != self.arity {
+            return None;
+        }
+        match self.layout {
+            Layout::VarFirst => Some((0, self.arity - 1)),
+            Layout::BodyFirst => Some((1, 0)),
+        }
+    }
+
+    /// The variable, body and remaining arguments of a call at `pos`.
+    pub fn split<'n>(&self, args: &'n [Node], pos: usize) -> Result<Binding<'n>, String> {
+        if let Some(TokenType::Lambda(ref param)) = args.first().map(|arg| &arg.token) {
+            let name = format!("{} with a lambda", self.name);
+            Arity::Exact(self.arity - 1).check(&name, args, pos)?;
+            let body = args[0]
+                .left
+                .as_deref()
+                .ok_or_else(|| format!("Lambda {} -> doesn't have a body!", param))?;
+            return Ok(Binding {
+                var: param,
+                body,
+                args: &args[1..],
+            });
+        }
+
+        Arity::Exact(self.arity).check(self.name, args, pos)?;
+        let (var, body) = self.scoped_args(args).unwrap();
+        let rest = match self.layout {
+            Layout::VarFirst => &args[1..self.arity - 1],
+            Layout::BodyFirst => &args[2..],
+        };
+        Ok(Binding {
+            var: variable_name(self.name, &args[var])?,
+            body: &args[body],
+            args: rest,
+        })
+    }
+
+    pub fn call(
+        &self,
+        evaluator: &mut Evaluator,
+        args: &[Node],
+        pos: usize,
+    ) -> Result<f64, String> {
+        let binding = self.split(args, pos)?;
+        (self.func)(evaluator, binding)
+    }
+}
+
+/// Checks that a bound of `sum` or `prod` is an integer that f64 holds
+/// exactly.
+pub fn integer_bound(name: &str, bound: f64) -> Result<f64, String> {
+    if bound.fract() != 0. || !bound.is_finite() {
+        Err(format!(
+            "Function {} expects integer bounds, but gets {}!",
+            name, bound
+        ))
+    } else if bound.abs() > MAX_BOUND {
+        Err(format!(
+            "Function {} expects bounds between -2^53 and 2^53!",
+            name
+        ))
+    } else {
+        Ok(bound)
+    }
+}
+
+/// Number of integers in `from..=to`, which is at most `MAX_STEPS`. It's
+/// counted with an integer, so a loop ends even where adding 1 to an f64
+/// doesn't change it.
+pub fn range_steps(name: &str, from: f64, to: f64) -> Result<i64, String> {
+    if to - from + 1. > MAX_STEPS {
+        return Err(format!(
+            "Function {} can't take more than {} steps!",
+            name, MAX_STEPS
+        ));
+    }
+    Ok((to - from) as i64 + 1)
+}
+
+/// Evaluates `name(var, from, to, body)` by combining the values of `body`
+/// for every integer `var` in `from..=to`.
+fn fold_range(
+    evaluator: &mut Evaluator,
+    name: &str,
+    binding: Binding,
+    init: f64,
+    combine: fn(f64, f64) -> f64,
+) -> Result<f64, String> {
+    let Binding { var, body, args } = binding;
+    let from = integer_bound(name, walk_node(evaluator, &args[0])?)?;
+    let to = integer_bound(name, walk_node(evaluator, &args[1])?)?;
+
+    let mut acc = init;
+    for step in 0..range_steps(name, from, to)? {
+        let i = from + step as f64;
+        acc = combine(acc, evaluator.eval_with_binding(var, i, body)?);
+    }
+    Ok(acc)
+}
+
+/// Evaluates `body` at `var = x`, failing on non-finite values.
+fn sample(
+    evaluator: &mut Evaluator,
+    name: &str,
+    var: &str,
+    body: &Node,
+    x: f64,
+) -> Result<f64, String> {
+    let y = evaluator.eval_sample(var, x, body)?;
+    if y.is_finite() {
+        Ok(y)
+    } else {
+        Err(format!(
+            "Function {} gets non-finite value {} at {} = {}!",
+            name, y, var, x
+        ))
+    }
+}
+
+fn simpson(a: f64, b: f64, fa: f64, fm: f64, fb: f64) -> f64 {
+    (b - a) / 6. * (fa + 4. * fm + fb)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn adaptive_simpson(
+    f: &mut dyn FnMut(f64) -> Result<f64, String>,
+    a: f64,
+    b: f64,
+    fa: f64,
+    fm: f64,
+    fb: f64,
+    whole: f64,
+    eps: f64,
+    depth: u32,
+) -> Result<f64, String> {
+    let m = (a + b) / 2.;
+    let flm = f((a + m) / 2.)?;
+    let frm = f((m + b) / 2.)?;
+
+    let left = simpson(a, m, fa, flm, fm);
+    let right = simpson(m, b, fm, frm, fb);
+    let delta = left + right - whole;
+
+    if depth == 0 || delta.abs() <= 15. * eps {
+        Ok(left + right + delta / 15.)
+    } else {
+        let left = adaptive_simpson(f, a, m, fa, flm, fm, left, eps / 2., depth - 1)?;
+        let right = adaptive_simpson(f, m, b, fm, frm, fb, right, eps / 2., depth - 1)?;
+        Ok(left + right)
+    }
+}
+
+/// Evaluates `integrate(body, var, a, b)` with adaptive Simpson's rule.
+fn integrate(evaluator: &mut Evaluator, binding: Binding) -> Result<f64, String> {
+    let Binding { var, body, args } = binding;
+    let a = walk_node(evaluator, &args[0])?;
+    let b = walk_node(evaluator, &args[1])?;
+    if !a.is_finite() || !b.is_finite() {
+        return Err(String::from("Function integrate expects finite bounds!"));
+    }
+
+    let mut samples = 0.;
+    let mut f = |x: f64| -> Result<f64, String> {
+        samples += 1.;
+        if samples > MAX_STEPS {
+            return Err(format!(
+                "Function integrate can't take more than {} steps!",
+                MAX_STEPS
+            ));
+        }
+
+        sample(evaluator, "integrate", var, body, x)
+    };
+
+    let (low, high, sign) = if a <= b { (a, b, 1.) } else { (b, a, -1.) };
+    let (fa, fm, fb) = (f(low)?, f((low + high) / 2.)?, f(high)?);
+    let whole = simpson(low, high, fa, fm, fb);
+    let area = adaptive_simpson(
+        &mut f,
+        low,
+        high,
+        fa,
+        fm,
+        fb,
+        whole,
+        INTEGRATION_EPS,
+        INTEGRATION_DEPTH,
+    )?;
+    Ok(sign * area)
+}
+
+/// Finds a root near `x` by looking for a sign change in ever wider
+/// intervals around it and bisecting the first one found.
+fn bisect_near(
+    f: &mut dyn FnMut(f64) -> Result<f64, String>,
+    var: &str,
+    x: f64,
+) -> Result<f64, String> {
+    let fx = f(x)?;
+    let mut step = 0.1 * x.abs().max(1.);
+
+    // Samples that fail end the search, the body is likely undefined
+    // further away.
+    let mut bracket = None;
+    'search: for _ in 0..SOLVE_ITERATIONS {
+        for &other in &[x + step, x - step] {
+            let fo = match f(other) {
+                Ok(fo) => fo,
+                Err(_) => break 'search,
+            };
+            if fo == 0. {
+                return Ok(other);
+            }
+            if fo.signum() != fx.signum() {
+                bracket = Some((x.min(other), x.max(other)));
+                break 'search;
+            }
+        }
+        step *= 2.;
+    }
+
+    let (mut low, mut high) = bracket.ok_or_else(|| {
+        format!(
+            "Function solve can't find a sign change near {} = {}!",
+            var, x
+        )
+    })?;
+
+    let low_sign = f(low)?.signum();
+    for _ in 0..(SOLVE_ITERATIONS * 2) {
+        let mid = (low + high) / 2.;
+        let fm = f(mid)?;
+        if fm == 0. || (high - low) / 2. <= SOLVE_TOLERANCE * mid.abs().max(1.) {
+            return Ok(mid);
+        }
+        if fm.signum() == low_sign {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    Ok((low + high) / 2.)
+}
+
+/// Evaluates `solve(body, var, x0)` with Newton's method, falling back to
+/// bisection where the numeric derivative vanishes.
+fn solve(evaluator: &mut Evaluator, binding: Binding) -> Result<f64, String> {
+    let Binding { var, body, args } = binding;
+    let mut x = walk_node(evaluator, &args[0])?;
+
+    let mut f = |x: f64| sample(evaluator, "solve", var, body, x);
+    for _ in 0..SOLVE_ITERATIONS {
+        let fx = f(x)?;
+        if fx == 0. {
+            return Ok(x);
+        }
+
+        let h = 1e-7 * x.abs().max(1.);
+        let derivative = (f(x + h)? - f(x - h)?) / (2. * h);
+        if derivative == 0. || !derivative.is_finite() {
+            return bisect_near(&mut f, var, x);
+        }
+
+        let next = x - fx / derivative;
+        if !next.is_finite() {
+            return bisect_near(&mut f, var, x);
+        }
+        if (next - x).abs() <= SOLVE_TOLERANCE * x.abs().max(1.) {
+            return Ok(next);
+        }
+        x = next;
+    }
+
+    Err(format!(
+        "Function solve did not converge after {} iterations!",
+        SOLVE_ITERATIONS
+    ))
+}
+
+#[cfg(test)]
+mod special_forms_tests {
+    use crate::context::Context;
+    use crate::evaluator::{evaluate, evaluate_with};
+
+    fn do_test(expr: &str, expect: f64) {
+        println!("Expression = {}", expr);
+
+        let expr = expr.to_string();
+        assert_eq!(evaluate(&expr), Ok(expect));
+    }
+
+    fn do_error_test(expr: &str, expect: &str) {
+        println!("Expression = {}", expr);
+
+        let expr = expr.to_string();
+        assert_eq!(evaluate(&expr), Err(String::from(expect)));
+    }
+
+    #[test]
+    fn sum_tests() {
+        do_test("sum(i, 1, 10, i^2)", 385.);
+        do_test("sum(i, 1, 1, i)", 1.);
+        do_test("sum(i, -2, 2, i)", 0.);
+        do_test("sum(k, 0, 4, 2^k) + 1", 32.);
+        do_test("sum(i, 10, 1, i)", 0.);
+        do_test("sum(i, 2^53 - 2, 2^53, 1)", 3.);
+    }
+
+    #[test]
+    fn prod_tests() {
+        do_test("prod(i, 1, 5, i)", 120.);
+        do_test("prod(i, 1, 5, i) / 5!", 1.);
+        do_test("prod(i, 3, 1, i)", 1.);
+    }
+
+    #[test]
+    fn nested_tests() {
+        do_test("sum(i, 1, 3, sum(j, 1, i, j))", 10.);
+        do_test("sum(i, 1, 3, prod(j, 1, i, 2))", 14.);
+        do_test("sum(i, 1, 2, sum(i, 1, 3, i))", 12.);
+    }
+
+    #[test]
+    fn scoped_variable_tests() {
+        do_error_test("sum(i, 1, 3, i) + i", "Unknown variable i!");
+
+        let mut ctx = Context::new();
+        ctx.set_var("i", 100.);
+        ctx.set_var("n", 4.);
+
+        let expr = "sum(i, 1, n, i) + i".to_string();
+        assert_eq!(evaluate_with(&expr, &ctx), Ok(110.));
+    }
+
+    #[test]
+    fn errors_tests() {
+        do_error_test(
+            "sum(i, 1.5, 3, i)",
+            "Function sum expects integer bounds, but gets 1.5!",
+        );
+        do_error_test(
+            "sum(2, 1, 3, i)",
+            "Function sum expects a variable name to bind!",
+        );
+        do_error_test(
+            "sum(i, 1, 1E12, i)",
+            "Function sum can't take more than 1000000 steps!",
+        );
+        do_error_test(
+            "sum(i, 2^53, 2^53 + 4, i)",
+            "Function sum expects bounds between -2^53 and 2^53!",
+        );
+        do_error_test(
+            "prod(i, 1E300, 1E300, 1)",
+            "Function prod expects bounds between -2^53 and 2^53!",
+        );
+        do_error_test(
+            "prod(i, 1, 3)",
+            "Function prod at pos 0 expects 4 arguments, but gets 3!",
+        );
+    }
+
+    fn do_approx_test(expr: &str, expect: f64) {
+        println!("Expression = {}", expr);
+
+        let expr = expr.to_string();
+        let result = evaluate(&expr).unwrap();
+
+        println!("Expected result = {}, result = {}", expect, result);
+        assert!((result - expect).abs() < 1e-6);
+    }
+
+    #[test]
+    fn integrate_tests() {
+        do_approx_test("integrate(x^2, x, 0, 1)", 1. / 3.);
+        do_approx_test("integrate(x^3 - 2*x + 1, x, -1, 2)", 3.75);
+        do_approx_test("integrate(5, t, 0, 2)", 10.);
+        do_approx_test("integrate(sin(x), x, 0, pi)", 2.);
+        do_approx_test("integrate(cos(x)^2, x, 0, pi)", std::f64::consts::PI / 2.);
+        do_approx_test("integrate(exp(x), x, 0, 1)", std::f64::consts::E - 1.);
+        do_approx_test("integrate(x, x, 2, 2)", 0.);
+    }
+
+    #[test]
+    fn integrate_reversed_bounds_tests() {
+        do_approx_test("integrate(x^2, x, 1, 0)", -1. / 3.);
+        do_approx_test("integrate(sin(x), x, pi, 0)", -2.);
+    }
+
+    #[test]
+    fn integrate_nested_tests() {
+        do_approx_test("integrate(integrate(x * y, y, 0, 1), x, 0, 2)", 1.);
+        do_approx_test(
+            "integrate(sum(i, 1, 3, x^i), x, 0, 1)",
+            1. / 2. + 1. / 3. + 1. / 4.,
+        );
+    }
+
+    #[test]
+    fn integrate_errors_tests() {
+        do_error_test(
+            "integrate(1 / x, x, 0, 1)",
+            "Function integrate gets non-finite value inf at x = 0!",
+        );
+        do_error_test(
+            "integrate(x, 2, 0, 1)",
+            "Function integrate expects a variable name to bind!",
+        );
+        do_error_test("integrate(x + y, x, 0, 1)", "Unknown variable y!");
+
+        // Strict mode doesn't hide the point behind the operation.
+        let mut strict = Context::new();
+        strict.set_strict_floats(true);
+        assert_eq!(
+            evaluate_with(&String::from("integrate(1/x, x, -1, 1)"), &strict),
+            Err(String::from(
+                "Function integrate gets non-finite value inf at x = 0!"
+            ))
+        );
+        // Outside of the samples it still applies.
+        assert_eq!(
+            evaluate_with(&String::from("integrate(x, x, 0, 1/0)"), &strict),
+            Err(String::from("Float error! 1 / 0 at pos 20 is inf!"))
+        );
+    }
+
+    #[test]
+    fn solve_tests() {
+        do_approx_test("solve(x^2 - 2, x, 1)", 2f64.sqrt());
+        do_approx_test("solve(x^2 - 2, x, -1)", -(2f64.sqrt()));
+        do_approx_test("solve(y^3 - 27, y, 1)", 3.);
+        do_approx_test("solve(cos(x) - x, x, 1)", 0.7390851332151607);
+        do_approx_test("solve(sin(x), x, 3)", std::f64::consts::PI);
+        do_approx_test("solve(x^3, x, 0)", 0.);
+    }
+
+    #[test]
+    fn solve_bisection_tests() {
+        do_approx_test("solve(x^2 - 4, x, 0)", 2.);
+        do_approx_test("solve(|x| - 1, x, 0)", 1.);
+    }
+
+    #[test]
+    fn solve_errors_tests() {
+        do_error_test(
+            "solve(exp(x), x, 0)",
+            "Function solve did not converge after 100 iterations!",
+        );
+        do_error_test(
+            "solve(x^2 + 1, x, 0)",
+            "Function solve can't find a sign change near x = 0!",
+        );
+        do_error_test(
+            "solve(1 / x, x, 0)",
+            "Function solve gets non-finite value inf at x = 0!",
+        );
+    }
+
+    #[test]
+    fn lambda_tests() {
+        do_test("sum(i -> i^2, 1, 10)", 385.);
+        do_test("prod(k -> 2 * k, 1, 3)", 48.);
+        do_approx_test("integrate(x -> x^2, 0, 1)", 1. / 3.);
+        do_approx_test("solve(x -> x^2 - 2, 1)", 2f64.sqrt());
+    }
+
+    #[test]
+    fn nested_lambda_tests() {
+        do_approx_test("integrate(x -> integrate(y -> x * y, 0, 1), 0, 2)", 1.);
+        do_test("sum(i -> sum(j -> j, 1, i), 1, 3)", 10.);
+        do_test("sum(i -> prod(i, 1, i, 2), 1, 3)", 14.);
+    }
+
+    #[test]
+    fn lambda_errors_tests() {
+        do_error_test(
+            "sum(i -> i, 1, 2, 3)",
+            "Function sum with a lambda at pos 0 expects 3 arguments, but gets 4, the first extra one at pos 18!",
+        );
+        do_error_test(
+            "solve(x^2, 1)",
+            "Function solve at pos 0 expects 3 arguments, but gets 2!",
+        );
+        do_error_test(
+            "integrate(x -> x, y -> y, 1)",
+            "Lambda y -> ... at pos 18 can't be used as a number!",
+        );
+        do_error_test(
+            "1 + (x -> x)",
+            "Lambda x -> ... at pos 5 can't be used as a number!",
+        );
+        do_error_test(
+            "2 * sum(i, 1, 3, i -> i)",
+            "Lambda i -> ... at pos 17 can't be used as a number!",
+        );
+    }
+}
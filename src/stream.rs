@@ -0,0 +1,180 @@
+use std::io::{self, BufRead, Write};
+use std::mem;
+
+use super::command::{
+    parse_line, run_command, run_line, show_evaluated, Evaluated, Line, Outcome, Session,
+};
+use super::context::Context;
+use super::error::Diagnostic;
+use super::scanner::Scanner;
+
+/// What a line shows.
+#[derive(Debug, PartialEq)]
+pub enum Shown {
+    Nothing,
+    /// The value of the last statement.
+    Value(Evaluated),
+    /// The output of a command.
+    Text(String),
+    Quit,
+}
+
+/// How many lines of a stream were evaluated, and how many of them failed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamStats {
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+/// Evaluates a command or the statements of `line`, of which the last one is
+/// shown.
+pub fn evaluate_line(line: &str, session: &mut Session) -> Result<Shown, Diagnostic> {
+    match parse_line(line) {
+        Line::Command(command) => match run_command(command, session)? {
+            Outcome::Quit => Ok(Shown::Quit),
+            Outcome::Print(text) => Ok(Shown::Text(text)),
+            Outcome::Continue => Ok(Shown::Nothing),
+        },
+        _ => Ok(run_line(line, session)?
+            .pop()
+            .map_or(Shown::Nothing, Shown::Value)),
+    }
+}
+
+/// Whether `line` has nothing to evaluate, like a blank line or a comment.
+fn is_blank(line: &str) -> bool {
+    if let Line::Command(_) = parse_line(line) {
+        return false;
+    }
+    let code = line.to_string();
+    let mut scanner = Scanner::new(&code);
+    scanner.scan();
+    scanner.is_empty()
+}
+
+/// Evaluates the lines of `input` one after another in `session`, and hands
+/// each to `report` with its number and what it shows. Blank lines and
+/// comments are skipped. It stops after a line that quits, or when `report`
+/// returns false. An error of reading stops it too, and is returned with the
+/// number of its line.
+pub fn eval_lines<R: BufRead>(
+    input: R,
+    session: &mut Session,
+    mut report: impl FnMut(usize, &str, &Result<Shown, Diagnostic>, &Session) -> bool,
+) -> Result<(), (usize, io::Error)> {
+    for (i, line) in input.lines().enumerate() {
+        let line = line.map_err(|err| (i + 1, err))?;
+        if is_blank(&line) {
+            continue;
+        }
+        let result = evaluate_line(&line, session);
+        if !report(i + 1, &line, &result, session) || result == Ok(Shown::Quit) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Evaluates the lines of `input` with the variables and settings of `ctx`,
+/// which keeps their assignments, and writes what each line shows to `out`:
+/// its value, `x = 3` for an assignment, or the number of the line and its
+/// error. An error doesn't stop the stream, the stats count it.
+pub fn eval_stream<R: BufRead, W: Write>(
+    input: R,
+    mut out: W,
+    ctx: &mut Context,
+) -> io::Result<StreamStats> {
+    let mut session = Session {
+        ctx: mem::take(ctx),
+        ..Session::default()
+    };
+    let mut stats = StreamStats::default();
+    let mut written = Ok(());
+    let read = eval_lines(input, &mut session, |number, _, result, session| {
+        written = match result {
+            Ok(Shown::Value(evaluated)) => {
+                writeln!(out, "{}", show_evaluated(evaluated, &session.ctx))
+            }
+            Ok(Shown::Text(text)) => writeln!(out, "{}", text),
+            Ok(_) => Ok(()),
+            Err(err) => writeln!(out, "Line {}: {}", number, err),
+        };
+        match result {
+            Ok(Shown::Quit) => {}
+            Ok(_) => stats.succeeded += 1,
+            Err(_) => stats.failed += 1,
+        }
+        written.is_ok()
+    });
+    *ctx = session.ctx;
+    written?;
+    read.map_err(|(_, err)| err)?;
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod stream_tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn do_test(input: &str, ctx: &mut Context, expect: &str, expect_stats: (usize, usize)) {
+        println!("Input = {:?}", input);
+
+        let mut out = Vec::new();
+        let stats = eval_stream(Cursor::new(input), &mut out, ctx).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), expect);
+        assert_eq!((stats.succeeded, stats.failed), expect_stats);
+    }
+
+    #[test]
+    fn eval_stream_test() {
+        let mut ctx = Context::new();
+        let input = "\
+# The area of a circle.
+r = 2
+
+pi * r^2
+1 / 0 + y
+  # Indented comment
+r = r + 1; 2r
+:precision 3
+$1 / 3
+2 +
+";
+        do_test(
+            input,
+            &mut ctx,
+            "r = 2\n\
+             12.5663706143592\n\
+             Line 5: Unknown variable y!\n\
+             6\n\
+             4.19\n\
+             Line 10: Ast build error! Operator Plus at pos 2 expects an operand, but gets End!\n",
+            (5, 2),
+        );
+        // The context keeps the assignments and settings of the stream.
+        assert_eq!(ctx.get_var("r"), Some(3.));
+        do_test("r / 3\nquit\nr", &mut ctx, "1\n", (1, 0));
+        do_test("", &mut ctx, "", (0, 0));
+    }
+
+    #[test]
+    fn eval_lines_test() {
+        let mut session = Session::default();
+        let mut lines = Vec::new();
+        let read = eval_lines(
+            Cursor::new("1\n\n2 +\n3\n4"),
+            &mut session,
+            |number, line, result, _| {
+                lines.push((number, line.to_string()));
+                result.is_ok()
+            },
+        );
+        assert!(read.is_ok());
+        assert_eq!(lines, [(1, String::from("1")), (3, String::from("2 +"))]);
+
+        let invalid = Cursor::new(b"1\n\xff\n2".to_vec());
+        let read = eval_lines(invalid, &mut session, |_, _, _, _| true);
+        assert_eq!(read.map_err(|(number, _)| number), Err(2));
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::BTreeSet;
+
+use super::ast::{Node, NodePtr, TokenType};
+use super::special_forms::find_special_form;
+use super::visitor::{transform, transform_args, Transformer};
+
+struct Substitution<'a> {
+    name: &'a str,
+    replacement: &'a Node,
+    /// Free variables of `replacement`, a variable bound around `name` with
+    /// one of these names would capture it.
+    free: BTreeSet<String>,
+}
+
+impl<'a> Substitution<'a> {
+    /// Renames `var` bound in `body` if it would capture a variable of the
+    /// replacement, `x -> x * y` with `y := x` becomes `xx -> xx * x`.
+    fn rename_bound(&self, var: &str, body: &Node) -> Option<(String, Node)> {
+        let taken = body.free_variables();
+        if !self.free.contains(var) || !taken.contains(self.name) {
+            return None;
+        }
+        // Names are made of letters only, so the last one is repeated.
+        let last = var.chars().last().map_or(String::new(), String::from);
+        let fresh = (1..)
+            .map(|n| format!("{}{}", var, last.repeat(n)))
+            .find(|fresh| !taken.contains(fresh) && !self.free.contains(fresh))
+            .expect("There are always unused names");
+        let renamed = Node::new(TokenType::Variable(fresh.clone()), None, None);
+        Some((fresh, body.substitute(var, &renamed)))
+    }
+}
+
+impl<'a> Transformer for Substitution<'a> {
+    fn transform_variable(&mut self, name: String) -> NodePtr {
+        if name == self.name {
+            Some(Box::new(self.replacement.clone()))
+        } else {
+            Node::new_ptr(TokenType::Variable(name), None, None)
+        }
+    }
+
+    fn transform_call(&mut self, name: String, args: Vec<Node>) -> NodePtr {
+        let scoped = find_special_form(&name).and_then(|form| form.scoped_args(&args));
+        let args = match scoped {
+            // The variable is rebound by the call, only the arguments outside
+            // of its scope are substituted.
+            Some((var, body)) if args[var].token == TokenType::Variable(self.name.to_string()) => {
+                args.into_iter()
+                    .enumerate()
+                    .map(|(i, arg)| {
+                        if i == var || i == body {
+                            arg
+                        } else {
+                            *transform(self, Some(Box::new(arg)))
+                                .expect("Substitution keeps every node")
+                        }
+                    })
+                    .collect()
+            }
+            Some((var, body)) => {
+                let mut args = args;
+                if let TokenType::Variable(ref bound) = args[var].token {
+                    if let Some((fresh, renamed)) = self.rename_bound(bound, &args[body]) {
+                        args[var] = Node::new(TokenType::Variable(fresh), None, None);
+                        args[body] = renamed;
+                    }
+                }
+                transform_args(self, args)
+            }
+            None => transform_args(self, args),
+        };
+        Node::new_ptr(TokenType::Call(name, args), None, None)
+    }
+
+    fn transform_lambda(&mut self, param: String, body: NodePtr) -> NodePtr {
+        if param == self.name {
+            return Node::new_ptr(TokenType::Lambda(param), body, None);
+        }
+        let renamed = body
+            .as_ref()
+            .and_then(|body| self.rename_bound(&param, body));
+        let (param, body) = match renamed {
+            Some((fresh, renamed)) => (fresh, Some(Box::new(renamed))),
+            None => (param, body),
+        };
+        Node::new_ptr(TokenType::Lambda(param), transform(self, body), None)
+    }
+}
+
+impl Node {
+    /// Returns a copy of the tree with every occurrence of variable `name`
+    /// replaced by a copy of `replacement`. Nothing gets evaluated, and
+    /// lambda parameters or variables bound by `sum`-like functions that
+    /// shadow `name` are left alone. Bound variables that would capture a
+    /// variable of `replacement` are renamed.
+    pub fn substitute(&self, name: &str, replacement: &Node) -> Node {
+        let mut substitution = Substitution {
+            name,
+            replacement,
+            free: replacement.free_variables(),
+        };
+        let tree = transform(&mut substitution, Some(Box::new(self.clone())));
+        *tree.expect("Transforming a node always yields a node")
+    }
+}
+
+#[cfg(test)]
+mod substitution_tests {
+    use super::*;
+    use crate::ast::Ast;
+    use crate::scanner::Scanner;
+
+    fn build(expr: &str) -> Node {
+        let s = expr.to_string();
+        let mut scanner = Scanner::new(&s);
+        scanner.scan();
+
+        let mut ast = Ast::new(&mut scanner);
+        ast.build().unwrap();
+        *ast.root.unwrap()
+    }
+
+    fn do_test(expr: &str, name: &str, replacement: &str, expected: &str) {
+        println!("Expression = {}, {} := {}", expr, name, replacement);
+
+        let result = build(expr).substitute(name, &build(replacement));
+        assert_eq!(result, build(expected));
+    }
+
+    #[test]
+    fn substitute_number_test() {
+        do_test("x", "x", "2", "2");
+        do_test("x + 1", "x", "2", "2 + 1");
+        do_test("-x! * |x|", "x", "3", "-3! * |3|");
+    }
+
+    #[test]
+    fn substitute_expression_test() {
+        do_test("x^2 + x", "x", "(y + 1)", "(y + 1)^2 + (y + 1)");
+        do_test("x * x", "x", "-y!", "(-y!) * (-y!)");
+        do_test("x / y", "x", "y * y", "(y * y) / y");
+    }
+
+    #[test]
+    fn substitute_leaves_other_variables_test() {
+        do_test("x + y", "z", "1", "x + y");
+        do_test("xx + x", "x", "1", "xx + 1");
+    }
+
+    #[test]
+    fn substitute_keeps_original_test() {
+        let tree = build("x + 1");
+        let result = tree.substitute("x", &build("2"));
+
+        assert_eq!(tree, build("x + 1"));
+        assert_eq!(result.left.unwrap().token, TokenType::Number(2.));
+    }
+
+    #[test]
+    fn substitute_shadowed_parameter_test() {
+        do_test(
+            "x + sum(x -> x^2, 1, x)",
+            "x",
+            "3",
+            "3 + sum(x -> x^2, 1, 3)",
+        );
+        do_test(
+            "integrate(x -> x * y, 0, x)",
+            "y",
+            "2",
+            "integrate(x -> x * 2, 0, x)",
+        );
+        do_test("x * sum(x, 1, x, x)", "x", "n", "n * sum(x, 1, n, x)");
+        do_test("solve(x^2 - x, x, x)", "x", "2", "solve(x^2 - x, x, 2)");
+        do_test("y -> x -> x + y", "x", "1", "y -> x -> x + y");
+        do_test("y -> x -> x + y", "y", "1", "y -> x -> x + y");
+        do_test("z -> x -> x + y", "y", "1", "z -> x -> x + 1");
+    }
+
+    #[test]
+    fn substitute_avoids_capture_test() {
+        do_test("x -> x * y", "y", "x", "xx -> xx * x");
+        do_test(
+            "integrate(x -> x * y, 0, x)",
+            "y",
+            "x",
+            "integrate(xx -> xx * x, 0, x)",
+        );
+        do_test("x -> x * y", "y", "x + xx", "xxx -> xxx * (x + xx)");
+        do_test("x -> x * y + xx", "y", "x", "xxx -> xxx * x + xx");
+        do_test("sum(i, 1, n, i * k)", "k", "i^2", "sum(ii, 1, n, ii * i^2)");
+        do_test("sum(i -> i * k, 1, i)", "k", "i", "sum(ii -> ii * i, 1, i)");
+        do_test(
+            "integrate(x * y, x, 0, 1)",
+            "y",
+            "x",
+            "integrate(xx * x, xx, 0, 1)",
+        );
+        // Nothing is captured if the bound variable doesn't surround `name`.
+        do_test("x -> x * 2", "y", "x", "x -> x * 2");
+        do_test("(x -> x) + y", "y", "x", "(x -> x) + x");
+    }
+}
@@ -0,0 +1,199 @@
+use super::arena::{eval_vec, Arena};
+use super::ast::parse_with;
+use super::context::Context;
+use super::error::CalcError;
+use super::format::format_value;
+use super::value::Value;
+
+/// How many rows a table has at most by default.
+pub const TABLE_ROWS: usize = 1000;
+
+/// A value of the variable and what the expression evaluates to for it.
+pub type Row = (f64, Result<f64, CalcError>);
+
+/// Rows whose `x` misses `to` by a rounding error still count.
+const EPSILON: f64 = 1e-9;
+
+/// The number of rows from `from` to `to` in steps of `step`.
+fn row_count(from: f64, to: f64, step: f64, max: usize) -> Result<usize, CalcError> {
+    if step == 0. || !step.is_finite() {
+        return Err(CalcError::Eval(format!(
+            "The step of a table can't be {}!",
+            step
+        )));
+    }
+    let steps = (to - from) / step;
+    if !steps.is_finite() || steps < -EPSILON {
+        return Err(CalcError::Eval(format!(
+            "The step {} doesn't lead from {} to {}!",
+            step, from, to
+        )));
+    }
+    let steps = (steps + EPSILON).floor();
+    if steps >= max as f64 {
+        return Err(CalcError::Eval(format!(
+            "The table has {} rows, more than the limit of {}!",
+            steps + 1.,
+            max
+        )));
+    }
+    Ok(steps as usize + 1)
+}
+
+/// Evaluates `expr` with `var` bound to every value from `from` to `to` in
+/// steps of `step`, `to` included if a step lands on it. The other variables
+/// are those of `ctx`. An error at a single value is its row's result, while
+/// a parse error, a step of zero or one away from `to`, or more rows than
+/// `ctx.table_rows()` fail the whole table.
+pub fn tabulate(
+    expr: &str,
+    var: &str,
+    from: f64,
+    to: f64,
+    step: f64,
+    ctx: &Context,
+) -> Result<Vec<Row>, CalcError> {
+    let root = parse_with(expr, ctx)
+        .map_err(CalcError::Parse)?
+        .ok_or_else(|| CalcError::Parse(String::from("Empty expression!")))?;
+
+    let rows = row_count(from, to, step, ctx.table_rows())?;
+    // Multiplying doesn't add up rounding errors like adding does.
+    let xs: Vec<f64> = (0..rows).map(|i| from + i as f64 * step).collect();
+    let ys = eval_vec(&Arena::from_tree(Some(root)), var, &xs, ctx);
+    let table = xs
+        .into_iter()
+        .zip(ys)
+        .map(|(x, y)| (x, y.map_err(CalcError::Eval)))
+        .collect();
+    Ok(table)
+}
+
+/// A table as two columns aligned to the right, under the headers `var` and
+/// `expr`. A row whose value couldn't be evaluated shows `error`.
+pub fn render(var: &str, expr: &str, table: &[Row], ctx: &Context) -> String {
+    let format = |n: f64| format_value(&Value::from_f64(n), ctx.format_options());
+    let rows: Vec<(String, String)> = table
+        .iter()
+        .map(|(x, y)| match y {
+            Ok(y) => (format(*x), format(*y)),
+            Err(_) => (format(*x), String::from("error")),
+        })
+        .collect();
+    let width = |header: &str, column: &dyn Fn(&(String, String)) -> usize| {
+        rows.iter()
+            .map(column)
+            .chain(Some(header.chars().count()))
+            .max()
+            .unwrap_or(0)
+    };
+    let x_width = width(var, &|row| row.0.chars().count());
+    let y_width = width(expr, &|row| row.1.chars().count());
+
+    let mut lines = vec![
+        format!("{:>xw$} | {:>yw$}", var, expr, xw = x_width, yw = y_width),
+        format!("{}-+-{}", "-".repeat(x_width), "-".repeat(y_width)),
+    ];
+    for (x, y) in rows {
+        lines.push(format!(
+            "{:>xw$} | {:>yw$}",
+            x,
+            y,
+            xw = x_width,
+            yw = y_width
+        ));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod table_tests {
+    use super::*;
+
+    fn values(table: &[Row]) -> Vec<(f64, Option<f64>)> {
+        table.iter().map(|(x, y)| (*x, y.clone().ok())).collect()
+    }
+
+    #[test]
+    fn tabulate_test() {
+        let ctx = Context::new();
+        let table = tabulate("x^2 - 3*x", "x", 0., 2., 0.5, &ctx).unwrap();
+        assert_eq!(
+            values(&table),
+            [
+                (0., Some(0.)),
+                (0.5, Some(-1.25)),
+                (1., Some(-2.)),
+                (1.5, Some(-2.25)),
+                (2., Some(-2.)),
+            ]
+        );
+
+        // Downwards, and `to` is left out if no step lands on it.
+        let table = tabulate("2 * t", "t", 1., -1.5, -1., &ctx).unwrap();
+        assert_eq!(
+            values(&table),
+            [(1., Some(2.)), (0., Some(0.)), (-1., Some(-2.))]
+        );
+        // Steps of 0.1 add up to slightly less than 1.
+        assert_eq!(tabulate("x", "x", 0., 1., 0.1, &ctx).unwrap().len(), 11);
+    }
+
+    #[test]
+    fn tabulate_errors_test() {
+        let mut ctx = Context::new();
+        ctx.set_strict_floats(true);
+        ctx.set_var("a", 2.);
+        let table = tabulate("a / x", "x", -1., 1., 1., &ctx).unwrap();
+        assert_eq!(table[0], (-1., Ok(-2.)));
+        assert_eq!(
+            table[1],
+            (
+                0.,
+                Err(CalcError::Eval(String::from(
+                    "Float error! 2 / 0 at pos 2 is inf!"
+                )))
+            )
+        );
+        assert_eq!(table[2], (1., Ok(2.)));
+
+        assert_eq!(
+            tabulate("x", "x", 0., 1., 0., &ctx),
+            Err(CalcError::Eval(String::from(
+                "The step of a table can't be 0!"
+            )))
+        );
+        assert_eq!(
+            tabulate("x", "x", 0., 1., -1., &ctx),
+            Err(CalcError::Eval(String::from(
+                "The step -1 doesn't lead from 0 to 1!"
+            )))
+        );
+        assert_eq!(
+            tabulate("x +", "x", 0., 1., 1., &ctx),
+            Err(CalcError::Parse(String::from(
+                "Operator Plus at pos 2 expects an operand, but gets End!"
+            )))
+        );
+
+        ctx.set_table_rows(10);
+        assert_eq!(tabulate("x", "x", 1., 10., 1., &ctx).unwrap().len(), 10);
+        assert_eq!(
+            tabulate("x", "x", 0., 10., 1., &ctx),
+            Err(CalcError::Eval(String::from(
+                "The table has 11 rows, more than the limit of 10!"
+            )))
+        );
+    }
+
+    #[test]
+    fn render_test() {
+        let mut ctx = Context::new();
+        ctx.set_strict_floats(true);
+        let table = tabulate("1 / x", "x", -1., 1., 0.5, &ctx).unwrap();
+        assert_eq!(
+            render("x", "1 / x", &table, &ctx),
+            "   x | 1 / x\n-----+------\n  -1 |    -1\n-0.5 |    -2\n   0 | error\n 0.5 |     2\n   1 |     1"
+        );
+    }
+}
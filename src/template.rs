@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+
+use super::ast::parse;
+use super::context::Context;
+use super::evaluator::evaluate_with;
+use super::scanner::{is_name, is_name_char};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Piece {
+    Text(String),
+    Placeholder(String),
+}
+
+/// An expression with named placeholders, like `({base} + {bonus}) *
+/// {multiplier}`, whose values are filled in later. A placeholder is a whole
+/// token, `1{x}` can't splice a value into a number. `{{` and `}}` are the
+/// braces themselves.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    pieces: Vec<Piece>,
+    /// The names of the placeholders in the order they first appear.
+    names: Vec<String>,
+}
+
+impl Template {
+    /// Reads the placeholders of `source` and checks that it parses with
+    /// them as variables.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut pieces = Vec::new();
+        let mut names: Vec<String> = Vec::new();
+        let mut text = String::new();
+        // `source` with the braces of placeholders as spaces, which parses
+        // with its positions.
+        let mut skeleton = String::with_capacity(source.len());
+        let chars: Vec<char> = source.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            match (chars[i], chars.get(i + 1)) {
+                ('{', Some('{')) | ('}', Some('}')) => {
+                    text.push(chars[i]);
+                    skeleton.push(' ');
+                    skeleton.push(chars[i]);
+                    i += 2;
+                }
+                ('{', _) => {
+                    let len = match chars[i + 1..].iter().position(|&c| c == '}') {
+                        Some(len) => len,
+                        None => return Err(format!("Unclosed placeholder at pos {}!", i)),
+                    };
+                    let name: String = chars[i + 1..i + 1 + len].iter().collect();
+                    if !is_name(&name) {
+                        return Err(format!(
+                            "Placeholder {{{}}} at pos {} isn't a variable name!",
+                            name, i
+                        ));
+                    }
+                    let end = i + len + 2;
+                    // A value is spliced into a name or a number right
+                    // before or after it, or into the value of a placeholder
+                    // right before it.
+                    let spliced =
+                        |c: Option<&char>| c.is_some_and(|&c| is_name_char(c) || c == '.');
+                    let after_placeholder =
+                        text.is_empty() && matches!(pieces.last(), Some(Piece::Placeholder(_)));
+                    if spliced(i.checked_sub(1).map(|before| &chars[before]))
+                        || spliced(chars.get(end))
+                        || after_placeholder
+                    {
+                        return Err(format!(
+                            "Placeholder {{{}}} at pos {} is spliced into a token!",
+                            name, i
+                        ));
+                    }
+                    if !text.is_empty() {
+                        pieces.push(Piece::Text(std::mem::take(&mut text)));
+                    }
+                    skeleton.push(' ');
+                    skeleton.push_str(&name);
+                    skeleton.push(' ');
+                    if !names.contains(&name) {
+                        names.push(name.clone());
+                    }
+                    pieces.push(Piece::Placeholder(name));
+                    i = end;
+                }
+                ('}', _) => return Err(format!("Unmatched }} at pos {}, }}}} is a brace!", i)),
+                (c, _) => {
+                    text.push(c);
+                    skeleton.push(c);
+                    i += 1;
+                }
+            }
+        }
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+        parse(&skeleton).map_err(|err| format!("Ast build error! {}", err))?;
+        Ok(Template { pieces, names })
+    }
+
+    /// The names of the placeholders in the order they first appear.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// The expression with the values of the placeholders filled in, a
+    /// negative one in parentheses. All of them need a finite value.
+    pub fn render(&self, values: &HashMap<String, f64>) -> Result<String, String> {
+        let missing: Vec<&str> = self
+            .names
+            .iter()
+            .filter(|name| !values.contains_key(*name))
+            .map(String::as_str)
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!(
+                "Placeholders without a value: {}!",
+                missing.join(", ")
+            ));
+        }
+
+        let mut expr = String::new();
+        for piece in &self.pieces {
+            match piece {
+                Piece::Text(text) => expr.push_str(text),
+                Piece::Placeholder(name) => match values[name] {
+                    value if !value.is_finite() => {
+                        return Err(format!(
+                            "The value {} of placeholder {} isn't a finite number!",
+                            value, name
+                        ))
+                    }
+                    value if value.is_sign_negative() => expr.push_str(&format!("({})", value)),
+                    value => expr.push_str(&value.to_string()),
+                },
+            }
+        }
+        Ok(expr)
+    }
+
+    /// Evaluates the expression with the values of the placeholders filled
+    /// in like `render`.
+    pub fn eval(&self, values: &HashMap<String, f64>) -> Result<f64, String> {
+        self.eval_with(values, &Context::new())
+    }
+
+    /// Like `eval`, with the variables and settings of `ctx`.
+    pub fn eval_with(&self, values: &HashMap<String, f64>, ctx: &Context) -> Result<f64, String> {
+        evaluate_with(&self.render(values)?, ctx)
+    }
+}
+
+#[cfg(test)]
+mod template_tests {
+    use super::*;
+    use crate::evaluator::evaluate;
+
+    fn values(pairs: &[(&str, f64)]) -> HashMap<String, f64> {
+        pairs
+            .iter()
+            .map(|&(name, value)| (name.to_string(), value))
+            .collect()
+    }
+
+    #[test]
+    fn parse_test() {
+        let template = Template::parse("({base} + {bonus}) * {multiplier} - {base}").unwrap();
+        assert_eq!(template.names(), ["base", "bonus", "multiplier"]);
+        assert!(Template::parse("2 * pi").unwrap().names().is_empty());
+
+        let errors = [
+            ("1{x}", "Placeholder {x} at pos 1 is spliced into a token!"),
+            ("{x}.5", "Placeholder {x} at pos 0 is spliced into a token!"),
+            (
+                "{a}{b}",
+                "Placeholder {b} at pos 3 is spliced into a token!",
+            ),
+            (
+                "{2x} + 1",
+                "Placeholder {2x} at pos 0 isn't a variable name!",
+            ),
+            ("{} + 1", "Placeholder {} at pos 0 isn't a variable name!"),
+            ("{x + 1", "Unclosed placeholder at pos 0!"),
+            ("x} + 1", "Unmatched } at pos 1, }} is a brace!"),
+            // Positions are those of the template.
+            (
+                "{x} +",
+                "Ast build error! Operator Plus at pos 4 expects an operand, but gets End!",
+            ),
+        ];
+        for (source, err) in errors.iter() {
+            assert_eq!(Template::parse(source), Err(err.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn render_test() {
+        let template = Template::parse("({base} + {bonus}) * {multiplier}").unwrap();
+        let all = values(&[("base", 100.), ("bonus", -2.5), ("multiplier", 1.1)]);
+        assert_eq!(
+            template.render(&all),
+            Ok(String::from("(100 + (-2.5)) * 1.1"))
+        );
+        assert_eq!(
+            template.render(&values(&[("bonus", 1.)])),
+            Err(String::from(
+                "Placeholders without a value: base, multiplier!"
+            ))
+        );
+        let nan = values(&[("base", f64::NAN), ("bonus", 0.), ("multiplier", 1.)]);
+        assert_eq!(
+            template.render(&nan),
+            Err(String::from(
+                "The value NaN of placeholder base isn't a finite number!"
+            ))
+        );
+
+        // Escaped braces stay braces.
+        let template = Template::parse("{x} # {{not}} a placeholder }}").unwrap();
+        assert_eq!(template.names(), ["x"]);
+        assert_eq!(
+            template.render(&values(&[("x", 2.)])),
+            Ok(String::from("2 # {not} a placeholder }"))
+        );
+    }
+
+    #[test]
+    fn eval_test() {
+        let template = Template::parse("({base} + {bonus}) * {multiplier}").unwrap();
+        let all = values(&[("base", 100.), ("bonus", -2.5), ("multiplier", 3.)]);
+        assert_eq!(template.eval(&all), Ok(292.5));
+        assert_eq!(
+            template.eval(&all),
+            evaluate(&String::from("(100 + -2.5) * 3"))
+        );
+        // A negative value is a value of its own, -3^2 would be -9.
+        let square = Template::parse("{x}^2 + {x}").unwrap();
+        assert_eq!(square.eval(&values(&[("x", -3.)])), Ok(6.));
+
+        let mut ctx = Context::new();
+        ctx.set_var("rate", 0.5);
+        let template = Template::parse("{amount} * rate").unwrap();
+        assert_eq!(template.eval_with(&values(&[("amount", 8.)]), &ctx), Ok(4.));
+        assert_eq!(
+            template.eval(&HashMap::new()),
+            Err(String::from("Placeholders without a value: amount!"))
+        );
+    }
+}
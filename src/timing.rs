@@ -0,0 +1,78 @@
+use std::fmt;
+use std::time::Duration;
+
+/// How long the steps of an evaluation took, measured with the wall clock.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EvalReport {
+    pub scan: Duration,
+    pub parse: Duration,
+    /// Zero if the expression couldn't be parsed.
+    pub eval: Duration,
+}
+
+impl EvalReport {
+    pub fn total(&self) -> Duration {
+        self.scan + self.parse + self.eval
+    }
+}
+
+impl fmt::Display for EvalReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "(scan {}, parse {}, eval {})",
+            format_duration(self.scan),
+            format_duration(self.parse),
+            format_duration(self.eval)
+        )
+    }
+}
+
+/// `duration` in ns, µs or ms, whichever keeps it below 1000 if it can.
+/// Milliseconds below 10 get one decimal.
+pub fn format_duration(duration: Duration) -> String {
+    let nanos = duration.as_nanos();
+    if nanos < 1000 {
+        format!("{}ns", nanos)
+    } else if nanos < 999_500 {
+        format!("{}µs", (nanos + 500) / 1000)
+    } else if nanos < 9_950_000 {
+        format!("{:.1}ms", nanos as f64 / 1e6)
+    } else {
+        format!("{}ms", (nanos + 500_000) / 1_000_000)
+    }
+}
+
+#[cfg(test)]
+mod timing_tests {
+    use super::*;
+
+    #[test]
+    fn format_duration_test() {
+        let tests = [
+            (0, "0ns"),
+            (999, "999ns"),
+            (1_000, "1µs"),
+            (12_400, "12µs"),
+            (999_499, "999µs"),
+            (999_500, "1.0ms"),
+            (2_340_000, "2.3ms"),
+            (9_950_000, "10ms"),
+            (1_520_000_000, "1520ms"),
+        ];
+        for (nanos, expect) in tests.iter() {
+            assert_eq!(format_duration(Duration::from_nanos(*nanos)), *expect);
+        }
+    }
+
+    #[test]
+    fn display_test() {
+        let report = EvalReport {
+            scan: Duration::from_micros(12),
+            parse: Duration::from_micros(48),
+            eval: Duration::from_nanos(3_100),
+        };
+        assert_eq!(report.to_string(), "(scan 12µs, parse 48µs, eval 3µs)");
+        assert_eq!(report.total(), Duration::from_nanos(63_100));
+    }
+}
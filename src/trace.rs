@@ -0,0 +1,132 @@
+use super::ast::{Node, TokenType};
+use super::context::Context;
+use super::evaluator::Evaluator;
+use super::special_forms::find_special_form;
+use super::visitor::walk_node;
+
+/// Evaluates the first operation of `node` whose operands are all numbers,
+/// left to right, and puts its value in its place. Variables count as such
+/// operations too. Special forms and their variables are evaluated in one
+/// step, and so are `and` and `or` once their left operand is a number, as
+/// their right one may not be evaluated at all. Returns false if `node` is a
+/// number already.
+fn reduce(node: &mut Node, ctx: &Context) -> Result<bool, String> {
+    match &mut node.token {
+        TokenType::Number(_) => return Ok(false),
+        TokenType::Call(name, args) if find_special_form(&ctx.builtin_name(name)).is_none() => {
+            for arg in args.iter_mut() {
+                if reduce(arg, ctx)? {
+                    return Ok(true);
+                }
+            }
+        }
+        TokenType::Call(..) | TokenType::Lambda(_) => (),
+        token => {
+            let short_circuit = matches!(token, TokenType::And | TokenType::Or);
+            if let Some(left) = &mut node.left {
+                if reduce(left, ctx)? {
+                    return Ok(true);
+                }
+            }
+            if let Some(right) = node.right.as_mut().filter(|_| !short_circuit) {
+                if reduce(right, ctx)? {
+                    return Ok(true);
+                }
+            }
+        }
+    }
+
+    let value = walk_node(&mut Evaluator::new(ctx), node)?;
+    *node = Node {
+        token: TokenType::Number(value),
+        pos: node.pos,
+        span: node.span,
+        left: None,
+        right: None,
+    };
+    Ok(true)
+}
+
+impl Node {
+    /// The steps of evaluating the tree one operation at a time, from the
+    /// tree itself to its value: `2 + 3! * 4`, `2 + 6 * 4`, `2 + 24`, `26`.
+    /// A step that reads like the one before, e.g. `-5` as a negated 5 and as
+    /// a number, is left out. Values are floats, like those of `Evaluator`.
+    pub fn trace(&self, ctx: &Context) -> Result<Vec<Node>, String> {
+        let mut steps = vec![self.clone()];
+        let mut node = self.clone();
+        while reduce(&mut node, ctx)? {
+            let last = steps.last().expect("The tree itself is the first step");
+            if node.to_string() != last.to_string() {
+                steps.push(node.clone());
+            }
+        }
+        Ok(steps)
+    }
+}
+
+#[cfg(test)]
+mod trace_tests {
+    use super::*;
+    use crate::ast::parse;
+
+    fn do_test(expr: &str, ctx: &Context, expect: &[&str]) {
+        println!("Expression = {}", expr);
+
+        let steps = parse(expr).unwrap().unwrap().trace(ctx).unwrap();
+        let steps: Vec<String> = steps.iter().map(Node::to_string).collect();
+        assert_eq!(steps, expect);
+    }
+
+    #[test]
+    fn trace_test() {
+        let ctx = Context::new();
+        do_test(
+            "2 + 3! * |1-5|",
+            &ctx,
+            &[
+                "2 + 3! * |1 - 5|",
+                "2 + 6 * |1 - 5|",
+                "2 + 6 * |-4|",
+                "2 + 6 * 4",
+                "2 + 24",
+                "26",
+            ],
+        );
+        do_test(
+            "-(2 + 3) * -4 + 1",
+            &ctx,
+            &["-(2 + 3) * -4 + 1", "-5 * -4 + 1", "20 + 1", "21"],
+        );
+        do_test(
+            "max(1 + 1, 3) / 4",
+            &ctx,
+            &["max(1 + 1, 3) / 4", "max(2, 3) / 4", "3 / 4", "0.75"],
+        );
+        do_test("7", &ctx, &["7"]);
+    }
+
+    #[test]
+    fn trace_variables_test() {
+        let mut ctx = Context::new();
+        ctx.set_var("x", 3.);
+        do_test("x * 2 + 1", &ctx, &["x * 2 + 1", "3 * 2 + 1", "6 + 1", "7"]);
+        // The right operand of `or` and the body of `sum` aren't reduced on
+        // their own.
+        do_test("1 < 2 or y", &ctx, &["1 < 2 or y", "1 or y", "1"]);
+        do_test(
+            "sum(i, 1, x, i) + 1",
+            &ctx,
+            &["sum(i, 1, x, i) + 1", "6 + 1", "7"],
+        );
+    }
+
+    #[test]
+    fn trace_error_test() {
+        let tree = parse("2 * (1 + y)").unwrap().unwrap();
+        assert_eq!(
+            tree.trace(&Context::new()),
+            Err(String::from("Unknown variable y!"))
+        );
+    }
+}
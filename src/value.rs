@@ -0,0 +1,96 @@
+use std::fmt;
+
+use super::bigint::BigInt;
+use super::complex::Complex;
+use super::fraction::Fraction;
+use super::printer::format_number;
+
+/// 2^53, up to which every whole f64 is an exact integer.
+pub const MAX_EXACT_INTEGER: f64 = 9_007_199_254_740_992.;
+
+/// A result of evaluation, which keeps integers exact.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Value {
+    /// The result of a comparison or logical operator, or `true` and `false`.
+    Bool(bool),
+    Int(i64),
+    /// An integer beyond the i64 range.
+    Big(BigInt),
+    /// A fraction that isn't an integer, in rational mode.
+    Ratio(Fraction),
+    Float(f64),
+    /// A number with a nonzero imaginary part, in complex mode.
+    Complex(Complex),
+}
+
+impl Value {
+    /// An integer if `n` is a whole number that f64 holds exactly, otherwise
+    /// a float.
+    pub fn from_f64(n: f64) -> Self {
+        if n.fract() == 0. && n.abs() <= MAX_EXACT_INTEGER {
+            Value::Int(n as i64)
+        } else {
+            Value::Float(n)
+        }
+    }
+
+    pub fn as_f64(&self) -> f64 {
+        match self {
+            Value::Bool(b) => *b as i64 as f64,
+            Value::Int(i) => *i as f64,
+            Value::Big(n) => n.to_f64(),
+            Value::Ratio(fraction) => fraction.to_f64(),
+            Value::Float(x) => *x,
+            // It has no real value.
+            Value::Complex(_) => f64::NAN,
+        }
+    }
+
+    /// The exact integer, unless it's a float.
+    pub fn to_big(&self) -> Option<BigInt> {
+        match self {
+            Value::Bool(b) => Some(BigInt::from(*b as i64)),
+            Value::Int(i) => Some(BigInt::from(*i)),
+            Value::Big(n) => Some(n.clone()),
+            Value::Ratio(_) | Value::Float(_) | Value::Complex(_) => None,
+        }
+    }
+
+    /// The exact value, unless it's a float.
+    pub fn to_fraction(&self) -> Option<Fraction> {
+        match self {
+            Value::Ratio(fraction) => Some(fraction.clone()),
+            _ => self.to_big().map(Fraction::from),
+        }
+    }
+
+    /// Booleans take part in arithmetic as 1 and 0, so `1 + true` is 2. All
+    /// operations promote their operands with this first.
+    pub fn to_number(self) -> Value {
+        match self {
+            Value::Bool(b) => Value::Int(b as i64),
+            value => value,
+        }
+    }
+
+    /// Any nonzero value is true.
+    pub fn is_true(&self) -> bool {
+        match self {
+            Value::Complex(z) => !z.is_zero(),
+            _ => self.as_f64() != 0.,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Big(n) => write!(f, "{}", n),
+            Value::Ratio(fraction) => write!(f, "{}", fraction),
+            Value::Float(x) => write!(f, "{}", format_number(*x)),
+            Value::Complex(z) => write!(f, "{}", z),
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use super::ast::{Node, NodePtr, TokenType};
+
+/// Read-only pass over an AST.
+///
+/// Every hook gets the children of the node it is called for, so an
+/// implementation decides itself when (and whether) to descend into them
+/// with `walk`.
+pub trait Visitor {
+    type Output;
+
+    fn visit_number(&mut self, n: f64) -> Self::Output;
+    fn visit_variable(&mut self, name: &str) -> Self::Output;
+    fn visit_history(&mut self, n: usize) -> Self::Output;
+    fn visit_text(&mut self, text: &str) -> Self::Output;
+    fn visit_call(&mut self, name: &str, args: &[Node]) -> Self::Output;
+    fn visit_lambda(&mut self, param: &str, body: &NodePtr) -> Self::Output;
+    fn visit_unary(&mut self, op: &TokenType, operand: &NodePtr) -> Self::Output;
+    fn visit_binary(&mut self, op: &TokenType, left: &NodePtr, right: &NodePtr) -> Self::Output;
+    fn visit_none(&mut self) -> Self::Output;
+
+    /// Called with the position of every node before its hook, for passes
+    /// that report where in the input something went wrong. The hook of a
+    /// node that walks its children sees their positions afterwards.
+    fn visit_pos(&mut self, _pos: usize) {}
+
+    /// Called with every node and what its hook returned, for passes that
+    /// report which node an error comes from: the first one it ends.
+    fn visit_end(&mut self, _node: &Node, _output: &Self::Output) {}
+}
+
+pub fn walk<V: Visitor>(visitor: &mut V, node: &NodePtr) -> V::Output {
+    match node {
+        Some(node) => walk_node(visitor, node),
+        None => visitor.visit_none(),
+    }
+}
+
+pub fn walk_node<V: Visitor>(visitor: &mut V, node: &Node) -> V::Output {
+    visitor.visit_pos(node.pos);
+    let output = match node.token {
+        TokenType::Number(n) => visitor.visit_number(n),
+        TokenType::Variable(ref name) => visitor.visit_variable(name),
+        TokenType::History(n) => visitor.visit_history(n),
+        TokenType::Text(ref text) => visitor.visit_text(text),
+        TokenType::Call(ref name, ref args) => visitor.visit_call(name, args),
+        TokenType::Lambda(ref param) => visitor.visit_lambda(param, &node.left),
+        ref op if op.is_unary() => visitor.visit_unary(op, &node.left),
+        ref op => visitor.visit_binary(op, &node.left, &node.right),
+    };
+    visitor.visit_end(node, &output);
+    output
+}
+
+/// Rewriting pass over an AST.
+///
+/// The tree is rebuilt bottom-up: children are transformed first and the
+/// hooks receive the already transformed subtrees. The exceptions are calls
+/// and lambdas, which bind variables: their hooks get the untransformed
+/// arguments/body, so a pass can respect the scope of those variables. The
+/// default hooks transform the children and rebuild the node unchanged.
+pub trait Transformer {
+    fn transform_number(&mut self, n: f64) -> NodePtr {
+        Node::new_ptr(TokenType::Number(n), None, None)
+    }
+
+    fn transform_variable(&mut self, name: String) -> NodePtr {
+        Node::new_ptr(TokenType::Variable(name), None, None)
+    }
+
+    fn transform_history(&mut self, n: usize) -> NodePtr {
+        Node::new_ptr(TokenType::History(n), None, None)
+    }
+
+    fn transform_text(&mut self, text: String) -> NodePtr {
+        Node::new_ptr(TokenType::Text(text), None, None)
+    }
+
+    fn transform_call(&mut self, name: String, args: Vec<Node>) -> NodePtr
+    where
+        Self: Sized,
+    {
+        let args = transform_args(self, args);
+        Node::new_ptr(TokenType::Call(name, args), None, None)
+    }
+
+    fn transform_lambda(&mut self, param: String, body: NodePtr) -> NodePtr
+    where
+        Self: Sized,
+    {
+        let body = transform(self, body);
+        Node::new_ptr(TokenType::Lambda(param), body, None)
+    }
+
+    fn transform_unary(&mut self, op: TokenType, operand: NodePtr) -> NodePtr {
+        Node::new_ptr(op, operand, None)
+    }
+
+    fn transform_binary(&mut self, op: TokenType, left: NodePtr, right: NodePtr) -> NodePtr {
+        Node::new_ptr(op, left, right)
+    }
+}
+
+pub fn transform<T: Transformer>(transformer: &mut T, node: NodePtr) -> NodePtr {
+    let node = node?;
+    let Node {
+        token, left, right, ..
+    } = *node;
+    match token {
+        TokenType::Number(n) => transformer.transform_number(n),
+        TokenType::Variable(name) => transformer.transform_variable(name),
+        TokenType::History(n) => transformer.transform_history(n),
+        TokenType::Text(text) => transformer.transform_text(text),
+        TokenType::Call(name, args) => transformer.transform_call(name, args),
+        TokenType::Lambda(param) => transformer.transform_lambda(param, left),
+        op if op.is_unary() => {
+            let operand = transform(transformer, left);
+            transformer.transform_unary(op, operand)
+        }
+        op => {
+            let left = transform(transformer, left);
+            let right = transform(transformer, right);
+            transformer.transform_binary(op, left, right)
+        }
+    }
+}
+
+/// Transforms every argument of a call. Arguments the transformer turns into
+/// nothing are dropped.
+pub fn transform_args<T: Transformer>(transformer: &mut T, args: Vec<Node>) -> Vec<Node> {
+    args.into_iter()
+        .filter_map(|arg| transform(transformer, Some(Box::new(arg))))
+        .map(|arg| *arg)
+        .collect()
+}
+
+#[cfg(test)]
+mod visitor_tests {
+    use super::*;
+    use crate::ast::Ast;
+    use crate::context::Context;
+    use crate::evaluator::Evaluator;
+    use crate::scanner::Scanner;
+
+    struct MultiplicationCounter {
+        count: usize,
+    }
+
+    impl Visitor for MultiplicationCounter {
+        type Output = ();
+
+        fn visit_number(&mut self, _n: f64) {}
+
+        fn visit_variable(&mut self, _name: &str) {}
+
+        fn visit_history(&mut self, _n: usize) {}
+
+        fn visit_text(&mut self, _text: &str) {}
+
+        fn visit_call(&mut self, _name: &str, args: &[Node]) {
+            for arg in args {
+                walk_node(self, arg);
+            }
+        }
+
+        fn visit_lambda(&mut self, _param: &str, body: &NodePtr) {
+            walk(self, body);
+        }
+
+        fn visit_unary(&mut self, _op: &TokenType, operand: &NodePtr) {
+            walk(self, operand);
+        }
+
+        fn visit_binary(&mut self, op: &TokenType, left: &NodePtr, right: &NodePtr) {
+            if *op == TokenType::Multiply {
+                self.count += 1;
+            }
+            walk(self, left);
+            walk(self, right);
+        }
+
+        fn visit_none(&mut self) {}
+    }
+
+    struct MultiplyToPlus;
+
+    impl Transformer for MultiplyToPlus {
+        fn transform_binary(&mut self, op: TokenType, left: NodePtr, right: NodePtr) -> NodePtr {
+            let op = if op == TokenType::Multiply {
+                TokenType::Plus
+            } else {
+                op
+            };
+            Node::new_ptr(op, left, right)
+        }
+    }
+
+    fn build(expr: &str) -> NodePtr {
+        let s = expr.to_string();
+        let mut scanner = Scanner::new(&s);
+        scanner.scan();
+
+        let mut ast = Ast::new(&mut scanner);
+        ast.build().unwrap();
+        ast.root
+    }
+
+    fn count_multiplications(expr: &str) -> usize {
+        println!("Expression = {}", expr);
+
+        let mut counter = MultiplicationCounter { count: 0 };
+        walk(&mut counter, &build(expr));
+        counter.count
+    }
+
+    #[test]
+    fn multiplication_counter_test() {
+        assert_eq!(count_multiplications("1 + 2"), 0);
+        assert_eq!(count_multiplications("2 * 3"), 1);
+        assert_eq!(count_multiplications("2 * 3 + 4 * 5"), 2);
+        assert_eq!(count_multiplications("-(2 * 3)! * |4 * 5|"), 3);
+        assert_eq!(count_multiplications("sum(i -> i * 2, 1, 3) * 2"), 2);
+    }
+
+    #[test]
+    fn identity_transform_test() {
+        let tree = build("-(1 + 2)! * |3 - 4| ^ 2 % 5");
+        let copy = build("-(1 + 2)! * |3 - 4| ^ 2 % 5");
+
+        struct Identity;
+        impl Transformer for Identity {}
+
+        assert_eq!(transform(&mut Identity, tree), copy);
+    }
+
+    #[test]
+    fn multiply_to_plus_transform_test() {
+        let tree = transform(&mut MultiplyToPlus, build("2 * 3 + |4 * -5|"));
+
+        assert_eq!(tree, build("2 + 3 + |4 + -5|"));
+        assert_eq!(walk(&mut Evaluator::new(&Context::new()), &tree), Ok(6.));
+    }
+}
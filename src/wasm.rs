@@ -0,0 +1,153 @@
+use wasm_bindgen::prelude::*;
+
+use super::ast::parse_expr;
+use super::command::{run_line, Session};
+use super::context::Context;
+use super::error::Diagnostic;
+use super::evaluator;
+use super::json;
+use super::value::Value;
+
+/// An error as JavaScript gets it, an object rather than a string: the code
+/// of its `ErrorCode`, the message and the position it's at, if any.
+#[wasm_bindgen(js_name = CalcError)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct WasmError {
+    code: String,
+    message: String,
+    position: Option<usize>,
+}
+
+impl WasmError {
+    pub fn new(err: Diagnostic) -> Self {
+        WasmError {
+            code: err.code.id().to_string(),
+            message: err.message,
+            position: err.pos,
+        }
+    }
+}
+
+#[wasm_bindgen(js_class = CalcError)]
+impl WasmError {
+    #[wasm_bindgen(getter)]
+    pub fn code(&self) -> String {
+        self.code.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn message(&self) -> String {
+        self.message.clone()
+    }
+
+    /// The position in chars of the input, `undefined` if there is none.
+    #[wasm_bindgen(getter)]
+    pub fn position(&self) -> Option<usize> {
+        self.position
+    }
+}
+
+fn to_js(err: Diagnostic) -> JsValue {
+    WasmError::new(err).into()
+}
+
+/// The real number a value is, an error for a complex one.
+fn real(value: Value) -> Result<f64, Diagnostic> {
+    match value {
+        Value::Complex(z) => Err(Diagnostic::from(format!("{} isn't a real number!", z))),
+        value => Ok(value.as_f64()),
+    }
+}
+
+/// Evaluates `expr` without variables, like `evaluator::evaluate`.
+#[wasm_bindgen]
+pub fn evaluate(expr: &str) -> Result<f64, JsValue> {
+    evaluator::evaluate_diagnosed(&expr.to_string(), &Context::new()).map_err(to_js)
+}
+
+/// The tree of `expr` as JSON, like `:ast json`.
+#[wasm_bindgen(js_name = parseToJson)]
+pub fn parse_to_json(expr: &str) -> Result<String, JsValue> {
+    parse_json(expr).map_err(to_js)
+}
+
+fn parse_json(expr: &str) -> Result<String, Diagnostic> {
+    let root = parse_expr(expr, &Context::new())?;
+    Ok(json::tree(&root))
+}
+
+/// A session for JavaScript, whose variables, assignments and results stay
+/// from one call to the next.
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct Calculator {
+    session: Session,
+}
+
+#[wasm_bindgen]
+impl Calculator {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs a line of statements like the REPL, `x = 2; x^2`, and returns
+    /// the value of the last one, `undefined` for a blank line.
+    pub fn evaluate(&mut self, line: &str) -> Result<Option<f64>, JsValue> {
+        self.run(line).map_err(to_js)
+    }
+
+    #[wasm_bindgen(js_name = setVar)]
+    pub fn set_var(&mut self, name: &str, value: f64) {
+        self.session.ctx.set_var(name, value);
+    }
+
+    #[wasm_bindgen(js_name = getVar)]
+    pub fn get_var(&self, name: &str) -> Option<f64> {
+        self.session.ctx.get_var(name)
+    }
+}
+
+impl Calculator {
+    fn run(&mut self, line: &str) -> Result<Option<f64>, Diagnostic> {
+        match run_line(line, &mut self.session)?.pop() {
+            Some(last) => real(last.value).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod wasm_tests {
+    use super::*;
+
+    // Values that cross into JavaScript only exist in wasm, the tests here
+    // check what the bindings return before that.
+    #[test]
+    fn error_test() {
+        let error = WasmError::new(Calculator::new().run("2 * sqrt(-1)").unwrap_err());
+        assert_eq!(
+            (error.code(), error.position()),
+            (String::from("E002"), Some(4))
+        );
+        let error = WasmError::new(parse_json("1 +").unwrap_err());
+        assert_eq!((error.code(), error.position()), (String::from("E001"), Some(2)));
+        let error = WasmError::new(Diagnostic::from(String::from("Empty!")));
+        assert_eq!(error.position(), None);
+    }
+
+    #[test]
+    fn calculator_test() {
+        let mut calculator = Calculator::new();
+        assert_eq!(calculator.run("x = 3; 2x"), Ok(Some(6.)));
+        assert_eq!(calculator.get_var("x"), Some(3.));
+        calculator.set_var("y", 0.5);
+        assert_eq!(calculator.run("x + y + $1"), Ok(Some(9.5)));
+        assert_eq!(calculator.run(""), Ok(None));
+        assert!(calculator.run("z").is_err());
+        assert_eq!(
+            parse_json("1 + 2"),
+            Ok(json::tree(&crate::ast::parse("1 + 2").unwrap()))
+        );
+    }
+}
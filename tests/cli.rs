@@ -0,0 +1,484 @@
+//! Tests of the binary, which needs the `cli` and `repl` features.
+#![cfg(all(feature = "cli", feature = "repl"))]
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ls_calc"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn do_test(args: &[&str], expect_code: i32, expect_stdout: &str) -> String {
+    println!("Args = {:?}", args);
+
+    let output = run(args);
+    assert_eq!(output.status.code(), Some(expect_code));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expect_stdout);
+    String::from_utf8(output.stderr).unwrap()
+}
+
+#[test]
+fn expr_test() {
+    assert_eq!(do_test(&["-e", "2^10 - 24"], 0, "1000\n"), "");
+    assert_eq!(do_test(&["-e", "x=3", "-e", "x*2"], 0, "6\n"), "");
+    assert_eq!(do_test(&["--expr", "1 + 2; 3 * 4"], 0, "12\n"), "");
+}
+
+#[test]
+fn exit_code_test() {
+    let stderr = do_test(&["-e", "1+"], 2, "");
+    assert!(stderr.contains("Ast build error!"));
+    let stderr = do_test(&["-e", "sqrt(-1)"], 3, "");
+    assert!(stderr.contains("Domain error!"));
+    let stderr = do_test(&["-e", "∞"], 2, "");
+    assert!(stderr.contains("'∞' at pos 0 is an unknown character!"));
+    // The expressions before the error are still printed.
+    do_test(&["-e", "1", "-e", "1 / 0", "-e", "2"], 3, "1\n");
+
+    let stderr = do_test(&["-x"], 1, "");
+    assert!(stderr.starts_with("Unknown option -x!"));
+    do_test(&["-e"], 1, "");
+}
+
+#[test]
+fn var_test() {
+    let args = [
+        "--var",
+        "r=2.5",
+        "--var",
+        "pi_approx=3.14",
+        "-e",
+        "pi_approx * r^2",
+    ];
+    assert_eq!(do_test(&args, 0, "19.625\n"), "");
+    assert_eq!(
+        do_test(
+            &["--var", "x=2^10", "--var", "y=x/2", "-e", "y"],
+            0,
+            "512\n"
+        ),
+        ""
+    );
+
+    let stderr = do_test(&["--var", "x", "-e", "1"], 1, "");
+    assert!(stderr.starts_with("Option --var expects NAME=EXPR, but gets x!"));
+    let stderr = do_test(&["--var", "x=1+", "-e", "1"], 2, "");
+    assert!(stderr.starts_with("Option --var x=1+:"));
+}
+
+#[test]
+fn dump_test() {
+    let tree = "{\n Token: Plus\n Left: \n {\n  Token: Multiply\n  Left: \n  {\n   Token: Number(2.0)\n  }\n  Right: \n  {\n   Token: Number(3.0)\n  }\n }\n Right: \n {\n  Token: Number(4.0)\n }\n}\n";
+    assert_eq!(do_test(&["--ast", "2*3+4"], 0, tree), "");
+    let tokens =
+        "  0: Number(2.0)\n  1: Multiplication\n  2: Number(3.0)\n  3: Plus\n  4: Number(4.0)\n";
+    assert_eq!(do_test(&["--tokens", "2*3+4"], 0, tokens), "");
+
+    let output = run(&["--ast=json", "2*3+4"]);
+    assert_eq!(output.status.code(), Some(0));
+    let root = parse_json(String::from_utf8(output.stdout).unwrap().trim_end());
+    assert_eq!(root.get("op"), &Json::String(String::from("+")));
+    assert_eq!(root.get("left").get("op"), &Json::String(String::from("*")));
+    assert_eq!(root.get("right").get("value"), &Json::Number(4.));
+    assert_eq!(root.get("right").get("pos"), &Json::Number(4.));
+
+    let stderr = do_test(&["--ast", "2*+"], 2, "");
+    assert!(stderr.starts_with("2*+\n  ^\nAst build error!"));
+    let stderr = do_test(&["--tokens", "1__0"], 2, "");
+    assert!(stderr.contains("Ast build error! Number 1__0 at pos 0"));
+}
+
+#[test]
+fn color_test() {
+    let stderr = do_test(&["--color=always", "-e", "2 * 0x1G"], 2, "");
+    assert_eq!(
+        stderr,
+        "2 * 0x1G\n    \x1b[31m^^^^\x1b[0m\n\x1b[1mAst build error! Number 0x1G at pos 4 is malformed!\x1b[0m \x1b[2m[E001]\x1b[0m\n"
+    );
+    // Output that isn't a terminal has no colors.
+    let plain = "2 * 0x1G\n    ^^^^\nAst build error! Number 0x1G at pos 4 is malformed!\n";
+    assert_eq!(do_test(&["-e", "2 * 0x1G"], 2, ""), plain);
+    assert_eq!(do_test(&["--color=never", "-e", "2 * 0x1G"], 2, ""), plain);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ls_calc"))
+        .args(["--color=auto", "-e", "2 * 0x1G"])
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), plain);
+}
+
+#[test]
+fn load_test() {
+    let path = std::env::temp_dir().join(format!("ls_calc_load_{}.calc", std::process::id()));
+    let path = path.to_str().unwrap();
+    let input = format!("r = 2.5\nd = 2 * r\n:save {}\n", path);
+    let output = run_piped(&[], input.as_bytes());
+    assert_eq!(output.status.code(), Some(0));
+
+    assert_eq!(do_test(&["--load", path, "-e", "d * r"], 0, "12.5\n"), "");
+    // `--var` comes after `--load`, so it can change a loaded variable.
+    assert_eq!(
+        do_test(&["--load", path, "--var", "r=1", "-e", "d + r"], 0, "6\n"),
+        ""
+    );
+
+    std::fs::write(path, "r = 1\nd = r +\n").unwrap();
+    let stderr = do_test(&["--load", path, "-e", "1"], 2, "");
+    assert!(stderr.starts_with(&format!("Option --load {}: Line 2: Ast build error!", path)));
+    std::fs::remove_file(path).unwrap();
+
+    let stderr = do_test(&["--load", "no/such/file.calc", "-e", "1"], 1, "");
+    assert!(stderr.starts_with("Option --load no/such/file.calc: Can't load"));
+}
+
+#[test]
+fn help_test() {
+    let output = run(&["--help"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .starts_with("Usage:"));
+}
+
+const BATCH: &str = "r = 2\n\n# the area of the circle\npi * r^2 / pi\n1 +\nr + 1\n";
+
+fn batch_file(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("ls_calc_{}_{}.txt", name, std::process::id()));
+    std::fs::write(&path, BATCH).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn file_test() {
+    let path = batch_file("stop");
+    let stderr = do_test(&["--file", &path], 2, "4\n");
+    assert!(stderr.starts_with("Line 5:\n1 +\n  ^\nAst build error!"));
+    std::fs::remove_file(path).unwrap();
+
+    let path = batch_file("keep_going");
+    let stderr = do_test(&["-f", &path, "--keep-going"], 2, "4\n3\n");
+    assert_eq!(stderr.matches("Line ").count(), 1);
+    std::fs::remove_file(path).unwrap();
+
+    let stderr = do_test(&["-f", "no/such/file.txt"], 1, "");
+    assert!(stderr.starts_with("Can't open the file no/such/file.txt!"));
+    let stderr = do_test(&["--watch", "no/such/file.txt"], 1, "");
+    assert!(stderr.starts_with("Can't open the file no/such/file.txt!"));
+}
+
+fn run_piped(args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_ls_calc"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn piped_input_test() {
+    let output = run_piped(&[], b"x = 3\nx * 2\nsqrt(-1)\n7\n");
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(output.stdout, b"6\n");
+    assert_eq!(
+        output.stderr,
+        &b"Line 3:\nsqrt(-1)\n^^^^^^^^\nDomain error! sqrt(-1) is undefined!\n"[..]
+    );
+
+    // No prompt, `<<<` or echo of the input, only the results.
+    let output = run_piped(&["-k"], b"1 + 2\n# note\n\n2^10\n1 +\n:base hex\n255\n");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(output.stdout, b"3\n1024\n0xFF\n");
+
+    let output = run_piped(&["--quiet"], b"1 + 2\n");
+    assert_eq!(output.stdout, b"3\n");
+}
+
+#[test]
+fn interactive_test() {
+    let output = run_piped(&["--interactive"], b"1 + 2\nx = 2\n1 +\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        ">>> <<< 3\n>>> <<< x = 2\n>>> ... Error happened: Ast build error! \
+         Operator Plus at pos 2 expects an operand, but gets End!\n>>> \n"
+    );
+    assert!(output.stderr.is_empty());
+
+    let stderr = do_test(&["-q", "-i"], 1, "");
+    assert!(stderr.starts_with("Options --quiet and --interactive exclude each other!"));
+}
+
+/// A parsed JSON value, to check that `--json` writes valid JSON.
+#[derive(Debug, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn get(&self, key: &str) -> &Json {
+        match self {
+            Json::Object(fields) => &fields.iter().find(|(k, _)| k == key).unwrap().1,
+            _ => panic!("{:?} isn't an object!", self),
+        }
+    }
+}
+
+/// Parses a whole JSON text, panicking at anything that isn't JSON.
+fn parse_json(text: &str) -> Json {
+    let chars: Vec<char> = text.chars().collect();
+    let mut pos = 0;
+    let json = parse_value(&chars, &mut pos);
+    skip_whitespace(&chars, &mut pos);
+    assert_eq!(pos, chars.len(), "Trailing text in {}", text);
+    json
+}
+
+fn skip_whitespace(chars: &[char], pos: &mut usize) {
+    while *pos < chars.len() && chars[*pos].is_whitespace() {
+        *pos += 1;
+    }
+}
+
+fn expect(chars: &[char], pos: &mut usize, text: &str) {
+    for c in text.chars() {
+        assert_eq!(chars.get(*pos), Some(&c), "Expected {} at {}", text, pos);
+        *pos += 1;
+    }
+}
+
+fn parse_value(chars: &[char], pos: &mut usize) -> Json {
+    skip_whitespace(chars, pos);
+    match chars[*pos] {
+        'n' => {
+            expect(chars, pos, "null");
+            Json::Null
+        }
+        't' => {
+            expect(chars, pos, "true");
+            Json::Bool(true)
+        }
+        'f' => {
+            expect(chars, pos, "false");
+            Json::Bool(false)
+        }
+        '"' => Json::String(parse_string(chars, pos)),
+        '[' => {
+            *pos += 1;
+            let mut items = Vec::new();
+            skip_whitespace(chars, pos);
+            while chars[*pos] != ']' {
+                items.push(parse_value(chars, pos));
+                skip_whitespace(chars, pos);
+                if chars[*pos] == ',' {
+                    *pos += 1;
+                }
+            }
+            *pos += 1;
+            Json::Array(items)
+        }
+        '{' => {
+            *pos += 1;
+            let mut fields = Vec::new();
+            skip_whitespace(chars, pos);
+            while chars[*pos] != '}' {
+                skip_whitespace(chars, pos);
+                let key = parse_string(chars, pos);
+                skip_whitespace(chars, pos);
+                expect(chars, pos, ":");
+                fields.push((key, parse_value(chars, pos)));
+                skip_whitespace(chars, pos);
+                if chars[*pos] == ',' {
+                    *pos += 1;
+                }
+            }
+            *pos += 1;
+            Json::Object(fields)
+        }
+        _ => {
+            let start = *pos;
+            while *pos < chars.len() && "+-.0123456789eE".contains(chars[*pos]) {
+                *pos += 1;
+            }
+            let number: String = chars[start..*pos].iter().collect();
+            Json::Number(number.parse().unwrap())
+        }
+    }
+}
+
+fn parse_string(chars: &[char], pos: &mut usize) -> String {
+    expect(chars, pos, "\"");
+    let mut s = String::new();
+    loop {
+        let c = chars[*pos];
+        *pos += 1;
+        match c {
+            '"' => return s,
+            '\\' => {
+                let escaped = chars[*pos];
+                *pos += 1;
+                match escaped {
+                    'n' => s.push('\n'),
+                    'r' => s.push('\r'),
+                    't' => s.push('\t'),
+                    'u' => {
+                        let hex: String = chars[*pos..*pos + 4].iter().collect();
+                        *pos += 4;
+                        s.push(char::from_u32(u32::from_str_radix(&hex, 16).unwrap()).unwrap());
+                    }
+                    c => s.push(c),
+                }
+            }
+            c => s.push(c),
+        }
+    }
+}
+
+fn json_lines(args: &[&str], expect_code: i32) -> Vec<Json> {
+    println!("Args = {:?}", args);
+
+    let output = run(args);
+    assert_eq!(output.status.code(), Some(expect_code));
+    assert!(output.stderr.is_empty());
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(parse_json)
+        .collect()
+}
+
+#[test]
+fn json_test() {
+    let lines = json_lines(
+        &["--json", "-e", "1/3", "-e", "x = 2 # \"two\"", "-e", "1+"],
+        2,
+    );
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0].get("expr"), &Json::String(String::from("1/3")));
+    assert_eq!(lines[0].get("ok"), &Json::Bool(true));
+    assert_eq!(lines[0].get("value"), &Json::Number(1. / 3.));
+    assert_eq!(lines[2].get("ok"), &Json::Bool(false));
+    assert_eq!(
+        lines[1].get("expr"),
+        &Json::String(String::from("x = 2 # \"two\""))
+    );
+
+    let lines = json_lines(&["--json", "-e", "1+"], 2);
+    let error = lines[0].get("error");
+    assert_eq!(error.get("code"), &Json::String(String::from("E001")));
+    assert_eq!(error.get("pos"), &Json::Number(1.));
+    assert!(matches!(error.get("message"), Json::String(message) if message.contains("at pos 1")));
+
+    let lines = json_lines(&["--json", "-e", "sqrt(-1)"], 3);
+    assert_eq!(
+        lines[0].get("error").get("code"),
+        &Json::String(String::from("E002"))
+    );
+    // A domain error is at its call.
+    assert_eq!(lines[0].get("error").get("pos"), &Json::Number(0.));
+}
+
+#[test]
+fn json_batch_test() {
+    let path = batch_file("json");
+    let lines = json_lines(&["--json", "-k", "-f", &path], 2);
+    std::fs::remove_file(path).unwrap();
+    let numbers: Vec<&Json> = lines.iter().map(|line| line.get("line")).collect();
+    assert_eq!(
+        numbers,
+        [
+            &Json::Number(1.),
+            &Json::Number(4.),
+            &Json::Number(5.),
+            &Json::Number(6.)
+        ]
+    );
+    assert_eq!(lines[0].get("name"), &Json::String(String::from("r")));
+    assert_eq!(lines[1].get("value"), &Json::Number(4.));
+    assert_eq!(lines[2].get("ok"), &Json::Bool(false));
+    assert_eq!(lines[3].get("value"), &Json::Number(3.));
+}
+
+#[test]
+fn bench_test() {
+    let args = [
+        "--bench",
+        "200",
+        "--json",
+        "-e",
+        "sin(x)*x^2",
+        "--var",
+        "x=1.2345",
+    ];
+    let output = run(&args);
+    assert_eq!(output.status.code(), Some(0));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("Benchmark of sin(x)*x^2: 200 iterations (parse "));
+
+    let lines: Vec<Json> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(parse_json)
+        .collect();
+    assert_eq!(lines.len(), 1);
+    let bench = lines[0].get("bench");
+    assert_eq!(bench.get("iterations"), &Json::Number(200.));
+    let checksum = match bench.get("checksum") {
+        Json::Number(checksum) => *checksum,
+        other => panic!("The checksum is {:?}!", other),
+    };
+    let x: f64 = 1.2345;
+    assert!((checksum - 200. * x.sin() * x * x).abs() < 1e-9);
+    for key in ["parse_ns", "total_ns", "mean_ns", "min_ns"] {
+        assert!(matches!(bench.get(key), Json::Number(nanos) if *nanos >= 0.));
+    }
+
+    do_test(&["--bench", "10", "-e", "1 +"], 2, "");
+}
+
+#[test]
+fn verbose_test() {
+    let stderr = do_test(&["-v", "-e", "1+2"], 0, "3\n");
+    assert!(stderr.contains("[DEBUG ls_calc::scanner] scans 4 tokens of \"1+2\"\n"));
+    assert!(stderr.ends_with("[DEBUG ls_calc::evaluator] evaluates \"1+2\" to Ok(Int(3))\n"));
+    assert!(!stderr.contains("TRACE"));
+    let stderr = do_test(&["-vv", "-e", "1+2"], 0, "3\n");
+    assert!(stderr.contains("[TRACE ls_calc::exact] Plus at pos 1 gives Ok(Int(3))\n"));
+}
+
+#[test]
+fn csv_test() {
+    let path = format!("{}/tests/fixtures/prices.csv", env!("CARGO_MANIFEST_DIR"));
+    let stderr = do_test(
+        &["--csv", &path, "--csv-error", "#N/A", "-e", "price * qty"],
+        3,
+        "item,price,qty,tax,result\npen,1.5,4,0.2,6\n\"paper, A4\",5,2,0.1,10\nink,n/a,1,0.2,#N/A\n",
+    );
+    assert_eq!(stderr, "Row 3: Unknown variable price!\n");
+}
+
+#[test]
+fn env_test() {
+    let output = Command::new(env!("CARGO_BIN_EXE_ls_calc"))
+        .args(["-e", "env(\"THREADS\") * 2"])
+        .env("THREADS", "8")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "16\n");
+
+    let stderr = do_test(&["-e", "env(\"LS_CALC_UNSET\")"], 3, "");
+    assert!(stderr.contains("Environment variable LS_CALC_UNSET isn't set!"));
+}
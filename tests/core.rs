@@ -0,0 +1,48 @@
+//! The library without its optional features, which
+//! `cargo test --no-default-features --test core` checks. It uses only the
+//! core API, expressions evaluated against a `Context`, so it passes with any
+//! features. Every dependency is optional,
+//! so that build compiles nothing but `std` and the crate itself.
+
+use ls_calc::ast::parse;
+use ls_calc::context::Context;
+use ls_calc::evaluator::{evaluate, evaluate_value, evaluate_with};
+use ls_calc::value::Value;
+
+#[test]
+fn core_test() {
+    assert_eq!(evaluate(&String::from("2^10 - 24")), Ok(1000.));
+    assert_eq!(
+        parse("1 + 2 * x").unwrap().unwrap().to_string(),
+        "1 + 2 * x"
+    );
+    assert!(parse("1 +").is_err());
+
+    let mut ctx = Context::new();
+    ctx.set_var("x", 3.);
+    assert_eq!(evaluate_with(&String::from("sqrt(x^2 + 16)"), &ctx), Ok(5.));
+
+    ctx.set_rational_mode(true);
+    assert_eq!(
+        evaluate_value(&String::from("1/3 + 1/6"), &ctx).map(|value| value.to_string()),
+        Ok(String::from("1/2"))
+    );
+    ctx.set_integer_mode(true);
+    assert_eq!(
+        evaluate_value(&String::from("x! + 1"), &ctx),
+        Ok(Value::Int(7))
+    );
+}
+
+#[test]
+fn dependencies_test() {
+    let manifest = include_str!("../Cargo.toml");
+    let mut section = "";
+    for line in manifest.lines().map(str::trim) {
+        if line.starts_with('[') {
+            section = line;
+        } else if section == "[dependencies]" && !line.is_empty() && !line.starts_with('#') {
+            assert!(line.contains("optional = true"), "{} is not optional", line);
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::panic;
+
+use ls_calc::ast::MAX_DEPTH;
+#[cfg(feature = "repl")]
+use ls_calc::command::{run_line, Session};
+use ls_calc::evaluator::evaluate;
+use ls_calc::random::Random;
+
+/// Evaluates `expr` alone and, with the `repl` feature, as a line of the
+/// REPL, which fails the test if either panics or an error shows the Rust form of a token.
+fn check(expr: &str) {
+    let input = expr.to_string();
+    let result = panic::catch_unwind(|| {
+        if let Err(err) = evaluate(&input) {
+            assert!(!err.contains("Token {"), "{:?} gives {:?}", expr, err);
+        }
+        #[cfg(feature = "repl")]
+        let _ = run_line(&input, &mut Session::default());
+    });
+    assert!(result.is_ok(), "Evaluating {:?} panics!", expr);
+}
+
+#[test]
+fn corpus_test() {
+    let path = format!("{}/tests/fixtures/panic_corpus", env!("CARGO_MANIFEST_DIR"));
+    let corpus = std::fs::read_to_string(path).unwrap();
+    for expr in corpus.lines() {
+        check(expr);
+    }
+}
+
+#[test]
+fn unexpected_token_test() {
+    let error = |expr: &str| evaluate(&expr.to_string()).unwrap_err();
+    assert_eq!(error("="), "Ast build error! Unexpected '=' at pos 0!");
+    assert_eq!(error(","), "Ast build error! Unexpected ',' at pos 0!");
+    assert_eq!(error("2 * )"), "Ast build error! Unexpected ')' at pos 4!");
+}
+
+#[test]
+fn nesting_test() {
+    let deep = |open: &str, close: &str, n: usize| open.repeat(n) + "1" + &close.repeat(n);
+    for n in [MAX_DEPTH - 10, MAX_DEPTH + 1, 10 * MAX_DEPTH] {
+        check(&deep("(", ")", n));
+        check(&deep("sin(", ")", n));
+        check(&deep("|", "|", n));
+        check(&deep("-", "", n));
+        check(&deep("2^", "", n));
+        check(&deep("1+", "", n));
+        check(&deep("x -> ", "", n));
+        check(&deep("", "!", n));
+    }
+    assert_eq!(evaluate(&deep("(", ")", MAX_DEPTH - 10)), Ok(1.));
+    assert_eq!(
+        evaluate(&deep("1+", "", MAX_DEPTH)),
+        Err(format!(
+            "Ast build error! Expression at pos {} nests deeper than {} levels!",
+            2 * MAX_DEPTH - 1,
+            MAX_DEPTH
+        ))
+    );
+}
+
+/// Pieces of expressions, valid or not, that random inputs are made of.
+const PIECES: &[&str] = &[
+    "0", "1", "12", "0.5", ".", ",", "e", "E", "_", "0x", "0b", "1e309", "x", "pi", "π", "i",
+    "sin(", "sqrt(", "max(", "sum(", "out(", "(", ")", "+", "-", "*", "/", "//", "^", "!", "%",
+    "|", "$", "$1", "->", "=", "==", "<", "and", "not", "mod", "xor", "²", "√", "−", "×", " ",
+    "\"", "#", "\\", ";", ":", "[", "°",
+];
+
+#[test]
+fn random_pieces_test() {
+    let mut random = Random::new(0x5eed);
+    for _ in 0..20_000 {
+        let len = random.below(12) + 1;
+        let expr: String = (0..len)
+            .map(|_| PIECES[random.below(PIECES.len())])
+            .collect();
+        check(&expr);
+    }
+}
+
+#[test]
+fn random_chars_test() {
+    let mut random = Random::new(0xc0ffee);
+    for _ in 0..20_000 {
+        let len = random.below(8) + 1;
+        let expr: String = (0..len)
+            .filter_map(|_| match random.below(4) {
+                // Mostly ASCII, which the scanner knows most of.
+                0..=2 => char::from_u32(random.below(0x80) as u32),
+                _ => char::from_u32(random.below(0x3000) as u32),
+            })
+            .collect();
+        check(&expr);
+    }
+}
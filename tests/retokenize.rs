@@ -0,0 +1,101 @@
+use ls_calc::ast::{reparse, Ast};
+use ls_calc::random::Random;
+use ls_calc::scanner::{Scanner, TextEdit};
+
+/// Pieces that expressions and the texts of edits are made of, with the
+/// chars the scanner looks ahead at, like `,` and the SI suffixes, and the
+/// quotes of strings.
+const PIECES: &[&str] = &[
+    "0", "1", "12", "0.5", ".", ",", ";", "E", "_", "0x", "0b", "k", "m", "x", "pi", "π", "sin(",
+    "max(", "(", ")", "+", "-", ">", "*", "/", "^", "!", "=", "<", "|", "$", "$1", "->", "²", "√",
+    "×", " ", "  ", "\n", "#", "°", "\"", "\"a b\"",
+];
+
+/// Up to `max` random pieces.
+fn pieces(random: &mut Random, max: usize) -> String {
+    let len = random.below(max + 1);
+    (0..len)
+        .map(|_| PIECES[random.below(PIECES.len())])
+        .collect()
+}
+
+/// An edit of `expr` that replaces up to a few chars.
+fn edit(random: &mut Random, expr: &str) -> (usize, usize, String) {
+    let bounds: Vec<usize> = expr
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(Some(expr.len()))
+        .collect();
+    let first = random.below(bounds.len());
+    let last = (first + random.below(4)).min(bounds.len() - 1);
+    (bounds[first], bounds[last], pieces(random, 2))
+}
+
+fn scanner<'a>(expr: &'a String, settings: (bool, bool)) -> Scanner<'a> {
+    let mut scanner = Scanner::new(expr);
+    scanner.set_decimal_comma(settings.0);
+    scanner.set_si_suffixes(settings.1);
+    scanner
+}
+
+#[test]
+fn random_edits_test() {
+    let mut random = Random::new(0xed17);
+    for round in 0..2_000 {
+        let settings = (round % 2 == 1, round % 4 >= 2);
+        // Every version stays alive as long as the scanner of the first.
+        let mut versions = vec![pieces(&mut random, 16)];
+        let mut edits = Vec::new();
+        for _ in 0..10 {
+            let (start, end, text) = edit(&mut random, versions.last().unwrap());
+            let edited = TextEdit::new(start, end, &text).apply(versions.last().unwrap());
+            edits.push((start, end, text));
+            versions.push(edited);
+        }
+
+        let mut edited = scanner(&versions[0], settings);
+        edited.scan();
+        for (version, (start, end, text)) in versions[1..].iter().zip(&edits) {
+            let edit = TextEdit::new(*start, *end, text);
+            edited.retokenize(version, &edit);
+
+            let mut fresh = scanner(version, settings);
+            fresh.scan();
+            assert_eq!(
+                edited.get_tokens(),
+                fresh.get_tokens(),
+                "{:?} after {:?}",
+                version,
+                edit
+            );
+        }
+    }
+}
+
+#[test]
+fn reparse_test() {
+    let mut random = Random::new(0xa57);
+    for _ in 0..2_000 {
+        let old = pieces(&mut random, 12);
+        let (start, end, text) = edit(&mut random, &old);
+        let edit = TextEdit::new(start, end, &text);
+        let new = edit.apply(&old);
+
+        let mut edited = Scanner::new(&old);
+        edited.scan();
+        let mut fresh = Scanner::new(&new);
+        fresh.scan();
+        let mut ast = Ast::new(&mut fresh);
+        let expected = ast.build().map(|_| ast.root);
+        assert_eq!(reparse(&mut edited, &new, &edit), expected, "{:?}", new);
+    }
+
+    // The scanner parses again after every edit.
+    let versions = [String::from("1 + 2"), String::from("10 + 2")];
+    let mut scanner = Scanner::new(&versions[0]);
+    scanner.scan();
+    let tree = reparse(&mut scanner, &versions[1], &TextEdit::new(1, 1, "0"));
+    assert_eq!(tree, ls_calc::ast::parse("10 + 2"));
+    let tree = reparse(&mut scanner, &versions[1], &TextEdit::new(0, 0, ""));
+    assert_eq!(tree, ls_calc::ast::parse("10 + 2"));
+}
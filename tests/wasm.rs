@@ -0,0 +1,27 @@
+//! Run with `wasm-pack test --node -- --features wasm`.
+#![cfg(all(target_arch = "wasm32", feature = "wasm"))]
+
+use ls_calc::wasm::{evaluate, parse_to_json, Calculator, WasmError};
+use wasm_bindgen::convert::TryFromJsValue;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+#[wasm_bindgen_test]
+fn evaluate_test() {
+    assert_eq!(evaluate("2^10 - 24").unwrap(), 1000.);
+    assert!(parse_to_json("1 + 2").unwrap().starts_with('{'));
+
+    let mut calculator = Calculator::new();
+    assert_eq!(calculator.evaluate("x = 3; 2x").unwrap(), Some(6.));
+    calculator.set_var("y", 0.5);
+    assert_eq!(calculator.evaluate("x + y").unwrap(), Some(3.5));
+    assert_eq!(calculator.get_var("x"), Some(3.));
+}
+
+#[wasm_bindgen_test]
+fn error_test() {
+    let error = evaluate("1 +* 2").unwrap_err();
+    let error = WasmError::try_from_js_value(error).expect("A CalcError");
+    assert_eq!(error.code(), "E001");
+    assert_eq!(error.position(), Some(3));
+    assert!(error.message().starts_with("Ast build error!"));
+}